@@ -2,12 +2,13 @@ use clap::Parser;
 use engine::{
     get_base_routes, get_cors_layer, get_jsonapi_route, get_metadata_routes,
     internal_flags::{resolve_unstable_features, UnstableFeature},
-    StartupError, VERSION,
+    CorsConfiguration, StartupError, VERSION,
 };
 use engine_types::ExposeInternalErrors;
 use graphql_ir::GraphqlRequestPipeline;
 use serde::Serialize;
 use std::net;
+use std::num::{NonZeroU32, NonZeroUsize};
 use std::path::PathBuf;
 use tracing_util::{add_event_on_active_span, set_attribute_on_active_span, SpanVisibility};
 
@@ -52,6 +53,41 @@ struct ServerOptions {
         value_delimiter = ','
     )]
     cors_allow_origin: Vec<String>,
+    /// The list of methods allowed over CORS. Requires `--enable-cors` to be set.
+    #[arg(
+        long,
+        value_name = "METHOD_LIST",
+        env = "CORS_ALLOW_METHODS",
+        requires = "enable_cors",
+        value_delimiter = ',',
+        default_value = "GET,HEAD,POST,OPTIONS"
+    )]
+    cors_allow_methods: Vec<axum::http::Method>,
+    /// The list of headers allowed over CORS, besides `Authorization`, `x-hasura-admin-secret`,
+    /// `x-hasura-role` and other session variables. If not provided, all headers are allowed.
+    /// Requires `--enable-cors` to be set.
+    #[arg(
+        long,
+        value_name = "HEADER_LIST",
+        env = "CORS_ALLOW_HEADERS",
+        requires = "enable_cors",
+        value_delimiter = ','
+    )]
+    cors_allow_headers: Vec<axum::http::HeaderName>,
+    /// Do not allow credentials, like cookies, in requests over CORS. Requires `--enable-cors` to
+    /// be set.
+    #[arg(long, env = "CORS_DISALLOW_CREDENTIALS", requires = "enable_cors")]
+    cors_disallow_credentials: bool,
+    /// How long, in seconds, browsers may cache the response to a CORS preflight request.
+    /// Requires `--enable-cors` to be set.
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        env = "CORS_MAX_AGE",
+        requires = "enable_cors",
+        default_value_t = 24 * 60 * 60
+    )]
+    cors_max_age: u64,
     /// List of internal unstable features to enable, separated by commas
     #[arg(
         long = "unstable-feature",
@@ -74,6 +110,143 @@ struct ServerOptions {
     /// Service name output in OpenTelemetry traces
     #[arg(long, env = "OTEL_SERVICE_NAME")]
     otel_service_name: Option<String>,
+
+    /// The number of resources returned by JSON:API listing endpoints when a request does not
+    /// specify `page[limit]`.
+    #[arg(long, env = "JSONAPI_DEFAULT_PAGE_LIMIT", default_value_t = jsonapi::DEFAULT_PAGE_LIMIT)]
+    jsonapi_default_page_limit: usize,
+
+    /// The largest `page[limit]` a JSON:API request may ask for. Requests above it are rejected.
+    #[arg(long, env = "JSONAPI_MAX_PAGE_LIMIT", default_value_t = jsonapi::DEFAULT_MAX_PAGE_LIMIT)]
+    jsonapi_max_page_limit: usize,
+
+    /// Use cursor based pagination for JSON:API listing endpoints by default. Requests can still
+    /// choose offset pagination by sending `page[offset]`.
+    #[arg(long, env = "JSONAPI_CURSOR_PAGINATION")]
+    jsonapi_cursor_pagination: bool,
+
+    /// The secret used to sign JSON:API `page[cursor]` tokens. If not given, a random secret is
+    /// generated on startup, so cursors are not valid across restarts or between replicas.
+    #[arg(long, env = "JSONAPI_CURSOR_SECRET", hide_env_values = true)]
+    jsonapi_cursor_secret: Option<String>,
+
+    /// How many relationships deep a JSON:API `include` path may go. Requests with deeper paths
+    /// are rejected.
+    #[arg(long, env = "JSONAPI_MAX_INCLUDE_DEPTH", default_value_t = jsonapi::DEFAULT_MAX_INCLUDE_DEPTH)]
+    jsonapi_max_include_depth: usize,
+
+    /// How many distinct `include` paths a JSON:API request may ask for. Requests with more are
+    /// rejected.
+    #[arg(long, env = "JSONAPI_MAX_INCLUDE_PATHS", default_value_t = jsonapi::DEFAULT_MAX_INCLUDE_PATHS)]
+    jsonapi_max_include_paths: usize,
+
+    /// How many reads a JSON:API batch request (`POST /v1/rest/__batch`) may have. Batches with
+    /// more are rejected.
+    #[arg(long, env = "JSONAPI_MAX_BATCH_OPERATIONS", default_value_t = jsonapi::DEFAULT_MAX_BATCH_OPERATIONS)]
+    jsonapi_max_batch_operations: usize,
+
+    /// The smallest JSON:API response, in bytes, that is compressed with gzip or brotli when the
+    /// client accepts it.
+    #[arg(long, env = "JSONAPI_COMPRESSION_MIN_SIZE", default_value_t = jsonapi::DEFAULT_COMPRESSION_MIN_SIZE)]
+    jsonapi_compression_min_size: u16,
+
+    /// Do not compress JSON:API responses, eg because a proxy in front of the engine already
+    /// does.
+    #[arg(long, env = "JSONAPI_DISABLE_COMPRESSION")]
+    jsonapi_disable_compression: bool,
+
+    /// The smallest page of JSON:API resources, in rows, that is streamed to the client as it is
+    /// serialized rather than serialized in full first. The rows are still all read from the data
+    /// connector before anything is sent. Requests with `include` are never streamed.
+    #[arg(long, env = "JSONAPI_STREAM_MIN_ROWS", default_value_t = jsonapi::DEFAULT_STREAM_MIN_ROWS)]
+    jsonapi_stream_min_rows: usize,
+
+    /// Do not stream JSON:API responses, so that every response gets an `ETag`.
+    #[arg(long, env = "JSONAPI_DISABLE_STREAMING")]
+    jsonapi_disable_streaming: bool,
+
+    /// The largest JSON:API document, in bytes, that is sent in a response. Requests for larger
+    /// documents get a 400 asking them to narrow `fields`, `page[limit]` or `include`. Responses
+    /// are not streamed when this is given, so that they can be rejected before anything is sent.
+    /// Not limited if not given.
+    #[arg(long, env = "JSONAPI_MAX_RESPONSE_SIZE", value_name = "BYTES")]
+    jsonapi_max_response_size: Option<usize>,
+
+    /// How many included relationships a JSON:API request fetches with queries of their own at
+    /// once. These are relationships a data connector cannot fetch along with the resources they
+    /// relate, eg as they are to a model of another data connector.
+    #[arg(long, env = "JSONAPI_INCLUDED_QUERY_CONCURRENCY", default_value_t = jsonapi::DEFAULT_INCLUDED_QUERY_CONCURRENCY)]
+    jsonapi_included_query_concurrency: NonZeroUsize,
+
+    /// The roles that see the messages of data connector errors in JSON:API responses, separated
+    /// by commas. For other roles, these messages are redacted.
+    #[arg(
+        long,
+        value_name = "ROLES",
+        env = "JSONAPI_CONNECTOR_ERROR_ROLES",
+        value_delimiter = ','
+    )]
+    jsonapi_connector_error_roles: Vec<String>,
+
+    /// The roles that may explain JSON:API requests, separated by commas, eg `admin`. Requests
+    /// under `/__explain`, eg `/v1/rest/__explain/default/Articles`, return the queries the
+    /// request would send to data connectors instead of running them. Off if not given.
+    #[arg(
+        long,
+        value_name = "ROLES",
+        env = "JSONAPI_EXPLAIN_ROLES",
+        value_delimiter = ','
+    )]
+    jsonapi_explain_roles: Vec<String>,
+
+    /// How many requests each role may make for the resources of each model per minute, over
+    /// `/v1/rest`. Requests over the limit get a 429. Not limited if not given.
+    #[arg(long, env = "JSONAPI_RATE_LIMIT", value_name = "REQUESTS_PER_MINUTE")]
+    jsonapi_rate_limit: Option<NonZeroU32>,
+
+    /// Roles that are not rate limited, separated by commas, eg `admin`. Requires
+    /// `--jsonapi-rate-limit` to be set.
+    #[arg(
+        long,
+        value_name = "ROLES",
+        env = "JSONAPI_RATE_LIMIT_EXEMPT_ROLES",
+        requires = "jsonapi_rate_limit",
+        value_delimiter = ','
+    )]
+    jsonapi_rate_limit_exempt_roles: Vec<String>,
+
+    /// How long, in seconds, a JSON:API request may take. Slower requests are cancelled, along
+    /// with their queries to data connectors, and get a 504. Requests are never cancelled if not
+    /// given.
+    #[arg(long, env = "JSONAPI_REQUEST_TIMEOUT", value_name = "SECONDS")]
+    jsonapi_request_timeout: Option<u64>,
+
+    /// The URL clients reach the engine at, eg `https://api.example.com` behind a reverse proxy.
+    /// Links in JSON:API responses start with it. If not given, links are paths on the host of
+    /// the request.
+    #[arg(long, env = "JSONAPI_EXTERNAL_BASE_URL", value_name = "URL")]
+    jsonapi_external_base_url: Option<reqwest::Url>,
+
+    /// How the names of types, attributes and relationships are written in JSON:API documents
+    /// and query parameters: `none` (as they are in metadata), `camelCase`, `snake_case` or
+    /// `kebab-case`.
+    #[arg(
+        long,
+        env = "JSONAPI_NAMING_CASE",
+        value_name = "CASE",
+        default_value = "none"
+    )]
+    jsonapi_naming_case: jsonapi::NameCase,
+
+    /// Use the plural of the name of an object type for the `type` of its JSON:API resources,
+    /// eg `default_Articles` for the `Article` type.
+    #[arg(long, env = "JSONAPI_PLURALIZE_TYPES")]
+    jsonapi_pluralize_types: bool,
+
+    /// Leave the top-level `jsonapi` member, with the version of JSON:API and the extensions
+    /// applied, out of JSON:API documents, eg for clients that reject members they don't know.
+    #[arg(long, env = "JSONAPI_DISABLE_VERSION_OBJECT")]
+    jsonapi_disable_version_object: bool,
 }
 
 #[tokio::main]
@@ -148,11 +321,64 @@ async fn start_engine(server: &ServerOptions) -> Result<(), StartupError> {
     )
     .map_err(StartupError::ReadSchema)?;
 
+    let jsonapi_configuration = jsonapi::Configuration {
+        default_page_limit: server.jsonapi_default_page_limit,
+        max_page_limit: server.jsonapi_max_page_limit,
+        pagination_mode: if server.jsonapi_cursor_pagination {
+            jsonapi::PaginationMode::Cursor
+        } else {
+            jsonapi::PaginationMode::Offset
+        },
+        cursor_secret: server
+            .jsonapi_cursor_secret
+            .clone()
+            .map_or_else(jsonapi::CursorSecret::random, jsonapi::CursorSecret::new),
+        max_include_depth: server.jsonapi_max_include_depth,
+        max_include_paths: server.jsonapi_max_include_paths,
+        max_batch_operations: server.jsonapi_max_batch_operations,
+        compression_min_size: (!server.jsonapi_disable_compression)
+            .then_some(server.jsonapi_compression_min_size),
+        stream_min_rows: (!server.jsonapi_disable_streaming)
+            .then_some(server.jsonapi_stream_min_rows),
+        max_response_size: server.jsonapi_max_response_size,
+        connector_error_roles: server
+            .jsonapi_connector_error_roles
+            .iter()
+            .map(|role| hasura_authn_core::Role::new(role))
+            .collect(),
+        explain_roles: server
+            .jsonapi_explain_roles
+            .iter()
+            .map(|role| hasura_authn_core::Role::new(role))
+            .collect(),
+        rate_limit: server
+            .jsonapi_rate_limit
+            .map(|requests_per_minute| jsonapi::RateLimit {
+                requests_per_minute,
+                exempt_roles: server
+                    .jsonapi_rate_limit_exempt_roles
+                    .iter()
+                    .map(|role| hasura_authn_core::Role::new(role))
+                    .collect(),
+            }),
+        request_timeout: server
+            .jsonapi_request_timeout
+            .map(std::time::Duration::from_secs),
+        external_base_url: server.jsonapi_external_base_url.clone(),
+        naming: jsonapi::NamingStrategy {
+            case: server.jsonapi_naming_case,
+            pluralize_types: server.jsonapi_pluralize_types,
+        },
+        included_query_concurrency: server.jsonapi_included_query_concurrency,
+        jsonapi_object: !server.jsonapi_disable_version_object,
+    };
+
     let state = engine::build_state(
         request_pipeline,
         expose_internal_errors,
         auth_config,
         resolved_metadata,
+        jsonapi_configuration,
     )
     .map_err(StartupError::ReadSchema)?;
 
@@ -168,7 +394,13 @@ async fn start_engine(server: &ServerOptions) -> Result<(), StartupError> {
 
     // If `--enable-cors` is specified, we add a CORS layer to the app.
     if server.enable_cors {
-        app = app.layer(get_cors_layer(&server.cors_allow_origin));
+        app = app.layer(get_cors_layer(&CorsConfiguration {
+            allow_origin: server.cors_allow_origin.clone(),
+            allow_methods: server.cors_allow_methods.clone(),
+            allow_headers: server.cors_allow_headers.clone(),
+            allow_credentials: !server.cors_disallow_credentials,
+            max_age: std::time::Duration::from_secs(server.cors_max_age),
+        }));
     }
 
     let address = net::SocketAddr::new(server.host, server.port);
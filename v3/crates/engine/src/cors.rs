@@ -1,6 +1,11 @@
-use axum::http::header::HeaderName;
+use axum::http::header::{self, HeaderName, HeaderValue};
+use axum::http::Request;
 use reqwest::Method;
+use std::sync::Arc;
+use std::task::{Context, Poll};
 use std::time::Duration;
+use tower::layer::util::Stack;
+use tower::{Layer, Service};
 use tower_http::cors;
 
 // Allow tracing headers to be retrievable on responses over CORS.
@@ -21,13 +26,58 @@ const TRACE_RESPONSE_HEADER_NAMES: [HeaderName; 7] = [
     HeaderName::from_static("x-b3-sampled"),
 ];
 
-/// Add CORS layer to the app.
-pub fn build_cors_layer(cors_allow_origin: &[String]) -> cors::CorsLayer {
-    let cors_allow_origin = if cors_allow_origin.is_empty() {
+// Headers used to authenticate requests, which are always allowed over CORS, so that
+// authenticated requests from browsers work. Session variables, which are any headers starting
+// with `x-hasura-`, are allowed too.
+#[allow(clippy::declare_interior_mutable_const)]
+const AUTH_REQUEST_HEADER_NAMES: [HeaderName; 3] = [
+    HeaderName::from_static("authorization"),
+    HeaderName::from_static("x-hasura-admin-secret"),
+    HeaderName::from_static("x-hasura-role"),
+];
+
+/// CORS settings, shared by the GraphQL and JSON:API endpoints
+#[derive(Debug, Clone)]
+pub struct CorsConfiguration {
+    /// origins allowed to make requests. all origins are allowed if empty.
+    pub allow_origin: Vec<String>,
+    /// methods allowed in requests
+    pub allow_methods: Vec<Method>,
+    /// headers allowed in requests, besides the authentication headers and session variables
+    /// like `x-hasura-user-id`. if empty, any of the headers a preflight request asks for are
+    /// allowed.
+    pub allow_headers: Vec<HeaderName>,
+    /// whether requests may include credentials, like cookies
+    pub allow_credentials: bool,
+    /// how long browsers may cache the response to a preflight request
+    pub max_age: Duration,
+}
+
+impl CorsConfiguration {
+    /// the default settings, allowing requests from the given origins
+    pub fn new(allow_origin: Vec<String>) -> Self {
+        Self {
+            allow_origin,
+            allow_methods: vec![Method::GET, Method::HEAD, Method::POST, Method::OPTIONS],
+            allow_headers: vec![],
+            allow_credentials: true,
+            max_age: Duration::from_secs(24 * 60 * 60), // 24 hours
+        }
+    }
+}
+
+/// The CORS layer, along with the layer allowing session variables when the allowed headers are
+/// restricted
+pub type CorsLayer = Stack<cors::CorsLayer, AllowSessionVariablesLayer>;
+
+/// Add CORS layer to the app. Preflight requests are answered by the layer itself, without
+/// reaching any handler.
+pub fn build_cors_layer(cors_configuration: &CorsConfiguration) -> CorsLayer {
+    let cors_allow_origin = if cors_configuration.allow_origin.is_empty() {
         // Allow all origins and mirror the request origin in 'Access-Control-Allow-Origin'
         cors::AllowOrigin::mirror_request()
     } else {
-        let allowed_origins = cors_allow_origin.to_owned();
+        let allowed_origins = cors_configuration.allow_origin.clone();
         cors::AllowOrigin::predicate(move |origin_header_value, _req| {
             allowed_origins.iter().any(|allowed_origin| {
                 // The allowed origins can include leading whitespace characters when
@@ -37,13 +87,105 @@ pub fn build_cors_layer(cors_allow_origin: &[String]) -> cors::CorsLayer {
             })
         })
     };
-    cors::CorsLayer::new()
-        .max_age(Duration::from_secs(24 * 60 * 60)) // 24 hours
+    let allow_session_variables = if cors_configuration.allow_headers.is_empty() {
+        AllowSessionVariablesLayer {
+            allow_headers: None,
+        }
+    } else {
+        AllowSessionVariablesLayer {
+            allow_headers: Some(
+                AUTH_REQUEST_HEADER_NAMES
+                    .into_iter()
+                    .chain(cors_configuration.allow_headers.iter().cloned())
+                    .collect(),
+            ),
+        }
+    };
+    let cors_layer = cors::CorsLayer::new()
+        .max_age(cors_configuration.max_age)
+        // the headers asked for are checked by `AllowSessionVariablesLayer` when the allowed
+        // headers are restricted
         .allow_headers(cors::AllowHeaders::mirror_request())
         .allow_origin(cors_allow_origin)
-        .allow_credentials(true)
-        .allow_methods(vec![Method::GET, Method::POST, Method::OPTIONS])
-        .expose_headers(TRACE_RESPONSE_HEADER_NAMES)
+        .allow_credentials(cors_configuration.allow_credentials)
+        .allow_methods(cors_configuration.allow_methods.clone())
+        .expose_headers(TRACE_RESPONSE_HEADER_NAMES);
+    Stack::new(cors_layer, allow_session_variables)
+}
+
+/// Restricts the headers a preflight request asks for to the allowed ones, plus any session
+/// variables, before the CORS layer mirrors them. Session variables can be named anything
+/// starting with `x-hasura-`, so they can't be listed up front.
+#[derive(Debug, Clone)]
+pub struct AllowSessionVariablesLayer {
+    allow_headers: Option<Arc<[HeaderName]>>,
+}
+
+impl<S> Layer<S> for AllowSessionVariablesLayer {
+    type Service = AllowSessionVariables<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AllowSessionVariables {
+            inner,
+            allow_headers: self.allow_headers.clone(),
+        }
+    }
+}
+
+/// The service built by [`AllowSessionVariablesLayer`]
+#[derive(Debug, Clone)]
+pub struct AllowSessionVariables<S> {
+    inner: S,
+    allow_headers: Option<Arc<[HeaderName]>>,
+}
+
+impl<S, B> Service<Request<B>> for AllowSessionVariables<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> Self::Future {
+        let is_preflight = request.method() == Method::OPTIONS
+            && request
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+        if let (true, Some(allow_headers)) = (is_preflight, &self.allow_headers) {
+            let allowed = allowed_request_headers(allow_headers, request.headers());
+            request
+                .headers_mut()
+                .insert(header::ACCESS_CONTROL_REQUEST_HEADERS, allowed);
+        }
+        self.inner.call(request)
+    }
+}
+
+// the allowed headers, followed by the session variables the preflight request asks for
+fn allowed_request_headers(
+    allow_headers: &[HeaderName],
+    request_headers: &axum::http::HeaderMap,
+) -> HeaderValue {
+    let session_variables = request_headers
+        .get_all(header::ACCESS_CONTROL_REQUEST_HEADERS)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .filter(|name| name.as_str().starts_with("x-hasura-") && !allow_headers.contains(name));
+    let allowed = allow_headers
+        .iter()
+        .cloned()
+        .chain(session_variables)
+        .map(|name| name.as_str().to_owned())
+        .collect::<Vec<_>>()
+        .join(",");
+    HeaderValue::from_str(&allowed).expect("header names are valid header values")
 }
 
 #[cfg(test)]
@@ -78,7 +220,9 @@ mod test {
 
     #[tokio::test]
     async fn test_cors_allow_all_origins() {
-        let app = Router::new().layer(super::build_cors_layer(&[]));
+        let app = Router::new().layer(super::build_cors_layer(&super::CorsConfiguration::new(
+            vec![],
+        )));
         // Preflight CORS request
         let response = app
             .oneshot(
@@ -102,7 +246,9 @@ mod test {
 
     #[tokio::test]
     async fn test_cors_restrict_origin() {
-        let app = Router::new().layer(super::build_cors_layer(&["http://example.com".to_string()]));
+        let app = Router::new().layer(super::build_cors_layer(&super::CorsConfiguration::new(
+            vec!["http://example.com".to_string()],
+        )));
         // Preflight CORS request
         let response = app
             .oneshot(
@@ -126,10 +272,12 @@ mod test {
 
     #[tokio::test]
     async fn test_cors_allow_origin() {
-        let app = Router::new().layer(super::build_cors_layer(&[
-            "http://localhost:8080".to_string(),
-            "http://example.com".to_string(),
-        ]));
+        let app = Router::new().layer(super::build_cors_layer(&super::CorsConfiguration::new(
+            vec![
+                "http://localhost:8080".to_string(),
+                "http://example.com".to_string(),
+            ],
+        )));
         // Preflight CORS request
         let response = app
             .oneshot(
@@ -150,4 +298,108 @@ mod test {
             Some(&HeaderValue::from_static("http://example.com"))
         );
     }
+
+    #[tokio::test]
+    async fn test_cors_preflight_for_jsonapi() {
+        let app = Router::new()
+            .route(
+                "/v1/rest/*path",
+                axum::routing::get(|| async {
+                    panic!("preflight requests must not reach handlers")
+                }),
+            )
+            .layer(super::build_cors_layer(&super::CorsConfiguration::new(
+                vec![],
+            )));
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/v1/rest/default/Articles")
+                    .method("OPTIONS")
+                    .header("Origin", "http://example.com")
+                    .header("Access-Control-Request-Method", "GET")
+                    .header(
+                        "Access-Control-Request-Headers",
+                        "x-hasura-role,x-hasura-user-id",
+                    )
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get("access-control-allow-methods"),
+            Some(&HeaderValue::from_static("GET,HEAD,POST,OPTIONS"))
+        );
+        assert_eq!(
+            response.headers().get("access-control-allow-headers"),
+            Some(&HeaderValue::from_static("x-hasura-role,x-hasura-user-id"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cors_allow_headers() {
+        let mut cors_configuration = super::CorsConfiguration::new(vec![]);
+        cors_configuration.allow_headers = vec![axum::http::header::CONTENT_TYPE];
+        cors_configuration.max_age = std::time::Duration::from_secs(600);
+        let app = Router::new().layer(super::build_cors_layer(&cors_configuration));
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/")
+                    .method("OPTIONS")
+                    .header("Origin", "http://example.com")
+                    .header("Access-Control-Request-Method", "GET")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // the authentication headers are always allowed
+        assert_eq!(
+            response.headers().get("access-control-allow-headers"),
+            Some(&HeaderValue::from_static(
+                "authorization,x-hasura-admin-secret,x-hasura-role,content-type"
+            ))
+        );
+        assert_eq!(
+            response.headers().get("access-control-max-age"),
+            Some(&HeaderValue::from_static("600"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cors_allow_headers_with_session_variables() {
+        let mut cors_configuration = super::CorsConfiguration::new(vec![]);
+        cors_configuration.allow_headers = vec![axum::http::header::CONTENT_TYPE];
+        let app = Router::new().layer(super::build_cors_layer(&cors_configuration));
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/v1/rest/default/Articles")
+                    .method("OPTIONS")
+                    .header("Origin", "http://example.com")
+                    .header("Access-Control-Request-Method", "GET")
+                    .header(
+                        "Access-Control-Request-Headers",
+                        "content-type,x-hasura-role,x-hasura-user-id,x-custom",
+                    )
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // session variables are allowed besides the configured headers, other headers are not
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get("access-control-allow-headers"),
+            Some(&HeaderValue::from_static(
+                "authorization,x-hasura-admin-secret,x-hasura-role,content-type,x-hasura-user-id"
+            ))
+        );
+    }
 }
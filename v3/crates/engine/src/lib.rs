@@ -6,12 +6,17 @@ mod middleware;
 mod routes;
 mod state;
 mod types;
-pub use cors::build_cors_layer;
+pub use cors::{
+    build_cors_layer, AllowSessionVariables, AllowSessionVariablesLayer, CorsConfiguration,
+    CorsLayer,
+};
 pub use middleware::{
     authentication_middleware, explain_request_tracing_middleware,
     graphql_request_tracing_middleware, plugins_middleware,
 };
-pub use routes::{get_base_routes, get_cors_layer, get_jsonapi_route, get_metadata_routes};
+pub use routes::{
+    get_base_routes, get_cors_layer, get_jsonapi_route, get_jsonapi_route_at, get_metadata_routes,
+};
 pub use state::{build_state, print_warnings, resolve_metadata};
 pub use types::{EngineState, RequestType, StartupError};
 
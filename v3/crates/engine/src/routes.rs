@@ -1,7 +1,7 @@
 mod graphql;
 pub use graphql::{handle_explain_request, handle_request, handle_websocket_request};
 mod jsonapi;
-pub use jsonapi::create_json_api_router;
+pub use jsonapi::{create_json_api_router, create_json_api_router_at};
 
 use axum::{
     extract::DefaultBodyLimit,
@@ -13,13 +13,12 @@ use base64::engine::Engine;
 use std::hash;
 use std::hash::{Hash, Hasher};
 use std::path::PathBuf;
-use tower_http::cors::CorsLayer;
 use tower_http::trace::TraceLayer;
 
 use crate::{
     authentication_middleware, build_cors_layer, explain_request_tracing_middleware,
     graphql_request_tracing_middleware, middleware::pre_route_request_tracing_middleware,
-    plugins_middleware, EngineState, StartupError,
+    plugins_middleware, CorsConfiguration, CorsLayer, EngineState, StartupError,
 };
 
 use super::types::RequestType;
@@ -130,8 +129,13 @@ pub fn get_jsonapi_route(state: EngineState) -> Router {
     create_json_api_router(state)
 }
 
-pub fn get_cors_layer(allow_origin: &[String]) -> CorsLayer {
-    build_cors_layer(allow_origin)
+/// The JSON:API routes at a mount path of our choosing, rather than `/v1/rest` and `/v1/jsonapi`
+pub fn get_jsonapi_route_at(state: EngineState, mount_path: &str) -> Router {
+    create_json_api_router_at(state, mount_path)
+}
+
+pub fn get_cors_layer(cors_configuration: &CorsConfiguration) -> CorsLayer {
+    build_cors_layer(cors_configuration)
 }
 
 /// Health check endpoint
@@ -1,37 +1,135 @@
 use axum::{
     http::{HeaderMap, Method, Uri},
     response::IntoResponse,
-    routing::get,
+    routing::{get, post, MethodRouter},
     Extension, Json, Router,
 };
 use hasura_authn_core::Session;
 use std::convert::Infallible;
 use std::sync::Arc;
+use tower_http::compression::{
+    predicate::{DefaultPredicate, Predicate, SizeAbove},
+    CompressionLayer,
+};
 use tower_http::trace::TraceLayer;
-use tracing_util::{set_status_on_current_span, SpanVisibility, Traceable};
+use tracing_util::{
+    set_attribute_on_active_span, set_status_on_current_span, AttributeVisibility, SpanVisibility,
+    Successful, Traceable,
+};
 
 use crate::{authentication_middleware, EngineState};
 
+// the methods of the routes whose requests are a document in the body, like `/__batch`. the
+// operations of these documents are still only reads.
+const DOCUMENT_METHODS: &[Method] = &[Method::POST, Method::OPTIONS];
+
 pub fn create_json_api_router(state: EngineState) -> axum::Router {
+    // both endpoints share the same rate limits
+    let rate_limiter = rate_limiter(&state);
+
     // Create the base router and nest both paths to the same handler
     Router::new()
         .nest(
             jsonapi::EndPoint::V1Rest.as_str(),
-            build_router(state.clone(), jsonapi::EndPoint::V1Rest),
+            build_router(
+                state.clone(),
+                jsonapi::EndPoint::V1Rest,
+                rate_limiter.clone(),
+            ),
         )
         .nest(
             jsonapi::EndPoint::V1Jsonapi.as_str(),
-            build_router(state, jsonapi::EndPoint::V1Jsonapi),
+            build_router(state, jsonapi::EndPoint::V1Jsonapi, rate_limiter),
         )
 }
 
-fn build_router(state: EngineState, endpoint: jsonapi::EndPoint) -> axum::Router {
-    Router::new()
-        .route("/__schema", get(handle_jsonapi_schema))
-        // TODO: update method GET; for now we are only supporting queries. And
-        // in JSON:API spec, all queries have the GET method. Not even HEAD is
-        // supported. So this should be fine.
-        .route("/*path", get(handle_jsonapi_request))
+/// The JSON:API routes mounted at `mount_path`, eg `/api/rest`, for embedding the engine in an
+/// axum app of its own: the router can be merged with the other routes of the app. Links in
+/// documents, the paths of the OpenAPI schema and the names of the request spans all follow the
+/// mount path. `mount_path` must start with a `/`, and can't be `/` itself, as axum doesn't nest
+/// routers at the root.
+pub fn create_json_api_router_at(state: EngineState, mount_path: &str) -> axum::Router {
+    let rate_limiter = rate_limiter(&state);
+    let endpoint = jsonapi::EndPoint::Custom(mount_path.into());
+    Router::new().nest(mount_path, build_router(state, endpoint, rate_limiter))
+}
+
+fn rate_limiter(state: &EngineState) -> Option<Arc<jsonapi::RateLimiter>> {
+    state
+        .jsonapi_configuration
+        .rate_limit
+        .clone()
+        .map(|rate_limit| Arc::new(jsonapi::RateLimiter::new(rate_limit)))
+}
+
+fn build_router(
+    state: EngineState,
+    endpoint: jsonapi::EndPoint,
+    rate_limiter: Option<Arc<jsonapi::RateLimiter>>,
+) -> axum::Router {
+    // rate limited requests are rejected before anything else is done for them. without a rate
+    // limit, there is no layer at all
+    let rate_limited = |handler: MethodRouter<EngineState>| match &rate_limiter {
+        Some(rate_limiter) => handler.layer(axum::middleware::from_fn_with_state(
+            (
+                rate_limiter.clone(),
+                state.jsonapi_catalog.clone(),
+                state.jsonapi_configuration.naming,
+            ),
+            jsonapi::jsonapi_rate_limit_middleware,
+        )),
+        None => handler,
+    };
+    let negotiated = |handler: MethodRouter<EngineState>| {
+        handler.layer(axum::middleware::from_fn(
+            jsonapi::jsonapi_content_negotiation_middleware,
+        ))
+    };
+
+    // every route answers `OPTIONS`, and rejects methods it doesn't support with a `405` listing
+    // the ones it does
+    let router = Router::new()
+        .route(
+            "/__schema",
+            jsonapi::with_allowed_methods(get(handle_jsonapi_schema), jsonapi::READ_METHODS),
+        )
+        // each read of a batch, an atomic request or an explain request counts towards the rate
+        // limit of its model
+        .route(
+            jsonapi::BATCH_ROUTE,
+            jsonapi::with_allowed_methods(
+                rate_limited(post(handle_jsonapi_batch_request)),
+                DOCUMENT_METHODS,
+            ),
+        )
+        // reads with the atomic operations extension, which must be applied in the
+        // `Content-Type` of the request, and is echoed in that of the response
+        .route(
+            jsonapi::OPERATIONS_ROUTE,
+            jsonapi::with_allowed_methods(
+                rate_limited(negotiated(post(handle_jsonapi_atomic_request))),
+                DOCUMENT_METHODS,
+            ),
+        )
+        // the queries a read would send to data connectors, for the roles allowed to see them
+        .route(
+            &format!("{}/*path", jsonapi::EXPLAIN_ROUTE),
+            jsonapi::with_allowed_methods(
+                rate_limited(negotiated(get(handle_jsonapi_explain_request))),
+                jsonapi::READ_METHODS,
+            ),
+        )
+        // we only support queries, which in the JSON:API spec all use the GET method. `get` also
+        // answers `HEAD` requests, with the headers of the GET response and no body. this covers
+        // collections, resources and relationships alike.
+        .route(
+            "/*path",
+            jsonapi::with_allowed_methods(
+                rate_limited(negotiated(get(handle_jsonapi_request))),
+                jsonapi::READ_METHODS,
+            ),
+        )
+        .layer(Extension(endpoint.clone()))
         .layer(axum::middleware::from_fn_with_state(
             jsonapi::build_state_with_middleware_error_converter(()),
             hasura_authn_core::resolve_session,
@@ -39,9 +137,22 @@ fn build_router(state: EngineState, endpoint: jsonapi::EndPoint) -> axum::Router
         .layer(axum::middleware::from_fn_with_state(
             jsonapi::build_state_with_middleware_error_converter(state.clone()),
             authentication_middleware,
-        ))
+        ));
+
+    // compression sits inside the tracing middleware, so that the request span covers it too.
+    // it replaces `Content-Length` with `Content-Encoding` on the responses it compresses.
+    let router = match state.jsonapi_configuration.compression_min_size {
+        Some(min_size) => router.layer(
+            CompressionLayer::new()
+                .compress_when(DefaultPredicate::new().and(SizeAbove::new(min_size))),
+        ),
+        None => router,
+    };
+
+    router
         .layer(axum::middleware::from_fn_with_state(
-            endpoint,
+            // recorded with the global meter provider, if one is set
+            (endpoint, jsonapi::OpenTelemetryJsonApiMetrics::default()),
             jsonapi::jsonapi_request_tracing_middleware,
         ))
         // *PLEASE DO NOT ADD ANY MIDDLEWARE
@@ -76,6 +187,7 @@ impl Traceable for JsonApiSchemaResponse {
 async fn handle_jsonapi_schema(
     axum::extract::State(state): axum::extract::State<EngineState>,
     Extension(session): Extension<Session>,
+    Extension(endpoint): Extension<jsonapi::EndPoint>,
 ) -> impl IntoResponse {
     let tracer = tracing_util::global_tracer();
 
@@ -84,12 +196,18 @@ async fn handle_jsonapi_schema(
         "Handle schema",
         SpanVisibility::User,
         || match state.jsonapi_catalog.state_per_role.get(&session.role) {
-            Some(jsonapi_state) => match jsonapi::openapi_schema(jsonapi_state) {
-                Ok(spec) => JsonApiSchemaResponse { spec },
-                Err(_) => JsonApiSchemaResponse {
-                    spec: jsonapi::empty_schema(),
-                },
-            },
+            Some(jsonapi_state) => {
+                match jsonapi::openapi_schema(
+                    jsonapi_state,
+                    &state.jsonapi_configuration.naming,
+                    &endpoint,
+                ) {
+                    Ok(spec) => JsonApiSchemaResponse { spec },
+                    Err(_) => JsonApiSchemaResponse {
+                        spec: jsonapi::empty_schema(),
+                    },
+                }
+            }
             None => JsonApiSchemaResponse {
                 spec: jsonapi::empty_schema(),
             },
@@ -104,8 +222,19 @@ async fn handle_jsonapi_request(
     axum::extract::RawQuery(raw_query): axum::extract::RawQuery,
     axum::extract::State(state): axum::extract::State<EngineState>,
     Extension(session): Extension<Session>,
+    Extension(endpoint): Extension<jsonapi::EndPoint>,
+    Extension(extensions): Extension<jsonapi::MediaTypeExtensions>,
 ) -> impl IntoResponse {
     let tracer = tracing_util::global_tracer();
+    let raw_query = raw_query.unwrap_or_default();
+    let conditional_headers = request_headers.clone();
+    let jsonapi_object = state.jsonapi_configuration.jsonapi_object(&extensions);
+    let resource_type = jsonapi::request_resource_type(
+        &state.jsonapi_catalog,
+        &state.jsonapi_configuration.naming,
+        &session,
+        &uri,
+    );
     let response = tracer
         .in_span_async(
             "handle_jsonapi_request",
@@ -117,10 +246,74 @@ async fn handle_jsonapi_request(
                     Arc::new(state.http_context.clone()),
                     Arc::new(session),
                     &state.jsonapi_catalog,
+                    &state.jsonapi_configuration,
                     state.resolved_metadata,
+                    &endpoint,
                     method,
                     uri,
-                    jsonapi_library::query::Query::from_params(&raw_query.unwrap_or_default()),
+                    &raw_query,
+                ))
+            },
+        )
+        .await;
+
+    set_status_on_current_span(&response);
+    let rows = response
+        .as_ref()
+        .ok()
+        .map(jsonapi::ResponseDocument::row_count);
+    let mut response = match response {
+        Ok(r) => tracer
+            .in_span(
+                "serialize_response",
+                "Serialize JSON:API document",
+                SpanVisibility::User,
+                || {
+                    Successful::new(jsonapi::document_response(
+                        &conditional_headers,
+                        jsonapi_object.as_ref(),
+                        state.jsonapi_configuration.max_response_size,
+                        r,
+                    ))
+                },
+            )
+            .into_inner(),
+        Err(e) => error_response(e, jsonapi_object),
+    };
+    response.extensions_mut().insert(jsonapi::ResponseMetrics {
+        resource_type,
+        rows,
+    });
+    response
+}
+
+async fn handle_jsonapi_explain_request(
+    request_headers: HeaderMap,
+    uri: Uri,
+    axum::extract::RawQuery(raw_query): axum::extract::RawQuery,
+    axum::extract::State(state): axum::extract::State<EngineState>,
+    Extension(session): Extension<Session>,
+    Extension(extensions): Extension<jsonapi::MediaTypeExtensions>,
+) -> axum::response::Response {
+    let tracer = tracing_util::global_tracer();
+    let raw_query = raw_query.unwrap_or_default();
+    let conditional_headers = request_headers.clone();
+    let jsonapi_object = state.jsonapi_configuration.jsonapi_object(&extensions);
+    let response = tracer
+        .in_span_async(
+            "handle_jsonapi_explain_request",
+            "Handle jsonapi explain request",
+            SpanVisibility::User,
+            || {
+                Box::pin(jsonapi::explain_handler_internal(
+                    Arc::new(request_headers),
+                    Arc::new(state.http_context.clone()),
+                    Arc::new(session),
+                    &state.jsonapi_catalog,
+                    &state.jsonapi_configuration,
+                    state.resolved_metadata,
+                    uri,
+                    &raw_query,
                 ))
             },
         )
@@ -128,7 +321,165 @@ async fn handle_jsonapi_request(
 
     set_status_on_current_span(&response);
     match response {
+        Ok(r) => jsonapi::document_response(
+            &conditional_headers,
+            jsonapi_object.as_ref(),
+            state.jsonapi_configuration.max_response_size,
+            r,
+        ),
+        Err(e) => error_response(e, jsonapi_object),
+    }
+}
+
+async fn handle_jsonapi_batch_request(
+    request_headers: HeaderMap,
+    axum::extract::State(state): axum::extract::State<EngineState>,
+    Extension(session): Extension<Session>,
+    Extension(endpoint): Extension<jsonapi::EndPoint>,
+    batch: Result<Json<jsonapi::BatchRequest>, axum::extract::rejection::JsonRejection>,
+) -> axum::response::Response {
+    // a batch is not a JSON:API document itself, but its errors are
+    let jsonapi_object = state
+        .jsonapi_configuration
+        .jsonapi_object(&jsonapi::MediaTypeExtensions::default());
+    let Json(batch) = match batch {
+        Ok(batch) => batch,
+        Err(rejection) => {
+            return jsonapi::JsonApiHttpError::new(
+                rejection.status(),
+                jsonapi::ErrorCode::InvalidBody,
+                rejection.body_text(),
+            )
+            .with_jsonapi_object(jsonapi_object)
+            .into_response();
+        }
+    };
+    let tracer = tracing_util::global_tracer();
+    let response = tracer
+        .in_span_async(
+            "handle_jsonapi_batch_request",
+            "Handle jsonapi batch request",
+            SpanVisibility::User,
+            || {
+                Box::pin(jsonapi::batch_handler_internal(
+                    Arc::new(request_headers),
+                    Arc::new(state.http_context.clone()),
+                    Arc::new(session),
+                    &state.jsonapi_catalog,
+                    &state.jsonapi_configuration,
+                    state.resolved_metadata,
+                    &endpoint,
+                    batch,
+                ))
+            },
+        )
+        .await;
+
+    set_status_on_current_span(&response);
+    let rows = response
+        .as_ref()
+        .ok()
+        .map(jsonapi::BatchResponse::row_count);
+    let mut response = match response {
         Ok(r) => (axum::http::StatusCode::OK, Json(r)).into_response(),
-        Err(e) => e.into_http_error().into_response(),
+        Err(e) => error_response(e, jsonapi_object),
+    };
+    response.extensions_mut().insert(jsonapi::ResponseMetrics {
+        resource_type: None,
+        rows,
+    });
+    response
+}
+
+async fn handle_jsonapi_atomic_request(
+    request_headers: HeaderMap,
+    axum::extract::State(state): axum::extract::State<EngineState>,
+    Extension(session): Extension<Session>,
+    Extension(endpoint): Extension<jsonapi::EndPoint>,
+    Extension(extensions): Extension<jsonapi::MediaTypeExtensions>,
+    request: Result<Json<jsonapi::AtomicRequest>, axum::extract::rejection::JsonRejection>,
+) -> axum::response::Response {
+    let jsonapi_object = state.jsonapi_configuration.jsonapi_object(&extensions);
+    if !extensions.contains(jsonapi::ATOMIC_EXTENSION) {
+        return jsonapi::JsonApiHttpError::new(
+            axum::http::StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            jsonapi::ErrorCode::UnsupportedMediaType,
+            format!(
+                "Content-Type must be {}; ext=\"{}\"",
+                jsonapi::JSONAPI_MEDIA_TYPE,
+                jsonapi::ATOMIC_EXTENSION
+            ),
+        )
+        .with_jsonapi_object(jsonapi_object)
+        .into_response();
     }
+    let Json(request) = match request {
+        Ok(request) => request,
+        Err(rejection) => {
+            return jsonapi::JsonApiHttpError::new(
+                rejection.status(),
+                jsonapi::ErrorCode::InvalidBody,
+                rejection.body_text(),
+            )
+            .with_jsonapi_object(jsonapi_object)
+            .into_response();
+        }
+    };
+    let tracer = tracing_util::global_tracer();
+    let response = tracer
+        .in_span_async(
+            "handle_jsonapi_atomic_request",
+            "Handle jsonapi atomic request",
+            SpanVisibility::User,
+            || {
+                Box::pin(jsonapi::atomic_handler_internal(
+                    Arc::new(request_headers),
+                    Arc::new(state.http_context.clone()),
+                    Arc::new(session),
+                    &state.jsonapi_catalog,
+                    &state.jsonapi_configuration,
+                    state.resolved_metadata,
+                    &endpoint,
+                    request,
+                ))
+            },
+        )
+        .await;
+
+    set_status_on_current_span(&response);
+    let rows = response
+        .as_ref()
+        .ok()
+        .map(jsonapi::AtomicResponse::row_count);
+    let mut response = match response {
+        Ok(r) => (
+            axum::http::StatusCode::OK,
+            Json(jsonapi::WithJsonApiObject {
+                jsonapi: jsonapi_object.as_ref(),
+                document: &r,
+            }),
+        )
+            .into_response(),
+        Err(e) => error_response(e, jsonapi_object),
+    };
+    response.extensions_mut().insert(jsonapi::ResponseMetrics {
+        resource_type: None,
+        rows,
+    });
+    response
+}
+
+// the response for a failed request. its status is recorded on the span of the request, next to
+// the error itself, whose visibility says whether it was the client's or ours.
+fn error_response(
+    error: jsonapi::RequestError,
+    jsonapi_object: Option<jsonapi::JsonApiObject>,
+) -> axum::response::Response {
+    let error = error.into_http_error().with_jsonapi_object(jsonapi_object);
+    set_attribute_on_active_span(
+        AttributeVisibility::Default,
+        "jsonapi.status",
+        i64::from(error.status.as_u16()),
+    );
+    error.into_response()
 }
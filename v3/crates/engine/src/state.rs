@@ -55,6 +55,7 @@ pub fn build_state(
     expose_internal_errors: ExposeInternalErrors,
     auth_config: hasura_authn::ResolvedAuthConfig,
     resolved_metadata: metadata_resolve::Metadata,
+    jsonapi_configuration: jsonapi::Configuration,
 ) -> Result<EngineState, anyhow::Error> {
     // Metadata
     let resolved_metadata = Arc::new(resolved_metadata);
@@ -78,6 +79,7 @@ pub fn build_state(
         http_context,
         graphql_state: Arc::new(schema),
         jsonapi_catalog: Arc::new(jsonapi_catalog),
+        jsonapi_configuration: Arc::new(jsonapi_configuration),
         resolved_metadata,
         auth_config: Arc::new(auth_config),
         plugin_configs: Arc::new(plugin_configs),
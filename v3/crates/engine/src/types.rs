@@ -16,6 +16,7 @@ pub struct EngineState {
     pub graphql_state: Arc<gql::schema::Schema<GDS>>,
     pub resolved_metadata: Arc<metadata_resolve::Metadata>,
     pub jsonapi_catalog: Arc<jsonapi::Catalog>,
+    pub jsonapi_configuration: Arc<jsonapi::Configuration>,
     pub auth_config: Arc<ResolvedAuthConfig>,
     pub plugin_configs: Arc<LifecyclePluginConfigs>,
     pub graphql_websocket_server:
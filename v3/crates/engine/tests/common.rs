@@ -25,7 +25,6 @@ extern crate json_value_merge;
 use axum::http::{HeaderMap, Method, Uri};
 use engine_types::{ExposeInternalErrors, HttpContext, ProjectId};
 use json_value_merge::Merge;
-use jsonapi_library::query::Query;
 use serde_json::Value;
 
 pub struct GoldenTestContext {
@@ -271,10 +270,12 @@ async fn test_jsonapi(
                 Arc::new(test_ctx.http_context.clone()),
                 Arc::new(session.clone()),
                 &catalog,
+                &jsonapi::Configuration::default(),
                 resolved_metadata.clone(),
+                &jsonapi::EndPoint::V1Rest,
                 request.to_method(),
                 Uri::try_from(&path).map_err(|e| anyhow::anyhow!("Invalid URI: {}", e))?,
-                Query::from_params(&query_params),
+                &query_params,
             )
             .await;
 
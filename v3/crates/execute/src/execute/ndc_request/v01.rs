@@ -26,6 +26,11 @@ pub enum NdcV01CompatibilityError {
 
     #[error("Groupings are not supported in NDC v0.1.x")]
     GroupByNotSupported,
+
+    #[error(
+        "Arguments of fields in comparisons and order by targets are not supported in NDC v0.1.x"
+    )]
+    ColumnArgumentsNotSupported,
 }
 
 pub fn make_query_request(
@@ -248,7 +253,7 @@ fn make_expression(
                 value,
             },
         ) => Ok(ndc_models_v01::Expression::BinaryComparisonOperator {
-            column: make_comparison_target(column),
+            column: make_comparison_target(column)?,
             operator: ndc_models_v01::ComparisonOperatorName::new(operator.into_inner()),
             value: make_comparison_value(value),
         }),
@@ -280,7 +285,7 @@ fn make_expression(
         ResolvedFilterExpression::LocalFieldComparison(
             plan_types::LocalFieldComparison::UnaryComparison { column, operator },
         ) => Ok(ndc_models_v01::Expression::UnaryComparisonOperator {
-            column: make_comparison_target(column),
+            column: make_comparison_target(column)?,
             operator: match operator {
                 metadata_resolve::UnaryComparisonOperator::IsNull => {
                     ndc_models_v01::UnaryComparisonOperator::IsNull
@@ -320,10 +325,21 @@ fn make_expression(
 
 fn make_comparison_target(
     comparison_target: plan_types::ComparisonTarget,
-) -> ndc_models_v01::ComparisonTarget {
+) -> Result<ndc_models_v01::ComparisonTarget, FieldError> {
     match comparison_target {
-        plan_types::ComparisonTarget::Column { name, field_path } => {
-            ndc_models_v01::ComparisonTarget::Column {
+        plan_types::ComparisonTarget::Column {
+            name,
+            arguments,
+            field_path,
+        } => {
+            if !arguments.is_empty() {
+                return Err(FieldError::InternalError(
+                    FieldInternalError::NdcV01CompatibilityError(
+                        NdcV01CompatibilityError::ColumnArgumentsNotSupported,
+                    ),
+                ));
+            }
+            Ok(ndc_models_v01::ComparisonTarget::Column {
                 name: ndc_models_v01::FieldName::new(name.into_inner()),
                 field_path: if field_path.is_empty() {
                     None
@@ -336,7 +352,7 @@ fn make_comparison_target(
                     )
                 },
                 path: vec![],
-            }
+            })
         }
     }
 }
@@ -486,9 +502,17 @@ fn make_order_by_target(
     match target {
         OrderByTarget::Column {
             name,
+            arguments,
             field_path,
             relationship_path,
         } => {
+            if !arguments.is_empty() {
+                return Err(FieldError::InternalError(
+                    FieldInternalError::NdcV01CompatibilityError(
+                        NdcV01CompatibilityError::ColumnArgumentsNotSupported,
+                    ),
+                ));
+            }
             let mut order_by_element_path = Vec::new();
             // When using a nested relationship column, you'll have to provide all the relationships(paths)
             // NDC has to traverse to access the column. The ordering of that paths is important.
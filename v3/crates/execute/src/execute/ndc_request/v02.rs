@@ -115,6 +115,21 @@ fn make_arguments(
         .collect::<Result<BTreeMap<_, _>, _>>()
 }
 
+// the arguments of a column in a comparison or an order by, which are always literals
+fn make_column_arguments(
+    arguments: BTreeMap<DataConnectorArgumentName, serde_json::Value>,
+) -> BTreeMap<ndc_models_v02::ArgumentName, ndc_models_v02::Argument> {
+    arguments
+        .into_iter()
+        .map(|(name, value)| {
+            (
+                ndc_models_v02::ArgumentName::new(name.into_inner()),
+                ndc_models_v02::Argument::Literal { value },
+            )
+        })
+        .collect()
+}
+
 fn make_argument(argument: Argument) -> Result<ndc_models_v02::Argument, FieldError> {
     match argument {
         Argument::Literal { value } => Ok(ndc_models_v02::Argument::Literal { value }),
@@ -320,22 +335,24 @@ fn make_comparison_target(
     comparison_target: plan_types::ComparisonTarget,
 ) -> ndc_models_v02::ComparisonTarget {
     match comparison_target {
-        plan_types::ComparisonTarget::Column { name, field_path } => {
-            ndc_models_v02::ComparisonTarget::Column {
-                name: ndc_models_v02::FieldName::new(name.into_inner()),
-                arguments: BTreeMap::new(),
-                field_path: if field_path.is_empty() {
-                    None
-                } else {
-                    Some(
-                        field_path
-                            .into_iter()
-                            .map(|f| ndc_models_v02::FieldName::new(f.into_inner()))
-                            .collect(),
-                    )
-                },
-            }
-        }
+        plan_types::ComparisonTarget::Column {
+            name,
+            arguments,
+            field_path,
+        } => ndc_models_v02::ComparisonTarget::Column {
+            name: ndc_models_v02::FieldName::new(name.into_inner()),
+            arguments: make_column_arguments(arguments),
+            field_path: if field_path.is_empty() {
+                None
+            } else {
+                Some(
+                    field_path
+                        .into_iter()
+                        .map(|f| ndc_models_v02::FieldName::new(f.into_inner()))
+                        .collect(),
+                )
+            },
+        },
     }
 }
 
@@ -529,6 +546,7 @@ fn make_order_by_target(
     match target {
         OrderByTarget::Column {
             name,
+            arguments,
             field_path,
             relationship_path,
         } => {
@@ -568,7 +586,7 @@ fn make_order_by_target(
 
             Ok(ndc_models_v02::OrderByTarget::Column {
                 name: ndc_models_v02::FieldName::new(name.into_inner()),
-                arguments: BTreeMap::new(),
+                arguments: make_column_arguments(arguments),
                 path: order_by_element_path,
                 field_path: if field_path.is_empty() {
                     None
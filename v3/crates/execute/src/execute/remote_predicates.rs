@@ -100,6 +100,7 @@ fn replace_predicates_in_order_by_target(
         OrderByTarget::Column {
             relationship_path,
             name,
+            arguments,
             field_path,
         } => Ok(OrderByTarget::Column {
             relationship_path: relationship_path
@@ -116,6 +117,7 @@ fn replace_predicates_in_order_by_target(
                 })
                 .collect::<Result<Vec<_>, FilterPredicateError>>()?,
             name,
+            arguments,
             field_path,
         }),
     }
@@ -332,6 +334,7 @@ pub fn build_source_column_comparisons(
                     column: plan_types::ComparisonTarget::Column {
                         // The column name is the root column
                         name: field_path.first().unwrap_or(source_column).clone(),
+                        arguments: BTreeMap::new(),
                         // The field path is the nesting path inside the root column, if any
                         field_path: field_path
                             .iter()
@@ -9,4 +9,4 @@ pub use execute::{
     replace_predicates_in_query_execution_plan, resolve_ndc_mutation_execution,
     resolve_ndc_query_execution, resolve_ndc_subscription_execution,
 };
-pub use ndc::fetch_from_data_connector;
+pub use ndc::{fetch_explain_from_data_connector, fetch_from_data_connector};
@@ -85,6 +85,42 @@ pub async fn fetch_from_data_connector(
         .await
 }
 
+/// Asks the data connector how it would run a query, without running it. `None` if the data
+/// connector can't explain queries.
+pub async fn fetch_explain_from_data_connector(
+    http_context: &HttpContext,
+    query_request: &NdcQueryRequest,
+    data_connector: &metadata_resolve::DataConnectorLink,
+    project_id: Option<&ProjectId>,
+) -> Result<Option<NdcExplainResponse>, client::Error> {
+    if !data_connector.capabilities.supports_explaining_queries {
+        return Ok(None);
+    }
+    let tracer = tracing_util::global_tracer();
+    tracer
+        .in_span_async(
+            "fetch_explain_from_data_connector",
+            format!("Execute explain on data connector {}", data_connector.name),
+            SpanVisibility::Internal,
+            || {
+                Box::pin(async {
+                    let headers =
+                        append_project_id_to_headers(&data_connector.headers.0, project_id)?;
+                    let ndc_config = client::Configuration {
+                        base_path: data_connector.url.get_url(ast::OperationType::Query),
+                        client: http_context.client.clone(),
+                        headers,
+                        response_size_limit: http_context.ndc_response_size_limit,
+                    };
+                    client::explain_query_post(ndc_config, query_request)
+                        .await
+                        .map(Some)
+                })
+            },
+        )
+        .await
+}
+
 // This function appends project-id (if present) to the HeaderMap defined by the data_connector object
 pub fn append_project_id_to_headers<'a>(
     headers: &'a HeaderMap,
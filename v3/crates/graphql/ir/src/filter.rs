@@ -735,6 +735,7 @@ fn build_is_null_expression<'s>(
             column: ComparisonTarget::Column {
                 // The column name is the root column
                 name: column_path.first().map_or(column, Deref::deref).clone(),
+                arguments: BTreeMap::new(),
                 // The field path is the nesting path inside the root column, if any
                 field_path: column_path
                     .iter()
@@ -803,6 +804,7 @@ fn build_binary_comparison_expression<'s>(
         column: ComparisonTarget::Column {
             // The column name is the root column
             name: column_path.first().map_or(column, Deref::deref).clone(),
+            arguments: BTreeMap::new(),
             // The field path is the nesting path inside the root column, if any
             field_path: column_path
                 .iter()
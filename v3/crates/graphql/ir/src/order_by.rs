@@ -177,6 +177,7 @@ pub fn build_ndc_order_by_element<'s>(
                             .first()
                             .map_or(&ndc_column, Deref::deref)
                             .clone(),
+                        arguments: BTreeMap::new(),
                         // The field path is the nesting path inside the root column, if any
                         field_path: column_path
                             .iter()
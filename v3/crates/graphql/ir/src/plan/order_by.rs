@@ -57,6 +57,7 @@ fn plan_order_by_target(
     let target = match order_by_target {
         plan_types::OrderByTarget::Column {
             name,
+            arguments,
             field_path,
             relationship_path,
         } => plan_types::OrderByTarget::Column {
@@ -89,6 +90,7 @@ fn plan_order_by_target(
                 })
                 .collect::<Result<Vec<_>, plan_error::Error>>()?,
             name: name.clone(),
+            arguments: arguments.clone(),
             field_path: field_path.clone(),
         },
     };
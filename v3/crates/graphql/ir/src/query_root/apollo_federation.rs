@@ -231,6 +231,7 @@ pub(crate) fn entities_ir<'n, 's>(
                                 LocalFieldComparison::BinaryComparison {
                                     column: ComparisonTarget::Column {
                                         name: field_mapping.column.clone(),
+                                        arguments: BTreeMap::new(),
                                         field_path: vec![], // We don't support nested fields in the key fields, so the path is empty
                                     },
                                     operator: field_mapping.equal_operator.clone(),
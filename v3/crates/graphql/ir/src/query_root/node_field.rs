@@ -206,6 +206,7 @@ pub(crate) fn relay_node_ir<'n, 's>(
                                 LocalFieldComparison::BinaryComparison {
                                     column: ComparisonTarget::Column {
                                         name: field_mapping.column.clone(),
+                                        arguments: BTreeMap::new(),
                                         field_path: vec![],
                                     },
                                     operator: field_mapping.equal_operator.clone(),
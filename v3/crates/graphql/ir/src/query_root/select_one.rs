@@ -195,6 +195,7 @@ pub fn select_one_generate_ir<'n, 's>(
                     Expression::LocalField(LocalFieldComparison::BinaryComparison {
                         column: ComparisonTarget::Column {
                             name: ndc_column.column.clone(),
+                            arguments: BTreeMap::new(),
                             field_path: vec![],
                         },
                         operator: ndc_column.equal_operator.clone(),
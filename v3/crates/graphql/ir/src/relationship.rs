@@ -894,6 +894,7 @@ pub fn build_remote_relationship<'s>(
                 let comparison_exp = LocalFieldComparison::BinaryComparison {
                     column: ComparisonTarget::Column {
                         name: target_column.column.clone(),
+                        arguments: BTreeMap::new(),
                         field_path: vec![],
                     },
                     operator: target_column.equal_operator.clone(),
@@ -0,0 +1,484 @@
+use std::collections::BTreeSet;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::catalog::Catalog;
+use crate::configuration::Configuration;
+use crate::endpoint::EndPoint;
+use crate::handler::handler_internal;
+use crate::types::{RequestError, ResponseDocument};
+use axum::http::{HeaderMap, Method, Uri};
+use engine_types::HttpContext;
+use hasura_authn_core::Session;
+use metadata_resolve::Metadata;
+use serde::{Deserialize, Serialize};
+use tracing_util::{set_attribute_on_active_span, AttributeVisibility, SpanVisibility};
+
+/// The route of batches, which are sent as the body of a `POST`
+pub const BATCH_ROUTE: &str = "/__batch";
+
+/// The route of requests with the atomic operations extension applied
+pub const OPERATIONS_ROUTE: &str = "/__operations";
+
+/// A batch of reads, sent as the body of `POST /v1/rest/__batch`
+#[derive(Debug, Deserialize)]
+pub struct BatchRequest {
+    pub operations: Vec<BatchOperation>,
+}
+
+/// One read of a batch
+#[derive(Debug, Deserialize)]
+pub struct BatchOperation {
+    /// identifies the operation in the response, so must be unique within the batch
+    pub key: String,
+    /// the route and query string of the read, eg `/default/Articles/1?include=author`. links
+    /// from earlier responses, which start with the endpoint (eg `/v1/rest`), can be used as is.
+    pub href: String,
+}
+
+/// The results of a batch, in the order of its operations
+#[derive(Debug, Serialize)]
+pub struct BatchResponse {
+    pub results: Vec<BatchResult>,
+}
+
+impl BatchResponse {
+    /// the number of resources, or resource identifiers, in the primary data of all of the reads
+    pub fn row_count(&self) -> usize {
+        self.results
+            .iter()
+            .map(|result| match &result.document {
+                BatchDocument::Data(document) => document.row_count(),
+                BatchDocument::Errors(_) => 0,
+            })
+            .sum()
+    }
+}
+
+/// The result of one read of a batch. a failing read does not fail the batch, and its errors are
+/// reported here with the status it would have had on its own.
+#[derive(Debug, Serialize)]
+pub struct BatchResult {
+    pub key: String,
+    pub status: u16,
+    pub document: BatchDocument,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum BatchDocument {
+    Data(ResponseDocument),
+    Errors(jsonapi_library::api::DocumentError),
+}
+
+impl BatchResult {
+    fn new(key: String, result: Result<ResponseDocument, RequestError>) -> Self {
+        match result {
+            Ok(document) => Self {
+                key,
+                status: axum::http::StatusCode::OK.as_u16(),
+                document: BatchDocument::Data(document),
+            },
+            Err(error) => {
+                let error = error.into_http_error();
+                Self {
+                    key,
+                    status: error.status.as_u16(),
+                    document: BatchDocument::Errors(error.into_document_error()),
+                }
+            }
+        }
+    }
+}
+
+// Run the reads of a batch. each of them goes through the same parsing, planning and permission
+// checks as it would as a request of its own. they run concurrently, but each is still its own
+// query to the data connector.
+//
+// only problems with the batch as a whole (it is empty, too big, or has duplicate keys) fail the
+// batch.
+pub async fn batch_handler_internal(
+    request_headers: Arc<HeaderMap>,
+    http_context: Arc<HttpContext>,
+    session: Arc<Session>,
+    catalog: &Catalog,
+    configuration: &Configuration,
+    metadata: Arc<Metadata>,
+    endpoint: &EndPoint,
+    batch: BatchRequest,
+) -> Result<BatchResponse, RequestError> {
+    validate_batch(configuration, &batch)?;
+
+    let tracer = tracing_util::global_tracer();
+    let results = futures_util::future::join_all(batch.operations.into_iter().map(
+        |BatchOperation { key, href }| {
+            let request_headers = request_headers.clone();
+            let http_context = http_context.clone();
+            let session = session.clone();
+            let metadata = metadata.clone();
+            let span_key = key.clone();
+            async move {
+                let result = tracer
+                    .in_span_async(
+                        "batch_operation",
+                        "Batch operation",
+                        SpanVisibility::User,
+                        || {
+                            Box::pin(async move {
+                                set_attribute_on_active_span(
+                                    AttributeVisibility::Default,
+                                    "jsonapi.batch_key",
+                                    span_key,
+                                );
+                                let uri = operation_uri(endpoint, &href)?;
+                                let raw_query = uri.query().unwrap_or_default().to_string();
+                                handler_internal(
+                                    request_headers,
+                                    http_context,
+                                    session,
+                                    catalog,
+                                    configuration,
+                                    metadata,
+                                    endpoint,
+                                    Method::GET,
+                                    uri,
+                                    &raw_query,
+                                )
+                                .await
+                            })
+                        },
+                    )
+                    .await;
+                BatchResult::new(key, result)
+            }
+        },
+    ))
+    .await;
+
+    Ok(BatchResponse { results })
+}
+
+/// An atomic request, sent as the body of `POST /v1/rest/__operations` with the atomic operations
+/// extension applied. only reads are supported for now.
+///
+/// spec: <https://jsonapi.org/ext/atomic/>
+#[derive(Debug, Deserialize)]
+pub struct AtomicRequest {
+    #[serde(rename = "atomic:operations")]
+    pub operations: Vec<AtomicOperation>,
+}
+
+/// One operation of an atomic request
+#[derive(Debug, Deserialize)]
+pub struct AtomicOperation {
+    /// the kind of operation. only `get` is supported.
+    pub op: String,
+    /// the route and query string of the read, as for a batch operation
+    pub href: String,
+}
+
+/// The results of an atomic request, in the order of its operations
+#[derive(Debug, Serialize)]
+pub struct AtomicResponse {
+    #[serde(rename = "atomic:results")]
+    pub results: Vec<ResponseDocument>,
+}
+
+impl AtomicResponse {
+    /// the number of resources, or resource identifiers, in the primary data of all of the reads
+    pub fn row_count(&self) -> usize {
+        self.results.iter().map(ResponseDocument::row_count).sum()
+    }
+}
+
+// Run the operations of an atomic request. unlike a batch, the request succeeds or fails as a
+// whole: the first operation to fail fails the request, with errors pointing at the operation.
+//
+// the operations are all reads, which don't depend on each other, so they run concurrently, as
+// one logical request of the role.
+pub async fn atomic_handler_internal(
+    request_headers: Arc<HeaderMap>,
+    http_context: Arc<HttpContext>,
+    session: Arc<Session>,
+    catalog: &Catalog,
+    configuration: &Configuration,
+    metadata: Arc<Metadata>,
+    endpoint: &EndPoint,
+    request: AtomicRequest,
+) -> Result<AtomicResponse, RequestError> {
+    validate_atomic(configuration, &request)?;
+
+    let tracer = tracing_util::global_tracer();
+    let results =
+        futures_util::future::try_join_all(request.operations.into_iter().enumerate().map(
+            |(index, AtomicOperation { href, .. })| {
+                let request_headers = request_headers.clone();
+                let http_context = http_context.clone();
+                let session = session.clone();
+                let metadata = metadata.clone();
+                async move {
+                    tracer
+                        .in_span_async(
+                            "atomic_operation",
+                            "Atomic operation",
+                            SpanVisibility::User,
+                            || {
+                                Box::pin(async move {
+                                    set_attribute_on_active_span(
+                                        AttributeVisibility::Default,
+                                        "jsonapi.atomic_operation",
+                                        index.to_string(),
+                                    );
+                                    let uri = operation_uri(endpoint, &href)?;
+                                    let raw_query = uri.query().unwrap_or_default().to_string();
+                                    handler_internal(
+                                        request_headers,
+                                        http_context,
+                                        session,
+                                        catalog,
+                                        configuration,
+                                        metadata,
+                                        endpoint,
+                                        Method::GET,
+                                        uri,
+                                        &raw_query,
+                                    )
+                                    .await
+                                })
+                            },
+                        )
+                        .await
+                        .map_err(|error| RequestError::AtomicOperation {
+                            index,
+                            error: Box::new(error),
+                        })
+                }
+            },
+        ))
+        .await?;
+
+    Ok(AtomicResponse { results })
+}
+
+fn validate_atomic(
+    configuration: &Configuration,
+    request: &AtomicRequest,
+) -> Result<(), RequestError> {
+    if request.operations.is_empty() {
+        return Err(RequestError::BadRequest(
+            "An atomic request must have at least one operation".to_string(),
+        ));
+    }
+    if request.operations.len() > configuration.max_batch_operations {
+        return Err(RequestError::BadRequest(format!(
+            "An atomic request must not have more than {} operations",
+            configuration.max_batch_operations
+        )));
+    }
+    for (index, operation) in request.operations.iter().enumerate() {
+        if operation.op != "get" {
+            return Err(RequestError::AtomicOperation {
+                index,
+                error: Box::new(RequestError::BadRequest(format!(
+                    "Unsupported atomic operation {}: only get is supported",
+                    operation.op
+                ))),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn validate_batch(configuration: &Configuration, batch: &BatchRequest) -> Result<(), RequestError> {
+    if batch.operations.is_empty() {
+        return Err(RequestError::BadRequest(
+            "A batch must have at least one operation".to_string(),
+        ));
+    }
+    if batch.operations.len() > configuration.max_batch_operations {
+        return Err(RequestError::BadRequest(format!(
+            "A batch must not have more than {} operations",
+            configuration.max_batch_operations
+        )));
+    }
+    let mut keys = BTreeSet::new();
+    for operation in &batch.operations {
+        if !keys.insert(operation.key.as_str()) {
+            return Err(RequestError::BadRequest(format!(
+                "Batch operation keys must be unique, but {} is used more than once",
+                operation.key
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// The routes of the reads of a batch or atomic request sent to `route`, eg to rate limit each of
+/// them before the request is handled. a body that can't be parsed has no routes, as the handler
+/// rejects it anyway, and so do hrefs that are not routes.
+pub(crate) fn operation_uris(endpoint: &EndPoint, route: &str, body: &[u8]) -> Vec<Uri> {
+    let hrefs: Vec<String> = match route {
+        BATCH_ROUTE => serde_json::from_slice::<BatchRequest>(body)
+            .map(|batch| {
+                batch
+                    .operations
+                    .into_iter()
+                    .map(|operation| operation.href)
+                    .collect()
+            })
+            .unwrap_or_default(),
+        OPERATIONS_ROUTE => serde_json::from_slice::<AtomicRequest>(body)
+            .map(|request| {
+                request
+                    .operations
+                    .into_iter()
+                    .map(|operation| operation.href)
+                    .collect()
+            })
+            .unwrap_or_default(),
+        _ => Vec::new(),
+    };
+    hrefs
+        .iter()
+        .filter_map(|href| operation_uri(endpoint, href).ok())
+        .collect()
+}
+
+// the route of an operation, without the endpoint it may start with
+fn operation_uri(endpoint: &EndPoint, href: &str) -> Result<Uri, RequestError> {
+    let href = match href.strip_prefix(endpoint.as_str()) {
+        Some(route) if route.starts_with('/') => route,
+        _ => href,
+    };
+    if !href.starts_with('/') {
+        return Err(RequestError::BadRequest(format!(
+            "Batch operation href must be a path, like /default/Articles, not {href}"
+        )));
+    }
+    Uri::from_str(href)
+        .map_err(|error| RequestError::BadRequest(format!("Invalid batch operation href: {error}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn operation(key: &str, href: &str) -> BatchOperation {
+        BatchOperation {
+            key: key.to_string(),
+            href: href.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_operation_uri() {
+        let uri = operation_uri(&EndPoint::V1Rest, "/default/Articles/1?include=author").unwrap();
+        assert_eq!(uri.path(), "/default/Articles/1");
+        assert_eq!(uri.query(), Some("include=author"));
+
+        // links from responses start with the endpoint
+        let uri = operation_uri(&EndPoint::V1Rest, "/v1/rest/default/Articles").unwrap();
+        assert_eq!(uri.path(), "/default/Articles");
+
+        assert!(operation_uri(&EndPoint::V1Rest, "https://example.com/default/Articles").is_err());
+    }
+
+    #[test]
+    fn test_operation_uris() {
+        let paths = |route, body: serde_json::Value| {
+            operation_uris(&EndPoint::V1Rest, route, body.to_string().as_bytes())
+                .iter()
+                .map(|uri| uri.path().to_string())
+                .collect::<Vec<_>>()
+        };
+        let batch = serde_json::json!({ "operations": [
+            { "key": "articles", "href": "/v1/rest/default/Articles?include=author" },
+            { "key": "authors", "href": "/default/Authors" },
+            { "key": "elsewhere", "href": "https://example.com/default/Articles" }
+        ] });
+        assert_eq!(
+            paths(BATCH_ROUTE, batch),
+            ["/default/Articles", "/default/Authors"]
+        );
+
+        let atomic = serde_json::json!({ "atomic:operations": [
+            { "op": "get", "href": "/default/Articles/1" }
+        ] });
+        assert_eq!(
+            paths(OPERATIONS_ROUTE, atomic.clone()),
+            ["/default/Articles/1"]
+        );
+        // the body of one route is not that of the other
+        assert!(paths(BATCH_ROUTE, atomic).is_empty());
+    }
+
+    #[test]
+    fn test_validate_batch() {
+        let configuration = Configuration::default();
+        let batch = |operations| BatchRequest { operations };
+
+        assert!(validate_batch(
+            &configuration,
+            &batch(vec![
+                operation("articles", "/default/Articles"),
+                operation("authors", "/default/Authors"),
+            ])
+        )
+        .is_ok());
+
+        assert!(validate_batch(&configuration, &batch(vec![])).is_err());
+        assert!(validate_batch(
+            &configuration,
+            &batch(vec![
+                operation("articles", "/default/Articles"),
+                operation("articles", "/default/Articles?page[offset]=20"),
+            ])
+        )
+        .is_err());
+        assert!(validate_batch(
+            &configuration,
+            &batch(
+                (0..=configuration.max_batch_operations)
+                    .map(|index| operation(&index.to_string(), "/default/Articles"))
+                    .collect()
+            )
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_validate_atomic() {
+        let configuration = Configuration::default();
+        let atomic = |operations: &[(&str, &str)]| AtomicRequest {
+            operations: operations
+                .iter()
+                .map(|(op, href)| AtomicOperation {
+                    op: (*op).to_string(),
+                    href: (*href).to_string(),
+                })
+                .collect(),
+        };
+
+        assert!(validate_atomic(
+            &configuration,
+            &atomic(&[("get", "/default/Articles"), ("get", "/default/Authors")])
+        )
+        .is_ok());
+        assert!(validate_atomic(&configuration, &atomic(&[])).is_err());
+
+        // the error points at the operation
+        let error = validate_atomic(
+            &configuration,
+            &atomic(&[("get", "/default/Articles"), ("add", "/default/Articles")]),
+        )
+        .unwrap_err()
+        .into_http_error();
+        assert_eq!(error.status, axum::http::StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error.errors[0].source,
+            Some(crate::types::ErrorSource::Pointer(
+                "/atomic:operations/1".to_string()
+            ))
+        );
+    }
+}
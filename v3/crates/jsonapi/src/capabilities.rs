@@ -0,0 +1,235 @@
+use std::collections::BTreeMap;
+
+use axum::http::Uri;
+use metadata_resolve::IncludeLogicalOperators;
+use open_dds::{relationships::RelationshipType, types::FieldName};
+use serde::Serialize;
+
+use crate::catalog::{Model, RelationshipTarget, State};
+use crate::naming::NamingStrategy;
+use crate::parse;
+
+/// The last segment of the routes that describe what can be read of a model, eg
+/// `/default/Articles/__capabilities`. a resource with the `id` "__capabilities" can't be fetched
+/// by its `id` as a result.
+pub const CAPABILITIES_ROUTE: &str = "__capabilities";
+
+/// Is this the capabilities route of a model, ie `/{subgraph}/{model}/__capabilities`?
+pub fn is_capabilities_route(uri: &Uri) -> bool {
+    let segments = uri
+        .path()
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>();
+    matches!(segments.as_slice(), [_, _, CAPABILITIES_ROUTE])
+}
+
+/// The document of the capabilities route: what the role can read of the resources of a model, in
+/// its `meta`
+#[derive(Debug, Serialize)]
+pub struct CapabilitiesDocument {
+    pub meta: CapabilitiesMeta,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CapabilitiesMeta {
+    pub capabilities: Capabilities,
+}
+
+/// What can be read of the resources of a model. fields and relationships are named as they are
+/// in documents, following the naming strategy.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Capabilities {
+    #[serde(rename = "type")]
+    pub resource_type: String,
+    /// the attributes that can be selected with `fields`
+    pub attributes: Vec<String>,
+    /// the fields that can be given in `sort`
+    pub sortable: Vec<String>,
+    /// the operators each field can be filtered with, as `filter[field][operator]=value`
+    pub filters: BTreeMap<String, Vec<String>>,
+    /// whether filters can be combined with `_and`, `_or` and `_not`
+    pub logical_operators: bool,
+    /// the arguments of the fields that take them, given with `args[field][argument]=value`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub arguments: BTreeMap<String, BTreeMap<String, ArgumentCapabilities>>,
+    pub relationships: BTreeMap<String, RelationshipCapabilities>,
+    /// whether the resources can be aggregated, with `/{subgraph}/{model}/__aggregate`
+    pub aggregates: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ArgumentCapabilities {
+    pub required: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RelationshipCapabilities {
+    /// the type of the related resources, if they are resources of a model
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub resource_type: Option<String>,
+    /// "one" or "many"
+    pub cardinality: &'static str,
+    /// whether the relationship can be given in `include`
+    pub includable: bool,
+    /// whether the fields of the related resource can be sorted on, as `relationship.field`
+    pub sortable: bool,
+    /// whether the relationship can be filtered on, as `filter[relationship][...]`
+    pub filterable: bool,
+}
+
+/// What the role of `state` can read of the resources of `model`: this is worked out from the
+/// metadata as it is resolved for the role, without asking the data connector, so the
+/// permissions of the role apply in the same way as they do for reads.
+pub fn capabilities_document(
+    model: &Model,
+    state: &State,
+    naming: &NamingStrategy,
+) -> CapabilitiesDocument {
+    let object_type = state.object_types.get(&model.data_type);
+    let type_fields = object_type
+        .into_iter()
+        .flat_map(|object_type| &object_type.type_fields);
+
+    let attributes = type_fields
+        .clone()
+        .map(|(field_name, _)| naming.field_name(field_name.as_str()))
+        .collect();
+    // the same checks as for `sort` itself
+    let sort = parse::Sortable {
+        object_types: &state.object_types,
+        order_by_expressions: &state.order_by_expressions,
+        naming,
+        data_connector_name: Some(&model.data_connector_name),
+    };
+    let sortable = object_type
+        .into_iter()
+        .flat_map(|object_type| {
+            object_type.type_fields.keys().filter(|field_name| {
+                sort.is_sortable_field(object_type, model.order_by_expression.as_ref(), field_name)
+            })
+        })
+        .map(|field_name| naming.field_name(field_name.as_str()))
+        .collect();
+
+    // the operators of the boolean expression type of a field, and the built-in `_in` and
+    // `_is_null`, as they are checked when filtering
+    let boolean_expression_type = model.filter_expression_type.as_ref();
+    let filters = type_fields
+        .filter_map(|(field_name, _)| {
+            let comparison = boolean_expression_type?
+                .fields
+                .scalar_fields
+                .get(field_name)?;
+            let mut operators = comparison
+                .operators
+                .keys()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>();
+            for built_in in ["_in", "_is_null"] {
+                if !operators.iter().any(|operator| operator == built_in) {
+                    operators.push(built_in.to_string());
+                }
+            }
+            Some((naming.field_name(field_name.as_str()), operators))
+        })
+        .collect();
+    let logical_operators = boolean_expression_type.is_some_and(|boolean_expression_type| {
+        boolean_expression_type.include_logical_operators == IncludeLogicalOperators::Yes
+    });
+
+    let arguments = object_type
+        .into_iter()
+        .flat_map(|object_type| &object_type.field_arguments)
+        .map(|(field_name, field_arguments)| {
+            (
+                naming.field_name(field_name.as_str()),
+                field_arguments
+                    .iter()
+                    .map(|(argument_name, argument)| {
+                        (
+                            argument_name.to_string(),
+                            ArgumentCapabilities {
+                                required: argument.required,
+                            },
+                        )
+                    })
+                    .collect(),
+            )
+        })
+        .collect();
+
+    let relationships = object_type
+        .into_iter()
+        .flat_map(|object_type| {
+            object_type
+                .type_relationships
+                .iter()
+                .map(move |(relationship_name, target)| (object_type, relationship_name, target))
+        })
+        .map(|(object_type, relationship_name, target)| {
+            let (resource_type, relationship_type) = match target {
+                RelationshipTarget::Model {
+                    object_type,
+                    relationship_type,
+                    ..
+                } => (
+                    Some(naming.resource_type(object_type)),
+                    relationship_type.clone(),
+                ),
+                RelationshipTarget::Command { type_reference } => (
+                    None,
+                    crate::helpers::type_reference_to_relationship_type(type_reference),
+                ),
+            };
+            let to_model = matches!(target, RelationshipTarget::Model { .. });
+            let filterable = to_model
+                && boolean_expression_type.is_some_and(|boolean_expression_type| {
+                    boolean_expression_type
+                        .fields
+                        .relationship_fields
+                        .contains_key(&FieldName::new(relationship_name.clone().into_inner()))
+                });
+            let capabilities = RelationshipCapabilities {
+                resource_type,
+                cardinality: match relationship_type {
+                    RelationshipType::Object => "one",
+                    RelationshipType::Array => "many",
+                },
+                includable: parse::can_include(
+                    model,
+                    &state.object_types,
+                    object_type,
+                    target,
+                    naming,
+                ),
+                sortable: sort
+                    .sortable_relationship(
+                        model.order_by_expression.as_ref(),
+                        relationship_name,
+                        target,
+                    )
+                    .is_some(),
+                filterable,
+            };
+            (naming.field_name(relationship_name.as_str()), capabilities)
+        })
+        .collect();
+
+    CapabilitiesDocument {
+        meta: CapabilitiesMeta {
+            capabilities: Capabilities {
+                resource_type: naming.resource_type(&model.data_type),
+                attributes,
+                sortable,
+                filters,
+                logical_operators,
+                arguments,
+                relationships,
+                aggregates: model.supports_aggregates,
+            },
+        },
+    }
+}
@@ -1,4 +1,8 @@
 mod types;
-pub use types::{Catalog, Model, ObjectType, RelationshipTarget, State, Type};
+pub use types::{
+    model_route, Catalog, FieldArgument, FieldMapping, Model, ObjectType, OrderByExpression,
+    RelationshipTarget, ScalarRepresentation, ScalarTypeForDataConnector, State, Type,
+    RESERVED_ROUTE_PREFIX,
+};
 mod models;
 mod object_types;
@@ -1,8 +1,11 @@
-use super::types::Model;
+use super::types::{model_route, Model, RESERVED_ROUTE_PREFIX};
 use crate::types::ModelWarning;
 use hasura_authn_core::Role;
-use metadata_resolve::{ModelWithPermissions, ObjectTypeWithRelationships, Qualified};
-use open_dds::types::CustomTypeName;
+use metadata_resolve::{
+    ModelOrderBy, ModelWithPermissions, ObjectTypeWithRelationships, OrderByExpressionIdentifier,
+    Qualified,
+};
+use open_dds::types::{CustomTypeName, FieldName};
 use std::collections::BTreeMap;
 
 // look at permissions and work out which fields we're allowed to see
@@ -12,6 +15,15 @@ pub fn build_model(
     role: &Role,
     object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectTypeWithRelationships>,
 ) -> Result<Model, ModelWarning> {
+    // a model must not shadow the routes of the endpoint itself
+    let route = model_route(&model.model.name);
+    if route
+        .split('/')
+        .any(|segment| segment.starts_with(RESERVED_ROUTE_PREFIX))
+    {
+        return Err(ModelWarning::ReservedRoute { route });
+    }
+
     // if we have no select permission for the model, ignore it
     if !model.select_permissions.contains_key(role) {
         return Err(ModelWarning::NoSelectPermission);
@@ -29,6 +41,14 @@ pub fn build_model(
         .ok_or(ModelWarning::NoModelSource)?;
 
     let data_connector_name = model_source.data_connector.name.clone();
+    let aggregate_capabilities = model_source
+        .data_connector
+        .capabilities
+        .supports_aggregates
+        .as_ref();
+    let supports_aggregates = aggregate_capabilities.is_some();
+    let supports_grouping =
+        aggregate_capabilities.is_some_and(|capabilities| capabilities.supports_grouping.is_some());
 
     Ok(Model {
         name: model.model.name.clone(),
@@ -36,5 +56,56 @@ pub fn build_model(
         data_type: model.model.data_type.clone(),
         data_connector_name,
         filter_expression_type: model.filter_expression_type.clone(),
+        unique_identifier: model_unique_identifier(model, role, object_types),
+        supports_aggregates,
+        supports_grouping,
+        order_by_expression: model_order_by_expression(&model.model),
     })
 }
+
+// the order by expression of a model, which says what its resources can be sorted on. v1 models
+// list their orderable fields, from which an expression is made with the name of the model,
+// while v2 models name one, if they can be sorted at all
+pub fn model_order_by_expression(
+    model: &metadata_resolve::Model,
+) -> Option<Qualified<OrderByExpressionIdentifier>> {
+    let identifier = match &model.raw.order_by {
+        ModelOrderBy::ModelV1 { .. } => {
+            OrderByExpressionIdentifier::FromModel(model.name.name.clone())
+        }
+        ModelOrderBy::ModelV2(order_by_expression) => {
+            OrderByExpressionIdentifier::FromOrderByExpression(order_by_expression.clone()?)
+        }
+    };
+    Some(Qualified::new(model.name.subgraph.clone(), identifier))
+}
+
+// the fields we use to identify a resource. we take the first unique identifier defined on the
+// model, and fall back to the global id fields if there are none. an empty list means we have no
+// way of identifying rows of this model, either because there is no unique identifier or because
+// `role` cannot see all of its fields
+pub fn model_unique_identifier(
+    model: &ModelWithPermissions,
+    role: &Role,
+    object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectTypeWithRelationships>,
+) -> Vec<FieldName> {
+    let unique_identifier = model.graphql_api.select_uniques.first().map_or_else(
+        || model.model.global_id_fields.clone(),
+        |select_unique| select_unique.unique_identifier.keys().cloned().collect(),
+    );
+
+    let visible_to_role = object_types
+        .get(&model.model.data_type)
+        .and_then(|object_type| object_type.type_output_permissions.get(role))
+        .is_some_and(|output_permissions| {
+            unique_identifier
+                .iter()
+                .all(|field_name| output_permissions.allowed_fields.contains(field_name))
+        });
+
+    if visible_to_role {
+        unique_identifier
+    } else {
+        vec![]
+    }
+}
@@ -1,19 +1,26 @@
-use super::types::{ObjectType, RelationshipTarget, ScalarTypeForDataConnector, Type};
+use super::models::{model_order_by_expression, model_unique_identifier};
+use super::types::{
+    FieldArgument, FieldMapping, ObjectType, RelationshipTarget, ScalarTypeForDataConnector, Type,
+};
 use crate::types::ObjectTypeWarning;
 use hasura_authn_core::Role;
 use indexmap::IndexMap;
 use metadata_resolve::{
-    unwrap_custom_type_name, ObjectTypeWithRelationships, Qualified, QualifiedBaseType,
-    QualifiedTypeName, QualifiedTypeReference, ScalarTypeRepresentation,
+    unwrap_custom_type_name, ModelWithPermissions, ObjectTypeWithRelationships, Qualified,
+    QualifiedBaseType, QualifiedTypeName, QualifiedTypeReference, ScalarTypeRepresentation,
+};
+use open_dds::{
+    models::ModelName,
+    types::{CustomTypeName, InbuiltType},
 };
-use open_dds::types::{CustomTypeName, InbuiltType};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 // look at permissions and work out which fields we're allowed to see
 // this is quite limited and leans to be overcautious
 pub fn build_object_type(
     object_type: &ObjectTypeWithRelationships,
     role: &Role,
+    models: &IndexMap<Qualified<ModelName>, ModelWithPermissions>,
     object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectTypeWithRelationships>,
     scalar_types: &BTreeMap<Qualified<CustomTypeName>, ScalarTypeRepresentation>,
 ) -> Result<ObjectType, ObjectTypeWarning> {
@@ -24,6 +31,8 @@ pub fn build_object_type(
         .ok_or(ObjectTypeWarning::NoObjectTypePermission {})?;
 
     let mut type_fields = IndexMap::new();
+    let mut nullable_fields = BTreeSet::new();
+    let mut field_arguments = BTreeMap::new();
 
     // otherwise return all fields
     for (field_name, field_info) in
@@ -41,6 +50,29 @@ pub fn build_object_type(
             type_from_type_representation(&field_info.field_type, scalar_types, object_types)?;
 
         type_fields.insert(field_name.clone(), field_type);
+        if field_info.field_type.nullable {
+            nullable_fields.insert(field_name.clone());
+        }
+        if !field_info.field_arguments.is_empty() {
+            let arguments = field_info
+                .field_arguments
+                .iter()
+                .map(|(argument_name, argument_info)| {
+                    Ok((
+                        argument_name.clone(),
+                        FieldArgument {
+                            argument_type: type_from_type_representation(
+                                &argument_info.argument_type,
+                                scalar_types,
+                                object_types,
+                            )?,
+                            required: !argument_info.argument_type.nullable,
+                        },
+                    ))
+                })
+                .collect::<Result<IndexMap<_, _>, ObjectTypeWarning>>()?;
+            field_arguments.insert(field_name.clone(), arguments);
+        }
     }
 
     // Relationships
@@ -51,9 +83,29 @@ pub fn build_object_type(
         match &relationship_field.target {
             metadata_resolve::RelationshipTarget::Model(model) => {
                 if object_type_permission_access(role, &model.target_typename, object_types) {
+                    let target_model = models.get(&model.model_name);
                     target = Some(RelationshipTarget::Model {
+                        model_name: model.model_name.clone(),
                         object_type: model.target_typename.clone(),
                         relationship_type: model.relationship_type.clone(),
+                        unique_identifier: target_model
+                            .map(|target_model| {
+                                model_unique_identifier(target_model, role, object_types)
+                            })
+                            .unwrap_or_default(),
+                        data_connector_name: target_model
+                            .and_then(|target_model| target_model.model.source.as_ref())
+                            .map(|source| source.data_connector.name.clone()),
+                        supports_relationships: relationship_field
+                            .target_capabilities
+                            .as_ref()
+                            .is_some_and(|capabilities| {
+                                capabilities.supports_relationships.is_some()
+                            }),
+                        order_by_expression: target_model.and_then(|target_model| {
+                            model_order_by_expression(&target_model.model)
+                        }),
+                        mappings: field_mappings(&model.mappings),
                     });
                 }
             }
@@ -82,10 +134,30 @@ pub fn build_object_type(
 
     Ok(ObjectType {
         type_fields,
+        nullable_fields,
+        field_arguments,
         type_relationships,
     })
 }
 
+// the fields a relationship to a model joins on, unless it maps to arguments of the model
+fn field_mappings(
+    mappings: &[metadata_resolve::RelationshipModelMapping],
+) -> Option<Vec<FieldMapping>> {
+    mappings
+        .iter()
+        .map(|mapping| match &mapping.target {
+            metadata_resolve::RelationshipModelMappingTarget::ModelField(field_target) => {
+                Some(FieldMapping {
+                    source_field: mapping.source_field.field_name.clone(),
+                    target_field: field_target.target_field.field_name.clone(),
+                })
+            }
+            metadata_resolve::RelationshipModelMappingTarget::Argument(_) => None,
+        })
+        .collect()
+}
+
 // Check if object_type is accessible to given role
 fn object_type_permission_access(
     role: &Role,
@@ -105,13 +177,14 @@ fn type_from_type_representation(
     scalar_types: &BTreeMap<Qualified<CustomTypeName>, ScalarTypeRepresentation>,
     object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectTypeWithRelationships>,
 ) -> Result<Type, ObjectTypeWarning> {
-    // NOTE: currently we assume everything is nullable because a user might
-    // not include a field in sparse fields
+    // NOTE: the nullability of fields is tracked separately, in `ObjectType::nullable_fields`.
+    // we don't track the nullability of list elements.
     match &qualified_type_reference.underlying_type {
         QualifiedBaseType::Named(name) => match name {
             QualifiedTypeName::Inbuilt(inbuilt) => Ok(Type::Scalar(match inbuilt {
                 InbuiltType::String | InbuiltType::ID => ndc_models::TypeRepresentation::String,
-                InbuiltType::Int => ndc_models::TypeRepresentation::Int64,
+                // OpenDD `Int` is a 32-bit integer, like GraphQL's
+                InbuiltType::Int => ndc_models::TypeRepresentation::Int32,
                 InbuiltType::Float => ndc_models::TypeRepresentation::Float64,
                 InbuiltType::Boolean => ndc_models::TypeRepresentation::Boolean,
             })),
@@ -4,12 +4,13 @@ use crate::types::{RoleWarning, Warning};
 use hasura_authn_core::Role;
 use indexmap::IndexMap;
 use metadata_resolve::{
-    deserialize_qualified_btreemap, serialize_qualified_btreemap, Qualified,
-    QualifiedTypeReference, ResolvedObjectBooleanExpressionType,
+    deserialize_qualified_btreemap, serialize_qualified_btreemap, OrderByExpressionIdentifier,
+    Qualified, QualifiedTypeReference, ResolvedObjectBooleanExpressionType,
 };
 use open_dds::{
     data_connector::DataConnectorName,
     models::ModelName,
+    query::ArgumentName,
     relationships::{RelationshipName, RelationshipType},
     types::{CustomTypeName, FieldName},
 };
@@ -50,25 +51,82 @@ pub struct State {
         deserialize_with = "deserialize_qualified_btreemap"
     )]
     pub object_types: BTreeMap<Qualified<CustomTypeName>, ObjectType>,
+    /// boolean expression types, used to validate filters. the fields a role can filter on are
+    /// limited by the fields it can see in `object_types`.
+    #[serde(
+        serialize_with = "serialize_qualified_btreemap",
+        deserialize_with = "deserialize_qualified_btreemap"
+    )]
+    pub boolean_expression_types:
+        BTreeMap<Qualified<CustomTypeName>, ResolvedObjectBooleanExpressionType>,
+    /// order by expressions, used to validate sorts. as with filters, the fields a role can sort
+    /// on are limited by the fields it can see in `object_types`.
+    #[serde(
+        serialize_with = "serialize_qualified_btreemap",
+        deserialize_with = "deserialize_qualified_btreemap"
+    )]
+    pub order_by_expressions: BTreeMap<Qualified<OrderByExpressionIdentifier>, OrderByExpression>,
 }
 
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
 pub struct ObjectType {
     pub type_fields: IndexMap<FieldName, Type>,
+    /// fields of `type_fields` whose values may be null
+    pub nullable_fields: BTreeSet<FieldName>,
+    /// fields of `type_fields` that take arguments, like computed fields backed by functions of
+    /// the data connector, and their arguments
+    pub field_arguments: BTreeMap<FieldName, IndexMap<ArgumentName, FieldArgument>>,
     pub type_relationships: IndexMap<RelationshipName, RelationshipTarget>,
 }
 
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct FieldArgument {
+    pub argument_type: Type,
+    /// the argument must be given, as its type is not nullable
+    pub required: bool,
+}
+
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
 pub enum RelationshipTarget {
     Model {
+        model_name: Qualified<ModelName>,
         object_type: Qualified<CustomTypeName>,
         relationship_type: RelationshipType,
+        /// fields of the target model used to render the resource `id`
+        unique_identifier: Vec<FieldName>,
+        /// the data connector of the target model
+        data_connector_name: Option<Qualified<DataConnectorName>>,
+        /// whether the data connector of the target model can fetch the relationship in the same
+        /// query as its source, when the source is from that data connector too
+        supports_relationships: bool,
+        /// the order by expression of the target model, if it can be sorted on
+        order_by_expression: Option<Qualified<OrderByExpressionIdentifier>>,
+        /// the fields the relationship joins on. `None` when it maps to arguments of the target
+        /// model, so is only ever fetched along with its source.
+        mappings: Option<Vec<FieldMapping>>,
     },
     Command {
         type_reference: QualifiedTypeReference,
     },
 }
 
+/// The fields and relationships of an object type that can be sorted on
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct OrderByExpression {
+    pub orderable_fields: BTreeSet<FieldName>,
+    /// object relationships that can be sorted on, and the order by expression to use for the
+    /// fields of their target. `None` means that of the target model.
+    pub orderable_relationships:
+        BTreeMap<RelationshipName, Option<Qualified<OrderByExpressionIdentifier>>>,
+}
+
+/// A field of the source type of a relationship, and the field of the target model it matches
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct FieldMapping {
+    pub source_field: FieldName,
+    pub target_field: FieldName,
+}
+
 impl State {
     pub fn new(metadata: &metadata_resolve::Metadata, role: &Role) -> (Self, Vec<RoleWarning>) {
         let mut warnings = vec![];
@@ -78,10 +136,7 @@ impl State {
             .iter()
             .filter_map(|(model_name, model)| {
                 match build_model(model, role, &metadata.object_types) {
-                    Ok(jsonapi_model) => Some((
-                        format!("/{}/{}", model_name.subgraph, model_name.name),
-                        jsonapi_model,
-                    )),
+                    Ok(jsonapi_model) => Some((model_route(model_name), jsonapi_model)),
                     Err(warning) => {
                         warnings.push(RoleWarning::Model {
                             model_name: model_name.clone(),
@@ -103,6 +158,7 @@ impl State {
                 match build_object_type(
                     object_type,
                     role,
+                    &metadata.models,
                     &metadata.object_types,
                     &metadata.scalar_types,
                 ) {
@@ -119,16 +175,65 @@ impl State {
                 }
             })
             .collect::<BTreeMap<_, _>>();
+
+        let boolean_expression_types = metadata.boolean_expression_types.objects.clone();
+        let order_by_expressions = metadata
+            .order_by_expressions
+            .objects
+            .iter()
+            .map(|(identifier, order_by_expression)| {
+                (
+                    identifier.clone(),
+                    OrderByExpression {
+                        orderable_fields: order_by_expression
+                            .orderable_fields
+                            .keys()
+                            .cloned()
+                            .collect(),
+                        orderable_relationships: order_by_expression
+                            .orderable_relationships
+                            .iter()
+                            .map(|(relationship_name, orderable_relationship)| {
+                                (
+                                    relationship_name.clone(),
+                                    orderable_relationship.order_by_expression.as_ref().map(
+                                        |name| {
+                                            Qualified::new(
+                                                name.subgraph.clone(),
+                                                OrderByExpressionIdentifier::FromOrderByExpression(
+                                                    name.name.clone(),
+                                                ),
+                                            )
+                                        },
+                                    ),
+                                )
+                            })
+                            .collect(),
+                    },
+                )
+            })
+            .collect();
         (
             Self {
                 routes,
                 object_types,
+                boolean_expression_types,
+                order_by_expressions,
             },
             warnings,
         )
     }
 }
 
+/// What the segments of the routes of the endpoint itself start with, eg `/__schema`. no route of
+/// a model may have a segment starting with it, so that models can't shadow them.
+pub const RESERVED_ROUTE_PREFIX: &str = "__";
+
+/// The route of the resources of a model, eg `/default/Articles`
+pub fn model_route(model_name: &Qualified<ModelName>) -> String {
+    format!("/{}/{}", model_name.subgraph, model_name.name)
+}
+
 // we're making the assumption here of one object type that works across all data connectors.
 // this is questionable - as the scalar types might be represented differently for each one
 // we have a few routes out of this:
@@ -154,6 +259,82 @@ pub struct ScalarTypeForDataConnector {
     pub type_representations: BTreeSet<ndc_models::TypeRepresentation>,
 }
 
+impl Type {
+    /// how the values of a scalar type are written in JSON:API documents and read from `filter`
+    /// values, or `None` for lists and objects
+    pub fn scalar_representation(&self) -> Option<ScalarRepresentation> {
+        match self {
+            Type::Scalar(representation) => Some(ScalarRepresentation::new(representation)),
+            Type::ScalarForDataConnector(scalar_type) => {
+                let mut representations = scalar_type
+                    .type_representations
+                    .iter()
+                    .map(ScalarRepresentation::new);
+                // a scalar the data connectors represent differently is passed on as is
+                let first = representations.next().unwrap_or(ScalarRepresentation::AsIs);
+                Some(if representations.all(|other| other == first) {
+                    first
+                } else {
+                    ScalarRepresentation::AsIs
+                })
+            }
+            Type::List(_) | Type::Object(_) => None,
+        }
+    }
+}
+
+/// The JSON:API representation of the values of a scalar type. It follows the representation
+/// of the scalar type in the schema of its data connector, so is set per scalar type in metadata.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScalarRepresentation {
+    /// JSON numbers, for numbers that fit in a double
+    Number { integer: bool },
+    /// JSON strings holding the number, for 64-bit and arbitrary-precision numbers, which lose
+    /// precision as JSON numbers in most clients
+    NumericString { integer: bool },
+    /// JSON booleans
+    Boolean,
+    /// RFC 3339 strings. timestamps without a time zone have no offset.
+    Timestamp { with_time_zone: bool },
+    /// `YYYY-MM-DD` strings
+    Date,
+    /// whatever the data connector returns
+    AsIs,
+}
+
+impl ScalarRepresentation {
+    #[allow(deprecated)]
+    fn new(representation: &ndc_models::TypeRepresentation) -> Self {
+        match representation {
+            ndc_models::TypeRepresentation::Int8
+            | ndc_models::TypeRepresentation::Int16
+            | ndc_models::TypeRepresentation::Int32 => Self::Number { integer: true },
+            ndc_models::TypeRepresentation::Float32 | ndc_models::TypeRepresentation::Float64 => {
+                Self::Number { integer: false }
+            }
+            ndc_models::TypeRepresentation::Int64 | ndc_models::TypeRepresentation::BigInteger => {
+                Self::NumericString { integer: true }
+            }
+            ndc_models::TypeRepresentation::BigDecimal => Self::NumericString { integer: false },
+            ndc_models::TypeRepresentation::Boolean => Self::Boolean,
+            ndc_models::TypeRepresentation::Timestamp => Self::Timestamp {
+                with_time_zone: false,
+            },
+            ndc_models::TypeRepresentation::TimestampTZ => Self::Timestamp {
+                with_time_zone: true,
+            },
+            ndc_models::TypeRepresentation::Date => Self::Date,
+            ndc_models::TypeRepresentation::String
+            | ndc_models::TypeRepresentation::UUID
+            | ndc_models::TypeRepresentation::Bytes
+            | ndc_models::TypeRepresentation::JSON
+            | ndc_models::TypeRepresentation::Geography
+            | ndc_models::TypeRepresentation::Geometry
+            | ndc_models::TypeRepresentation::Enum { .. } => Self::AsIs,
+        }
+    }
+}
+
 // only the parts of a Model we need to construct a JSONAPI
 // we'll filter out fields a given role can't see
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
@@ -163,4 +344,15 @@ pub struct Model {
     pub data_type: Qualified<CustomTypeName>,
     pub data_connector_name: Qualified<DataConnectorName>,
     pub filter_expression_type: Option<ResolvedObjectBooleanExpressionType>,
+    /// fields used to render the resource `id`, if the model has a unique identifier
+    pub unique_identifier: Vec<FieldName>,
+    /// whether the data connector of the model can aggregate its rows, which we need to count
+    /// them for `page[totals]`
+    pub supports_aggregates: bool,
+    /// whether the data connector of the model can group its rows, for aggregates with
+    /// `group_by`
+    pub supports_grouping: bool,
+    /// which fields and relationships resources can be sorted on. `None` when the model has no
+    /// order by expression, so can only be sorted by its unique identifier.
+    pub order_by_expression: Option<Qualified<OrderByExpressionIdentifier>>,
 }
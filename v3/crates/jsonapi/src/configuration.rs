@@ -0,0 +1,184 @@
+use std::collections::BTreeSet;
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::time::Duration;
+
+use hasura_authn_core::Role;
+
+use crate::middleware::MediaTypeExtensions;
+use crate::naming::NamingStrategy;
+use crate::types::{JsonApiObject, JSONAPI_VERSION};
+
+/// Number of resources returned by a listing endpoint when the request does not specify
+/// `page[limit]`
+pub const DEFAULT_PAGE_LIMIT: usize = 20;
+
+/// Largest `page[limit]` a request may ask for, unless configured otherwise
+pub const DEFAULT_MAX_PAGE_LIMIT: usize = 1000;
+
+/// How many relationships deep an `include` path may go, unless configured otherwise
+pub const DEFAULT_MAX_INCLUDE_DEPTH: usize = 3;
+
+/// How many distinct `include` paths a request may ask for, unless configured otherwise
+pub const DEFAULT_MAX_INCLUDE_PATHS: usize = 10;
+
+/// How many reads a batch request may have, unless configured otherwise
+pub const DEFAULT_MAX_BATCH_OPERATIONS: usize = 20;
+
+/// Smallest response, in bytes, that is compressed, unless configured otherwise
+pub const DEFAULT_COMPRESSION_MIN_SIZE: u16 = 1024;
+
+/// Smallest page of resources, in rows, that is streamed, unless configured otherwise
+pub const DEFAULT_STREAM_MIN_ROWS: usize = 100;
+
+/// How many included relationships a request fetches with queries of their own at once, unless
+/// configured otherwise
+pub const DEFAULT_INCLUDED_QUERY_CONCURRENCY: NonZeroUsize = NonZeroUsize::new(4).unwrap();
+
+/// Server-wide configuration for the JSON:API endpoints
+#[derive(Debug, Clone)]
+pub struct Configuration {
+    /// `page[limit]` used when the request does not provide one
+    pub default_page_limit: usize,
+    /// requests with a `page[limit]` above this are rejected
+    pub max_page_limit: usize,
+    /// how listing endpoints paginate when the request does not choose for itself
+    pub pagination_mode: PaginationMode,
+    /// key used to sign `page[cursor]` tokens
+    pub cursor_secret: CursorSecret,
+    /// requests with an `include` path deeper than this are rejected
+    pub max_include_depth: usize,
+    /// requests with more distinct `include` paths than this are rejected
+    pub max_include_paths: usize,
+    /// batch requests with more reads than this are rejected
+    pub max_batch_operations: usize,
+    /// responses of at least this many bytes are compressed with gzip or brotli, when the client
+    /// accepts it. `None` turns compression off, eg when a proxy in front already compresses.
+    pub compression_min_size: Option<u16>,
+    /// pages of at least this many resources are streamed to the client as they are serialized,
+    /// when the request has no `include`. only the serialization is streamed: all of the rows are
+    /// still read from the data connector, and held in memory, first. streamed responses have no
+    /// `ETag`. `None` turns streaming off.
+    pub stream_min_rows: Option<usize>,
+    /// responses whose documents serialize to more than this many bytes are rejected, rather than
+    /// sent, as the document is written out. responses are never streamed when this is set, as
+    /// they could not be rejected once their status is sent. `None` doesn't limit the size of
+    /// responses.
+    pub max_response_size: Option<usize>,
+    /// roles that see the messages of data connector errors. for other roles, the `detail` of
+    /// these errors is redacted.
+    pub connector_error_roles: BTreeSet<Role>,
+    /// roles that may explain requests with the `/__explain` routes, which show the queries sent
+    /// to data connectors. for other roles, these routes don't exist.
+    pub explain_roles: BTreeSet<Role>,
+    /// limits how often each role may request the resources of each model. `None` turns rate
+    /// limiting off.
+    pub rate_limit: Option<RateLimit>,
+    /// requests taking longer than this are cancelled, along with their queries to data
+    /// connectors, and get a `504 Gateway Timeout`. `None` never cancels requests.
+    pub request_timeout: Option<Duration>,
+    /// the URL of the engine as clients reach it, eg `https://api.example.com` behind a reverse
+    /// proxy. links in responses start with it. `None` gives links that are paths on the host of
+    /// the request.
+    pub external_base_url: Option<url::Url>,
+    /// how the names of types, attributes and relationships are written in documents and
+    /// requests
+    pub naming: NamingStrategy,
+    /// how many included relationships a request fetches with queries of their own at once.
+    /// these are the relationships the data connector of a model cannot fetch along with its
+    /// resources, eg as they are to a model of another data connector.
+    pub included_query_concurrency: NonZeroUsize,
+    /// whether documents have a top-level `jsonapi` member, with the version of JSON:API and the
+    /// extensions applied. off for clients that reject members they don't know.
+    pub jsonapi_object: bool,
+}
+
+impl Default for Configuration {
+    fn default() -> Self {
+        Self {
+            default_page_limit: DEFAULT_PAGE_LIMIT,
+            max_page_limit: DEFAULT_MAX_PAGE_LIMIT,
+            pagination_mode: PaginationMode::default(),
+            cursor_secret: CursorSecret::random(),
+            max_include_depth: DEFAULT_MAX_INCLUDE_DEPTH,
+            max_include_paths: DEFAULT_MAX_INCLUDE_PATHS,
+            max_batch_operations: DEFAULT_MAX_BATCH_OPERATIONS,
+            compression_min_size: Some(DEFAULT_COMPRESSION_MIN_SIZE),
+            stream_min_rows: Some(DEFAULT_STREAM_MIN_ROWS),
+            max_response_size: None,
+            connector_error_roles: BTreeSet::new(),
+            explain_roles: BTreeSet::new(),
+            rate_limit: None,
+            request_timeout: None,
+            external_base_url: None,
+            naming: NamingStrategy::default(),
+            included_query_concurrency: DEFAULT_INCLUDED_QUERY_CONCURRENCY,
+            jsonapi_object: true,
+        }
+    }
+}
+
+impl Configuration {
+    /// the top-level `jsonapi` member of the documents for a request applying `extensions`, if
+    /// documents have one
+    pub fn jsonapi_object(&self, extensions: &MediaTypeExtensions) -> Option<JsonApiObject> {
+        self.jsonapi_object.then(|| JsonApiObject {
+            version: JSONAPI_VERSION,
+            ext: extensions.extensions().to_vec(),
+        })
+    }
+}
+
+/// Rate limit for requests to the resources of a model, applied to each role and model
+/// separately.
+///
+/// Each role and model has a bucket of `requests_per_minute` tokens, refilled at that rate. A
+/// request takes a token, and is rejected with `429 Too Many Requests` when there are none left,
+/// so a client can burst up to a minute's worth of requests at once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimit {
+    pub requests_per_minute: NonZeroU32,
+    /// roles that are never rate limited, eg `admin`
+    pub exempt_roles: BTreeSet<Role>,
+}
+
+/// Pagination strategy for listing endpoints.
+///
+/// A request can always opt into cursor pagination by sending `page[cursor]`, and into offset
+/// pagination by sending `page[offset]`; this only decides what happens when it sends neither.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PaginationMode {
+    /// `page[offset]` based pagination
+    #[default]
+    Offset,
+    /// keyset pagination using opaque `page[cursor]` tokens
+    Cursor,
+}
+
+/// Secret used to sign `page[cursor]` tokens, so that clients cannot forge a cursor to skip
+/// past filters or read from an arbitrary point.
+#[derive(Clone)]
+pub struct CursorSecret(Vec<u8>);
+
+impl CursorSecret {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self(secret.into())
+    }
+
+    /// a fresh secret for this process. cursors signed with it stop working after a restart, and
+    /// are not accepted by other replicas, so deployments running more than one engine should
+    /// configure a shared secret instead.
+    pub fn random() -> Self {
+        Self(rand::random::<[u8; 32]>().to_vec())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+// never print the secret
+impl std::fmt::Debug for CursorSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("CursorSecret(<redacted>)")
+    }
+}
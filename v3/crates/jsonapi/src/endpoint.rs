@@ -1,14 +1,20 @@
+use std::sync::Arc;
+
+/// The path the JSON:API routes are mounted at
 #[derive(Debug, Clone)]
 pub enum EndPoint {
     V1Rest,
     V1Jsonapi,
+    /// a path of the embedder's choosing, eg `/api/rest`
+    Custom(Arc<str>),
 }
 
 impl EndPoint {
-    pub fn as_str(&self) -> &'static str {
+    pub fn as_str(&self) -> &str {
         match self {
             EndPoint::V1Rest => "/v1/rest",
             EndPoint::V1Jsonapi => "/v1/jsonapi",
+            EndPoint::Custom(path) => path,
         }
     }
 }
@@ -0,0 +1,196 @@
+use std::sync::Arc;
+
+use axum::http::{HeaderMap, Uri};
+use engine_types::HttpContext;
+use hasura_authn_core::Session;
+use metadata_resolve::Metadata;
+use plan_types::{ProcessResponseAs, QueryExecutionTree};
+use serde::Serialize;
+
+use crate::catalog::Catalog;
+use crate::configuration::Configuration;
+use crate::handler::{process_response_as, validate_route, with_timeout};
+use crate::parse;
+use crate::types::{ConnectorError, RelationshipTree, RequestError, ResponseDocument};
+
+/// The prefix of the routes that explain the request for the route after it, eg
+/// `/__explain/default/Articles?filter[title][_eq]=A` for `/default/Articles?filter[title][_eq]=A`
+pub const EXPLAIN_ROUTE: &str = "/__explain";
+
+/// The document of an explained request: the queries it would send to data connectors, in its
+/// `meta`
+#[derive(Debug, Serialize)]
+pub struct ExplainDocument {
+    pub meta: ExplainMeta,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExplainMeta {
+    pub queries: Vec<ExplainedQuery>,
+}
+
+/// A query the request would send to a data connector
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExplainedQuery {
+    pub data_connector: String,
+    /// the NDC query request, with the permissions of the role applied
+    pub request: execute::ndc::NdcQueryRequest,
+    /// how the data connector would run the query, for data connectors that can explain queries
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plan: Option<execute::ndc::NdcExplainResponse>,
+}
+
+/// Explain a read, rather than running it: the queries it would send to data connectors, as they
+/// are planned for the role of the session, with the data connector's own plan for each.
+///
+/// Only roles in `explain_roles` may explain requests, as this shows how models map onto the
+/// data sources. Other roles get the same 404 as for a route that doesn't exist.
+///
+/// Filters on relationships to models of other data connectors are queries of their own, which
+/// are run so that the query explained has their results, as GraphQL explain does. Included
+/// relationships fetched by queries of their own need the rows of the request, so are not
+/// explained.
+pub async fn explain_handler_internal(
+    request_headers: Arc<HeaderMap>,
+    http_context: Arc<HttpContext>,
+    session: Arc<Session>,
+    catalog: &Catalog,
+    configuration: &Configuration,
+    metadata: Arc<Metadata>,
+    uri: Uri,
+    raw_query: &str,
+) -> Result<ResponseDocument, RequestError> {
+    if !configuration.explain_roles.contains(&session.role) {
+        return Err(RequestError::NotFound);
+    }
+    let uri = uri
+        .path()
+        .strip_prefix(EXPLAIN_ROUTE)
+        .and_then(|route| route.parse::<Uri>().ok())
+        .ok_or(RequestError::NotFound)?;
+    with_timeout(
+        configuration.request_timeout,
+        explain_request(
+            &request_headers,
+            &http_context,
+            &session,
+            catalog,
+            configuration,
+            &metadata,
+            &uri,
+            raw_query,
+        ),
+    )
+    .await
+}
+
+async fn explain_request(
+    request_headers: &HeaderMap,
+    http_context: &HttpContext,
+    session: &Session,
+    catalog: &Catalog,
+    configuration: &Configuration,
+    metadata: &Metadata,
+    uri: &Uri,
+    raw_query: &str,
+) -> Result<ResponseDocument, RequestError> {
+    let state = catalog
+        .state_per_role
+        .get(&session.role)
+        .ok_or(RequestError::NotFound)?;
+    let model = validate_route(state, &configuration.naming, uri).ok_or(RequestError::NotFound)?;
+
+    let query_params = parse::QueryParams::parse(raw_query);
+    let query_request = if parse::is_aggregate_route(uri) {
+        parse::create_aggregate_ir(
+            model,
+            &state.object_types,
+            &state.boolean_expression_types,
+            &configuration.naming,
+            &query_params,
+        )?
+        .query_request
+    } else {
+        parse::create_query_ir(
+            configuration,
+            model,
+            &state.object_types,
+            &state.boolean_expression_types,
+            &state.order_by_expressions,
+            uri,
+            &mut RelationshipTree::default(),
+            &jsonapi_library::query::Query::from_params(raw_query),
+            &query_params,
+        )?
+        .query_request
+    };
+
+    let execution_plan =
+        plan::plan_query_request(&query_request, metadata, session, request_headers)
+            .map_err(RequestError::PlanError)?;
+    let open_dds::query::QueryRequest::V1(query_request) = &query_request;
+    let expose_connector_errors = configuration.connector_error_roles.contains(&session.role);
+    let queries = match execution_plan {
+        plan::ExecutionPlan::Queries(queries) => queries,
+        plan::ExecutionPlan::Mutation(_) => {
+            return Err(RequestError::BadRequest(
+                "Only queries can be explained".to_string(),
+            ));
+        }
+    };
+    let queries =
+        futures_util::future::try_join_all(queries.into_iter().map(|(alias, execution_tree)| {
+            explain_query(
+                http_context,
+                execution_tree,
+                process_response_as(query_request, &alias),
+                expose_connector_errors,
+            )
+        }))
+        .await?;
+    Ok(ResponseDocument::Explain(ExplainDocument {
+        meta: ExplainMeta { queries },
+    }))
+}
+
+async fn explain_query(
+    http_context: &HttpContext,
+    execution_tree: QueryExecutionTree,
+    process_response_as: ProcessResponseAs,
+    expose_connector_errors: bool,
+) -> Result<ExplainedQuery, RequestError> {
+    let connector_error = |error: execute::FieldError| {
+        RequestError::ExecuteError(ConnectorError {
+            error,
+            expose_detail: expose_connector_errors,
+        })
+    };
+    let predicates = execute::execute_remote_predicates(
+        &execution_tree.remote_predicates,
+        http_context,
+        "REST",
+        "REST",
+        &process_response_as,
+        None,
+    )
+    .await
+    .map_err(connector_error)?;
+    let query_execution_plan = execute::replace_predicates_in_query_execution_plan(
+        execution_tree.query_execution_plan,
+        &predicates,
+    )
+    .map_err(|error| connector_error(error.into()))?;
+
+    let data_connector = query_execution_plan.data_connector.clone();
+    let request = execute::make_ndc_query_request(query_execution_plan).map_err(connector_error)?;
+    let plan =
+        execute::fetch_explain_from_data_connector(http_context, &request, &data_connector, None)
+            .await
+            .map_err(|error| connector_error(error.into()))?;
+    Ok(ExplainedQuery {
+        data_connector: data_connector.name.to_string(),
+        request,
+        plan,
+    })
+}
@@ -1,134 +1,807 @@
+use std::future::Future;
 use std::sync::Arc;
+use std::time::Duration;
 
+use super::capabilities;
+use super::links;
 use super::parse;
 use super::process_response;
-use super::types::{RelationshipTree, RequestError};
-use crate::catalog::{Catalog, Model, State};
+use super::types::{
+    ConnectorError, InternalError, LinkageDocument, RelationshipTree, RequestError,
+    ResponseDocument,
+};
+use crate::catalog::{model_route, Catalog, Model, State, RESERVED_ROUTE_PREFIX};
+use crate::configuration::Configuration;
+use crate::endpoint::EndPoint;
+use crate::naming::NamingStrategy;
 use axum::http::{HeaderMap, Method, Uri};
 use engine_types::HttpContext;
+use futures_util::{StreamExt, TryStreamExt};
 use hasura_authn_core::Session;
+use indexmap::IndexMap;
 use metadata_resolve::Metadata;
-use plan_types::{NDCQueryExecution, ProcessResponseAs};
-use tracing_util::SpanVisibility;
+use plan_types::{NDCQueryExecution, ProcessResponseAs, QueryExecutionTree};
+use tracing_util::{
+    set_attribute_on_active_span, AttributeValue, AttributeVisibility, SpanVisibility,
+};
 
-#[allow(clippy::unused_async)]
 pub async fn handler_internal(
     request_headers: Arc<HeaderMap>,
     http_context: Arc<HttpContext>,
     session: Arc<Session>,
     catalog: &Catalog,
+    configuration: &Configuration,
     metadata: Arc<Metadata>,
+    endpoint: &EndPoint,
     http_method: Method,
     uri: Uri,
-    query_string: jsonapi_library::query::Query,
-) -> Result<jsonapi_library::api::DocumentData, RequestError> {
-    let tracer = tracing_util::global_tracer();
+    raw_query: &str,
+) -> Result<ResponseDocument, RequestError> {
+    with_timeout(
+        configuration.request_timeout,
+        handle_request(
+            request_headers,
+            http_context,
+            session,
+            catalog,
+            configuration,
+            metadata,
+            endpoint,
+            http_method,
+            uri,
+            raw_query,
+        ),
+    )
+    .await
+}
+
+// runs the request, giving up on it once it has taken longer than the timeout. giving up drops
+// it, and with it any requests to data connectors that are still in flight, which aborts them.
+pub(crate) async fn with_timeout<T>(
+    timeout: Option<Duration>,
+    request: impl Future<Output = Result<T, RequestError>>,
+) -> Result<T, RequestError> {
+    let Some(timeout) = timeout else {
+        return request.await;
+    };
+    tokio::time::timeout(timeout, request)
+        .await
+        .unwrap_or_else(|_elapsed| {
+            set_attribute_on_active_span(
+                AttributeVisibility::Default,
+                "jsonapi.end_reason",
+                "timeout",
+            );
+            Err(RequestError::Timeout { timeout })
+        })
+}
 
+async fn handle_request(
+    request_headers: Arc<HeaderMap>,
+    http_context: Arc<HttpContext>,
+    session: Arc<Session>,
+    catalog: &Catalog,
+    configuration: &Configuration,
+    metadata: Arc<Metadata>,
+    endpoint: &EndPoint,
+    http_method: Method,
+    uri: Uri,
+    raw_query: &str,
+) -> Result<ResponseDocument, RequestError> {
+    let tracer = tracing_util::global_tracer();
     let state = catalog
         .state_per_role
         .get(&session.role)
         .ok_or_else(|| RequestError::NotFound)?;
 
+    // the route must be that of a model the role can access before we look at anything else of
+    // the request, so that unknown types get a 404 however bad their query parameters are
+    let model = validate_route(state, &configuration.naming, &uri).ok_or(RequestError::NotFound)?;
+
+    // what can be read of the model is known from the metadata alone
+    if capabilities::is_capabilities_route(&uri) {
+        return Ok(ResponseDocument::Capabilities(
+            capabilities::capabilities_document(model, state, &configuration.naming),
+        ));
+    }
+
+    let query_string = jsonapi_library::query::Query::from_params(raw_query);
+    let query_params = parse::QueryParams::parse(raw_query);
+    let link_base = links::LinkBase::new(configuration.external_base_url.as_ref(), endpoint);
+
     // relationship tree for processing the response
     let mut relationship_tree = RelationshipTree::default();
 
-    // route matching/validation
-    match validate_route(state, &uri) {
-        None => Err(RequestError::NotFound),
-        Some(model) => {
-            // create the query IR
-            let query_ir = tracer.in_span(
-                "create_query_ir",
-                "Create query IR",
+    if parse::is_aggregate_route(&uri) {
+        let aggregate_ir = tracer.in_span(
+            "create_aggregate_ir",
+            "Create aggregate IR",
+            SpanVisibility::User,
+            || {
+                parse::create_aggregate_ir(
+                    model,
+                    &state.object_types,
+                    &state.boolean_expression_types,
+                    &configuration.naming,
+                    &query_params,
+                )
+            },
+        );
+        let aggregate_ir = aggregate_ir.inspect_err(|error| {
+            set_attribute_on_active_span(
+                AttributeVisibility::Internal,
+                "jsonapi.error_category",
+                error.category(),
+            );
+        })?;
+
+        let rowsets = tracer
+            .in_span_async(
+                "query_engine_execute",
+                "Execute query",
                 SpanVisibility::User,
                 || {
-                    parse::create_query_ir(
-                        model,
-                        &state.object_types,
-                        &http_method,
-                        &uri,
-                        &mut relationship_tree,
-                        &query_string,
-                    )
+                    Box::pin(query_engine_execute(
+                        &aggregate_ir.query_request,
+                        configuration,
+                        &metadata,
+                        &session,
+                        &http_context,
+                        &request_headers,
+                    ))
                 },
-            )?;
-
-            // execute the query with the query-engine
-            let rowsets = tracer
-                .in_span_async(
-                    "query_engine_execute",
-                    "Execute query",
-                    SpanVisibility::User,
-                    || {
-                        Box::pin(query_engine_execute(
-                            &query_ir.query_request,
-                            &metadata,
-                            &session,
-                            &http_context,
-                            &request_headers,
-                        ))
-                    },
+            )
+            .await?
+            .into_iter()
+            .next()
+            .unwrap_or_default();
+
+        let mut document =
+            process_response::aggregate_document(rowsets, &aggregate_ir, &configuration.naming);
+        document.links = Some(links::self_links(&link_base, &uri, raw_query));
+        Ok(ResponseDocument::Resources(document))
+    } else {
+        // create the query IR
+        let query_ir = tracer.in_span(
+            "create_query_ir",
+            "Create query IR",
+            SpanVisibility::User,
+            || {
+                parse::create_query_ir(
+                    configuration,
+                    model,
+                    &state.object_types,
+                    &state.boolean_expression_types,
+                    &state.order_by_expressions,
+                    &uri,
+                    &mut relationship_tree,
+                    &query_string,
+                    &query_params,
                 )
-                .await?;
+            },
+        );
+        let query_ir = match query_ir {
+            Ok(query_ir) => {
+                record_query_shape(model, &query_string, &query_params, &query_ir);
+                query_ir
+            }
+            Err(error) => {
+                set_attribute_on_active_span(
+                    AttributeVisibility::Internal,
+                    "jsonapi.error_category",
+                    error.category(),
+                );
+                return Err(error);
+            }
+        };
 
-            // process result to JSON:API compliant response
-            tracer.in_span(
-                "process_response",
-                "Process response",
+        // execute the query with the query-engine
+        let mut results = tracer
+            .in_span_async(
+                "query_engine_execute",
+                "Execute query",
                 SpanVisibility::User,
                 || {
-                    process_response::process_result(
-                        rowsets,
-                        &query_ir.root_type_name,
-                        &relationship_tree,
-                        &query_string,
-                        &state.object_types,
-                    )
+                    Box::pin(query_engine_execute(
+                        &query_ir.query_request,
+                        configuration,
+                        &metadata,
+                        &session,
+                        &http_context,
+                        &request_headers,
+                    ))
                 },
             )
+            .await?
+            .into_iter();
+        let mut rowsets = results.next().unwrap_or_default();
+
+        // the included relationships fetched by queries of their own, once we have the rows they
+        // are joined to
+        if !query_ir.included_queries.is_empty() {
+            let rows = rowsets
+                .first()
+                .and_then(|rowset| rowset.rows.as_deref())
+                .unwrap_or_default();
+            let included_rows = execute_included_queries(
+                &query_ir.included_queries,
+                rows,
+                configuration,
+                &metadata,
+                &session,
+                &http_context,
+                &request_headers,
+            )
+            .await?;
+            for (included_query, related_rows) in
+                query_ir.included_queries.iter().zip(included_rows)
+            {
+                process_response::join_included_rows(&mut rowsets, included_query, related_rows);
+            }
+        }
+
+        // the count asked for with `page[totals]`, from the second query
+        let meta = results
+            .next()
+            .map(|count_rowsets| {
+                total_count(&count_rowsets).map(|total| {
+                    jsonapi_library::api::Meta::from([(
+                        "total".to_string(),
+                        serde_json::Value::from(total),
+                    )])
+                })
+            })
+            .transpose()?;
+
+        // a full page in cursor mode gets a cursor for the page after its last row
+        let next_cursor = match &query_ir.fetch {
+            parse::Fetch::Collection(parse::Pagination::Cursor { limit, keyset }) => rowsets
+                .first()
+                .and_then(|rowset| rowset.rows.as_ref())
+                .filter(|rows| rows.len() >= *limit)
+                .and_then(|rows| rows.last())
+                .and_then(|row| keyset.next_cursor(&configuration.cursor_secret, row)),
+            parse::Fetch::Collection(parse::Pagination::Offset { .. })
+            | parse::Fetch::Resource(_)
+            | parse::Fetch::Related(_) => None,
+        };
+
+        // large pages without included resources are streamed to the client, rather than
+        // built up as a list of resources and serialized in full
+        if let parse::Fetch::Collection(pagination) = &query_ir.fetch {
+            let row_count = rowsets
+                .first()
+                .and_then(|rowset| rowset.rows.as_ref())
+                .map_or(0, Vec::len);
+            // `HEAD` requests get the `ETag` and `Content-Length` of the full document, which
+            // streamed responses don't have
+            if relationship_tree.relationships.is_empty()
+                && !query_ir.identifiers
+                && http_method != Method::HEAD
+                && configuration
+                    .stream_min_rows
+                    .is_some_and(|stream_min_rows| row_count >= stream_min_rows)
+            {
+                let mut document = process_response::streamed_result(
+                    rowsets,
+                    &query_ir.root_type_name,
+                    &query_ir.root_unique_identifier,
+                    &query_ir.sparse_fields,
+                    &state.object_types,
+                    &configuration.naming,
+                    query_ir.time_zone,
+                    &link_base,
+                    &model_route(&model.name),
+                )?;
+                document.links = Some(links::pagination_links(
+                    &link_base,
+                    &uri,
+                    raw_query,
+                    pagination,
+                    next_cursor.as_deref(),
+                    row_count,
+                ));
+                document.meta = meta;
+                return Ok(ResponseDocument::Streamed(document));
+            }
+        }
+
+        // process result to JSON:API compliant response
+        let mut document = tracer.in_span(
+            "process_response",
+            "Process response",
+            SpanVisibility::User,
+            || {
+                process_response::process_result(
+                    rowsets,
+                    &query_ir.root_type_name,
+                    &query_ir.root_unique_identifier,
+                    &relationship_tree,
+                    &query_ir.sparse_fields,
+                    &state.object_types,
+                    &configuration.naming,
+                    query_ir.time_zone,
+                    &link_base,
+                    &model_route(&model.name),
+                )
+            },
+        )?;
+
+        match &query_ir.fetch {
+            parse::Fetch::Collection(pagination) => {
+                let resource_count = match &document.data {
+                    Some(jsonapi_library::api::PrimaryData::Multiple(resources)) => resources.len(),
+                    _ => 0,
+                };
+                document.links = Some(links::pagination_links(
+                    &link_base,
+                    &uri,
+                    raw_query,
+                    pagination,
+                    next_cursor.as_deref(),
+                    resource_count,
+                ));
+                document.meta = meta;
+                Ok(resources_document(document, query_ir.identifiers))
+            }
+            parse::Fetch::Resource(id) => {
+                // a single resource is the primary data itself, not a list of one
+                let resource = match document.data.take() {
+                    Some(jsonapi_library::api::PrimaryData::Multiple(resources)) => {
+                        resources.into_iter().next()
+                    }
+                    Some(jsonapi_library::api::PrimaryData::Single(resource)) => Some(*resource),
+                    _ => None,
+                };
+                let resource = resource.ok_or_else(|| RequestError::ResourceNotFound {
+                    model_name: model.name.name.clone(),
+                    id: id.clone(),
+                })?;
+                document.data = Some(jsonapi_library::api::PrimaryData::Single(Box::new(
+                    resource,
+                )));
+                document.links = Some(links::self_links(&link_base, &uri, raw_query));
+                Ok(resources_document(document, query_ir.identifiers))
+            }
+            parse::Fetch::Related(related) => {
+                let (linkage, mut document) = process_response::related_document(
+                    document,
+                    &configuration.naming.field_name(&related.relationship),
+                    &related.relationship_type,
+                )
+                .ok_or_else(|| RequestError::ResourceNotFound {
+                    model_name: model.name.name.clone(),
+                    id: related.id.clone(),
+                })?;
+                let mut document_links = match &related.pagination {
+                    Some(pagination) => {
+                        let resource_count = match &linkage {
+                            jsonapi_library::model::IdentifierData::Multiple(identifiers) => {
+                                identifiers.len()
+                            }
+                            _ => 0,
+                        };
+                        links::pagination_links(
+                            &link_base,
+                            &uri,
+                            raw_query,
+                            pagination,
+                            None,
+                            resource_count,
+                        )
+                    }
+                    None => links::self_links(&link_base, &uri, raw_query),
+                };
+                if related.linkage {
+                    document_links
+                        .insert("related".to_string(), links::related_link(&link_base, &uri));
+                    Ok(ResponseDocument::Linkage(LinkageDocument {
+                        data: linkage,
+                        links: document_links,
+                        included: None,
+                        meta: None,
+                    }))
+                } else {
+                    document.links = Some(document_links);
+                    Ok(ResponseDocument::Resources(document))
+                }
+            }
         }
     }
 }
 
-fn validate_route<'a>(state: &'a State, uri: &'a Uri) -> Option<&'a Model> {
-    // TODO: to_string() maybe not optimal. Optimize later
-    let uri_s = uri.to_string();
-    for (route, model) in &state.routes {
-        if uri_s.starts_with(route) {
-            return Some(model);
+// the document of resources as primary data, or of just their identifiers for `data=identifiers`
+fn resources_document(
+    document: jsonapi_library::api::DocumentData,
+    identifiers: bool,
+) -> ResponseDocument {
+    if identifiers {
+        ResponseDocument::Linkage(process_response::identifiers_document(document))
+    } else {
+        ResponseDocument::Resources(document)
+    }
+}
+
+// record what the request asked for on the request span, to help with debugging slow queries
+fn record_query_shape(
+    model: &Model,
+    query_string: &jsonapi_library::query::Query,
+    query_params: &parse::QueryParams,
+    query_ir: &parse::QueryIR,
+) {
+    let include_count = query_string.include.as_ref().map_or(0, Vec::len);
+    let has_filter = query_params
+        .iter()
+        .any(|(parameter, _)| parameter.starts_with("filter"));
+    let has_sort = query_string
+        .sort
+        .as_ref()
+        .is_some_and(|sort| !sort.is_empty());
+    let (pagination, page_offset) = match &query_ir.fetch {
+        parse::Fetch::Collection(parse::Pagination::Offset { offset, .. }) => {
+            ("offset", Some(*offset))
         }
+        parse::Fetch::Collection(parse::Pagination::Cursor { .. }) => ("cursor", None),
+        parse::Fetch::Related(parse::Related {
+            pagination: Some(parse::Pagination::Offset { offset, .. }),
+            ..
+        }) => ("offset", Some(*offset)),
+        parse::Fetch::Related(_) | parse::Fetch::Resource(_) => ("none", None),
+    };
+
+    let set_attribute = |key: &'static str, value: AttributeValue| {
+        set_attribute_on_active_span(AttributeVisibility::Internal, key, value);
+    };
+    set_attribute("jsonapi.model", model.name.to_string().into());
+    set_attribute("jsonapi.type", model.data_type.to_string().into());
+    set_attribute("jsonapi.include_count", count(include_count));
+    set_attribute("jsonapi.has_filter", has_filter.into());
+    set_attribute("jsonapi.has_sort", has_sort.into());
+    set_attribute("jsonapi.pagination", pagination.into());
+    set_attribute("jsonapi.totals", query_ir.totals.into());
+    if let parse::Fetch::Collection(pagination)
+    | parse::Fetch::Related(parse::Related {
+        pagination: Some(pagination),
+        ..
+    }) = &query_ir.fetch
+    {
+        set_attribute("jsonapi.page_limit", count(pagination.limit()));
+    }
+    if let Some(page_offset) = page_offset {
+        set_attribute("jsonapi.page_offset", count(page_offset));
+    }
+}
+
+fn count(value: usize) -> AttributeValue {
+    i64::try_from(value).unwrap_or(i64::MAX).into()
+}
+
+/// The JSON:API type of the resources a request is for, eg to label its metrics with. `None` if
+/// the route is not that of a model the role of the session can access.
+pub fn request_resource_type(
+    catalog: &Catalog,
+    naming: &NamingStrategy,
+    session: &Session,
+    uri: &Uri,
+) -> Option<String> {
+    let state = catalog.state_per_role.get(&session.role)?;
+    validate_route(state, naming, uri).map(|model| naming.resource_type(&model.data_type))
+}
+
+// routes are `/{subgraph}/{model}`, and may be followed by the `id` of a resource. the model may
+// be named in the case of the naming strategy. routes of the endpoint itself, like `/__schema`,
+// are never those of a model.
+pub(crate) fn validate_route<'a>(
+    state: &'a State,
+    naming: &NamingStrategy,
+    uri: &Uri,
+) -> Option<&'a Model> {
+    let mut segments = uri.path().split('/').filter(|segment| !segment.is_empty());
+    let (subgraph, model_name) = (segments.next()?, segments.next()?);
+    if [subgraph, model_name]
+        .iter()
+        .any(|segment| segment.starts_with(RESERVED_ROUTE_PREFIX))
+    {
+        return None;
     }
-    None
+    state
+        .routes
+        .get(&format!("/{subgraph}/{model_name}"))
+        .or_else(|| {
+            let subgraph_routes = format!("/{subgraph}/");
+            state
+                .routes
+                .iter()
+                .find(|(route, model)| {
+                    route.starts_with(&subgraph_routes)
+                        && naming.is_model_name(model.name.name.as_str(), model_name)
+                })
+                .map(|(_, model)| model)
+        })
 }
 
+// execute the queries of the request, returning the rowsets of each of them in the order they
+// are in the request. each query is a request to its data connector, and they run concurrently.
 async fn query_engine_execute(
-    query_ir: &open_dds::query::QueryRequest,
+    query_request: &open_dds::query::QueryRequest,
+    configuration: &Configuration,
     metadata: &Metadata,
     session: &Session,
     http_context: &Arc<HttpContext>,
     request_headers: &HeaderMap,
-) -> Result<Vec<ndc_models::RowSet>, RequestError> {
-    let execution_plan = plan::plan_query_request(query_ir, metadata, session, request_headers)
-        .map_err(RequestError::PlanError)?;
+) -> Result<Vec<Vec<ndc_models::RowSet>>, RequestError> {
+    let execution_plan =
+        plan::plan_query_request(query_request, metadata, session, request_headers)
+            .map_err(RequestError::PlanError)?;
+    let open_dds::query::QueryRequest::V1(query_request) = query_request;
+    let expose_connector_errors = configuration.connector_error_roles.contains(&session.role);
     match execution_plan {
-        plan::ExecutionPlan::Queries(queries) => match queries.first() {
-            Some((_alias, execution_tree)) => {
-                let ndc_query_execution = NDCQueryExecution {
-                    execution_span_attribute: "REST",
-                    execution_tree: execution_tree.clone(),
-                    field_span_attribute: "REST".into(),
-                    process_response_as: ProcessResponseAs::Array { is_nullable: false },
-                };
-                Ok(
-                    execute::resolve_ndc_query_execution(http_context, ndc_query_execution, None)
-                        .await
-                        .map_err(RequestError::ExecuteError)?,
-                )
+        plan::ExecutionPlan::Queries(queries) => {
+            if queries.is_empty() {
+                return Err(RequestError::InternalError(InternalError::EmptyQuerySet));
             }
-            None => todo!("handle empty query result in JSONAPI"),
-        },
+            futures_util::future::try_join_all(queries.into_iter().map(
+                |(alias, execution_tree)| {
+                    data_connector_query(
+                        http_context,
+                        execution_tree,
+                        process_response_as(query_request, &alias),
+                        expose_connector_errors,
+                    )
+                },
+            ))
+            .await
+        }
         plan::ExecutionPlan::Mutation(_) => {
             todo!("Executing mutations not implemented in JSONAPI yet")
         }
     }
 }
+
+// the rows of aggregate queries are their aggregates, and those of other queries are resources
+pub(crate) fn process_response_as(
+    query_request: &open_dds::query::QueryRequestV1,
+    alias: &open_dds::query::Alias,
+) -> ProcessResponseAs {
+    match query_request.queries.get(alias) {
+        Some(open_dds::query::Query::ModelAggregate(_)) => ProcessResponseAs::Aggregates,
+        _ => ProcessResponseAs::Array { is_nullable: false },
+    }
+}
+
+// run the queries of included relationships, at most `included_query_concurrency` of them at once,
+// each in a span of its own under that of the request. the queries don't wait on one another, as
+// relationships nested in an included relationship are fetched by its query, so the bound cannot
+// deadlock.
+async fn execute_included_queries(
+    included_queries: &[parse::IncludedQuery],
+    rows: &[IndexMap<ndc_models::FieldName, ndc_models::RowFieldValue>],
+    configuration: &Configuration,
+    metadata: &Metadata,
+    session: &Session,
+    http_context: &Arc<HttpContext>,
+    request_headers: &HeaderMap,
+) -> Result<Vec<Vec<IndexMap<ndc_models::FieldName, ndc_models::RowFieldValue>>>, RequestError> {
+    let tracer = tracing_util::global_tracer();
+    futures_util::stream::iter(included_queries)
+        .map(|included_query| {
+            tracer.in_span_async(
+                "included_query",
+                format!(
+                    "Fetch included relationship {}",
+                    included_query.relationship.as_str()
+                ),
+                SpanVisibility::User,
+                || {
+                    Box::pin(execute_included_query(
+                        included_query,
+                        rows,
+                        configuration,
+                        metadata,
+                        session,
+                        http_context,
+                        request_headers,
+                    ))
+                },
+            )
+        })
+        .buffered(configuration.included_query_concurrency.get())
+        .try_collect()
+        .await
+}
+
+// the related rows of an included relationship, for all of `rows`
+async fn execute_included_query(
+    included_query: &parse::IncludedQuery,
+    rows: &[IndexMap<ndc_models::FieldName, ndc_models::RowFieldValue>],
+    configuration: &Configuration,
+    metadata: &Metadata,
+    session: &Session,
+    http_context: &Arc<HttpContext>,
+    request_headers: &HeaderMap,
+) -> Result<Vec<IndexMap<ndc_models::FieldName, ndc_models::RowFieldValue>>, RequestError> {
+    // none of the rows has related rows
+    let Some(query_request) = included_query.query_request(rows) else {
+        return Ok(vec![]);
+    };
+    let results = query_engine_execute(
+        &query_request,
+        configuration,
+        metadata,
+        session,
+        http_context,
+        request_headers,
+    )
+    .await?;
+    Ok(results
+        .into_iter()
+        .next()
+        .and_then(|rowsets| rowsets.into_iter().next())
+        .and_then(|rowset| rowset.rows)
+        .unwrap_or_default())
+}
+
+async fn data_connector_query(
+    http_context: &Arc<HttpContext>,
+    execution_tree: QueryExecutionTree,
+    process_response_as: ProcessResponseAs,
+    expose_connector_errors: bool,
+) -> Result<Vec<ndc_models::RowSet>, RequestError> {
+    let data_connector_name = execution_tree
+        .query_execution_plan
+        .data_connector
+        .name
+        .to_string();
+    let ndc_query_execution = NDCQueryExecution {
+        execution_span_attribute: "REST",
+        execution_tree,
+        field_span_attribute: "REST".into(),
+        process_response_as,
+    };
+    // a span of its own, so that connector latency can be read directly from traces
+    let tracer = tracing_util::global_tracer();
+    tracer
+        .in_span_async(
+            "data_connector_query",
+            format!("Query data connector {data_connector_name}"),
+            SpanVisibility::User,
+            || {
+                Box::pin(async move {
+                    set_attribute_on_active_span(
+                        AttributeVisibility::Default,
+                        "data_connector",
+                        data_connector_name,
+                    );
+                    let rowsets = execute::resolve_ndc_query_execution(
+                        http_context,
+                        ndc_query_execution,
+                        None,
+                    )
+                    .await
+                    .map_err(|error| {
+                        RequestError::ExecuteError(ConnectorError {
+                            error,
+                            expose_detail: expose_connector_errors,
+                        })
+                    })?;
+                    let row_count = rowsets
+                        .iter()
+                        .map(|rowset| rowset.rows.as_ref().map_or(0, Vec::len))
+                        .sum();
+                    set_attribute_on_active_span(
+                        AttributeVisibility::Default,
+                        "row_count",
+                        count(row_count),
+                    );
+                    Ok(rowsets)
+                })
+            },
+        )
+        .await
+}
+
+// the number of resources counted by the `page[totals]` query
+fn total_count(rowsets: &[ndc_models::RowSet]) -> Result<u64, RequestError> {
+    rowsets
+        .first()
+        .and_then(|rowset| rowset.aggregates.as_ref())
+        .and_then(|aggregates| {
+            aggregates.get(&ndc_models::FieldName::from(parse::TOTAL_COUNT_FIELD))
+        })
+        .and_then(serde_json::Value::as_u64)
+        .ok_or(RequestError::InternalError(
+            InternalError::MissingTotalCount,
+        ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use metadata_resolve::Qualified;
+    use open_dds::{data_connector::DataConnectorName, models::ModelName, types::CustomTypeName};
+    use std::collections::BTreeMap;
+    use std::str::FromStr;
+
+    #[tokio::test]
+    async fn test_with_timeout() {
+        let timeout = Duration::from_millis(10);
+
+        let result = with_timeout(Some(timeout), std::future::pending::<Result<(), _>>()).await;
+        assert!(matches!(result, Err(RequestError::Timeout { timeout: t }) if t == timeout));
+
+        let result = with_timeout(Some(timeout), async { Ok(1) }).await;
+        assert!(matches!(result, Ok(1)));
+
+        let result = with_timeout(None, async { Ok(2) }).await;
+        assert!(matches!(result, Ok(2)));
+    }
+
+    #[test]
+    fn test_validate_route() {
+        use crate::naming::NameCase;
+        use open_dds::identifier::{Identifier, SubgraphName};
+
+        let subgraph = SubgraphName::try_new("default").unwrap();
+        let model_name = Qualified::new(
+            subgraph.clone(),
+            ModelName::new(Identifier::new("InvoiceLines").unwrap()),
+        );
+        let model = Model {
+            name: model_name.clone(),
+            description: None,
+            data_type: Qualified::new(
+                subgraph.clone(),
+                CustomTypeName(Identifier::new("InvoiceLine").unwrap()),
+            ),
+            data_connector_name: Qualified::new(
+                subgraph,
+                DataConnectorName::new(Identifier::new("db").unwrap()),
+            ),
+            filter_expression_type: None,
+            unique_identifier: vec![],
+            supports_aggregates: false,
+            supports_grouping: false,
+            order_by_expression: None,
+        };
+        let state = State {
+            routes: BTreeMap::from_iter([(model_route(&model_name), model)]),
+            object_types: BTreeMap::new(),
+            boolean_expression_types: BTreeMap::new(),
+            order_by_expressions: BTreeMap::new(),
+        };
+        let route = |naming: &NamingStrategy, path: &str| {
+            validate_route(&state, naming, &Uri::from_str(path).unwrap())
+                .map(|model| model.name.name.to_string())
+        };
+
+        // without a naming case, routes are case sensitive
+        let naming = NamingStrategy::default();
+        assert_eq!(
+            route(&naming, "/default/InvoiceLines/1").as_deref(),
+            Some("InvoiceLines")
+        );
+        assert_eq!(route(&naming, "/default/invoice-lines"), None);
+        assert_eq!(route(&naming, "/default/invoicelines"), None);
+        assert_eq!(route(&naming, "/other/InvoiceLines"), None);
+        assert_eq!(route(&naming, "/default"), None);
+
+        // with one, the model may also be named in it
+        let naming = NamingStrategy {
+            case: NameCase::KebabCase,
+            pluralize_types: false,
+        };
+        assert_eq!(
+            route(&naming, "/default/invoice-lines").as_deref(),
+            Some("InvoiceLines")
+        );
+        assert_eq!(
+            route(&naming, "/default/InvoiceLines").as_deref(),
+            Some("InvoiceLines")
+        );
+        assert_eq!(route(&naming, "/other/invoice-lines"), None);
+
+        // the routes of the endpoint itself are never those of a model
+        assert_eq!(route(&naming, "/__schema/InvoiceLines"), None);
+        assert_eq!(route(&naming, "/default/__schema"), None);
+    }
+}
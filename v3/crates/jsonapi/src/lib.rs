@@ -1,20 +1,59 @@
+mod batch;
+mod capabilities;
 mod catalog;
+mod configuration;
 mod endpoint;
+mod explain;
 mod handler;
 mod helpers;
+mod links;
+mod metrics;
 mod middleware;
+mod naming;
 mod parse;
 mod process_response;
+mod rate_limit;
+mod response;
 mod schema;
 mod types;
 
 // explicit exports
+pub use batch::{
+    atomic_handler_internal, batch_handler_internal, AtomicOperation, AtomicRequest,
+    AtomicResponse, BatchDocument, BatchOperation, BatchRequest, BatchResponse, BatchResult,
+    BATCH_ROUTE, OPERATIONS_ROUTE,
+};
+pub use capabilities::{
+    ArgumentCapabilities, Capabilities, CapabilitiesDocument, CapabilitiesMeta,
+    RelationshipCapabilities, CAPABILITIES_ROUTE,
+};
 pub use catalog::Catalog;
+pub use configuration::{
+    Configuration, CursorSecret, PaginationMode, RateLimit, DEFAULT_COMPRESSION_MIN_SIZE,
+    DEFAULT_INCLUDED_QUERY_CONCURRENCY, DEFAULT_MAX_BATCH_OPERATIONS, DEFAULT_MAX_INCLUDE_DEPTH,
+    DEFAULT_MAX_INCLUDE_PATHS, DEFAULT_MAX_PAGE_LIMIT, DEFAULT_PAGE_LIMIT, DEFAULT_STREAM_MIN_ROWS,
+};
 pub use endpoint::EndPoint;
-pub use handler::handler_internal;
+pub use explain::{
+    explain_handler_internal, ExplainDocument, ExplainMeta, ExplainedQuery, EXPLAIN_ROUTE,
+};
+pub use handler::{handler_internal, request_resource_type};
+pub use metrics::{
+    JsonApiMetrics, NoOpJsonApiMetrics, OpenTelemetryJsonApiMetrics, RequestMetrics,
+    ResponseMetrics, JSONAPI_METER_NAME,
+};
 pub use middleware::{
-    build_state_with_middleware_error_converter, jsonapi_request_tracing_middleware,
+    build_state_with_middleware_error_converter, jsonapi_content_negotiation_middleware,
+    jsonapi_rate_limit_middleware, jsonapi_request_tracing_middleware, MediaTypeExtensions,
 };
-pub use parse::ParseError;
+pub use naming::{NameCase, NamingStrategy};
+pub use parse::{ParseError, AGGREGATE_ROUTE};
+pub use process_response::StreamedDocument;
+pub use rate_limit::RateLimiter;
+pub use response::{document_response, with_allowed_methods, READ_METHODS};
 pub use schema::{empty_schema, openapi_schema};
-pub use types::{InternalError, JsonApiHttpError, ModelInfo, RequestError};
+pub use types::{
+    ConnectorError, ErrorCode, ErrorSource, InternalError, JsonApiErrorObject, JsonApiHttpError,
+    JsonApiObject, LinkageDocument, ModelInfo, ParameterError, RequestError, ResponseDocument,
+    WithJsonApiObject, ATOMIC_EXTENSION, JSONAPI_MEDIA_TYPE, JSONAPI_VERSION,
+};
@@ -0,0 +1,366 @@
+use crate::endpoint::EndPoint;
+use crate::parse::Pagination;
+use axum::http::Uri;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+
+const PAGE_LIMIT: &str = "page[limit]";
+const PAGE_OFFSET: &str = "page[offset]";
+const PAGE_CURSOR: &str = "page[cursor]";
+
+// the characters escaped in the `id` of a resource in its route: the path percent-encode set,
+// plus `/` and `%`, so that the `id` stays one segment and decodes back to itself, and `,`, which
+// separates the values of composite ids
+// spec: <https://url.spec.whatwg.org/#path-percent-encode-set>
+const PATH_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}')
+    .add(b'/')
+    .add(b'%')
+    .add(b',');
+
+/// What every link starts with: the external base URL of the engine, when one is configured,
+/// followed by the endpoint. All links are built from this, so that they are correct behind a
+/// reverse proxy.
+#[derive(Debug, Clone)]
+pub struct LinkBase(String);
+
+impl LinkBase {
+    pub fn new(external_base_url: Option<&url::Url>, endpoint: &EndPoint) -> Self {
+        let external_base_url =
+            external_base_url.map_or("", |base_url| base_url.as_str().trim_end_matches('/'));
+        Self(format!("{external_base_url}{}", endpoint.as_str()))
+    }
+
+    // the URL of a route, eg `/default/Articles`
+    fn url(&self, route: &str) -> String {
+        format!("{}{route}", self.0)
+    }
+}
+
+// top-level pagination links for a listing request. every query parameter other than the
+// `page[..]` ones is preserved as it was sent, so following a link keeps the same filters, sorts,
+// sparse fields and includes.
+// spec: <https://jsonapi.org/format/#fetching-pagination>
+//
+// `next` is omitted when we returned fewer resources than the limit, as there is nothing more
+// to fetch. in cursor mode it carries the `next_cursor` token instead of an offset, and there is
+// no `prev`, as cursors only point forwards.
+pub fn pagination_links(
+    link_base: &LinkBase,
+    uri: &Uri,
+    raw_query: &str,
+    pagination: &Pagination,
+    next_cursor: Option<&str>,
+    resource_count: usize,
+) -> jsonapi_library::api::Links {
+    let path = link_base.url(uri.path());
+
+    let other_params = raw_query
+        .split('&')
+        .filter(|param| !param.is_empty() && !is_page_param(param))
+        .collect::<Vec<_>>();
+
+    let page_link = |page_param: String| {
+        let mut params = other_params.clone();
+        let limit_param = format!("{PAGE_LIMIT}={}", pagination.limit());
+        params.push(&limit_param);
+        params.push(&page_param);
+        serde_json::Value::String(format!("{path}?{}", params.join("&")))
+    };
+
+    let mut links = vec![("self", self_link(link_base, uri, raw_query))];
+    match pagination {
+        Pagination::Offset { limit, offset } => {
+            links.push(("first", page_link(format!("{PAGE_OFFSET}=0"))));
+            if *offset > 0 {
+                let prev_offset = offset.saturating_sub(*limit);
+                links.push(("prev", page_link(format!("{PAGE_OFFSET}={prev_offset}"))));
+            }
+            if resource_count >= *limit {
+                let next_offset = offset + limit;
+                links.push(("next", page_link(format!("{PAGE_OFFSET}={next_offset}"))));
+            }
+        }
+        Pagination::Cursor { .. } => {
+            links.push(("first", page_link(format!("{PAGE_CURSOR}="))));
+            if let Some(next_cursor) = next_cursor {
+                links.push(("next", page_link(format!("{PAGE_CURSOR}={next_cursor}"))));
+            }
+        }
+    }
+
+    links
+        .into_iter()
+        .map(|(name, link)| (name.to_string(), link))
+        .collect()
+}
+
+// the link to the request as it was sent
+pub fn self_link(link_base: &LinkBase, uri: &Uri, raw_query: &str) -> serde_json::Value {
+    let path = link_base.url(uri.path());
+    serde_json::Value::String(if raw_query.is_empty() {
+        path
+    } else {
+        format!("{path}?{raw_query}")
+    })
+}
+
+// links with only the link to the request itself
+pub fn self_links(link_base: &LinkBase, uri: &Uri, raw_query: &str) -> jsonapi_library::api::Links {
+    [("self".to_string(), self_link(link_base, uri, raw_query))]
+        .into_iter()
+        .collect()
+}
+
+// the `self` link of a resource object, ie the route of the resource in its model
+// spec: <https://jsonapi.org/format/#document-resource-object-links>
+pub fn resource_links(
+    link_base: &LinkBase,
+    model_route: &str,
+    id: &str,
+) -> jsonapi_library::api::Links {
+    let route = format!("{model_route}/{}", utf8_percent_encode(id, PATH_SEGMENT));
+    [(
+        "self".to_string(),
+        serde_json::Value::String(link_base.url(&route)),
+    )]
+    .into_iter()
+    .collect()
+}
+
+// the related resources of the relationship route `/{id}/relationships/{relationship}`, ie the
+// same route without the `relationships` segment
+// spec: <https://jsonapi.org/format/#document-resource-object-relationships>
+pub fn related_link(link_base: &LinkBase, uri: &Uri) -> serde_json::Value {
+    let path = uri.path();
+    let related_path = match path.rsplit_once("/relationships/") {
+        Some((resource_path, relationship)) => format!("{resource_path}/{relationship}"),
+        None => path.to_string(),
+    };
+    serde_json::Value::String(link_base.url(&related_path))
+}
+
+// is this `key=value` pair one of the pagination params? the brackets may arrive percent-encoded
+fn is_page_param(param: &str) -> bool {
+    let key = param.split_once('=').map_or(param, |(key, _)| key);
+    let key = key
+        .replace("%5B", "[")
+        .replace("%5b", "[")
+        .replace("%5D", "]")
+        .replace("%5d", "]");
+    key == PAGE_LIMIT || key == PAGE_OFFSET || key == PAGE_CURSOR
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn link_base() -> LinkBase {
+        LinkBase::new(None, &EndPoint::V1Rest)
+    }
+
+    fn link(links: &jsonapi_library::api::Links, name: &str) -> Option<String> {
+        links
+            .get(name)
+            .and_then(|link| link.as_str())
+            .map(ToString::to_string)
+    }
+
+    #[test]
+    fn test_pagination_links_preserve_other_params() {
+        let uri = Uri::from_str("/default/Articles").unwrap();
+        let links = pagination_links(
+            &link_base(),
+            &uri,
+            "fields[Articles]=title&page%5Blimit%5D=10&page[offset]=20&sort=-title",
+            &Pagination::Offset {
+                limit: 10,
+                offset: 20,
+            },
+            None,
+            10,
+        );
+
+        assert_eq!(
+            link(&links, "self").unwrap(),
+            "/v1/rest/default/Articles?fields[Articles]=title&page%5Blimit%5D=10&page[offset]=20&sort=-title"
+        );
+        assert_eq!(
+            link(&links, "first").unwrap(),
+            "/v1/rest/default/Articles?fields[Articles]=title&sort=-title&page[limit]=10&page[offset]=0"
+        );
+        assert_eq!(
+            link(&links, "prev").unwrap(),
+            "/v1/rest/default/Articles?fields[Articles]=title&sort=-title&page[limit]=10&page[offset]=10"
+        );
+        assert_eq!(
+            link(&links, "next").unwrap(),
+            "/v1/rest/default/Articles?fields[Articles]=title&sort=-title&page[limit]=10&page[offset]=30"
+        );
+    }
+
+    #[test]
+    fn test_pagination_links_first_and_last_page() {
+        let uri = Uri::from_str("/default/Articles").unwrap();
+
+        // first page: no `prev`
+        let first_page = pagination_links(
+            &link_base(),
+            &uri,
+            "",
+            &Pagination::Offset {
+                limit: 20,
+                offset: 0,
+            },
+            None,
+            20,
+        );
+        assert_eq!(
+            link(&first_page, "self").unwrap(),
+            "/v1/rest/default/Articles"
+        );
+        assert_eq!(link(&first_page, "prev"), None);
+        assert!(link(&first_page, "next").is_some());
+
+        // fewer resources than the limit: no `next`
+        let last_page = pagination_links(
+            &link_base(),
+            &uri,
+            "page[offset]=5",
+            &Pagination::Offset {
+                limit: 20,
+                offset: 5,
+            },
+            None,
+            3,
+        );
+        assert_eq!(
+            link(&last_page, "prev").unwrap(),
+            "/v1/rest/default/Articles?page[limit]=20&page[offset]=0"
+        );
+        assert_eq!(link(&last_page, "next"), None);
+    }
+
+    #[test]
+    fn test_pagination_links_cursor() {
+        let uri = Uri::from_str("/default/Articles").unwrap();
+        let keyset = crate::parse::Keyset::new(
+            &metadata_resolve::Qualified::new(
+                open_dds::identifier::SubgraphName::try_new("default").unwrap(),
+                open_dds::models::ModelName::new(
+                    open_dds::identifier::Identifier::new("Articles").unwrap(),
+                ),
+            ),
+            &[],
+            &[open_dds::types::FieldName::new(
+                open_dds::identifier::Identifier::new("article_id").unwrap(),
+            )],
+        )
+        .unwrap();
+        let pagination = Pagination::Cursor { limit: 2, keyset };
+
+        let links = pagination_links(
+            &link_base(),
+            &uri,
+            "sort=title&page[cursor]=abc.def",
+            &pagination,
+            Some("ghi.jkl"),
+            2,
+        );
+        assert_eq!(
+            link(&links, "first").unwrap(),
+            "/v1/rest/default/Articles?sort=title&page[limit]=2&page[cursor]="
+        );
+        assert_eq!(link(&links, "prev"), None);
+        assert_eq!(
+            link(&links, "next").unwrap(),
+            "/v1/rest/default/Articles?sort=title&page[limit]=2&page[cursor]=ghi.jkl"
+        );
+
+        // no cursor for the last page
+        let last_page = pagination_links(&link_base(), &uri, "", &pagination, None, 1);
+        assert_eq!(link(&last_page, "next"), None);
+    }
+
+    #[test]
+    fn test_related_link() {
+        let uri = Uri::from_str("/default/Artist/1/relationships/Albums").unwrap();
+        assert_eq!(
+            related_link(&link_base(), &uri),
+            "/v1/rest/default/Artist/1/Albums"
+        );
+
+        // an `id` that happens to be `relationships`
+        let uri = Uri::from_str("/default/Tag/relationships/relationships/Articles").unwrap();
+        assert_eq!(
+            related_link(&link_base(), &uri),
+            "/v1/rest/default/Tag/relationships/Articles"
+        );
+    }
+
+    #[test]
+    fn test_resource_links() {
+        assert_eq!(
+            link(
+                &resource_links(&link_base(), "/default/Articles", "1"),
+                "self"
+            )
+            .unwrap(),
+            "/v1/rest/default/Articles/1"
+        );
+        // ids are escaped as path segments
+        assert_eq!(
+            link(
+                &resource_links(&link_base(), "/default/Tags", "a/b c"),
+                "self"
+            )
+            .unwrap(),
+            "/v1/rest/default/Tags/a%2Fb%20c"
+        );
+        // including commas, which would otherwise split the id into the values of a composite one
+        assert_eq!(
+            link(
+                &resource_links(&link_base(), "/default/Tags", "Rock, Roll"),
+                "self"
+            )
+            .unwrap(),
+            "/v1/rest/default/Tags/Rock%2C%20Roll"
+        );
+
+        // behind a reverse proxy, links start with the external base URL
+        let external_base_url = url::Url::parse("https://example.com/engine/").unwrap();
+        let link_base = LinkBase::new(Some(&external_base_url), &EndPoint::V1Rest);
+        assert_eq!(
+            link(
+                &resource_links(&link_base, "/default/Articles", "1"),
+                "self"
+            )
+            .unwrap(),
+            "https://example.com/engine/v1/rest/default/Articles/1"
+        );
+        let uri = Uri::from_str("/default/Articles").unwrap();
+        assert_eq!(
+            self_link(&link_base, &uri, "page[limit]=10"),
+            "https://example.com/engine/v1/rest/default/Articles?page[limit]=10"
+        );
+
+        // routes mounted at a path of the embedder's choosing
+        let link_base = LinkBase::new(None, &EndPoint::Custom("/api/rest".into()));
+        assert_eq!(
+            link(
+                &resource_links(&link_base, "/default/Articles", "1"),
+                "self"
+            )
+            .unwrap(),
+            "/api/rest/default/Articles/1"
+        );
+    }
+}
@@ -0,0 +1,269 @@
+use std::time::Duration;
+
+use axum::http::{Method, StatusCode};
+use opentelemetry::metrics::{Counter, Histogram, Meter};
+use opentelemetry::KeyValue;
+
+/// the name of the meter JSON:API metrics are recorded with, by default
+pub static JSONAPI_METER_NAME: &str = "engine-jsonapi";
+
+/// Trait defining metrics tracking behavior for JSON:API requests, eg as Prometheus counters and
+/// histograms.
+///
+/// Everything passed to it is low-cardinality, so it can be used as labels as is: there are no
+/// resource ids, filters or other parts of the query string.
+pub trait JsonApiMetrics: Clone + Send + Sync + 'static {
+    /// Records a JSON:API request, once its response is ready
+    fn record_request(&self, request: &RequestMetrics<'_>);
+}
+
+/// What is recorded for a JSON:API request
+#[derive(Debug)]
+pub struct RequestMetrics<'a> {
+    /// the JSON:API type of the requested resources, eg `default_Articles`. `None` for requests
+    /// that are not for the resources of a model, like `__schema` and `__batch`, and for routes
+    /// that match no model.
+    pub resource_type: Option<&'a str>,
+    pub method: &'a Method,
+    pub status: StatusCode,
+    /// time until the response was ready to be sent. for streamed responses, this does not
+    /// include writing out the body.
+    pub duration: Duration,
+    /// number of resources (or resource identifiers) in the primary data. `None` for responses
+    /// without a document, like errors.
+    pub rows: Option<usize>,
+}
+
+/// Labels the handler puts on its response for `jsonapi_request_tracing_middleware`, which
+/// records the metrics of the request
+#[derive(Debug, Clone)]
+pub struct ResponseMetrics {
+    pub resource_type: Option<String>,
+    pub rows: Option<usize>,
+}
+
+/// A no-operation implementation of `JsonApiMetrics`
+#[derive(Clone)]
+pub struct NoOpJsonApiMetrics;
+
+// Does nothing for all metric recording methods.
+impl JsonApiMetrics for NoOpJsonApiMetrics {
+    fn record_request(&self, _request: &RequestMetrics<'_>) {}
+}
+
+/// `JsonApiMetrics` recorded as OpenTelemetry instruments:
+///  - `jsonapi.requests`: a counter of requests
+///  - `jsonapi.request.duration`: a histogram of the durations of requests, in seconds
+///  - `jsonapi.response.rows`: a histogram of the rows of responses with a document
+///
+/// Each is labelled with the HTTP method and status of the request, and with its resource type if
+/// it has one.
+#[derive(Clone)]
+pub struct OpenTelemetryJsonApiMetrics {
+    requests: Counter<u64>,
+    duration: Histogram<f64>,
+    rows: Histogram<u64>,
+}
+
+impl OpenTelemetryJsonApiMetrics {
+    pub fn new(meter: &Meter) -> Self {
+        Self {
+            requests: meter
+                .u64_counter("jsonapi.requests")
+                .with_description("Number of JSON:API requests")
+                .with_unit("{request}")
+                .init(),
+            duration: meter
+                .f64_histogram("jsonapi.request.duration")
+                .with_description("Time until the response of a JSON:API request is ready")
+                .with_unit("s")
+                .init(),
+            rows: meter
+                .u64_histogram("jsonapi.response.rows")
+                .with_description("Number of resources in the primary data of a JSON:API response")
+                .with_unit("{row}")
+                .init(),
+        }
+    }
+}
+
+/// Records with the global meter provider, as traces are sent to the global tracer provider. Until
+/// one is set, nothing is recorded.
+impl Default for OpenTelemetryJsonApiMetrics {
+    fn default() -> Self {
+        Self::new(&opentelemetry::global::meter(JSONAPI_METER_NAME))
+    }
+}
+
+impl JsonApiMetrics for OpenTelemetryJsonApiMetrics {
+    fn record_request(&self, request: &RequestMetrics<'_>) {
+        let mut attributes = vec![
+            KeyValue::new("http.request.method", request.method.as_str().to_string()),
+            KeyValue::new(
+                "http.response.status_code",
+                i64::from(request.status.as_u16()),
+            ),
+        ];
+        if let Some(resource_type) = request.resource_type {
+            attributes.push(KeyValue::new(
+                "jsonapi.resource_type",
+                resource_type.to_string(),
+            ));
+        }
+        self.requests.add(1, &attributes);
+        self.duration
+            .record(request.duration.as_secs_f64(), &attributes);
+        if let Some(rows) = request.rows {
+            self.rows
+                .record(u64::try_from(rows).unwrap_or(u64::MAX), &attributes);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::metrics::MeterProvider as _;
+    use opentelemetry_sdk::metrics::data::{self, ResourceMetrics, Temporality};
+    use opentelemetry_sdk::metrics::reader::{
+        AggregationSelector, MetricReader, TemporalitySelector,
+    };
+    use opentelemetry_sdk::metrics::{
+        Aggregation, InstrumentKind, ManualReader, Pipeline, SdkMeterProvider,
+    };
+    use std::sync::{Arc, Weak};
+
+    // a reader that can still be collected from once the meter provider has it
+    #[derive(Debug, Clone)]
+    struct SharedReader(Arc<ManualReader>);
+
+    impl TemporalitySelector for SharedReader {
+        fn temporality(&self, kind: InstrumentKind) -> Temporality {
+            self.0.temporality(kind)
+        }
+    }
+
+    impl AggregationSelector for SharedReader {
+        fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+            self.0.aggregation(kind)
+        }
+    }
+
+    impl MetricReader for SharedReader {
+        fn register_pipeline(&self, pipeline: Weak<Pipeline>) {
+            self.0.register_pipeline(pipeline);
+        }
+
+        fn collect(&self, rm: &mut ResourceMetrics) -> opentelemetry::metrics::Result<()> {
+            self.0.collect(rm)
+        }
+
+        fn force_flush(&self) -> opentelemetry::metrics::Result<()> {
+            self.0.force_flush()
+        }
+
+        fn shutdown(&self) -> opentelemetry::metrics::Result<()> {
+            self.0.shutdown()
+        }
+    }
+
+    fn attribute(attributes: &[KeyValue], key: &str) -> Option<String> {
+        attributes
+            .iter()
+            .find(|attribute| attribute.key.as_str() == key)
+            .map(|attribute| attribute.value.to_string())
+    }
+
+    #[test]
+    fn test_opentelemetry_metrics() {
+        let reader = SharedReader(Arc::new(ManualReader::builder().build()));
+        let provider = SdkMeterProvider::builder()
+            .with_reader(reader.clone())
+            .build();
+        let metrics = OpenTelemetryJsonApiMetrics::new(&provider.meter(JSONAPI_METER_NAME));
+
+        let request = |resource_type, status, rows| RequestMetrics {
+            resource_type,
+            method: &Method::GET,
+            status,
+            duration: Duration::from_millis(250),
+            rows,
+        };
+        metrics.record_request(&request(Some("default_Articles"), StatusCode::OK, Some(3)));
+        metrics.record_request(&request(Some("default_Articles"), StatusCode::OK, Some(5)));
+        metrics.record_request(&request(None, StatusCode::NOT_FOUND, None));
+
+        let mut resource_metrics = ResourceMetrics {
+            resource: opentelemetry_sdk::Resource::empty(),
+            scope_metrics: Vec::new(),
+        };
+        reader.collect(&mut resource_metrics).unwrap();
+        let metric = |name: &str| {
+            resource_metrics
+                .scope_metrics
+                .iter()
+                .flat_map(|scope| &scope.metrics)
+                .find(|metric| metric.name == name)
+                .unwrap_or_else(|| panic!("no metric {name}"))
+                .data
+                .as_any()
+        };
+
+        let requests = metric("jsonapi.requests")
+            .downcast_ref::<data::Sum<u64>>()
+            .unwrap();
+        let mut requests = requests
+            .data_points
+            .iter()
+            .map(|point| {
+                (
+                    attribute(&point.attributes, "jsonapi.resource_type"),
+                    attribute(&point.attributes, "http.request.method"),
+                    attribute(&point.attributes, "http.response.status_code"),
+                    point.value,
+                )
+            })
+            .collect::<Vec<_>>();
+        requests.sort();
+        assert_eq!(
+            requests,
+            vec![
+                (None, Some("GET".to_string()), Some("404".to_string()), 1),
+                (
+                    Some("default_Articles".to_string()),
+                    Some("GET".to_string()),
+                    Some("200".to_string()),
+                    2
+                ),
+            ]
+        );
+
+        let duration = metric("jsonapi.request.duration")
+            .downcast_ref::<data::Histogram<f64>>()
+            .unwrap();
+        let (count, sum) = duration
+            .data_points
+            .iter()
+            .fold((0, 0.0), |(count, sum), point| {
+                (count + point.count, sum + point.sum)
+            });
+        assert_eq!((count, sum), (3, 0.75));
+
+        // errors have no rows
+        let rows = metric("jsonapi.response.rows")
+            .downcast_ref::<data::Histogram<u64>>()
+            .unwrap();
+        let rows = rows
+            .data_points
+            .iter()
+            .map(|point| {
+                (
+                    attribute(&point.attributes, "jsonapi.resource_type"),
+                    point.count,
+                    point.sum,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(rows, vec![(Some("default_Articles".to_string()), 2, 8)]);
+    }
+}
@@ -1,35 +1,350 @@
-use axum::{extract::State, http::Request, middleware::Next, response::IntoResponse};
+use axum::{
+    body::HttpBody,
+    extract::{Extension, MatchedPath, State},
+    http::{header, HeaderMap, HeaderValue, Request, StatusCode, Uri},
+    middleware::Next,
+    response::IntoResponse,
+    RequestExt,
+};
 use axum_core::body::Body;
-use tracing_util::{SpanVisibility, TraceableHttpResponse};
+use futures_util::StreamExt;
+use hasura_authn_core::Session;
+use std::sync::Arc;
+use std::time::Instant;
+use tracing_util::{
+    set_attribute_on_active_span, AttributeVisibility, SpanVisibility, TraceableHttpResponse,
+};
+
+use crate::batch::{operation_uris, BATCH_ROUTE, OPERATIONS_ROUTE};
+use crate::catalog::Catalog;
+use crate::explain::EXPLAIN_ROUTE;
+use crate::handler::request_resource_type;
+use crate::naming::NamingStrategy;
+use crate::rate_limit::{retry_after_seconds, RateLimiter};
 
 use crate::endpoint::EndPoint;
-use crate::types::JsonApiHttpError;
+use crate::metrics::{JsonApiMetrics, RequestMetrics, ResponseMetrics};
+use crate::types::{ErrorCode, JsonApiHttpError, JSONAPI_MEDIA_TYPE, SUPPORTED_EXTENSIONS};
 
-/// Middleware to start tracing of the JSON:API request. This middleware
-/// must be active for the entire duration of the request i.e. this middleware
+/// Middleware to start tracing of the JSON:API request, and to record its metrics. This
+/// middleware must be active for the entire duration of the request i.e. this middleware
 /// should be the entry point and the exit point of the JSON:API request.
-pub async fn jsonapi_request_tracing_middleware(
-    State(endpoint): State<EndPoint>,
+///
+/// The span is named after the route the request matched, eg `/api/rest/*path` for the routes
+/// mounted at `/api/rest`, or after the endpoint for requests that matched none.
+pub async fn jsonapi_request_tracing_middleware<M: JsonApiMetrics>(
+    State((endpoint, metrics)): State<(EndPoint, M)>,
     request: Request<Body>,
     next: Next,
 ) -> axum::response::Response {
     let tracer = tracing_util::global_tracer();
-    let path = endpoint.as_str();
-    tracer
+    let path = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or(endpoint.as_str(), MatchedPath::as_str)
+        .to_string();
+    let method = request.method().clone();
+    let start = Instant::now();
+    let response = tracer
         .in_span_async_with_parent_context(
-            path,
-            path,
+            path.clone(),
+            path.clone(),
             SpanVisibility::User,
             &request.headers().clone(),
             || {
                 Box::pin(async move {
                     let response = next.run(request).await;
-                    TraceableHttpResponse::new(response, path)
+                    TraceableHttpResponse::new(with_span_until_sent(response), path)
                 })
             },
         )
         .await
-        .response
+        .response;
+
+    // the handler tells us what the request was for, as only it knows which model a route is for
+    let response_metrics = response.extensions().get::<ResponseMetrics>();
+    metrics.record_request(&RequestMetrics {
+        resource_type: response_metrics.and_then(|labels| labels.resource_type.as_deref()),
+        method: &method,
+        status: response.status(),
+        duration: start.elapsed(),
+        rows: response_metrics.and_then(|labels| labels.rows),
+    });
+    response
+}
+
+// a span ends once the last context it is in is dropped. bodies of unknown size, like streamed
+// documents, are still being written out once the response is returned, so they hold on to the
+// context of the request span, which then ends once they have been sent.
+fn with_span_until_sent(response: axum::response::Response) -> axum::response::Response {
+    if response.body().size_hint().exact().is_some() {
+        return response;
+    }
+    let context = tracing_util::Context::current();
+    let (parts, body) = response.into_parts();
+    let body = futures_util::stream::unfold(
+        (body.into_data_stream(), context),
+        |(mut body, context)| async move {
+            let chunk = body.next().await?;
+            Some((chunk, (body, context)))
+        },
+    );
+    axum::response::Response::from_parts(parts, Body::from_stream(body))
+}
+
+/// Middleware enforcing the JSON:API media type rules, and setting the JSON:API media type as the
+/// content type of every response with a body, ie other than `204 No Content` and `304 Not
+/// Modified` responses.
+///
+/// spec: <https://jsonapi.org/format/1.1/#content-negotiation-servers>
+///
+/// - a request whose `Content-Type` is malformed, or is the JSON:API media type with media type
+///   parameters other than `ext` and `profile`, or with an `ext` we don't support, is rejected
+///   with `415 Unsupported Media Type`
+/// - a request whose `Accept` header contains the JSON:API media type, but only ever with media
+///   type parameters other than `ext` and `profile`, or with an `ext` we don't support, is
+///   rejected with `406 Not Acceptable`
+///
+/// The extensions of the `Content-Type` of the request are given to the handler as the
+/// `MediaTypeExtensions` extension of the request, and are advertised in the `Content-Type` of
+/// the response.
+pub async fn jsonapi_content_negotiation_middleware(
+    mut request: Request<Body>,
+    next: Next,
+) -> axum::response::Response {
+    let (mut response, extensions) = match negotiate(request.headers()) {
+        Ok(extensions) => {
+            request.extensions_mut().insert(extensions.clone());
+            (next.run(request).await, extensions)
+        }
+        Err(error) => (error.into_response(), MediaTypeExtensions::default()),
+    };
+    if ![StatusCode::NO_CONTENT, StatusCode::NOT_MODIFIED].contains(&response.status()) {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, extensions.media_type());
+    }
+    response
+}
+
+/// The JSON:API extensions a request applies, from the `ext` parameter of its `Content-Type`.
+/// These are always ones we support.
+///
+/// spec: <https://jsonapi.org/format/1.1/#extensions>
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MediaTypeExtensions(Vec<&'static str>);
+
+impl MediaTypeExtensions {
+    pub fn contains(&self, extension: &str) -> bool {
+        self.0.contains(&extension)
+    }
+
+    pub fn extensions(&self) -> &[&'static str] {
+        &self.0
+    }
+
+    // the JSON:API media type, with these extensions
+    fn media_type(&self) -> HeaderValue {
+        if self.0.is_empty() {
+            HeaderValue::from_static(JSONAPI_MEDIA_TYPE)
+        } else {
+            HeaderValue::from_str(&format!(
+                "{JSONAPI_MEDIA_TYPE}; ext=\"{}\"",
+                self.0.join(" ")
+            ))
+            .expect("extension URIs are valid header values")
+        }
+    }
+}
+
+// the JSON:API media type parameters we understand
+const JSONAPI_PARAMETERS: [&str; 2] = ["ext", "profile"];
+
+fn negotiate(headers: &HeaderMap) -> Result<MediaTypeExtensions, JsonApiHttpError> {
+    let mut extensions = MediaTypeExtensions::default();
+    if let Some(content_type) = headers.get(header::CONTENT_TYPE) {
+        let media_type = content_type.to_str().ok().and_then(MediaType::parse);
+        match media_type {
+            Some(media_type) if !media_type.is_jsonapi() => {}
+            Some(media_type) if !media_type.has_parameters_other_than(&JSONAPI_PARAMETERS) => {
+                extensions = MediaTypeExtensions(media_type.extensions().map_err(|extension| {
+                    JsonApiHttpError::new(
+                        StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                        ErrorCode::UnsupportedMediaType,
+                        format!("Unsupported JSON:API extension {extension}"),
+                    )
+                })?);
+            }
+            _ => {
+                return Err(JsonApiHttpError::new(
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    ErrorCode::UnsupportedMediaType,
+                    format!(
+                        "Content-Type must be {JSONAPI_MEDIA_TYPE} without media type parameters \
+                         other than ext and profile"
+                    ),
+                ));
+            }
+        }
+    }
+
+    // clients that don't ask for JSON:API at all (eg, `*/*`) get it anyway
+    let jsonapi_accepts = headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(MediaType::parse)
+        .filter(MediaType::is_jsonapi)
+        .collect::<Vec<_>>();
+    let acceptable = |media_type: &MediaType| {
+        !media_type.has_parameters_other_than(&["q", "ext", "profile"])
+            && media_type.extensions().is_ok()
+    };
+    if !jsonapi_accepts.is_empty() && !jsonapi_accepts.iter().any(acceptable) {
+        return Err(JsonApiHttpError::new(
+            StatusCode::NOT_ACCEPTABLE,
+            ErrorCode::NotAcceptable,
+            format!(
+                "Accept must allow {JSONAPI_MEDIA_TYPE} without media type parameters other than \
+                 ext and profile, and only with supported extensions"
+            ),
+        ));
+    }
+
+    Ok(extensions)
+}
+
+// a media type like `application/vnd.api+json; charset=utf-8`
+struct MediaType<'a> {
+    essence: String,
+    parameters: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> MediaType<'a> {
+    fn parse(value: &'a str) -> Option<Self> {
+        let mut parts = value.split(';');
+        let essence = parts.next()?.trim();
+        let (type_, subtype) = essence.split_once('/')?;
+        if type_.is_empty() || subtype.is_empty() || type_.contains(' ') || subtype.contains(' ') {
+            return None;
+        }
+        let parameters = parts
+            .map(|parameter| {
+                let (name, value) = parameter.split_once('=')?;
+                let name = name.trim();
+                (!name.is_empty()).then_some((name, value.trim()))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(MediaType {
+            essence: essence.to_ascii_lowercase(),
+            parameters,
+        })
+    }
+
+    fn is_jsonapi(&self) -> bool {
+        self.essence == JSONAPI_MEDIA_TYPE
+    }
+
+    fn has_parameters_other_than(&self, names: &[&str]) -> bool {
+        self.parameters
+            .iter()
+            .any(|(name, _)| !names.iter().any(|known| name.eq_ignore_ascii_case(known)))
+    }
+
+    // the supported extensions of the `ext` parameter, a space separated list of URIs, or the
+    // first one we don't support
+    fn extensions(&self) -> Result<Vec<&'static str>, &'a str> {
+        self.parameters
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("ext"))
+            .flat_map(|(_, value)| value.trim_matches('"').split_whitespace())
+            .map(|uri| {
+                SUPPORTED_EXTENSIONS
+                    .iter()
+                    .find(|extension| **extension == uri)
+                    .copied()
+                    .ok_or(uri)
+            })
+            .collect()
+    }
+}
+
+/// Middleware rate limiting requests for the resources of a model, for each role and model. It
+/// needs the session of the request, so must run after the session has been resolved.
+///
+/// Requests over the limit are rejected with `429 Too Many Requests` and a `Retry-After` header,
+/// before anything is planned or sent to a data connector. Each read of a batch or atomic request
+/// takes a token for its own model, as does the read an explain request is for. Routes that are
+/// not for a model, and roles that are exempt, are never limited.
+pub async fn jsonapi_rate_limit_middleware(
+    State((rate_limiter, catalog, naming)): State<(Arc<RateLimiter>, Arc<Catalog>, NamingStrategy)>,
+    Extension(session): Extension<Session>,
+    Extension(endpoint): Extension<EndPoint>,
+    request: Request<Body>,
+    next: Next,
+) -> axum::response::Response {
+    let (request, uris) = match operation_routes(&endpoint, request).await {
+        Ok(routes) => routes,
+        Err(response) => return response,
+    };
+    for uri in &uris {
+        let Some(resource_type) = request_resource_type(&catalog, &naming, &session, uri) else {
+            continue;
+        };
+        if let Err(retry_after) = rate_limiter.check(&session.role, &resource_type, Instant::now())
+        {
+            let retry_after = retry_after_seconds(retry_after);
+            set_attribute_on_active_span(
+                AttributeVisibility::Default,
+                "jsonapi.rate_limited",
+                resource_type.clone(),
+            );
+            let mut response = JsonApiHttpError::new(
+                StatusCode::TOO_MANY_REQUESTS,
+                ErrorCode::RateLimited,
+                format!("Too many requests for {resource_type}, retry after {retry_after} seconds"),
+            )
+            .into_response();
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+            return response;
+        }
+    }
+    next.run(request).await
+}
+
+// the routes of the reads of a request: its own route, that of the read an explain request is
+// for, or those of the operations of a batch or atomic request. the body of the latter is buffered
+// to read them, within the body limit of the request, and handed on to the handler as is.
+async fn operation_routes(
+    endpoint: &EndPoint,
+    request: Request<Body>,
+) -> Result<(Request<Body>, Vec<Uri>), axum::response::Response> {
+    let route = request.uri().path().to_string();
+    if route != BATCH_ROUTE && route != OPERATIONS_ROUTE {
+        let uri = match route.strip_prefix(EXPLAIN_ROUTE) {
+            Some(explained_route) => explained_route.parse().ok(),
+            None => Some(request.uri().clone()),
+        };
+        return Ok((request, uri.into_iter().collect()));
+    }
+    let (parts, body) = request.with_limited_body().into_parts();
+    // the only error worth answering is a body over the limit: other errors are of a client that
+    // has gone away
+    let body = axum::body::to_bytes(body, usize::MAX)
+        .await
+        .map_err(|error| {
+            JsonApiHttpError::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                ErrorCode::InvalidBody,
+                format!("Failed to buffer the request body: {error}"),
+            )
+            .into_response()
+        })?;
+    let uris = operation_uris(endpoint, &route, &body);
+    Ok((Request::from_parts(parts, Body::from(body)), uris))
 }
 
 /// Utility to build any server state with middleware error converter for JSON:API
@@ -40,3 +355,205 @@ pub fn build_state_with_middleware_error_converter<S>(
         JsonApiHttpError::from_middleware_error(error).into_response()
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ATOMIC_EXTENSION;
+    use axum::http::Method;
+    use std::sync::{Arc, Mutex};
+    use tower::ServiceExt;
+
+    fn headers(pairs: &[(header::HeaderName, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.clone(), HeaderValue::from_static(value)))
+            .collect()
+    }
+
+    fn status(headers: &HeaderMap) -> Option<StatusCode> {
+        negotiate(headers).err().map(|error| error.status)
+    }
+
+    #[test]
+    fn test_negotiate_accept() {
+        for accept in [
+            "application/vnd.api+json",
+            "*/*",
+            "application/json",
+            "application/vnd.api+json; q=0.9",
+            "application/vnd.api+json; charset=utf-8, application/vnd.api+json",
+        ] {
+            assert_eq!(
+                status(&headers(&[(header::ACCEPT, accept)])),
+                None,
+                "{accept}"
+            );
+        }
+        assert_eq!(
+            status(&headers(&[(
+                header::ACCEPT,
+                "application/vnd.api+json; charset=utf-8, text/html"
+            )])),
+            Some(StatusCode::NOT_ACCEPTABLE)
+        );
+        assert_eq!(status(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn test_negotiate_content_type() {
+        for content_type in ["application/vnd.api+json", "application/json"] {
+            assert_eq!(
+                status(&headers(&[(header::CONTENT_TYPE, content_type)])),
+                None,
+                "{content_type}"
+            );
+        }
+        for content_type in [
+            "application/vnd.api+json; charset=utf-8",
+            "vnd.api+json",
+            "application/vnd.api+json; charset",
+        ] {
+            assert_eq!(
+                status(&headers(&[(header::CONTENT_TYPE, content_type)])),
+                Some(StatusCode::UNSUPPORTED_MEDIA_TYPE),
+                "{content_type}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_negotiate_extensions() {
+        let atomic = negotiate(&headers(&[(
+            header::CONTENT_TYPE,
+            "application/vnd.api+json; ext=\"https://jsonapi.org/ext/atomic\"",
+        )]))
+        .unwrap();
+        assert!(atomic.contains(ATOMIC_EXTENSION));
+        assert_eq!(
+            atomic.media_type(),
+            "application/vnd.api+json; ext=\"https://jsonapi.org/ext/atomic\""
+        );
+        assert_eq!(
+            negotiate(&HeaderMap::new()).unwrap().media_type(),
+            JSONAPI_MEDIA_TYPE
+        );
+
+        assert_eq!(
+            status(&headers(&[(
+                header::CONTENT_TYPE,
+                "application/vnd.api+json; ext=\"https://example.com/ext/unknown\""
+            )])),
+            Some(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+        );
+
+        for accept in [
+            "application/vnd.api+json; ext=\"https://jsonapi.org/ext/atomic\"",
+            "application/vnd.api+json; ext=\"https://example.com/ext/unknown\", application/vnd.api+json",
+            "application/vnd.api+json; profile=\"https://example.com/profile\"",
+        ] {
+            assert_eq!(
+                status(&headers(&[(header::ACCEPT, accept)])),
+                None,
+                "{accept}"
+            );
+        }
+        assert_eq!(
+            status(&headers(&[(
+                header::ACCEPT,
+                "application/vnd.api+json; ext=\"https://example.com/ext/unknown\""
+            )])),
+            Some(StatusCode::NOT_ACCEPTABLE)
+        );
+    }
+
+    #[derive(Clone, Default)]
+    struct TestMetrics(Arc<Mutex<Vec<(Option<String>, Method, StatusCode, Option<usize>)>>>);
+
+    impl JsonApiMetrics for TestMetrics {
+        fn record_request(&self, request: &RequestMetrics<'_>) {
+            self.0.lock().unwrap().push((
+                request.resource_type.map(ToString::to_string),
+                request.method.clone(),
+                request.status,
+                request.rows,
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_content_type() {
+        let app = axum::Router::new()
+            .route("/ok", axum::routing::get(|| async { "{}" }))
+            .route(
+                "/not-modified",
+                axum::routing::get(|| async { StatusCode::NOT_MODIFIED }),
+            )
+            .route(
+                "/no-content",
+                axum::routing::get(|| async { StatusCode::NO_CONTENT }),
+            )
+            .layer(axum::middleware::from_fn(
+                jsonapi_content_negotiation_middleware,
+            ));
+        let content_type = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let response = app
+                    .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                response.headers().get(header::CONTENT_TYPE).cloned()
+            }
+        };
+
+        assert_eq!(
+            content_type("/ok").await,
+            Some(HeaderValue::from_static(JSONAPI_MEDIA_TYPE))
+        );
+        // responses without a body have no content type
+        assert_eq!(content_type("/not-modified").await, None);
+        assert_eq!(content_type("/no-content").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_request_metrics() {
+        let metrics = TestMetrics::default();
+        let app = axum::Router::new()
+            .route(
+                "/default/Articles",
+                axum::routing::get(|| async {
+                    let mut response = StatusCode::OK.into_response();
+                    response.extensions_mut().insert(ResponseMetrics {
+                        resource_type: Some("default_Articles".to_string()),
+                        rows: Some(3),
+                    });
+                    response
+                }),
+            )
+            .layer(axum::middleware::from_fn_with_state(
+                (EndPoint::V1Rest, metrics.clone()),
+                jsonapi_request_tracing_middleware,
+            ));
+
+        for uri in ["/default/Articles", "/default/Nope"] {
+            app.clone()
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            *metrics.0.lock().unwrap(),
+            vec![
+                (
+                    Some("default_Articles".to_string()),
+                    Method::GET,
+                    StatusCode::OK,
+                    Some(3)
+                ),
+                (None, Method::GET, StatusCode::NOT_FOUND, None),
+            ]
+        );
+    }
+}
@@ -0,0 +1,345 @@
+use metadata_resolve::Qualified;
+use open_dds::types::CustomTypeName;
+use std::str::FromStr;
+
+/// How the names of types, attributes and relationships from metadata are written in JSON:API
+/// documents, and so in the query parameters of requests, eg `fields[...]`, `sort` and
+/// `include`.
+///
+/// Names in requests are matched against the rewritten names of the metadata, rather than
+/// rewritten back, so any name a response has can be used in a request as is. Should two names of
+/// a type rewrite to the same name, a request for it gets the first of them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NamingStrategy {
+    pub case: NameCase,
+    /// use the plural of the name of an object type for its resources, eg `default_Articles` for
+    /// the `Article` type. the plural is made with simple English rules, so irregular plurals are
+    /// not supported.
+    pub pluralize_types: bool,
+}
+
+/// Casing of names in JSON:API documents
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NameCase {
+    /// names as they are in metadata
+    #[default]
+    None,
+    /// eg `firstName`
+    CamelCase,
+    /// eg `first_name`
+    SnakeCase,
+    /// eg `first-name`
+    KebabCase,
+}
+
+impl FromStr for NameCase {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "camelCase" => Ok(Self::CamelCase),
+            "snake_case" => Ok(Self::SnakeCase),
+            "kebab-case" => Ok(Self::KebabCase),
+            _ => Err(format!(
+                "unknown naming case {s}: expected none, camelCase, snake_case or kebab-case"
+            )),
+        }
+    }
+}
+
+impl NamingStrategy {
+    /// the name of an attribute or relationship
+    pub fn field_name(&self, name: &str) -> String {
+        self.case.apply(name)
+    }
+
+    /// the name of an object type in `fields[...]`, eg `Article`, or `articles` for snake case
+    /// plurals
+    pub fn type_name(&self, type_name: &CustomTypeName) -> String {
+        self.case.apply(&self.plural(type_name.0.as_str()))
+    }
+
+    /// the `type` of the resources of an object type, which starts with its subgraph so that types
+    /// of the same name in different subgraphs remain distinct, eg `default_Article`, or
+    /// `default-articles` for kebab case plurals
+    pub fn resource_type(&self, type_name: &Qualified<CustomTypeName>) -> String {
+        self.case.apply(&format!(
+            "{}_{}",
+            type_name.subgraph,
+            self.plural(type_name.name.0.as_str())
+        ))
+    }
+
+    /// whether the name of a model in a route is that of `model_name`. without a case, it must
+    /// be the name as it is in metadata, so is case sensitive. with one, it may also be the name
+    /// in that case, eg `invoice-lines` for `InvoiceLines` in kebab case, and is compared in it.
+    pub fn is_model_name(&self, model_name: &str, requested: &str) -> bool {
+        model_name == requested
+            || (self.case != NameCase::None
+                && self.case.apply(model_name) == self.case.apply(requested))
+    }
+
+    fn plural(&self, name: &str) -> String {
+        if self.pluralize_types {
+            pluralize(name)
+        } else {
+            name.to_string()
+        }
+    }
+}
+
+impl NameCase {
+    fn apply(self, name: &str) -> String {
+        match self {
+            Self::None => name.to_string(),
+            Self::CamelCase => {
+                let mut camel_case = String::new();
+                for (index, word) in words(name).into_iter().enumerate() {
+                    if index == 0 {
+                        camel_case.push_str(&word.to_lowercase());
+                    } else {
+                        let mut chars = word.chars();
+                        if let Some(first) = chars.next() {
+                            camel_case.extend(first.to_uppercase());
+                            camel_case.push_str(&chars.as_str().to_lowercase());
+                        }
+                    }
+                }
+                camel_case
+            }
+            Self::SnakeCase => join_lowercase(name, "_"),
+            Self::KebabCase => join_lowercase(name, "-"),
+        }
+    }
+}
+
+fn join_lowercase(name: &str, separator: &str) -> String {
+    words(name)
+        .iter()
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+// split a name into words, at underscores and hyphens, and where the case changes, eg
+// `InvoiceLine_ID` and `invoiceLineId` are both `invoice`, `line` and `id`, with their case as is.
+// a run of capitals is a word of its own, up to the start of the next word, so `HTTPServer` is
+// `HTTP` and `Server`.
+fn words(name: &str) -> Vec<String> {
+    let chars = name.chars().collect::<Vec<_>>();
+    let mut words = vec![];
+    let mut word = String::new();
+    for (index, &character) in chars.iter().enumerate() {
+        if character == '_' || character == '-' {
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+            continue;
+        }
+        if character.is_uppercase() && index > 0 {
+            let previous = chars[index - 1];
+            let next_is_lowercase = chars.get(index + 1).is_some_and(|next| next.is_lowercase());
+            let starts_word = previous.is_lowercase()
+                || previous.is_ascii_digit()
+                || (previous.is_uppercase() && next_is_lowercase);
+            if starts_word && !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+        }
+        word.push(character);
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+// the plural of a name, by the plural of its last word, eg `Categories` for `Category`
+fn pluralize(name: &str) -> String {
+    let lowercase = name.to_lowercase();
+    if ["s", "x", "z", "ch", "sh"]
+        .iter()
+        .any(|suffix| lowercase.ends_with(suffix))
+    {
+        format!("{name}es")
+    } else if lowercase.ends_with('y')
+        && !lowercase
+            .chars()
+            .rev()
+            .nth(1)
+            .is_some_and(|character| "aeiou".contains(character) || !character.is_alphabetic())
+    {
+        let stem = &name[..name.len() - 1];
+        if name.ends_with('Y') {
+            format!("{stem}IES")
+        } else {
+            format!("{stem}ies")
+        }
+    } else if name.chars().last().is_some_and(char::is_uppercase) {
+        format!("{name}S")
+    } else {
+        format!("{name}s")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use open_dds::identifier::{Identifier, SubgraphName};
+
+    fn type_name(name: &str) -> Qualified<CustomTypeName> {
+        Qualified::new(
+            SubgraphName::try_new("default").unwrap(),
+            CustomTypeName(Identifier::new(name).unwrap()),
+        )
+    }
+
+    fn naming(case: NameCase, pluralize_types: bool) -> NamingStrategy {
+        NamingStrategy {
+            case,
+            pluralize_types,
+        }
+    }
+
+    #[test]
+    fn test_field_names() {
+        let cases = [
+            (
+                "first_name",
+                "first_name",
+                "firstName",
+                "first_name",
+                "first-name",
+            ),
+            (
+                "firstName",
+                "firstName",
+                "firstName",
+                "first_name",
+                "first-name",
+            ),
+            (
+                "FirstName",
+                "FirstName",
+                "firstName",
+                "first_name",
+                "first-name",
+            ),
+            ("ArtistId", "ArtistId", "artistId", "artist_id", "artist-id"),
+            (
+                "HTTPServer",
+                "HTTPServer",
+                "httpServer",
+                "http_server",
+                "http-server",
+            ),
+            (
+                "address_line2",
+                "address_line2",
+                "addressLine2",
+                "address_line2",
+                "address-line2",
+            ),
+            ("id", "id", "id", "id", "id"),
+        ];
+        for (name, none, camel_case, snake_case, kebab_case) in cases {
+            assert_eq!(naming(NameCase::None, false).field_name(name), none);
+            assert_eq!(
+                naming(NameCase::CamelCase, false).field_name(name),
+                camel_case
+            );
+            assert_eq!(
+                naming(NameCase::SnakeCase, false).field_name(name),
+                snake_case
+            );
+            assert_eq!(
+                naming(NameCase::KebabCase, false).field_name(name),
+                kebab_case
+            );
+        }
+    }
+
+    #[test]
+    fn test_type_names() {
+        let article = type_name("Article");
+        assert_eq!(
+            NamingStrategy::default().resource_type(&article),
+            "default_Article"
+        );
+        assert_eq!(
+            naming(NameCase::None, true).resource_type(&article),
+            "default_Articles"
+        );
+        assert_eq!(
+            naming(NameCase::SnakeCase, true).resource_type(&article),
+            "default_articles"
+        );
+        assert_eq!(
+            naming(NameCase::CamelCase, true).resource_type(&type_name("InvoiceLine")),
+            "defaultInvoiceLines"
+        );
+        assert_eq!(
+            naming(NameCase::KebabCase, true).resource_type(&type_name("InvoiceLine")),
+            "default-invoice-lines"
+        );
+        assert_eq!(
+            naming(NameCase::SnakeCase, true).type_name(&article.name),
+            "articles"
+        );
+        assert_eq!(
+            NamingStrategy::default().type_name(&article.name),
+            "Article"
+        );
+    }
+
+    #[test]
+    fn test_pluralize() {
+        assert_eq!(pluralize("Article"), "Articles");
+        assert_eq!(pluralize("Category"), "Categories");
+        assert_eq!(pluralize("Day"), "Days");
+        assert_eq!(pluralize("Address"), "Addresses");
+        assert_eq!(pluralize("Box"), "Boxes");
+        assert_eq!(pluralize("Branch"), "Branches");
+        assert_eq!(pluralize("CITY"), "CITIES");
+        assert_eq!(pluralize("ID"), "IDS");
+    }
+
+    #[test]
+    fn test_names_are_stable() {
+        // a name that has been rewritten is rewritten to itself, so names in responses can be used
+        // in requests
+        for case in [
+            NameCase::CamelCase,
+            NameCase::SnakeCase,
+            NameCase::KebabCase,
+        ] {
+            for name in ["first_name", "ArtistId", "HTTPServer", "address_line2"] {
+                let rewritten = case.apply(name);
+                assert_eq!(case.apply(&rewritten), rewritten);
+            }
+        }
+    }
+
+    #[test]
+    fn test_is_model_name() {
+        assert!(NamingStrategy::default().is_model_name("InvoiceLines", "InvoiceLines"));
+        assert!(!NamingStrategy::default().is_model_name("InvoiceLines", "invoiceLines"));
+        assert!(!NamingStrategy::default().is_model_name("InvoiceLines", "invoice-lines"));
+
+        let kebab_case = naming(NameCase::KebabCase, false);
+        assert!(kebab_case.is_model_name("InvoiceLines", "InvoiceLines"));
+        assert!(kebab_case.is_model_name("InvoiceLines", "invoice-lines"));
+        assert!(kebab_case.is_model_name("InvoiceLines", "invoice_lines"));
+        assert!(!kebab_case.is_model_name("InvoiceLines", "invoices"));
+    }
+
+    #[test]
+    fn test_parse_name_case() {
+        assert_eq!("none".parse(), Ok(NameCase::None));
+        assert_eq!("camelCase".parse(), Ok(NameCase::CamelCase));
+        assert_eq!("snake_case".parse(), Ok(NameCase::SnakeCase));
+        assert_eq!("kebab-case".parse(), Ok(NameCase::KebabCase));
+        assert!("PascalCase".parse::<NameCase>().is_err());
+    }
+}
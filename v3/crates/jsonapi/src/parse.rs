@@ -1,28 +1,51 @@
-use super::types::{ModelInfo, RelationshipNode, RelationshipTree, RequestError};
-use axum::http::{Method, Uri};
+use super::types::{
+    ErrorCode, ModelInfo, ParameterError, RelationshipNode, RelationshipTree, RequestError,
+};
+use axum::http::Uri;
 use indexmap::IndexMap;
 use open_dds::{
+    data_connector::DataConnectorName,
     identifier,
-    identifier::{Identifier, SubgraphName},
-    models::ModelName,
+    identifier::Identifier,
     query::{
         Alias, ObjectSubSelection, RelationshipSelection,
         RelationshipTarget as OpenDdRelationshipTarget,
     },
-    relationships::RelationshipName,
+    relationships::{RelationshipName, RelationshipType},
     types::{CustomTypeName, FieldName},
 };
 use serde::{Deserialize, Serialize};
+mod aggregate;
+mod arguments;
+pub use aggregate::{
+    create_aggregate_ir, is_aggregate_route, Aggregate, AggregateFunction, AggregateIR,
+    AGGREGATE_ROUTE,
+};
+use arguments::FieldArguments;
+mod cursor;
+pub use cursor::Keyset;
+mod fields;
+pub use fields::SparseFields;
 mod filter;
 mod include;
+pub use include::{can_include, join_key, IncludedQuery};
+mod query_params;
+mod resource;
+mod sort;
 use super::helpers::get_object_type;
-use crate::catalog::{Model, ObjectType, RelationshipTarget, Type};
-use metadata_resolve::{unwrap_custom_type_name, Qualified};
+use crate::catalog::{Model, ObjectType, OrderByExpression, RelationshipTarget, Type};
+use crate::configuration::{Configuration, PaginationMode};
+use crate::naming::NamingStrategy;
+use metadata_resolve::{
+    unwrap_custom_type_name, OrderByExpressionIdentifier, Qualified,
+    ResolvedObjectBooleanExpressionType,
+};
+pub use query_params::QueryParams;
+pub use sort::Sortable;
 use std::collections::BTreeMap;
 
 #[derive(Debug, derive_more::Display, Serialize, Deserialize)]
 pub enum ParseError {
-    Filter(filter::FilterError),
     InvalidFieldName(String),
     InvalidModelName(String),
     InvalidSubgraph(String),
@@ -33,73 +56,423 @@ pub enum ParseError {
 pub struct QueryIR {
     pub query_request: open_dds::query::QueryRequest,
     pub root_type_name: Qualified<CustomTypeName>,
+    pub root_unique_identifier: Vec<FieldName>,
+    pub sparse_fields: SparseFields,
+    pub fetch: Fetch,
+    /// the total number of resources in the collection is counted too, by a second query in
+    /// `query_request`. only when asked for with `page[totals]`, as counting can be much more
+    /// expensive than fetching a page.
+    pub totals: bool,
+    /// the primary data is resource identifier objects only, as asked for with
+    /// `data=identifiers`, so only the fields of their `id`s are fetched
+    pub identifiers: bool,
+    /// included relationships fetched after `query_request`, by queries of their own
+    pub included_queries: Vec<IncludedQuery>,
+    /// the time zone timestamps with a time zone are written in, as asked for with `timezone`.
+    /// UTC unless the request asks otherwise.
+    pub time_zone: chrono_tz::Tz,
+}
+
+/// The aggregate the resources of a collection are counted in, for `page[totals]`
+pub const TOTAL_COUNT_FIELD: &str = "total";
+
+/// What a request fetches
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fetch {
+    /// a page of the resources of the model
+    Collection(Pagination),
+    /// the resource of the model with this `id`
+    Resource(String),
+    /// the resources related to the resource of the model with this `id`
+    Related(Related),
+}
+
+/// A relationship route, ie `/{id}/{relationship}` or `/{id}/relationships/{relationship}`
+/// spec: <https://jsonapi.org/format/#fetching-relationships>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Related {
+    /// the `id` of the resource whose relationship we fetch
+    pub id: String,
+    /// the name of the relationship in metadata, whatever the naming strategy
+    pub relationship: String,
+    pub relationship_type: RelationshipType,
+    /// only the resource linkage (`type` and `id`) of the related resources is returned
+    pub linkage: bool,
+    /// the page of related resources, for to-many relationships
+    pub pagination: Option<Pagination>,
+}
+
+/// The page of resources requested by a listing request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pagination {
+    /// `page[limit]` resources, skipping the first `page[offset]`
+    Offset { limit: usize, offset: usize },
+    /// `page[limit]` resources following the position in `page[cursor]`
+    Cursor { limit: usize, keyset: Keyset },
+}
+
+impl Pagination {
+    pub fn limit(&self) -> usize {
+        match self {
+            Pagination::Offset { limit, .. } | Pagination::Cursor { limit, .. } => *limit,
+        }
+    }
 }
 
+const PAGE_CURSOR: &str = "page[cursor]";
+const PAGE_LIMIT: &str = "page[limit]";
+const PAGE_OFFSET: &str = "page[offset]";
+const PAGE_TOTALS: &str = "page[totals]";
+const DATA: &str = "data";
+const TIMEZONE: &str = "timezone";
+
+// the only value of `data`, for resource identifier objects as the primary data
+const DATA_IDENTIFIERS: &str = "identifiers";
+
+// the parameters a request may give at most once
+const SINGLE_VALUED_PARAMETERS: [&str; 7] = [
+    PAGE_LIMIT,
+    PAGE_OFFSET,
+    PAGE_CURSOR,
+    PAGE_TOTALS,
+    sort::SORT,
+    DATA,
+    TIMEZONE,
+];
+
 pub fn create_query_ir(
+    configuration: &Configuration,
     model: &Model,
     object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectType>,
-    _http_method: &Method,
+    boolean_expression_types: &BTreeMap<
+        Qualified<CustomTypeName>,
+        ResolvedObjectBooleanExpressionType,
+    >,
+    order_by_expressions: &BTreeMap<Qualified<OrderByExpressionIdentifier>, OrderByExpression>,
     uri: &Uri,
     relationship_tree: &mut RelationshipTree,
     query_string: &jsonapi_library::query::Query,
+    query_params: &QueryParams,
 ) -> Result<QueryIR, RequestError> {
     // get model info from parsing URI
     let ModelInfo {
         subgraph,
         name: model_name,
-        unique_identifier: _,
-        relationship: _,
-    } = parse_url(uri).map_err(RequestError::ParseError)?;
+        unique_identifier: resource_id,
+        relationship,
+    } = parse_url(uri, model).map_err(RequestError::ParseError)?;
+    let naming = &configuration.naming;
 
-    // validate the sparse fields in the query string
-    validate_sparse_fields(object_types, query_string)?;
+    // below a resource there are the routes for its related resources, and for its relationship
+    let relationship_path = relationship.iter().map(String::as_str).collect::<Vec<_>>();
+    let relationship_route = match relationship_path.as_slice() {
+        [] => None,
+        [relationship] => Some((*relationship, false)),
+        ["relationships", relationship] => Some((*relationship, true)),
+        _ => return Err(RequestError::NotFound),
+    };
+    let related_target = relationship_route
+        .map(|(relationship, _)| resolve_related_target(object_types, model, naming, relationship))
+        .transpose()?;
+
+    // problems with the query parameters. we carry on past each one, using a placeholder for the
+    // bad parameter, so that we can report everything that is wrong with the request at once
+    let mut errors = vec![];
 
-    // Parse the include relationships
-    let include_relationships = query_string
-        .include
+    if let Err(duplicate_errors) = query_params.check_single_valued(&SINGLE_VALUED_PARAMETERS) {
+        errors.extend(duplicate_errors);
+    }
+
+    // parse and resolve the sparse fields in the query string
+    let mut sparse_fields = query_string
+        .fields
         .as_ref()
-        .map(|include| include::IncludeRelationships::parse(include));
+        .map(SparseFields::parse)
+        .unwrap_or_default();
+    if let Err(sparse_fields_errors) = sparse_fields.resolve(object_types, naming) {
+        errors.extend(sparse_fields_errors);
+    }
 
-    let field_selection = resolve_field_selection(
+    // the arguments of fields of the model that take them
+    let field_arguments = FieldArguments::parse(
+        &model.data_type,
+        get_object_type(object_types, &model.data_type).map_err(RequestError::ParseError)?,
+        naming,
+        query_params,
+    )
+    .unwrap_or_else(|argument_errors| {
+        errors.extend(argument_errors);
+        FieldArguments::none(&model.data_type, naming)
+    });
+
+    // Parse the include relationships. we don't resolve them at all if they are over the limits,
+    // as resolving is the work the limits are there to bound.
+    let mut include_relationships = match &query_string.include {
+        Some(include) => match include::check_include_limits(
+            include,
+            configuration.max_include_depth,
+            configuration.max_include_paths,
+        ) {
+            Ok(()) => Some(include::IncludeRelationships::parse(include)),
+            Err(include_errors) => {
+                errors.extend(include_errors);
+                None
+            }
+        },
+        None => None,
+    };
+
+    // related resources are fetched by including the relationship in the query for the resource
+    // whose relationship it is. the `include` parameter is relative to the related resources.
+    if let (Some((relationship, linkage)), Some(related_target)) =
+        (relationship_route, &related_target)
+    {
+        let nested_include = if linkage {
+            // the linkage only needs the `id`s of the related resources
+            sparse_fields = SparseFields::no_attributes([
+                &model.data_type.name,
+                &related_target.object_type.name,
+            ]);
+            None
+        } else {
+            include_relationships
+        };
+        include_relationships = Some(include::IncludeRelationships {
+            include: BTreeMap::from_iter([(relationship.to_string(), nested_include)]),
+        });
+    }
+
+    // a single resource is looked up by its id; `filter`, `sort` and `page` only apply to
+    // collections. `sort` and `page` also apply to the related resources of to-many
+    // relationships.
+    let mut related_order_by = vec![];
+    let (filter_query, sort_query, fetch) = match &resource_id {
+        Some(id) => {
+            let object_type = get_object_type(object_types, &model.data_type)
+                .map_err(RequestError::ParseError)?;
+            let filter_query = resource::build_resource_filter(model, object_type, id)?;
+            let fetch = match (relationship_route, related_target) {
+                (Some((_, linkage)), Some(related_target)) => {
+                    let pagination = match related_target.relationship_type {
+                        RelationshipType::Object => None,
+                        RelationshipType::Array => {
+                            let sortable = sort::Sortable {
+                                object_types,
+                                order_by_expressions,
+                                naming,
+                                data_connector_name: related_target.data_connector_name.as_ref(),
+                            };
+                            related_order_by = sort::build_order_by(
+                                &sortable,
+                                &field_arguments,
+                                &related_target.object_type,
+                                related_target.order_by_expression.as_ref(),
+                                &related_target.unique_identifier,
+                                query_string.sort.as_deref().unwrap_or_default(),
+                            )
+                            .unwrap_or_else(|sort_errors| {
+                                errors.extend(sort_errors);
+                                vec![]
+                            });
+                            let pagination =
+                                resolve_related_pagination(configuration, query_params)
+                                    .unwrap_or_else(|pagination_error| {
+                                        errors.push(pagination_error);
+                                        Pagination::Offset {
+                                            limit: configuration.default_page_limit,
+                                            offset: 0,
+                                        }
+                                    });
+                            Some(pagination)
+                        }
+                    };
+                    Fetch::Related(Related {
+                        id: id.clone(),
+                        relationship: related_target.relationship,
+                        relationship_type: related_target.relationship_type,
+                        linkage,
+                        pagination,
+                    })
+                }
+                _ => Fetch::Resource(id.clone()),
+            };
+            (Some(filter_query), vec![], fetch)
+        }
+        None => {
+            // create filters
+            let filter_query = filter::build_boolean_expression(
+                model,
+                object_types,
+                boolean_expression_types,
+                naming,
+                &field_arguments,
+                query_params,
+            )
+            .unwrap_or_else(|filter_errors| {
+                errors.extend(filter_errors);
+                None
+            });
+
+            // create sorts
+            let sortable = sort::Sortable {
+                object_types,
+                order_by_expressions,
+                naming,
+                data_connector_name: Some(&model.data_connector_name),
+            };
+            let sort_query = sort::build_order_by(
+                &sortable,
+                &field_arguments,
+                &model.data_type,
+                model.order_by_expression.as_ref(),
+                &model.unique_identifier,
+                query_string.sort.as_deref().unwrap_or_default(),
+            )
+            .unwrap_or_else(|sort_errors| {
+                errors.extend(sort_errors);
+                vec![]
+            });
+
+            // pagination
+            // spec: <https://jsonapi.org/format/#fetching-pagination>
+            let pagination = resolve_pagination(configuration, model, query_params, &sort_query)
+                .unwrap_or_else(|pagination_error| {
+                    errors.push(pagination_error);
+                    Pagination::Offset {
+                        limit: configuration.default_page_limit,
+                        offset: 0,
+                    }
+                });
+            (filter_query, sort_query, Fetch::Collection(pagination))
+        }
+    };
+
+    let totals = resolve_totals(model, query_params, &fetch).unwrap_or_else(|totals_error| {
+        errors.push(totals_error);
+        false
+    });
+    let identifiers = resolve_identifiers(query_params, &fetch).unwrap_or_else(|data_error| {
+        errors.push(data_error);
+        false
+    });
+    let time_zone = resolve_time_zone(query_params).unwrap_or_else(|time_zone_error| {
+        errors.push(time_zone_error);
+        chrono_tz::Tz::UTC
+    });
+    // the total is that of the whole collection, so it is counted with the filter of the
+    // request, but without the narrowing to the rows after the cursor
+    let total_filter = if totals { filter_query.clone() } else { None };
+
+    // in cursor mode, the keyset decides the ordering and narrows the filter to the rows after
+    // the cursor, and we need its fields in the response to build the next cursor
+    let (filter_query, sort_query, required_fields, limit, offset) = match &fetch {
+        Fetch::Resource(_) | Fetch::Related(_) => (
+            filter_query,
+            sort_query,
+            model.unique_identifier.clone(),
+            1,
+            None,
+        ),
+        Fetch::Collection(Pagination::Offset { limit, offset }) => (
+            filter_query,
+            sort_query,
+            model.unique_identifier.clone(),
+            *limit,
+            Some(*offset).filter(|offset| *offset > 0),
+        ),
+        Fetch::Collection(Pagination::Cursor { limit, keyset }) => {
+            let filter_query = match (filter_query, keyset.predicate()) {
+                (Some(filter), Some(predicate)) => {
+                    Some(open_dds::query::BooleanExpression::And(vec![
+                        filter, predicate,
+                    ]))
+                }
+                (filter, predicate) => filter.or(predicate),
+            };
+            let mut required_fields = model.unique_identifier.clone();
+            required_fields.extend(keyset.fields());
+            (
+                filter_query,
+                keyset.order_by(),
+                required_fields,
+                *limit,
+                None,
+            )
+        }
+    };
+
+    let mut field_selection = match resolve_field_selection(
         object_types,
+        naming,
+        &field_arguments,
         &model.data_type,
         relationship_tree,
-        query_string,
+        &sparse_fields,
         include_relationships.as_ref(),
-    )?;
+        &required_fields,
+        "",
+    ) {
+        Ok(field_selection) => field_selection,
+        Err(RequestError::InvalidParameters(include_errors)) => {
+            errors.extend(include_errors);
+            IndexMap::new()
+        }
+        Err(error) => return Err(error),
+    };
+
+    // identifiers only need the fields of their `id`s (and of the cursor for the next page), but
+    // resources are still included through the relationships of the primary data
+    if identifiers {
+        field_selection.retain(|alias, selection| match selection {
+            ObjectSubSelection::Field(_) => required_fields
+                .iter()
+                .any(|field_name| field_name.as_str() == alias.as_str()),
+            ObjectSubSelection::Relationship(_) | ObjectSubSelection::RelationshipAggregate(_) => {
+                true
+            }
+        });
+    }
 
-    // create filters
-    let filter_query = match &query_string.filter {
-        Some(filter) => {
-            let boolean_expression = filter::build_boolean_expression(model, filter)
-                .map_err(|parse_error| RequestError::ParseError(ParseError::Filter(parse_error)))?;
-            Ok(Some(boolean_expression))
+    // included relationships that the data connector of the model cannot fetch along with its
+    // resources are fetched by queries of their own
+    let included_queries = match &fetch {
+        Fetch::Collection(_) | Fetch::Resource(_) => {
+            let object_type = get_object_type(object_types, &model.data_type)
+                .map_err(RequestError::ParseError)?;
+            include::split_included_queries(
+                model,
+                object_type,
+                object_types,
+                naming,
+                &mut field_selection,
+            )
+            .unwrap_or_else(|include_errors| {
+                errors.extend(include_errors);
+                vec![]
+            })
         }
-        None => Ok(None),
-    }?;
-
-    // create sorts
-    let sort_query = match &query_string.sort {
-        None => Ok(vec![]),
-        Some(sort) => sort
-            .iter()
-            .map(|elem| build_order_by_element(elem).map_err(RequestError::ParseError))
-            .collect::<Result<Vec<_>, RequestError>>(),
-    }?;
-
-    // pagination
-    // spec: <https://jsonapi.org/format/#fetching-pagMetadata>
-    let limit = query_string
-        .page
-        .as_ref()
-        .and_then(|page| usize::try_from(page.limit).ok())
-        .filter(|page| *page > 0);
+        Fetch::Related(_) => vec![],
+    };
 
-    let offset = query_string
-        .page
-        .as_ref()
-        .and_then(|page| usize::try_from(page.offset).ok())
-        .filter(|page| *page > 0);
+    // the page of related resources
+    if let Fetch::Related(Related {
+        relationship,
+        pagination: Some(Pagination::Offset { limit, offset }),
+        ..
+    }) = &fetch
+    {
+        if let Some(ObjectSubSelection::Relationship(relationship_selection)) =
+            field_selection.get_mut(relationship.as_str())
+        {
+            relationship_selection.target.order_by = related_order_by;
+            relationship_selection.target.limit = Some(*limit);
+            relationship_selection.target.offset = Some(*offset).filter(|offset| *offset > 0);
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(RequestError::InvalidParameters(errors));
+    }
 
     // form the model selection
     let model_selection = open_dds::query::ModelSelection {
@@ -108,39 +481,98 @@ pub fn create_query_ir(
             arguments: IndexMap::new(),
             filter: filter_query,
             order_by: sort_query,
-            limit,
+            limit: Some(limit),
             offset,
-            model_name,
-            subgraph,
+            model_name: model_name.clone(),
+            subgraph: subgraph.clone(),
         },
     };
 
-    let queries = IndexMap::from_iter([(
+    let mut queries = IndexMap::from_iter([(
         open_dds::query::Alias::new(identifier!("jsonapi_model_query")),
         open_dds::query::Query::Model(model_selection),
     )]);
+    if totals {
+        let count_selection = open_dds::query::ModelAggregateSelection {
+            selection: IndexMap::from_iter([(
+                open_dds::query::Name::new(TOTAL_COUNT_FIELD.to_string()),
+                open_dds::query::Aggregate {
+                    function: open_dds::query::AggregationFunction::Count {},
+                    operand: None,
+                },
+            )]),
+            target: open_dds::query::ModelTarget {
+                arguments: IndexMap::new(),
+                filter: total_filter,
+                order_by: vec![],
+                limit: None,
+                offset: None,
+                model_name,
+                subgraph,
+            },
+        };
+        queries.insert(
+            open_dds::query::Alias::new(identifier!("jsonapi_total_count")),
+            open_dds::query::Query::ModelAggregate(count_selection),
+        );
+    }
     Ok(QueryIR {
         query_request: open_dds::query::QueryRequest::V1(open_dds::query::QueryRequestV1 {
             queries,
         }),
         root_type_name: model.data_type.clone(),
+        root_unique_identifier: model.unique_identifier.clone(),
+        sparse_fields,
+        fetch,
+        totals,
+        identifiers,
+        included_queries,
+        time_zone,
     })
 }
 
 fn resolve_field_selection(
     object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectType>,
+    naming: &NamingStrategy,
+    field_arguments: &FieldArguments,
     object_type_name: &Qualified<CustomTypeName>,
     relationship_tree: &mut RelationshipTree,
-    query_string: &jsonapi_library::query::Query,
+    sparse_fields: &SparseFields,
     include_relationships: Option<&include::IncludeRelationships>,
+    required_fields: &[FieldName],
+    include_path: &str,
 ) -> Result<IndexMap<Alias, ObjectSubSelection>, RequestError> {
     let object_type =
         get_object_type(object_types, object_type_name).map_err(RequestError::ParseError)?;
 
-    // create the selection fields; include all fields of the model output type
+    // create the selection fields; include all fields of the model output type. we always fetch
+    // the required fields (the unique identifier, and any cursor keys), even if they are not in
+    // the sparse fields, as we need them to render the resource `id` and the next cursor. they
+    // are dropped from the attributes when processing the response.
     let mut selection = IndexMap::new();
     for (field_name, field_type) in &object_type.type_fields {
-        if include_field(query_string, field_name, &object_type_name.name) {
+        if sparse_fields.include_field(&object_type_name.name, field_name)
+            || required_fields.contains(field_name)
+        {
+            // fields whose required arguments were not given are left out, unless asked for
+            let arguments =
+                match field_arguments.for_field(object_type_name, object_type, field_name) {
+                    Ok(arguments) => arguments,
+                    Err(detail)
+                        if sparse_fields.names_field(&object_type_name.name, field_name)
+                            || required_fields.contains(field_name) =>
+                    {
+                        return Err(ParameterError::new(
+                            format!("fields[{}]", naming.type_name(&object_type_name.name)),
+                            format!(
+                                "Cannot select {}: {detail}",
+                                naming.field_name(field_name.as_str())
+                            ),
+                        )
+                        .into());
+                    }
+                    Err(_) => continue,
+                };
             let field_name_ident = Identifier::new(field_name.as_str())
                 .map_err(|e| RequestError::BadRequest(e.into()))?;
 
@@ -149,15 +581,18 @@ fn resolve_field_selection(
             let sub_sel =
                 open_dds::query::ObjectSubSelection::Field(open_dds::query::ObjectFieldSelection {
                     target: open_dds::query::ObjectFieldTarget {
-                        arguments: IndexMap::new(),
+                        arguments,
                         field_name,
                     },
                     selection: resolve_nested_field_selection(
                         object_types,
+                        naming,
+                        field_arguments,
                         relationship_tree,
-                        query_string,
+                        sparse_fields,
                         include_relationships,
                         field_type,
+                        include_path,
                     )?,
                 });
             selection.insert(field_alias, sub_sel);
@@ -167,9 +602,12 @@ fn resolve_field_selection(
     let mut relationship_fields = resolve_include_relationships(
         object_type,
         object_types,
+        naming,
+        field_arguments,
         relationship_tree,
-        query_string,
+        sparse_fields,
         include_relationships,
+        include_path,
     )?;
     selection.append(&mut relationship_fields);
     Ok(selection)
@@ -177,27 +615,38 @@ fn resolve_field_selection(
 
 fn resolve_nested_field_selection(
     object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectType>,
+    naming: &NamingStrategy,
+    field_arguments: &FieldArguments,
     relationship_tree: &mut RelationshipTree,
-    query_string: &jsonapi_library::query::Query,
+    sparse_fields: &SparseFields,
     include_relationships: Option<&include::IncludeRelationships>,
     field_type: &Type,
+    include_path: &str,
 ) -> Result<Option<IndexMap<Alias, ObjectSubSelection>>, RequestError> {
     let selection = match field_type {
         Type::Scalar(_) | Type::ScalarForDataConnector(_) => None,
         Type::List(inner) => resolve_nested_field_selection(
             object_types,
+            naming,
+            field_arguments,
             relationship_tree,
-            query_string,
+            sparse_fields,
             include_relationships,
             inner.as_ref(),
+            include_path,
         )?,
         Type::Object(type_name) => {
             let object_field_selection = resolve_field_selection(
                 object_types,
+                naming,
+                field_arguments,
                 type_name,
                 relationship_tree,
-                query_string,
+                sparse_fields,
                 include_relationships,
+                // nested objects are attributes, not resources, so they have no `id`
+                &[],
+                include_path,
             )?;
             Some(object_field_selection)
         }
@@ -205,80 +654,79 @@ fn resolve_nested_field_selection(
     Ok(selection)
 }
 
-// check all types in sparse fields are accessible,
-// for each type check all fields in sparse fields are accessible, explode if not
-fn validate_sparse_fields(
-    object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectType>,
-    query_string: &jsonapi_library::query::Query,
-) -> Result<(), RequestError> {
-    if let Some(types) = &query_string.fields {
-        for (type_name, type_fields) in types {
-            let (_, object_type) = object_types
-                .iter()
-                .find(|(object_type_name, _)| object_type_name.name.0.as_str() == type_name)
-                .ok_or_else(|| {
-                    RequestError::BadRequest(format!("Unknown type in sparse fields: {type_name}"))
-                })?;
-
-            for type_field in type_fields {
-                let string_fields: Vec<_> = object_type
-                    .type_fields
-                    .keys()
-                    .map(ToString::to_string)
-                    .collect();
-
-                if !string_fields.contains(type_field) {
-                    return Err(RequestError::BadRequest(format!(
-                        "Unknown field in sparse fields: {type_field} in {type_name}"
-                    )));
-                }
-            }
-        }
-    }
-    Ok(())
-}
-
 fn resolve_include_relationships(
     object_type: &ObjectType,
     object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectType>,
+    naming: &NamingStrategy,
+    field_arguments: &FieldArguments,
     relationship_tree: &mut RelationshipTree,
-    query_string: &jsonapi_library::query::Query,
+    sparse_fields: &SparseFields,
     include_relationships: Option<&include::IncludeRelationships>,
+    include_path: &str,
 ) -> Result<IndexMap<Alias, ObjectSubSelection>, RequestError> {
     let mut fields = IndexMap::new();
     if let Some(include_relationships) = include_relationships {
         for (relationship, nested_include) in &include_relationships.include {
+            // the dotted include path up to and including this relationship, used to point at the
+            // offending segment in errors
+            let relationship_path = if include_path.is_empty() {
+                relationship.clone()
+            } else {
+                format!("{include_path}.{relationship}")
+            };
             // Check the presence of the relationship
-            let Some((relationship_name, target)) = object_type
-                .type_relationships
-                .iter()
-                .find(|&(relationship_name, _)| relationship_name.as_str() == relationship)
+            let Some((relationship_name, target)) =
+                object_type
+                    .type_relationships
+                    .iter()
+                    .find(|&(relationship_name, _)| {
+                        naming.field_name(relationship_name.as_str()) == *relationship
+                    })
             else {
-                return Err(RequestError::BadRequest(format!(
-                    "Relationship {relationship} not found"
-                )));
+                return Err(ParameterError::new(
+                    "include",
+                    format!(
+                        "Relationship {relationship} not found (in include path {relationship_path})"
+                    ),
+                )
+                .into());
             };
-            let field_name_ident = Identifier::new(relationship)
-                .map_err(|e| RequestError::BadRequest(format!("Invalid relationship name: {e}")))?;
+            // the relationship is fetched, and kept in the tree, by its name in metadata; it is
+            // only renamed when the response is rendered
+            let field_name_ident = Identifier::new(relationship_name.as_str()).map_err(|e| {
+                ParameterError::new("include", format!("Invalid relationship name: {e}"))
+            })?;
 
             let mut is_command_relationship = false;
-            let (target_type, relationship_type) = match &target {
+            let (target_type, relationship_type, unique_identifier, model_route) = match &target {
                 RelationshipTarget::Model {
+                    model_name,
                     object_type,
                     relationship_type,
-                } => (object_type, relationship_type.clone()),
+                    unique_identifier,
+                    ..
+                } => (
+                    object_type,
+                    relationship_type.clone(),
+                    unique_identifier.as_slice(),
+                    Some(crate::catalog::model_route(model_name)),
+                ),
                 RelationshipTarget::Command { type_reference } => {
                     is_command_relationship = true;
                     match unwrap_custom_type_name(type_reference) {
                         Some(object_type) => (
                             object_type,
                             crate::helpers::type_reference_to_relationship_type(type_reference),
+                            // commands have no unique identifier, nor a route
+                            [].as_slice(),
+                            None,
                         ),
                         None => {
-                            return Err(RequestError::BadRequest(
-                                "Command relationship with built-in output type not supported yet"
-                                    .to_string(),
-                            ));
+                            return Err(ParameterError::new(
+                                "include",
+                                "Command relationship with built-in output type not supported yet",
+                            )
+                            .into());
                         }
                     }
                 }
@@ -286,20 +734,26 @@ fn resolve_include_relationships(
             let mut nested_relationships = RelationshipTree::default();
             let selection = resolve_field_selection(
                 object_types,
+                naming,
+                field_arguments,
                 target_type,
                 &mut nested_relationships,
-                query_string,
+                sparse_fields,
                 nested_include.as_ref(),
+                unique_identifier,
+                &relationship_path,
             )?;
             let relationship_node = RelationshipNode {
                 object_type: target_type.clone(),
                 relationship_type: relationship_type.clone(),
+                unique_identifier: unique_identifier.to_vec(),
+                model_route,
                 is_command_relationship,
                 nested: nested_relationships,
             };
             relationship_tree
                 .relationships
-                .insert(relationship.to_string(), relationship_node);
+                .insert(relationship_name.as_str().to_string(), relationship_node);
             let sub_selection = ObjectSubSelection::Relationship(RelationshipSelection {
                 target: build_relationship_target(relationship_name.clone()),
                 selection: Some(selection),
@@ -322,64 +776,220 @@ fn build_relationship_target(relationship_name: RelationshipName) -> OpenDdRelat
     }
 }
 
-// given the sparse fields for this request, should be include a given field in the query?
-// this does not consider subgraphs at the moment - we match on `CustomTypeName` not
-// `Qualified<CustomTypeName>`.
-// This means that the below field is ambiguous where `Authors` type is defined in multiple
-// subgraphs
-// fields[Authors]=author_id,first_name
-//
-// This will need to be solved when we make users give JSONAPI types explicit names
-// like we do in GraphQL
+// The related resources of a model, through one of its relationships
+struct RelatedTarget {
+    // the name of the relationship in metadata
+    relationship: String,
+    object_type: Qualified<CustomTypeName>,
+    relationship_type: RelationshipType,
+    unique_identifier: Vec<FieldName>,
+    data_connector_name: Option<Qualified<DataConnectorName>>,
+    order_by_expression: Option<Qualified<OrderByExpressionIdentifier>>,
+}
+
+// only relationships to models have routes, as the results of commands are not resources with
+// an `id` of their own
+fn resolve_related_target(
+    object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectType>,
+    model: &Model,
+    naming: &NamingStrategy,
+    relationship: &str,
+) -> Result<RelatedTarget, RequestError> {
+    let object_type =
+        get_object_type(object_types, &model.data_type).map_err(RequestError::ParseError)?;
+    match object_type
+        .type_relationships
+        .iter()
+        .find(|(relationship_name, _)| {
+            naming.field_name(relationship_name.as_str()) == relationship
+        }) {
+        Some((
+            relationship_name,
+            RelationshipTarget::Model {
+                object_type,
+                relationship_type,
+                unique_identifier,
+                data_connector_name,
+                order_by_expression,
+                ..
+            },
+        )) => Ok(RelatedTarget {
+            relationship: relationship_name.as_str().to_string(),
+            object_type: object_type.clone(),
+            relationship_type: relationship_type.clone(),
+            unique_identifier: unique_identifier.clone(),
+            data_connector_name: data_connector_name.clone(),
+            order_by_expression: order_by_expression.clone(),
+        }),
+        Some((_, RelationshipTarget::Command { .. })) | None => Err(RequestError::NotFound),
+    }
+}
+
+// related resources are paginated with `page[limit]` and `page[offset]` only, as cursors are
+// tied to the top-level model
+fn resolve_related_pagination(
+    configuration: &Configuration,
+    query_params: &QueryParams,
+) -> Result<Pagination, ParameterError> {
+    if query_params.contains(PAGE_CURSOR) {
+        return Err(ParameterError::new(
+            PAGE_CURSOR,
+            "page[cursor] is not supported for related resources",
+        ));
+    }
+    Ok(Pagination::Offset {
+        limit: resolve_page_limit(configuration, query_params)?,
+        offset: requested_offset(query_params)?,
+    })
+}
+
+// use the requested `page[limit]` and `page[offset]`, falling back to the configured default limit
+// when none is given. a limit above the configured maximum is rejected rather than silently
+// truncated, so clients don't mistake a short page for the last one.
 //
-// fields[subgraphAuthors]=author_id,firstName&fields[otherAuthors]=author_id,last_name
-fn include_field(
-    query_string: &jsonapi_library::query::Query,
-    field_name: &FieldName,
-    object_type_name: &CustomTypeName,
-) -> bool {
-    if let Some(fields) = &query_string.fields {
-        if let Some(object_fields) = fields.get(object_type_name.0.as_str()) {
-            for object_field in object_fields {
-                if object_field == field_name.as_str() {
-                    return true;
-                }
-            }
-            return false;
+// requests using `page[cursor]` (an empty one asks for the first page) get cursor pagination, as
+// do requests without a `page[offset]` when cursor pagination is the configured default.
+fn resolve_pagination(
+    configuration: &Configuration,
+    model: &Model,
+    query_params: &QueryParams,
+    sort_query: &[open_dds::query::OrderByElement],
+) -> Result<Pagination, ParameterError> {
+    let limit = resolve_page_limit(configuration, query_params)?;
+
+    let cursor = match (
+        query_params.get(PAGE_CURSOR),
+        query_params.contains(PAGE_OFFSET),
+    ) {
+        (Some(_), true) => {
+            return Err(ParameterError::new(
+                PAGE_CURSOR,
+                "page[cursor] and page[offset] cannot be used together",
+            ));
+        }
+        (Some(cursor), false) => Some(cursor),
+        (None, false) if configuration.pagination_mode == PaginationMode::Cursor => Some(""),
+        (None, _) => None,
+    };
+
+    match cursor {
+        Some(cursor) => {
+            let keyset = Keyset::new(&model.name, sort_query, &model.unique_identifier)?
+                .resume_from(&configuration.cursor_secret, cursor)?;
+            Ok(Pagination::Cursor { limit, keyset })
+        }
+        None => Ok(Pagination::Offset {
+            limit,
+            offset: requested_offset(query_params)?,
+        }),
+    }
+}
+
+// `page[totals]=true` asks for the number of resources in the collection as `meta.total`. this
+// needs the data connector to count them, so it is an error for models whose connector can't
+// rather than a missing total.
+fn resolve_totals(
+    model: &Model,
+    query_params: &QueryParams,
+    fetch: &Fetch,
+) -> Result<bool, ParameterError> {
+    match query_params.get(PAGE_TOTALS) {
+        None | Some("false") => return Ok(false),
+        Some("true") => {}
+        Some(_) => {
+            return Err(ParameterError::new(
+                PAGE_TOTALS,
+                "page[totals] must be true or false",
+            ))
         }
     }
-    // if no sparse fields provided for our model, return everything
-    true
+    match fetch {
+        Fetch::Resource(_) | Fetch::Related(_) => Err(ParameterError::new(
+            PAGE_TOTALS,
+            "page[totals] is only supported for collections",
+        )),
+        Fetch::Collection(_) if !model.supports_aggregates => Err(ParameterError::new(
+            PAGE_TOTALS,
+            format!(
+                "Counts are not available for {}, as its data connector does not support aggregates",
+                model.name.name
+            ),
+        )),
+        Fetch::Collection(_) => Ok(true),
+    }
 }
 
-fn create_field_name(field_name: &str) -> Result<FieldName, ParseError> {
-    let identifier = Identifier::new(field_name)
-        .map_err(|_| ParseError::InvalidFieldName(field_name.to_string()))?;
-    Ok(open_dds::types::FieldName::new(identifier))
+fn resolve_identifiers(query_params: &QueryParams, fetch: &Fetch) -> Result<bool, ParameterError> {
+    match query_params.get(DATA) {
+        None => Ok(false),
+        Some(DATA_IDENTIFIERS) => match fetch {
+            Fetch::Collection(_) | Fetch::Resource(_) => Ok(true),
+            Fetch::Related(_) => Err(ParameterError::new(
+                DATA,
+                "data=identifiers is not supported for related resources, whose identifiers \
+                 are at the relationships route",
+            )),
+        },
+        Some(_) => Err(ParameterError::new(DATA, "data must be identifiers")),
+    }
 }
 
-// Sorting spec: <https://jsonapi.org/format/#fetching-sorting>
-fn build_order_by_element(elem: &String) -> Result<open_dds::query::OrderByElement, ParseError> {
-    let (field_name, direction) = if elem.starts_with('-') {
-        (
-            elem.split_at(1).1.to_string(),
-            open_dds::models::OrderByDirection::Desc,
-        )
-    } else {
-        (elem.to_string(), open_dds::models::OrderByDirection::Asc)
+fn resolve_time_zone(query_params: &QueryParams) -> Result<chrono_tz::Tz, ParameterError> {
+    match query_params.get(TIMEZONE) {
+        None => Ok(chrono_tz::Tz::UTC),
+        Some(time_zone) => time_zone.parse().map_err(|_| {
+            ParameterError::new(
+                TIMEZONE,
+                format!(
+                    "Unknown time zone {time_zone}, timezone must be an IANA time zone name, eg \
+                     Europe/Amsterdam"
+                ),
+            )
+        }),
+    }
+}
+
+// the limit is read from the raw query string, as the parsed query treats a limit that isn't a
+// number the same as none at all
+fn resolve_page_limit(
+    configuration: &Configuration,
+    query_params: &QueryParams,
+) -> Result<usize, ParameterError> {
+    let Some(requested_limit) = query_params.get(PAGE_LIMIT) else {
+        return Ok(configuration
+            .default_page_limit
+            .min(configuration.max_page_limit));
     };
+    match requested_limit.parse::<usize>() {
+        Ok(limit) if limit > configuration.max_page_limit => Err(ParameterError::new(
+            PAGE_LIMIT,
+            format!(
+                "page[limit] must not be greater than {}",
+                configuration.max_page_limit
+            ),
+        )
+        .with_code(ErrorCode::PaginationLimitExceeded)),
+        Ok(limit) if limit > 0 => Ok(limit),
+        Ok(_) | Err(_) => Err(ParameterError::new(
+            PAGE_LIMIT,
+            "page[limit] must be a positive integer",
+        )),
+    }
+}
 
-    let operand = open_dds::query::Operand::Field(open_dds::query::ObjectFieldOperand {
-        target: Box::new(open_dds::query::ObjectFieldTarget {
-            field_name: create_field_name(&field_name)?,
-            arguments: IndexMap::new(),
+// read from the raw query string, as `page[limit]` is
+fn requested_offset(query_params: &QueryParams) -> Result<usize, ParameterError> {
+    match query_params.get(PAGE_OFFSET) {
+        None => Ok(0),
+        Some(requested_offset) => requested_offset.parse().map_err(|_| {
+            ParameterError::new(PAGE_OFFSET, "page[offset] must be a non-negative integer")
         }),
-        nested: None,
-    });
-    Ok(open_dds::query::OrderByElement { operand, direction })
+    }
 }
 
-fn parse_url(uri: &Uri) -> Result<ModelInfo, ParseError> {
+// the parts of the route below the model it has been matched to. the model is named as it is in
+// metadata, which the route may not be, eg when it is in the case of the naming strategy.
+fn parse_url(uri: &Uri, model: &Model) -> Result<ModelInfo, ParseError> {
     let path = uri.path();
     let paths = path
         .split('/')
@@ -388,20 +998,14 @@ fn parse_url(uri: &Uri) -> Result<ModelInfo, ParseError> {
     if paths.len() < 2 {
         return Err(ParseError::PathLengthMustBeAtLeastTwo);
     }
-    let subgraph = paths[0];
-    let model_name = paths[1];
     let unique_identifier = paths.get(2).map(|x| (*x).to_string());
     let mut relationship = Vec::new();
     if paths.get(3).is_some() {
         relationship = paths[3..].iter().map(|i| (*i).to_string()).collect();
     }
     Ok(ModelInfo {
-        name: ModelName::new(
-            Identifier::new(model_name)
-                .map_err(|_| ParseError::InvalidModelName(model_name.to_string()))?,
-        ),
-        subgraph: SubgraphName::try_new(subgraph)
-            .map_err(|_| ParseError::InvalidSubgraph(subgraph.to_string()))?,
+        name: model.name.name.clone(),
+        subgraph: model.name.subgraph.clone(),
         unique_identifier,
         relationship,
     })
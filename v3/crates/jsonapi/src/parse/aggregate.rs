@@ -0,0 +1,611 @@
+use super::{arguments::FieldArguments, filter, QueryParams};
+use crate::catalog::{Model, ObjectType, Type};
+use crate::helpers::get_object_type;
+use crate::naming::NamingStrategy;
+use crate::types::{ParameterError, RequestError};
+use axum::http::Uri;
+use indexmap::IndexMap;
+use metadata_resolve::{Qualified, ResolvedObjectBooleanExpressionType};
+use open_dds::{
+    identifier,
+    query::{
+        AggregationFunction, Dimension, ModelDimensions, ModelTarget, Name, ObjectFieldOperand,
+        ObjectFieldTarget, Operand,
+    },
+    types::{CustomTypeName, FieldName},
+};
+use std::collections::BTreeMap;
+
+const AGGREGATE: &str = "aggregate";
+const GROUP_BY: &str = "group_by";
+
+/// An aggregate function, as named in an `aggregate[{function}]` query parameter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggregateFunction {
+    /// the number of non-null values
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+impl AggregateFunction {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "count" => Some(Self::Count),
+            "sum" => Some(Self::Sum),
+            "avg" => Some(Self::Avg),
+            "min" => Some(Self::Min),
+            "max" => Some(Self::Max),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Count => "count",
+            Self::Sum => "sum",
+            Self::Avg => "avg",
+            Self::Min => "min",
+            Self::Max => "max",
+        }
+    }
+
+    fn to_open_dd(self) -> AggregationFunction {
+        match self {
+            Self::Count => AggregationFunction::Count {},
+            Self::Sum => AggregationFunction::Sum,
+            Self::Avg => AggregationFunction::Average,
+            Self::Min => AggregationFunction::Min,
+            Self::Max => AggregationFunction::Max,
+        }
+    }
+
+    // can this function aggregate a field of this type? sums and averages need numbers, and
+    // minimums and maximums need values that can be ordered. scalars of a data connector with no
+    // known representation (which data connectors that don't give one get as `JSON`) are left to
+    // the data connector to decide.
+    fn applies_to(self, field_type: &Type) -> bool {
+        let applies = |representation: &ndc_models::TypeRepresentation| {
+            *representation == ndc_models::TypeRepresentation::JSON
+                || match self {
+                    Self::Count => true,
+                    Self::Sum | Self::Avg => is_numeric(representation),
+                    Self::Min | Self::Max => is_orderable(representation),
+                }
+        };
+        match field_type {
+            Type::Scalar(representation) => applies(representation),
+            Type::ScalarForDataConnector(scalar_type) => {
+                scalar_type.type_representations.iter().all(applies)
+            }
+            Type::List(_) | Type::Object(_) => false,
+        }
+    }
+
+    fn requirement(self) -> &'static str {
+        match self {
+            Self::Count => "a scalar",
+            Self::Sum | Self::Avg => "numeric",
+            Self::Min | Self::Max => "orderable",
+        }
+    }
+}
+
+fn is_numeric(representation: &ndc_models::TypeRepresentation) -> bool {
+    matches!(
+        representation,
+        ndc_models::TypeRepresentation::Int8
+            | ndc_models::TypeRepresentation::Int16
+            | ndc_models::TypeRepresentation::Int32
+            | ndc_models::TypeRepresentation::Int64
+            | ndc_models::TypeRepresentation::BigInteger
+            | ndc_models::TypeRepresentation::Float32
+            | ndc_models::TypeRepresentation::Float64
+            | ndc_models::TypeRepresentation::BigDecimal
+    )
+}
+
+fn is_orderable(representation: &ndc_models::TypeRepresentation) -> bool {
+    is_numeric(representation)
+        || matches!(
+            representation,
+            ndc_models::TypeRepresentation::String
+                | ndc_models::TypeRepresentation::Date
+                | ndc_models::TypeRepresentation::Timestamp
+                | ndc_models::TypeRepresentation::TimestampTZ
+        )
+}
+
+/// One aggregate asked for, ie a function over a field of the model
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Aggregate {
+    pub function: AggregateFunction,
+    pub field_name: FieldName,
+}
+
+impl Aggregate {
+    /// the name the data connector returns the aggregate under. field names are identifiers, so
+    /// the `.` keeps these apart from each other.
+    pub fn alias(&self) -> String {
+        format!("{}.{}", self.function.as_str(), self.field_name)
+    }
+}
+
+/// A request for the aggregates of a model, at `/{subgraph}/{model}/__aggregate`
+#[derive(Debug)]
+pub struct AggregateIR {
+    pub query_request: open_dds::query::QueryRequest,
+    pub aggregates: Vec<Aggregate>,
+    /// the fields the rows are grouped by, in the order the data connector returns their values
+    /// for each group. empty when the rows are aggregated as a whole.
+    pub group_by: Vec<FieldName>,
+}
+
+/// The last segment of the aggregate routes of models, eg `/default/Orders/__aggregate`. a
+/// resource with the `id` "__aggregate" can't be fetched by its `id` as a result.
+pub const AGGREGATE_ROUTE: &str = "__aggregate";
+
+/// Is this the aggregate route of a model, ie `/{subgraph}/{model}/__aggregate`?
+pub fn is_aggregate_route(uri: &Uri) -> bool {
+    let segments = uri
+        .path()
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>();
+    matches!(segments.as_slice(), [_, _, AGGREGATE_ROUTE])
+}
+
+// Build the query for the aggregates of a model. `aggregate[{function}]` gives the fields to
+// aggregate with the function, eg `aggregate[sum]=amount,tax&aggregate[max]=amount`, and
+// `group_by` the fields to group the rows by. `filter` narrows down the rows as it does for a
+// collection, and the permissions of the role apply in the same way.
+pub fn create_aggregate_ir(
+    model: &Model,
+    object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectType>,
+    boolean_expression_types: &BTreeMap<
+        Qualified<CustomTypeName>,
+        ResolvedObjectBooleanExpressionType,
+    >,
+    naming: &NamingStrategy,
+    query_params: &QueryParams,
+) -> Result<AggregateIR, RequestError> {
+    let mut errors = vec![];
+
+    if let Err(duplicate_errors) = query_params.check_single_valued(&[GROUP_BY]) {
+        errors.extend(duplicate_errors);
+    }
+
+    let aggregates = parse_aggregates(object_types, naming, &model.data_type, query_params)
+        .unwrap_or_else(|aggregate_errors| {
+            errors.extend(aggregate_errors);
+            vec![]
+        });
+    if !model.supports_aggregates {
+        errors.push(ParameterError::new(
+            AGGREGATE,
+            format!(
+                "Aggregates are not available for {}, as its data connector does not support them",
+                model.name.name
+            ),
+        ));
+    }
+
+    let group_by = parse_group_by(object_types, naming, &model.data_type, query_params)
+        .unwrap_or_else(|group_by_error| {
+            errors.push(group_by_error);
+            vec![]
+        });
+    if !group_by.is_empty() && !model.supports_grouping {
+        errors.push(ParameterError::new(
+            GROUP_BY,
+            format!(
+                "Grouping is not available for {}, as its data connector does not support it",
+                model.name.name
+            ),
+        ));
+    }
+
+    let field_arguments = FieldArguments::parse(
+        &model.data_type,
+        get_object_type(object_types, &model.data_type).map_err(RequestError::ParseError)?,
+        naming,
+        query_params,
+    )
+    .unwrap_or_else(|argument_errors| {
+        errors.extend(argument_errors);
+        FieldArguments::none(&model.data_type, naming)
+    });
+    let filter_query = filter::build_boolean_expression(
+        model,
+        object_types,
+        boolean_expression_types,
+        naming,
+        &field_arguments,
+        query_params,
+    )
+    .unwrap_or_else(|filter_errors| {
+        errors.extend(filter_errors);
+        None
+    });
+
+    if !errors.is_empty() {
+        return Err(RequestError::InvalidParameters(errors));
+    }
+
+    let selection = aggregates
+        .iter()
+        .map(|aggregate| {
+            (
+                Name::new(aggregate.alias()),
+                open_dds::query::Aggregate {
+                    function: aggregate.function.to_open_dd(),
+                    operand: Some(field_operand(aggregate.field_name.clone())),
+                },
+            )
+        })
+        .collect();
+    let target = ModelTarget {
+        arguments: IndexMap::new(),
+        filter: filter_query,
+        order_by: vec![],
+        limit: None,
+        offset: None,
+        model_name: model.name.name.clone(),
+        subgraph: model.name.subgraph.clone(),
+    };
+    let query = if group_by.is_empty() {
+        open_dds::query::Query::ModelAggregate(open_dds::query::ModelAggregateSelection {
+            target,
+            selection,
+        })
+    } else {
+        open_dds::query::Query::ModelGroups(open_dds::query::ModelGroupsSelection {
+            target,
+            selection,
+            dimensions: ModelDimensions {
+                dimensions: group_by
+                    .iter()
+                    .map(|field_name| {
+                        (
+                            Name::new(field_name.to_string()),
+                            Dimension::Field {
+                                column: field_operand(field_name.clone()),
+                                extraction: None,
+                            },
+                        )
+                    })
+                    .collect(),
+                limit: None,
+                offset: None,
+            },
+        })
+    };
+
+    Ok(AggregateIR {
+        query_request: open_dds::query::QueryRequest::V1(open_dds::query::QueryRequestV1 {
+            queries: IndexMap::from_iter([(
+                open_dds::query::Alias::new(identifier!("jsonapi_model_aggregate")),
+                query,
+            )]),
+        }),
+        aggregates,
+        group_by,
+    })
+}
+
+// the aggregates of the `aggregate[{function}]` parameters, in the order they were asked for.
+// at least one is needed, as there is nothing to return otherwise.
+fn parse_aggregates(
+    object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectType>,
+    naming: &NamingStrategy,
+    object_type_name: &Qualified<CustomTypeName>,
+    query_params: &QueryParams,
+) -> Result<Vec<Aggregate>, Vec<ParameterError>> {
+    let object_type = object_types.get(object_type_name);
+    let mut aggregates = vec![];
+    let mut errors = vec![];
+    for (parameter, value) in query_params.iter() {
+        let Some(function_name) = parameter
+            .strip_prefix("aggregate[")
+            .and_then(|rest| rest.strip_suffix(']'))
+        else {
+            continue;
+        };
+        let Some(function) = AggregateFunction::parse(function_name) else {
+            errors.push(ParameterError::new(
+                parameter,
+                format!(
+                    "Unknown aggregate function {function_name}, expected one of count, sum, avg, min or max"
+                ),
+            ));
+            continue;
+        };
+        for field in value.split(',').map(str::trim) {
+            let field_type = object_type.and_then(|object_type| {
+                object_type
+                    .type_fields
+                    .iter()
+                    .find(|(field_name, _)| naming.field_name(field_name.as_str()) == field)
+            });
+            match field_type {
+                None => errors.push(ParameterError::new(
+                    parameter,
+                    format!(
+                        "Cannot {} {field}: unknown field {field} in {}",
+                        function.as_str(),
+                        object_type_name.name
+                    ),
+                )),
+                Some((field_name, _)) if has_arguments(object_type, field_name) => {
+                    errors.push(ParameterError::new(
+                        parameter,
+                        format!(
+                            "Cannot {} {field}: field {field} in {} takes arguments",
+                            function.as_str(),
+                            object_type_name.name
+                        ),
+                    ));
+                }
+                Some((_, field_type)) if !function.applies_to(field_type) => {
+                    errors.push(ParameterError::new(
+                        parameter,
+                        format!(
+                            "Cannot {} {field}: field {field} in {} is not {}",
+                            function.as_str(),
+                            object_type_name.name,
+                            function.requirement()
+                        ),
+                    ));
+                }
+                Some((field_name, _)) => {
+                    let aggregate = Aggregate {
+                        function,
+                        field_name: field_name.clone(),
+                    };
+                    if !aggregates.contains(&aggregate) {
+                        aggregates.push(aggregate);
+                    }
+                }
+            }
+        }
+    }
+    if aggregates.is_empty() && errors.is_empty() {
+        errors.push(ParameterError::new(
+            AGGREGATE,
+            "At least one aggregate is needed, eg aggregate[count]=id",
+        ));
+    }
+    if errors.is_empty() {
+        Ok(aggregates)
+    } else {
+        Err(errors)
+    }
+}
+
+// fields with arguments are not aggregated or grouped by, as aggregates and dimensions don't
+// take arguments
+fn has_arguments(object_type: Option<&ObjectType>, field_name: &FieldName) -> bool {
+    object_type.is_some_and(|object_type| object_type.field_arguments.contains_key(field_name))
+}
+
+// the scalar fields of the `group_by` parameter, eg `group_by=status,region`
+fn parse_group_by(
+    object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectType>,
+    naming: &NamingStrategy,
+    object_type_name: &Qualified<CustomTypeName>,
+    query_params: &QueryParams,
+) -> Result<Vec<FieldName>, ParameterError> {
+    let Some(value) = query_params.get(GROUP_BY) else {
+        return Ok(vec![]);
+    };
+    let object_type = object_types.get(object_type_name);
+    let mut group_by = vec![];
+    for field in value.split(',').map(str::trim) {
+        let field_type = object_type.and_then(|object_type| {
+            object_type
+                .type_fields
+                .iter()
+                .find(|(field_name, _)| naming.field_name(field_name.as_str()) == field)
+        });
+        match field_type {
+            Some((field_name, _)) if has_arguments(object_type, field_name) => {
+                return Err(ParameterError::new(
+                    GROUP_BY,
+                    format!(
+                        "Cannot group by {field}: field {field} in {} takes arguments",
+                        object_type_name.name
+                    ),
+                ));
+            }
+            Some((field_name, Type::Scalar(_) | Type::ScalarForDataConnector(_))) => {
+                if !group_by.contains(field_name) {
+                    group_by.push(field_name.clone());
+                }
+            }
+            Some((_, Type::List(_) | Type::Object(_))) => {
+                return Err(ParameterError::new(
+                    GROUP_BY,
+                    format!(
+                        "Cannot group by {field}: field {field} in {} is not a scalar",
+                        object_type_name.name
+                    ),
+                ));
+            }
+            None => {
+                return Err(ParameterError::new(
+                    GROUP_BY,
+                    format!(
+                        "Cannot group by {field}: unknown field {field} in {}",
+                        object_type_name.name
+                    ),
+                ));
+            }
+        }
+    }
+    Ok(group_by)
+}
+
+fn field_operand(field_name: FieldName) -> Operand {
+    Operand::Field(ObjectFieldOperand {
+        target: Box::new(ObjectFieldTarget {
+            field_name,
+            arguments: IndexMap::new(),
+        }),
+        nested: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use open_dds::identifier::{Identifier, SubgraphName};
+    use std::collections::BTreeSet;
+
+    fn field_name(name: &str) -> FieldName {
+        FieldName::new(Identifier::new(name).unwrap())
+    }
+
+    fn type_name(name: &str) -> Qualified<CustomTypeName> {
+        Qualified::new(
+            SubgraphName::try_new("default").unwrap(),
+            CustomTypeName(Identifier::new(name).unwrap()),
+        )
+    }
+
+    // orders, with a numeric amount, a string status and a list of tags
+    fn object_types() -> BTreeMap<Qualified<CustomTypeName>, ObjectType> {
+        BTreeMap::from_iter([(
+            type_name("Order"),
+            ObjectType {
+                type_fields: IndexMap::from_iter([
+                    (
+                        field_name("amount"),
+                        Type::ScalarForDataConnector(crate::catalog::ScalarTypeForDataConnector {
+                            type_representations: BTreeSet::from_iter([
+                                ndc_models::TypeRepresentation::BigDecimal,
+                            ]),
+                        }),
+                    ),
+                    (
+                        field_name("status"),
+                        Type::Scalar(ndc_models::TypeRepresentation::String),
+                    ),
+                    (
+                        field_name("paid"),
+                        Type::Scalar(ndc_models::TypeRepresentation::Boolean),
+                    ),
+                    (
+                        field_name("tags"),
+                        Type::List(Box::new(Type::Scalar(
+                            ndc_models::TypeRepresentation::String,
+                        ))),
+                    ),
+                ]),
+                nullable_fields: BTreeSet::new(),
+                field_arguments: BTreeMap::new(),
+                type_relationships: IndexMap::new(),
+            },
+        )])
+    }
+
+    fn aggregates(query: &str) -> Result<Vec<String>, Vec<(String, String)>> {
+        parse_aggregates(
+            &object_types(),
+            &NamingStrategy::default(),
+            &type_name("Order"),
+            &QueryParams::parse(query),
+        )
+        .map(|aggregates| aggregates.iter().map(Aggregate::alias).collect())
+        .map_err(|errors| {
+            errors
+                .into_iter()
+                .map(|error| (error.parameter, error.detail))
+                .collect()
+        })
+    }
+
+    #[test]
+    fn test_aggregates() {
+        assert_eq!(
+            aggregates("aggregate[sum]=amount&aggregate[avg]=amount&aggregate[max]=amount,status&aggregate[count]=paid,paid").unwrap(),
+            vec!["sum.amount", "avg.amount", "max.amount", "max.status", "count.paid"]
+        );
+    }
+
+    #[test]
+    fn test_aggregate_errors() {
+        let error = |parameter: &str, detail: &str| (parameter.to_string(), detail.to_string());
+        assert_eq!(
+            aggregates("aggregate[sum]=status&aggregate[min]=paid,nope&aggregate[median]=amount&aggregate[count]=tags")
+                .unwrap_err(),
+            vec![
+                error(
+                    "aggregate[sum]",
+                    "Cannot sum status: field status in Order is not numeric"
+                ),
+                error(
+                    "aggregate[min]",
+                    "Cannot min paid: field paid in Order is not orderable"
+                ),
+                error(
+                    "aggregate[min]",
+                    "Cannot min nope: unknown field nope in Order"
+                ),
+                error(
+                    "aggregate[median]",
+                    "Unknown aggregate function median, expected one of count, sum, avg, min or max"
+                ),
+                error(
+                    "aggregate[count]",
+                    "Cannot count tags: field tags in Order is not a scalar"
+                ),
+            ]
+        );
+        assert_eq!(
+            aggregates("group_by=status").unwrap_err(),
+            vec![error(
+                "aggregate",
+                "At least one aggregate is needed, eg aggregate[count]=id"
+            )]
+        );
+    }
+
+    #[test]
+    fn test_group_by() {
+        let group_by = |query: &str| {
+            parse_group_by(
+                &object_types(),
+                &NamingStrategy::default(),
+                &type_name("Order"),
+                &QueryParams::parse(query),
+            )
+            .map_err(|error| error.detail)
+        };
+        assert_eq!(group_by("").unwrap(), vec![]);
+        assert_eq!(
+            group_by("group_by=status,paid,status").unwrap(),
+            vec![field_name("status"), field_name("paid")]
+        );
+        assert_eq!(
+            group_by("group_by=tags").unwrap_err(),
+            "Cannot group by tags: field tags in Order is not a scalar"
+        );
+        assert_eq!(
+            group_by("group_by=nope").unwrap_err(),
+            "Cannot group by nope: unknown field nope in Order"
+        );
+    }
+
+    #[test]
+    fn test_is_aggregate_route() {
+        let route = |path: &str| is_aggregate_route(&Uri::from_static(path));
+        assert!(route("/default/Orders/__aggregate"));
+        assert!(!route("/default/Orders"));
+        assert!(!route("/default/Orders/1"));
+        // a resource can have the `id` "aggregate"
+        assert!(!route("/default/Orders/aggregate"));
+        assert!(!route("/default/Orders/1/__aggregate"));
+    }
+}
@@ -0,0 +1,333 @@
+use super::filter::{coerce, parse_path};
+use super::QueryParams;
+use crate::catalog::ObjectType;
+use crate::naming::NamingStrategy;
+use crate::types::ParameterError;
+use indexmap::IndexMap;
+use metadata_resolve::Qualified;
+use open_dds::{
+    query::{ArgumentName, Value},
+    types::{CustomTypeName, FieldName},
+};
+use std::collections::BTreeMap;
+
+pub(super) const ARGS: &str = "args";
+
+// The arguments of fields that take them, like computed fields backed by functions of the data
+// connector, given with the `args[...]` query parameters
+//
+//   args[word_count][language]=en
+//
+// gives the `language` argument of the `word_count` field. the arguments are used wherever the
+// field is: in the attributes of the resources, and when sorting or filtering on it. fields are
+// named as they are in documents, following the naming strategy, and arguments as they are in
+// metadata.
+//
+// only the fields of the resources of the model can be given arguments. fields with required
+// arguments that are not given them are left out of the attributes, and asking for them with
+// `fields`, or sorting or filtering on them, is an error.
+#[derive(Debug)]
+pub struct FieldArguments {
+    object_type_name: Qualified<CustomTypeName>,
+    naming: NamingStrategy,
+    arguments: BTreeMap<FieldName, IndexMap<ArgumentName, Value>>,
+}
+
+impl FieldArguments {
+    pub fn parse(
+        object_type_name: &Qualified<CustomTypeName>,
+        object_type: &ObjectType,
+        naming: &NamingStrategy,
+        query_params: &QueryParams,
+    ) -> Result<Self, Vec<ParameterError>> {
+        let mut arguments: BTreeMap<FieldName, IndexMap<ArgumentName, Value>> = BTreeMap::new();
+        let mut errors = vec![];
+        for (parameter, value) in query_params.iter() {
+            let Some(path) = parameter
+                .strip_prefix(ARGS)
+                .filter(|path| path.is_empty() || path.starts_with('['))
+            else {
+                continue;
+            };
+            let segments = parse_path(path).unwrap_or_default();
+            let [field, argument] = segments.as_slice() else {
+                errors.push(ParameterError::new(
+                    parameter,
+                    format!("Invalid {parameter}, expected {ARGS}[field][argument]=value"),
+                ));
+                continue;
+            };
+            match resolve_argument(
+                object_type_name,
+                object_type,
+                naming,
+                field,
+                argument,
+                value,
+            ) {
+                Ok((field_name, argument_name, value)) => {
+                    let field_arguments = arguments.entry(field_name).or_default();
+                    if field_arguments.contains_key(&argument_name) {
+                        errors.push(ParameterError::new(
+                            parameter,
+                            format!("{parameter} may only be given once"),
+                        ));
+                    } else {
+                        field_arguments.insert(argument_name, value);
+                    }
+                }
+                Err(detail) => errors.push(ParameterError::new(parameter, detail)),
+            }
+        }
+        if errors.is_empty() {
+            Ok(FieldArguments {
+                object_type_name: object_type_name.clone(),
+                naming: *naming,
+                arguments,
+            })
+        } else {
+            Err(errors)
+        }
+    }
+
+    // no arguments, for fields of the other types of a request
+    pub fn none(object_type_name: &Qualified<CustomTypeName>, naming: &NamingStrategy) -> Self {
+        FieldArguments {
+            object_type_name: object_type_name.clone(),
+            naming: *naming,
+            arguments: BTreeMap::new(),
+        }
+    }
+
+    // the arguments given to a field of `object_type_name`, or else why the field can't be used:
+    // it has required arguments that were not given
+    pub fn for_field(
+        &self,
+        object_type_name: &Qualified<CustomTypeName>,
+        object_type: &ObjectType,
+        field_name: &FieldName,
+    ) -> Result<IndexMap<ArgumentName, Value>, String> {
+        let given = if *object_type_name == self.object_type_name {
+            self.arguments.get(field_name).cloned().unwrap_or_default()
+        } else {
+            IndexMap::new()
+        };
+        let missing = object_type
+            .field_arguments
+            .get(field_name)
+            .into_iter()
+            .flatten()
+            .filter(|(argument_name, argument)| {
+                argument.required && !given.contains_key(*argument_name)
+            })
+            .map(|(argument_name, _)| argument_name.as_str())
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            return Ok(given);
+        }
+        let field = self.naming.field_name(field_name.as_str());
+        if *object_type_name == self.object_type_name {
+            Err(format!(
+                "field {field} of {} needs the arguments {}, given with {ARGS}[{field}][{}]=value",
+                object_type_name.name,
+                missing.join(", "),
+                missing[0]
+            ))
+        } else {
+            Err(format!(
+                "field {field} of {} needs the arguments {}, which can only be given for the \
+                 fields of {}",
+                object_type_name.name,
+                missing.join(", "),
+                self.object_type_name.name
+            ))
+        }
+    }
+}
+
+fn resolve_argument(
+    object_type_name: &Qualified<CustomTypeName>,
+    object_type: &ObjectType,
+    naming: &NamingStrategy,
+    field: &str,
+    argument: &str,
+    value: &str,
+) -> Result<(FieldName, ArgumentName, Value), String> {
+    let field_name = object_type
+        .type_fields
+        .keys()
+        .find(|field_name| naming.field_name(field_name.as_str()) == field)
+        .ok_or_else(|| format!("Unknown field {field} in {}", object_type_name.name))?;
+    let arguments = object_type.field_arguments.get(field_name).ok_or_else(|| {
+        format!(
+            "Field {field} of {} has no arguments",
+            object_type_name.name
+        )
+    })?;
+    let (argument_name, argument_info) = arguments
+        .iter()
+        .find(|(argument_name, _)| argument_name.as_str() == argument)
+        .ok_or_else(|| {
+            let expected = arguments
+                .keys()
+                .map(ArgumentName::as_str)
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!("Unknown argument {argument} of field {field}, expected one of: {expected}")
+        })?;
+    let value = coerce(&argument_info.argument_type, value).map_err(|expected| {
+        format!(
+            "Invalid value {value} for argument {argument} of field {field}, expected {expected}"
+        )
+    })?;
+    Ok((
+        field_name.clone(),
+        argument_name.clone(),
+        Value::Literal(value),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::{FieldArgument, Type};
+    use open_dds::identifier::{Identifier, SubgraphName};
+    use std::collections::BTreeSet;
+
+    fn field_name(name: &str) -> FieldName {
+        FieldName::new(Identifier::new(name).unwrap())
+    }
+
+    fn argument_name(name: &str) -> ArgumentName {
+        ArgumentName::new(Identifier::new(name).unwrap())
+    }
+
+    fn type_name(name: &str) -> Qualified<CustomTypeName> {
+        Qualified::new(
+            SubgraphName::try_new("default").unwrap(),
+            CustomTypeName(Identifier::new(name).unwrap()),
+        )
+    }
+
+    // articles, with a `word_count` computed field that needs the `language` of the article and
+    // can be given a `min_length` for the words to count
+    fn article() -> ObjectType {
+        ObjectType {
+            type_fields: IndexMap::from_iter([
+                (
+                    field_name("title"),
+                    Type::Scalar(ndc_models::TypeRepresentation::String),
+                ),
+                (
+                    field_name("word_count"),
+                    Type::Scalar(ndc_models::TypeRepresentation::Int32),
+                ),
+            ]),
+            nullable_fields: BTreeSet::new(),
+            field_arguments: BTreeMap::from_iter([(
+                field_name("word_count"),
+                IndexMap::from_iter([
+                    (
+                        argument_name("language"),
+                        FieldArgument {
+                            argument_type: Type::Scalar(ndc_models::TypeRepresentation::String),
+                            required: true,
+                        },
+                    ),
+                    (
+                        argument_name("min_length"),
+                        FieldArgument {
+                            argument_type: Type::Scalar(ndc_models::TypeRepresentation::Int32),
+                            required: false,
+                        },
+                    ),
+                ]),
+            )]),
+            type_relationships: IndexMap::new(),
+        }
+    }
+
+    fn parse(query: &str) -> Result<FieldArguments, Vec<String>> {
+        FieldArguments::parse(
+            &type_name("Article"),
+            &article(),
+            &NamingStrategy::default(),
+            &QueryParams::parse(query),
+        )
+        .map_err(|errors| errors.into_iter().map(|error| error.detail).collect())
+    }
+
+    #[test]
+    fn test_field_arguments() {
+        let arguments = parse("args[word_count][language]=en&args[word_count][min_length]=4")
+            .unwrap()
+            .for_field(&type_name("Article"), &article(), &field_name("word_count"))
+            .unwrap();
+        assert_eq!(
+            arguments,
+            IndexMap::from_iter([
+                (
+                    argument_name("language"),
+                    Value::Literal(serde_json::json!("en"))
+                ),
+                (
+                    argument_name("min_length"),
+                    Value::Literal(serde_json::json!(4))
+                ),
+            ])
+        );
+
+        // fields without arguments need none
+        assert_eq!(
+            parse("")
+                .unwrap()
+                .for_field(&type_name("Article"), &article(), &field_name("title"))
+                .unwrap(),
+            IndexMap::new()
+        );
+    }
+
+    #[test]
+    fn test_missing_arguments() {
+        assert_eq!(
+            parse("args[word_count][min_length]=4")
+                .unwrap()
+                .for_field(&type_name("Article"), &article(), &field_name("word_count"))
+                .unwrap_err(),
+            "field word_count of Article needs the arguments language, given with \
+             args[word_count][language]=value"
+        );
+
+        // arguments are only given to the fields of the resources of the model
+        assert_eq!(
+            FieldArguments::none(&type_name("Author"), &NamingStrategy::default())
+                .for_field(&type_name("Article"), &article(), &field_name("word_count"))
+                .unwrap_err(),
+            "field word_count of Article needs the arguments language, which can only be given \
+             for the fields of Author"
+        );
+    }
+
+    #[test]
+    fn test_invalid_arguments() {
+        assert_eq!(
+            parse(
+                "args[word_count]=en&args[body][language]=en&args[title][language]=en\
+                 &args[word_count][lang]=en&args[word_count][min_length]=four"
+            )
+            .unwrap_err(),
+            vec![
+                "Invalid args[word_count], expected args[field][argument]=value",
+                "Unknown field body in Article",
+                "Field title of Article has no arguments",
+                "Unknown argument lang of field word_count, expected one of: language, min_length",
+                "Invalid value four for argument min_length of field word_count, expected an integer",
+            ]
+        );
+
+        assert_eq!(
+            parse("args[word_count][language]=en&args[word_count][language]=fr").unwrap_err(),
+            vec!["args[word_count][language] may only be given once"]
+        );
+    }
+}
@@ -0,0 +1,392 @@
+use crate::configuration::CursorSecret;
+use crate::types::ParameterError;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use hmac::{Hmac, Mac};
+use indexmap::IndexMap;
+use metadata_resolve::Qualified;
+use open_dds::{
+    models::{ModelName, OrderByDirection},
+    query::{BooleanExpression, ComparisonOperator, ObjectFieldOperand, Operand, OrderByElement},
+    types::FieldName,
+};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Keyset ("seek") pagination over `page[cursor]` tokens.
+//
+// A cursor records the sort key values of the last resource on a page. The next page is fetched
+// by filtering for rows that sort after those values rather than by skipping rows, so it stays
+// cheap for deep pages and does not skip or repeat resources when rows are inserted or removed
+// between requests.
+//
+// The sort keys are the requested `sort` fields followed by the unique identifier fields of the
+// model, which act as a tiebreaker so that the ordering is total.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keyset {
+    model: String,
+    keys: Vec<KeysetKey>,
+    // sort key values of the last resource on the previous page, if this is not the first page
+    after: Option<Vec<serde_json::Value>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct KeysetKey {
+    field_name: FieldName,
+    direction: OrderByDirection,
+}
+
+// the signed contents of a `page[cursor]` token. the model and sort keys are recorded so that a
+// cursor cannot be replayed against a different model or ordering.
+#[derive(Serialize, Deserialize)]
+struct CursorPayload {
+    #[serde(rename = "m")]
+    model: String,
+    #[serde(rename = "k")]
+    keys: Vec<(String, OrderByDirection)>,
+    #[serde(rename = "v")]
+    values: Vec<serde_json::Value>,
+}
+
+fn invalid_cursor() -> ParameterError {
+    ParameterError::new("page[cursor]", "Invalid page[cursor]")
+}
+
+impl Keyset {
+    pub fn new(
+        model_name: &Qualified<ModelName>,
+        order_by: &[OrderByElement],
+        unique_identifier: &[FieldName],
+    ) -> Result<Self, ParameterError> {
+        if unique_identifier.is_empty() {
+            return Err(ParameterError::new(
+                "page[cursor]",
+                format!(
+                    "Cursor pagination is not supported for model {}, as it has no unique identifier",
+                    model_name.name
+                ),
+            ));
+        }
+
+        let mut keys = order_by
+            .iter()
+            .map(|element| match &element.operand {
+                Operand::Field(ObjectFieldOperand {
+                    target,
+                    nested: None,
+                }) if target.arguments.is_empty() => Ok(KeysetKey {
+                    field_name: target.field_name.clone(),
+                    direction: element.direction.clone(),
+                }),
+                _ => Err(ParameterError::new(
+                    "sort",
+                    "Cursor pagination only supports sorting on fields without arguments",
+                )),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for field_name in unique_identifier {
+            if !keys.iter().any(|key| &key.field_name == field_name) {
+                keys.push(KeysetKey {
+                    field_name: field_name.clone(),
+                    direction: OrderByDirection::Asc,
+                });
+            }
+        }
+
+        Ok(Keyset {
+            model: model_name.to_string(),
+            keys,
+            after: None,
+        })
+    }
+
+    // continue from the position recorded in a `page[cursor]` token. an empty token means the
+    // first page.
+    pub fn resume_from(
+        mut self,
+        secret: &CursorSecret,
+        token: &str,
+    ) -> Result<Self, ParameterError> {
+        if token.is_empty() {
+            return Ok(self);
+        }
+
+        let (payload, signature) = token.split_once('.').ok_or_else(invalid_cursor)?;
+        let payload = URL_SAFE_NO_PAD
+            .decode(payload)
+            .map_err(|_| invalid_cursor())?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| invalid_cursor())?;
+
+        // check the signature before looking at the contents
+        let mut mac = hmac(secret);
+        mac.update(&payload);
+        mac.verify_slice(&signature).map_err(|_| invalid_cursor())?;
+
+        let payload: CursorPayload =
+            serde_json::from_slice(&payload).map_err(|_| invalid_cursor())?;
+
+        // the cursor must have been issued for this model and ordering
+        if payload.model != self.model
+            || payload.keys != self.payload_keys()
+            || payload.values.len() != self.keys.len()
+        {
+            return Err(invalid_cursor());
+        }
+
+        self.after = Some(payload.values);
+        Ok(self)
+    }
+
+    // fields the query must fetch so that we can build the cursor for the next page
+    pub fn fields(&self) -> Vec<FieldName> {
+        self.keys.iter().map(|key| key.field_name.clone()).collect()
+    }
+
+    // the ordering for the query: the requested sorts plus the unique identifier tiebreaker
+    pub fn order_by(&self) -> Vec<OrderByElement> {
+        self.keys
+            .iter()
+            .map(|key| OrderByElement {
+                operand: field_operand(&key.field_name),
+                direction: key.direction.clone(),
+            })
+            .collect()
+    }
+
+    // the predicate selecting rows that sort after the cursor position, ie for keys `(a, b)`
+    //
+    //   a > $a OR (a = $a AND b > $b)
+    //
+    // with `<` in place of `>` for descending keys.
+    pub fn predicate(&self) -> Option<BooleanExpression> {
+        let after = self.after.as_ref()?;
+
+        let disjuncts = (0..self.keys.len())
+            .map(|index| {
+                let mut conjuncts = self.keys[..index]
+                    .iter()
+                    .zip(after)
+                    .map(|(key, value)| {
+                        comparison(&key.field_name, ComparisonOperator::Equals, value)
+                    })
+                    .collect::<Vec<_>>();
+                let key = &self.keys[index];
+                let operator = match key.direction {
+                    OrderByDirection::Asc => ComparisonOperator::GreaterThan,
+                    OrderByDirection::Desc => ComparisonOperator::LessThan,
+                };
+                conjuncts.push(comparison(&key.field_name, operator, &after[index]));
+
+                if conjuncts.len() == 1 {
+                    conjuncts.remove(0)
+                } else {
+                    BooleanExpression::And(conjuncts)
+                }
+            })
+            .collect::<Vec<_>>();
+
+        Some(BooleanExpression::Or(disjuncts))
+    }
+
+    // the `page[cursor]` token for the page after `row`, the last row of the current page.
+    //
+    // there is no sensible keyset predicate for a null sort key, as connectors disagree on where
+    // nulls sort, so we return no cursor if any of the sort key values are null.
+    pub fn next_cursor(
+        &self,
+        secret: &CursorSecret,
+        row: &IndexMap<ndc_models::FieldName, ndc_models::RowFieldValue>,
+    ) -> Option<String> {
+        let values = self
+            .keys
+            .iter()
+            .map(
+                |key| match row.get(&ndc_models::FieldName::from(key.field_name.as_str())) {
+                    None | Some(ndc_models::RowFieldValue(serde_json::Value::Null)) => None,
+                    Some(ndc_models::RowFieldValue(value)) => Some(value.clone()),
+                },
+            )
+            .collect::<Option<Vec<_>>>()?;
+
+        let payload = serde_json::to_vec(&CursorPayload {
+            model: self.model.clone(),
+            keys: self.payload_keys(),
+            values,
+        })
+        .ok()?;
+
+        let mut mac = hmac(secret);
+        mac.update(&payload);
+        let signature = mac.finalize().into_bytes();
+
+        Some(format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(payload),
+            URL_SAFE_NO_PAD.encode(signature)
+        ))
+    }
+
+    fn payload_keys(&self) -> Vec<(String, OrderByDirection)> {
+        self.keys
+            .iter()
+            .map(|key| (key.field_name.to_string(), key.direction.clone()))
+            .collect()
+    }
+}
+
+fn hmac(secret: &CursorSecret) -> HmacSha256 {
+    HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length")
+}
+
+fn field_operand(field_name: &FieldName) -> Operand {
+    Operand::Field(ObjectFieldOperand {
+        target: Box::new(open_dds::query::ObjectFieldTarget {
+            field_name: field_name.clone(),
+            arguments: IndexMap::new(),
+        }),
+        nested: None,
+    })
+}
+
+fn comparison(
+    field_name: &FieldName,
+    operator: ComparisonOperator,
+    value: &serde_json::Value,
+) -> BooleanExpression {
+    BooleanExpression::Comparison {
+        operand: field_operand(field_name),
+        operator,
+        argument: Box::new(open_dds::query::Value::Literal(value.clone())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use open_dds::identifier::{Identifier, SubgraphName};
+
+    fn field_name(name: &str) -> FieldName {
+        FieldName::new(Identifier::new(name).unwrap())
+    }
+
+    fn model_name(name: &str) -> Qualified<ModelName> {
+        Qualified::new(
+            SubgraphName::try_new("default").unwrap(),
+            ModelName::new(Identifier::new(name).unwrap()),
+        )
+    }
+
+    // `sort=-title` on a model identified by `article_id`
+    fn articles_keyset() -> Keyset {
+        let sort = [OrderByElement {
+            operand: field_operand(&field_name("title")),
+            direction: OrderByDirection::Desc,
+        }];
+        Keyset::new(&model_name("Articles"), &sort, &[field_name("article_id")]).unwrap()
+    }
+
+    fn last_row() -> IndexMap<ndc_models::FieldName, ndc_models::RowFieldValue> {
+        IndexMap::from_iter([
+            (
+                ndc_models::FieldName::from("title"),
+                ndc_models::RowFieldValue(serde_json::json!("The Next 700")),
+            ),
+            (
+                ndc_models::FieldName::from("article_id"),
+                ndc_models::RowFieldValue(serde_json::json!(3)),
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_cursor_round_trip() {
+        let secret = CursorSecret::new("secret");
+        let keyset = articles_keyset();
+
+        // the unique identifier is appended as an ascending tiebreaker
+        assert_eq!(
+            keyset.fields(),
+            vec![field_name("title"), field_name("article_id")]
+        );
+        assert_eq!(keyset.predicate(), None);
+
+        let token = keyset.next_cursor(&secret, &last_row()).unwrap();
+        let resumed = articles_keyset().resume_from(&secret, &token).unwrap();
+        assert_eq!(
+            resumed.after,
+            Some(vec![
+                serde_json::json!("The Next 700"),
+                serde_json::json!(3)
+            ])
+        );
+
+        // title < $title OR (title = $title AND article_id > $article_id)
+        assert_eq!(
+            resumed.predicate(),
+            Some(BooleanExpression::Or(vec![
+                comparison(
+                    &field_name("title"),
+                    ComparisonOperator::LessThan,
+                    &serde_json::json!("The Next 700")
+                ),
+                BooleanExpression::And(vec![
+                    comparison(
+                        &field_name("title"),
+                        ComparisonOperator::Equals,
+                        &serde_json::json!("The Next 700")
+                    ),
+                    comparison(
+                        &field_name("article_id"),
+                        ComparisonOperator::GreaterThan,
+                        &serde_json::json!(3)
+                    ),
+                ]),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_cursor_rejects_tampering() {
+        let secret = CursorSecret::new("secret");
+        let token = articles_keyset().next_cursor(&secret, &last_row()).unwrap();
+        let (_, signature) = token.split_once('.').unwrap();
+
+        // forged payload with the original signature
+        let forged_payload = URL_SAFE_NO_PAD.encode(
+            r#"{"m":"default:Articles","k":[["title","Desc"],["article_id","Asc"]],"v":["A",1]}"#,
+        );
+        let forged = format!("{forged_payload}.{signature}");
+
+        for token in [forged.as_str(), "not-a-cursor", "a.b"] {
+            assert_eq!(
+                articles_keyset()
+                    .resume_from(&secret, token)
+                    .unwrap_err()
+                    .detail,
+                "Invalid page[cursor]"
+            );
+        }
+
+        // signed with a different secret
+        assert!(articles_keyset()
+            .resume_from(&CursorSecret::new("other secret"), &token)
+            .is_err());
+    }
+
+    #[test]
+    fn test_cursor_rejects_other_ordering() {
+        let secret = CursorSecret::new("secret");
+        let token = articles_keyset().next_cursor(&secret, &last_row()).unwrap();
+
+        // the same cursor with the default ordering
+        let unsorted = Keyset::new(&model_name("Articles"), &[], &[field_name("article_id")]);
+        assert!(unsorted.unwrap().resume_from(&secret, &token).is_err());
+
+        // models without a unique identifier can't be paginated by cursor
+        assert!(Keyset::new(&model_name("Articles"), &[], &[]).is_err());
+    }
+}
@@ -0,0 +1,276 @@
+use crate::catalog::ObjectType;
+use crate::naming::NamingStrategy;
+use crate::types::ParameterError;
+use metadata_resolve::Qualified;
+use open_dds::types::{CustomTypeName, FieldName};
+use std::collections::{BTreeMap, BTreeSet};
+
+// Represents the parsed "fields[TYPE]" query parameters, ie a per-type allow-list of attributes
+// spec: <https://jsonapi.org/format/#fetching-sparse-fieldsets>
+#[derive(Debug, Default)]
+pub struct SparseFields {
+    pub fields: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl SparseFields {
+    pub fn parse<'a>(fields: impl IntoIterator<Item = (&'a String, &'a Vec<String>)>) -> Self {
+        let fields = fields
+            .into_iter()
+            .map(|(type_name, type_fields)| {
+                // `fields[Articles]=` means "no attributes", so we drop any empty field names
+                // rather than treating them as unknown fields
+                let type_fields = type_fields
+                    .iter()
+                    .map(String::as_str)
+                    .map(str::trim)
+                    .filter(|type_field| !type_field.is_empty())
+                    .map(ToString::to_string)
+                    .collect();
+                (type_name.trim().to_string(), type_fields)
+            })
+            .collect();
+        SparseFields { fields }
+    }
+
+    // no attributes for any of these types, eg for resource linkage, which is only `type` and `id`
+    pub fn no_attributes<'a>(type_names: impl IntoIterator<Item = &'a CustomTypeName>) -> Self {
+        let fields = type_names
+            .into_iter()
+            .map(|type_name| (type_name.0.as_str().to_string(), BTreeSet::new()))
+            .collect();
+        SparseFields { fields }
+    }
+
+    // check all types in sparse fields are accessible,
+    // for each type check all fields in sparse fields are accessible, explode if not.
+    // every problem is reported, not just the first.
+    //
+    // types and fields are requested by their names in documents, which depend on the naming
+    // strategy, and are replaced here by their names in metadata. unknown ones are left as they
+    // are, and reported.
+    pub fn resolve(
+        &mut self,
+        object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectType>,
+        naming: &NamingStrategy,
+    ) -> Result<(), Vec<ParameterError>> {
+        let mut errors = vec![];
+        let mut resolved_fields = BTreeMap::new();
+        for (type_name, type_fields) in std::mem::take(&mut self.fields) {
+            let parameter = format!("fields[{type_name}]");
+            let Some((object_type_name, object_type)) =
+                object_types.iter().find(|(object_type_name, _)| {
+                    naming.type_name(&object_type_name.name) == type_name
+                })
+            else {
+                errors.push(ParameterError::new(
+                    parameter,
+                    format!("Unknown type in sparse fields: {type_name}"),
+                ));
+                resolved_fields.insert(type_name, type_fields);
+                continue;
+            };
+
+            let mut resolved_type_fields = BTreeSet::new();
+            for type_field in type_fields {
+                match object_type
+                    .type_fields
+                    .keys()
+                    .find(|field_name| naming.field_name(field_name.as_str()) == type_field)
+                {
+                    Some(field_name) => {
+                        resolved_type_fields.insert(field_name.as_str().to_string());
+                    }
+                    None => {
+                        errors.push(ParameterError::new(
+                            parameter.clone(),
+                            format!("Unknown field in sparse fields: {type_field} in {type_name}"),
+                        ));
+                        resolved_type_fields.insert(type_field);
+                    }
+                }
+            }
+            resolved_fields
+                .entry(object_type_name.name.0.as_str().to_string())
+                .or_default()
+                .extend(resolved_type_fields);
+        }
+        self.fields = resolved_fields;
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    // given the sparse fields for this request, should be include a given field in the query?
+    // this does not consider subgraphs at the moment - we match on `CustomTypeName` not
+    // `Qualified<CustomTypeName>`.
+    // This means that the below field is ambiguous where `Authors` type is defined in multiple
+    // subgraphs
+    // fields[Authors]=author_id,first_name
+    //
+    // This will need to be solved when we make users give JSONAPI types explicit names
+    // like we do in GraphQL
+    //
+    // fields[subgraphAuthors]=author_id,firstName&fields[otherAuthors]=author_id,last_name
+    pub fn include_field(&self, object_type_name: &CustomTypeName, field_name: &FieldName) -> bool {
+        match self.fields.get(object_type_name.0.as_str()) {
+            Some(object_fields) => object_fields.contains(field_name.as_str()),
+            // if no sparse fields provided for our model, return everything
+            None => true,
+        }
+    }
+
+    // whether a field is asked for by name, rather than by there being no sparse fields for its
+    // type
+    pub fn names_field(&self, object_type_name: &CustomTypeName, field_name: &FieldName) -> bool {
+        self.fields
+            .get(object_type_name.0.as_str())
+            .is_some_and(|object_fields| object_fields.contains(field_name.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::Type;
+    use indexmap::IndexMap;
+    use open_dds::identifier::{Identifier, SubgraphName};
+
+    fn field_name(name: &str) -> FieldName {
+        FieldName::new(Identifier::new(name).unwrap())
+    }
+
+    fn article_object_types() -> BTreeMap<Qualified<CustomTypeName>, ObjectType> {
+        let type_fields = ["title", "body", "author_id"]
+            .into_iter()
+            .map(|name| {
+                (
+                    field_name(name),
+                    Type::Scalar(ndc_models::TypeRepresentation::String),
+                )
+            })
+            .collect();
+        BTreeMap::from_iter([(
+            Qualified::new(
+                SubgraphName::try_new("default").unwrap(),
+                CustomTypeName(Identifier::new("Articles").unwrap()),
+            ),
+            ObjectType {
+                type_fields,
+                nullable_fields: BTreeSet::new(),
+                field_arguments: BTreeMap::new(),
+                type_relationships: IndexMap::new(),
+            },
+        )])
+    }
+
+    #[test]
+    fn test_parse_sparse_fields() {
+        let input = BTreeMap::from_iter([(
+            "Articles".to_string(),
+            vec!["title".to_string(), " body ".to_string()],
+        )]);
+        let mut sparse_fields = SparseFields::parse(&input);
+        let article = CustomTypeName(Identifier::new("Articles").unwrap());
+        let author = CustomTypeName(Identifier::new("Authors").unwrap());
+
+        assert!(sparse_fields.include_field(&article, &field_name("title")));
+        assert!(sparse_fields.include_field(&article, &field_name("body")));
+        assert!(!sparse_fields.include_field(&article, &field_name("author_id")));
+        // types without sparse fields get everything
+        assert!(sparse_fields.include_field(&author, &field_name("name")));
+        assert!(sparse_fields
+            .resolve(&article_object_types(), &NamingStrategy::default())
+            .is_ok());
+    }
+
+    #[test]
+    fn test_parse_empty_sparse_fields() {
+        // `fields[Articles]=` selects no attributes at all
+        let input = BTreeMap::from_iter([("Articles".to_string(), vec![String::new()])]);
+        let mut sparse_fields = SparseFields::parse(&input);
+        let article = CustomTypeName(Identifier::new("Articles").unwrap());
+
+        assert!(!sparse_fields.include_field(&article, &field_name("title")));
+        assert!(!sparse_fields.include_field(&article, &field_name("body")));
+        assert!(sparse_fields
+            .resolve(&article_object_types(), &NamingStrategy::default())
+            .is_ok());
+    }
+
+    #[test]
+    fn test_validate_sparse_fields() {
+        let unknown_type =
+            BTreeMap::from_iter([("Unknown".to_string(), vec!["title".to_string()])]);
+        let errors = SparseFields::parse(&unknown_type)
+            .resolve(&article_object_types(), &NamingStrategy::default())
+            .unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].parameter, "fields[Unknown]");
+        assert_eq!(errors[0].detail, "Unknown type in sparse fields: Unknown");
+
+        let unknown_field =
+            BTreeMap::from_iter([("Articles".to_string(), vec!["nope".to_string()])]);
+        let errors = SparseFields::parse(&unknown_field)
+            .resolve(&article_object_types(), &NamingStrategy::default())
+            .unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].parameter, "fields[Articles]");
+        assert_eq!(
+            errors[0].detail,
+            "Unknown field in sparse fields: nope in Articles"
+        );
+
+        // all of the unknown fields are reported
+        let unknown_fields = BTreeMap::from_iter([(
+            "Articles".to_string(),
+            vec!["nope".to_string(), "title".to_string(), "nada".to_string()],
+        )]);
+        let errors = SparseFields::parse(&unknown_fields)
+            .resolve(&article_object_types(), &NamingStrategy::default())
+            .unwrap_err();
+        assert_eq!(
+            errors
+                .iter()
+                .map(|error| error.detail.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "Unknown field in sparse fields: nada in Articles",
+                "Unknown field in sparse fields: nope in Articles",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve_sparse_fields_with_naming_strategy() {
+        let naming = NamingStrategy {
+            case: crate::naming::NameCase::CamelCase,
+            pluralize_types: false,
+        };
+        let input = BTreeMap::from_iter([(
+            "articles".to_string(),
+            vec!["title".to_string(), "authorId".to_string()],
+        )]);
+        let mut sparse_fields = SparseFields::parse(&input);
+        assert!(sparse_fields
+            .resolve(&article_object_types(), &naming)
+            .is_ok());
+
+        // the type and fields are resolved to their names in metadata
+        let article = CustomTypeName(Identifier::new("Articles").unwrap());
+        assert!(sparse_fields.include_field(&article, &field_name("title")));
+        assert!(sparse_fields.include_field(&article, &field_name("author_id")));
+        assert!(!sparse_fields.include_field(&article, &field_name("body")));
+
+        // names in metadata are not accepted when they are written differently in documents
+        let input = BTreeMap::from_iter([("articles".to_string(), vec!["author_id".to_string()])]);
+        let errors = SparseFields::parse(&input)
+            .resolve(&article_object_types(), &naming)
+            .unwrap_err();
+        assert_eq!(
+            errors[0].detail,
+            "Unknown field in sparse fields: author_id in articles"
+        );
+    }
+}
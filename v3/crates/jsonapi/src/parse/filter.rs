@@ -1,295 +1,1320 @@
-use crate::catalog::Model;
+use super::arguments::FieldArguments;
+use super::QueryParams;
+use crate::catalog::{Model, ObjectType, RelationshipTarget, ScalarRepresentation, Type};
+use crate::naming::NamingStrategy;
+use crate::types::ParameterError;
 use indexmap::IndexMap;
-use metadata_resolve::Qualified;
-use open_dds::query::{BooleanExpression, ObjectFieldOperand, ObjectFieldTarget, Operand, Value};
-use open_dds::{identifier::Identifier, models::ModelName, types::FieldName};
-use serde::{Deserialize, Serialize};
-
-#[derive(Debug, Clone, PartialEq, Eq)]
-enum JsonApiFilter {
-    And {
-        and: Vec<JsonApiFilter>,
+use metadata_resolve::{
+    ComparisonExpressionInfo, IncludeLogicalOperators, Qualified, QualifiedBaseType,
+    ResolvedObjectBooleanExpressionType,
+};
+use open_dds::{
+    data_connector::DataConnectorName,
+    query::{
+        ArgumentName, BooleanExpression, ComparisonOperator, ObjectFieldOperand, ObjectFieldTarget,
+        Operand, Value,
     },
-    Or {
-        or: Vec<JsonApiFilter>,
-    },
-    Nested {
-        field_name: String,
-        rest: Box<JsonApiFilter>,
-    },
-    Comparison {
-        field_name: String,
-        operator: String,
-        comparison_value: serde_json::Value,
-    },
-}
+    relationships::{RelationshipName, RelationshipType},
+    types::{CustomTypeName, FieldName},
+};
+use std::collections::BTreeMap;
 
-#[derive(Debug, derive_more::Display, Serialize, Deserialize)]
-pub enum FilterError {
-    NoBooleanExpressionDefined(Qualified<ModelName>),
-}
+const FILTER: &str = "filter";
+const AND: &str = "_and";
+const OR: &str = "_or";
+const NOT: &str = "_not";
+const IS_NULL: &str = "_is_null";
+const IN: &str = "_in";
 
+// Build the filter for the `filter[...]` query parameters
+// spec: <https://jsonapi.org/format/#fetching-filtering> (the strategy is left to the server)
+//
+// each parameter is a bracketed path ending in an operator, eg
+//
+//   filter[status][_eq]=published&filter[author][name][_ilike]=%jane%
+//
+// - the path is a field of the model, or an object relationship followed by a path into the
+//   related model, or a relationship followed by `_is_null` for whether there is no related
+//   resource, eg `filter[comments][_is_null]=true` for articles without comments
+// - the operator is one of the operators for that field in the boolean expression type, by its
+//   name in metadata or the name the data connector gives it, or `_is_null` (`true` or `false`),
+//   or `_in` (a comma separated list of values)
+// - `filter[_and][i]...` and `filter[_or][i]...` group the filters sharing the index `i`, and
+//   `filter[_not]...` negates the filters below it
+// - separate parameters are combined with `and`
+//
+// only the fields and relationships the role can see can be filtered on, by their names in
+// documents, following the naming strategy. fields with arguments are filtered on with the
+// arguments given to them in `args[...]`. timestamps with a time zone are in UTC unless they
+// have an offset, whatever `timezone` the response is written in.
 pub fn build_boolean_expression(
     model: &Model,
-    filter: &serde_json::Value,
-) -> Result<open_dds::query::BooleanExpression, FilterError> {
-    // filters in jsonapi-rust work like
-    // `filter={"name":{"$eq":"Horse"},"age":{"$gt":100}}`
-    // with multiple expressions that we should && together
+    object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectType>,
+    boolean_expression_types: &BTreeMap<
+        Qualified<CustomTypeName>,
+        ResolvedObjectBooleanExpressionType,
+    >,
+    naming: &NamingStrategy,
+    field_arguments: &FieldArguments,
+    query_params: &QueryParams,
+) -> Result<Option<BooleanExpression>, Vec<ParameterError>> {
+    let mut errors = vec![];
+    let mut filters = vec![];
+    for (parameter, value) in query_params.iter() {
+        if parameter == FILTER {
+            errors.push(ParameterError::new(
+                FILTER,
+                "Filters must be given as filter[field][operator]=value",
+            ));
+        } else if let Some(path) = parameter
+            .strip_prefix(FILTER)
+            .filter(|path| path.starts_with('['))
+        {
+            match parse_path(path) {
+                Some(path) => filters.push(Filter {
+                    parameter,
+                    path,
+                    depth: 0,
+                    value,
+                }),
+                None => errors.push(ParameterError::new(
+                    parameter,
+                    format!("Invalid filter {parameter}, expected filter[field][operator]=value"),
+                )),
+            }
+        }
+    }
 
-    if model.filter_expression_type.is_some() {
-        // only include a filter if the model has a `BooleanExpressionType`
-        let parsed_filter = parse_filter_value(filter).unwrap();
+    if filters.is_empty() {
+        return if errors.is_empty() {
+            Ok(None)
+        } else {
+            Err(errors)
+        };
+    }
 
-        Ok(expression_from_jsonapi_filter(&parsed_filter).unwrap())
+    // only include a filter if the model has a `BooleanExpressionType`
+    let Some(boolean_expression_type) = &model.filter_expression_type else {
+        errors.push(ParameterError::new(
+            FILTER,
+            format!("Model {} does not support filtering", model.name.name),
+        ));
+        return Err(errors);
+    };
+
+    let builder = FilterBuilder {
+        object_types,
+        boolean_expression_types,
+        naming,
+        field_arguments,
+    };
+    let expression = builder.build(
+        &model.data_type,
+        Some(&model.data_connector_name),
+        boolean_expression_type,
+        filters,
+        &mut errors,
+    );
+    if errors.is_empty() {
+        Ok(expression)
     } else {
-        Err(FilterError::NoBooleanExpressionDefined(model.name.clone()))
+        Err(errors)
     }
 }
 
-// not sure if we should be validating boolean expression against the BooleanExpressionType here
-// or whether we expect that to happen in planning
-fn expression_from_jsonapi_filter(
-    filter: &JsonApiFilter,
-) -> Result<open_dds::query::BooleanExpression, ()> {
-    match filter {
-        JsonApiFilter::Comparison {
-            field_name,
-            comparison_value,
-            operator,
-        } => {
-            let field_name = FieldName::new(Identifier::new(field_name).unwrap());
+// a `filter[...]` parameter, and how far along its path we are
+struct Filter<'a> {
+    parameter: &'a str,
+    path: Vec<String>,
+    depth: usize,
+    value: &'a str,
+}
 
-            let field_operand = Operand::Field(ObjectFieldOperand {
-                target: Box::new(ObjectFieldTarget {
-                    field_name,
-                    arguments: IndexMap::new(),
-                }),
-                nested: None,
+impl Filter<'_> {
+    // the bracketed path up to the current segment, used to point at the offending segment in
+    // errors
+    fn parameter_path(&self) -> String {
+        let segments = self.path[..self.depth]
+            .iter()
+            .map(|segment| format!("[{segment}]"))
+            .collect::<String>();
+        format!("{FILTER}{segments}")
+    }
+}
+
+// split `[a][b][c]` into its segments
+pub(super) fn parse_path(mut path: &str) -> Option<Vec<String>> {
+    let mut segments = vec![];
+    while !path.is_empty() {
+        let (segment, rest) = path.strip_prefix('[')?.split_once(']')?;
+        if segment.is_empty() {
+            return None;
+        }
+        segments.push(segment.to_string());
+        path = rest;
+    }
+    Some(segments)
+}
+
+// group filters by their current segment, moving past it. filters that have run out of segments
+// are reported as incomplete.
+fn group_by_segment<'a>(
+    filters: Vec<Filter<'a>>,
+    expected: &str,
+    errors: &mut Vec<ParameterError>,
+) -> IndexMap<String, Vec<Filter<'a>>> {
+    let mut groups: IndexMap<String, Vec<Filter<'a>>> = IndexMap::new();
+    for mut filter in filters {
+        match filter.path.get(filter.depth).cloned() {
+            Some(segment) => {
+                filter.depth += 1;
+                groups.entry(segment).or_default().push(filter);
+            }
+            None => errors.push(ParameterError::new(
+                filter.parameter,
+                format!(
+                    "Incomplete filter {}, expected {expected}",
+                    filter.parameter
+                ),
+            )),
+        }
+    }
+    groups
+}
+
+fn conjunction(mut expressions: Vec<BooleanExpression>) -> BooleanExpression {
+    if expressions.len() == 1 {
+        expressions.remove(0)
+    } else {
+        BooleanExpression::And(expressions)
+    }
+}
+
+struct FilterBuilder<'a> {
+    object_types: &'a BTreeMap<Qualified<CustomTypeName>, ObjectType>,
+    boolean_expression_types:
+        &'a BTreeMap<Qualified<CustomTypeName>, ResolvedObjectBooleanExpressionType>,
+    naming: &'a NamingStrategy,
+    field_arguments: &'a FieldArguments,
+}
+
+impl FilterBuilder<'_> {
+    // build the filters on an object type, whose resources are those of `data_connector_name`.
+    // problems are added to `errors`, in which case we return `None`.
+    fn build(
+        &self,
+        object_type_name: &Qualified<CustomTypeName>,
+        data_connector_name: Option<&Qualified<DataConnectorName>>,
+        boolean_expression_type: &ResolvedObjectBooleanExpressionType,
+        filters: Vec<Filter<'_>>,
+        errors: &mut Vec<ParameterError>,
+    ) -> Option<BooleanExpression> {
+        let groups = group_by_segment(filters, "a field, relationship or _and/_or/_not", errors);
+        let mut expressions = vec![];
+        let mut valid = true;
+        for (segment, filters) in groups {
+            let expression = match segment.as_str() {
+                AND | OR | NOT
+                    if boolean_expression_type.include_logical_operators
+                        == IncludeLogicalOperators::No =>
+                {
+                    errors.push(ParameterError::new(
+                        filters[0].parameter_path(),
+                        format!(
+                            "Logical operators are not supported when filtering {}",
+                            object_type_name.name
+                        ),
+                    ));
+                    None
+                }
+                AND | OR => {
+                    let groups = group_by_segment(
+                        filters,
+                        &format!("an index, eg {FILTER}[{segment}][0][field][operator]"),
+                        errors,
+                    );
+                    // build every operand before checking them, so that we report the
+                    // problems with all of them
+                    let operands = groups
+                        .into_values()
+                        .map(|filters| {
+                            self.build(
+                                object_type_name,
+                                data_connector_name,
+                                boolean_expression_type,
+                                filters,
+                                errors,
+                            )
+                        })
+                        .collect::<Vec<_>>();
+                    operands
+                        .into_iter()
+                        .collect::<Option<Vec<_>>>()
+                        .map(|operands| {
+                            if segment == AND {
+                                BooleanExpression::And(operands)
+                            } else {
+                                BooleanExpression::Or(operands)
+                            }
+                        })
+                }
+                NOT => self
+                    .build(
+                        object_type_name,
+                        data_connector_name,
+                        boolean_expression_type,
+                        filters,
+                        errors,
+                    )
+                    .map(|expression| BooleanExpression::Not(Box::new(expression))),
+                _ => self.build_field_or_relationship(
+                    object_type_name,
+                    data_connector_name,
+                    boolean_expression_type,
+                    &segment,
+                    filters,
+                    errors,
+                ),
+            };
+            match expression {
+                Some(expression) => expressions.push(expression),
+                None => valid = false,
+            }
+        }
+        valid.then(|| conjunction(expressions))
+    }
+
+    fn build_field_or_relationship(
+        &self,
+        object_type_name: &Qualified<CustomTypeName>,
+        data_connector_name: Option<&Qualified<DataConnectorName>>,
+        boolean_expression_type: &ResolvedObjectBooleanExpressionType,
+        segment: &str,
+        filters: Vec<Filter<'_>>,
+        errors: &mut Vec<ParameterError>,
+    ) -> Option<BooleanExpression> {
+        match self.resolve_segment(object_type_name, boolean_expression_type, segment) {
+            Ok(Segment::Field {
+                field_name,
+                field_type,
+                comparison,
+                arguments,
+            }) => build_comparisons(
+                &field_operand_with_arguments(field_name, arguments),
+                field_name,
+                field_type,
+                comparison,
+                data_connector_name,
+                filters,
+                errors,
+            ),
+            Ok(Segment::Relationship {
+                relationship_name,
+                relationship_type,
+                object_type_name: target_object_type_name,
+                data_connector_name: target_data_connector_name,
+                boolean_expression_type: target_boolean_expression_type,
+            }) => {
+                // `filter[relationship][_is_null]` is an existence check, the rest are filters on
+                // the related resource
+                let (existence_filters, filters): (Vec<_>, Vec<_>) =
+                    filters.into_iter().partition(|filter| {
+                        filter
+                            .path
+                            .get(filter.depth)
+                            .is_some_and(|segment| segment == IS_NULL)
+                    });
+                let mut expressions = vec![];
+                let mut valid = true;
+                for mut filter in existence_filters {
+                    filter.depth += 1;
+                    match build_existence(relationship_name, &filter) {
+                        Ok(expression) => expressions.push(expression),
+                        Err(detail) => {
+                            errors.push(ParameterError::new(filter.parameter_path(), detail));
+                            valid = false;
+                        }
+                    }
+                }
+                if !filters.is_empty() {
+                    let predicate = match (relationship_type, target_boolean_expression_type) {
+                        (RelationshipType::Object, Some(target_boolean_expression_type)) => self
+                            .build(
+                                target_object_type_name,
+                                target_data_connector_name,
+                                target_boolean_expression_type,
+                                filters,
+                                errors,
+                            ),
+                        (RelationshipType::Object, None) => {
+                            errors.push(ParameterError::new(
+                                filters[0].parameter_path(),
+                                format!(
+                                    "Relationship {segment} of {} cannot be filtered on",
+                                    object_type_name.name
+                                ),
+                            ));
+                            None
+                        }
+                        (RelationshipType::Array, _) => {
+                            errors.push(ParameterError::new(
+                                filters[0].parameter_path(),
+                                format!(
+                                    "Relationship {segment} of {} is a to-many relationship, so can only be filtered on with {IS_NULL}",
+                                    object_type_name.name
+                                ),
+                            ));
+                            None
+                        }
+                    };
+                    match predicate {
+                        Some(predicate) => expressions.push(BooleanExpression::Relationship {
+                            operand: None,
+                            relationship_name: relationship_name.clone(),
+                            predicate: Box::new(predicate),
+                        }),
+                        None => valid = false,
+                    }
+                }
+                valid.then(|| conjunction(expressions))
+            }
+            Err(detail) => {
+                errors.push(ParameterError::new(filters[0].parameter_path(), detail));
+                None
+            }
+        }
+    }
+
+    // look up a path segment, which is either a field or an object relationship
+    fn resolve_segment<'b>(
+        &'b self,
+        object_type_name: &Qualified<CustomTypeName>,
+        boolean_expression_type: &'b ResolvedObjectBooleanExpressionType,
+        segment: &str,
+    ) -> Result<Segment<'b>, String> {
+        let object_type = self
+            .object_types
+            .get(object_type_name)
+            .ok_or_else(|| format!("type {} is not accessible", object_type_name.name))?;
+
+        if let Some((field_name, field_type)) = object_type
+            .type_fields
+            .iter()
+            .find(|(field_name, _)| self.naming.field_name(field_name.as_str()) == segment)
+        {
+            let comparison = boolean_expression_type
+                .fields
+                .scalar_fields
+                .get(field_name)
+                .ok_or_else(|| {
+                    format!(
+                        "Field {segment} of {} cannot be filtered on",
+                        object_type_name.name
+                    )
+                })?;
+            let arguments = self
+                .field_arguments
+                .for_field(object_type_name, object_type, field_name)
+                .map_err(|detail| format!("Cannot filter on {segment}: {detail}"))?;
+            return Ok(Segment::Field {
+                field_name,
+                field_type,
+                comparison,
+                arguments,
             });
+        }
+
+        let (relationship_name, target) = object_type
+            .type_relationships
+            .iter()
+            .find(|(relationship_name, _)| {
+                self.naming.field_name(relationship_name.as_str()) == segment
+            })
+            .ok_or_else(|| format!("Unknown field {segment} in {}", object_type_name.name))?;
+        let RelationshipTarget::Model {
+            object_type: target_object_type,
+            relationship_type,
+            data_connector_name,
+            ..
+        } = target
+        else {
+            return Err(format!(
+                "Relationship {segment} of {} is not a relationship to a model",
+                object_type_name.name
+            ));
+        };
+        let relationship_field = boolean_expression_type
+            .fields
+            .relationship_fields
+            .get(&FieldName::new(relationship_name.clone().into_inner()))
+            .ok_or_else(|| {
+                format!(
+                    "Relationship {segment} of {} cannot be filtered on",
+                    object_type_name.name
+                )
+            })?;
+        Ok(Segment::Relationship {
+            relationship_name,
+            relationship_type,
+            object_type_name: target_object_type,
+            data_connector_name: data_connector_name.as_ref(),
+            boolean_expression_type: self
+                .boolean_expression_types
+                .get(&relationship_field.boolean_expression_type),
+        })
+    }
+}
 
-            let operator = open_dds::types::OperatorName::new(operator.into());
+enum Segment<'a> {
+    Field {
+        field_name: &'a FieldName,
+        field_type: &'a Type,
+        comparison: &'a ComparisonExpressionInfo,
+        // the arguments of a field that takes them
+        arguments: IndexMap<ArgumentName, Value>,
+    },
+    Relationship {
+        relationship_name: &'a RelationshipName,
+        relationship_type: &'a RelationshipType,
+        object_type_name: &'a Qualified<CustomTypeName>,
+        data_connector_name: Option<&'a Qualified<DataConnectorName>>,
+        // the boolean expression type of the related model, without which it can only be checked
+        // for existence
+        boolean_expression_type: Option<&'a ResolvedObjectBooleanExpressionType>,
+    },
+}
 
-            let comparison = BooleanExpression::Comparison {
-                operand: field_operand,
-                argument: Box::new(Value::Literal(comparison_value.clone())),
-                operator: open_dds::query::ComparisonOperator::Custom(operator),
+// `filter[relationship][_is_null]=true` for when there is no related resource, eg articles without
+// comments, and `false` for when there is at least one
+fn build_existence(
+    relationship_name: &RelationshipName,
+    filter: &Filter<'_>,
+) -> Result<BooleanExpression, String> {
+    if filter.depth < filter.path.len() {
+        return Err(format!(
+            "Invalid filter {}, nothing may follow the operator {IS_NULL}",
+            filter.parameter
+        ));
+    }
+    let exists = BooleanExpression::Relationship {
+        operand: None,
+        relationship_name: relationship_name.clone(),
+        predicate: Box::new(BooleanExpression::And(vec![])),
+    };
+    match filter.value {
+        "true" => Ok(BooleanExpression::Not(Box::new(exists))),
+        "false" => Ok(exists),
+        _ => Err(format!("The {IS_NULL} operator expects true or false")),
+    }
+}
+
+fn build_comparisons(
+    operand: &Operand,
+    field_name: &FieldName,
+    field_type: &Type,
+    comparison: &ComparisonExpressionInfo,
+    data_connector_name: Option<&Qualified<DataConnectorName>>,
+    filters: Vec<Filter<'_>>,
+    errors: &mut Vec<ParameterError>,
+) -> Option<BooleanExpression> {
+    let groups = group_by_segment(
+        filters,
+        &format!("an operator, eg {FILTER}[{field_name}][_eq]"),
+        errors,
+    );
+    let mut expressions = vec![];
+    let mut valid = true;
+    for (operator, filters) in groups {
+        for filter in filters {
+            let result = if filter.depth < filter.path.len() {
+                Err(format!(
+                    "Invalid filter {}, nothing may follow the operator {operator}",
+                    filter.parameter
+                ))
+            } else {
+                build_comparison(
+                    operand,
+                    field_name,
+                    field_type,
+                    comparison,
+                    data_connector_name,
+                    &operator,
+                    filter.value,
+                )
             };
+            match result {
+                Ok(expression) => expressions.push(expression),
+                Err(detail) => {
+                    errors.push(ParameterError::new(filter.parameter_path(), detail));
+                    valid = false;
+                }
+            }
+        }
+    }
+    valid.then(|| conjunction(expressions))
+}
+
+fn build_comparison(
+    operand: &Operand,
+    field_name: &FieldName,
+    field_type: &Type,
+    comparison: &ComparisonExpressionInfo,
+    data_connector_name: Option<&Qualified<DataConnectorName>>,
+    operator: &str,
+    value: &str,
+) -> Result<BooleanExpression, String> {
+    let operand = operand.clone();
+
+    // operators from the boolean expression type take precedence over our built-in ones. they
+    // are named as they are in metadata, or as the data connector names them, eg `_fts` for a
+    // full-text search operator named `search` in metadata.
+    let operator_mapping =
+        data_connector_name.and_then(|name| comparison.operator_mapping.get(name));
+    let custom_operator = comparison
+        .operators
+        .iter()
+        .find(|(operator_name, _)| operator_name.as_str() == operator)
+        .or_else(|| {
+            let operator_mapping = operator_mapping?;
+            comparison
+                .operators
+                .iter()
+                .find(|(operator_name, _)| operator_mapping.get(operator_name).as_str() == operator)
+        });
+    if let Some((operator_name, argument_type)) = custom_operator {
+        let argument = match &argument_type.underlying_type {
+            QualifiedBaseType::List(_) => serde_json::Value::Array(
+                split_list(value)
+                    .map(|value| coerce_value(field_name, field_type, value))
+                    .collect::<Result<_, _>>()?,
+            ),
+            QualifiedBaseType::Named(_) => coerce_value(field_name, field_type, value)?,
+        };
+        return Ok(BooleanExpression::Comparison {
+            operand,
+            operator: ComparisonOperator::Custom(operator_name.clone()),
+            argument: Box::new(Value::Literal(argument)),
+        });
+    }
 
-            Ok(comparison)
+    match operator {
+        IS_NULL => match value {
+            "true" => Ok(BooleanExpression::IsNull(operand)),
+            "false" => Ok(BooleanExpression::Not(Box::new(BooleanExpression::IsNull(
+                operand,
+            )))),
+            _ => Err(format!("The {IS_NULL} operator expects true or false")),
+        },
+        IN => Ok(BooleanExpression::Or(
+            split_list(value)
+                .map(|value| {
+                    Ok(BooleanExpression::Comparison {
+                        operand: operand.clone(),
+                        operator: ComparisonOperator::Equals,
+                        argument: Box::new(Value::Literal(coerce_value(
+                            field_name, field_type, value,
+                        )?)),
+                    })
+                })
+                .collect::<Result<_, String>>()?,
+        )),
+        _ => {
+            let operators = comparison
+                .operators
+                .keys()
+                .map(open_dds::types::OperatorName::as_str)
+                .chain([IN, IS_NULL])
+                .collect::<Vec<_>>()
+                .join(", ");
+            Err(format!(
+                "Unknown operator {operator} for field {field_name}, expected one of: {operators}"
+            ))
         }
-        JsonApiFilter::Or { or } => {
-            let mut expressions = vec![];
-            for item in or {
-                let expression = expression_from_jsonapi_filter(item)?;
-                expressions.push(expression);
+    }
+}
+
+// `a,b,c`. an empty value is an empty list.
+fn split_list(value: &str) -> impl Iterator<Item = &str> {
+    value.split(',').filter(move |_| !value.is_empty())
+}
+
+// query parameter values are strings, so read them according to the representation of the
+// field's scalar type, which is how the values of the field are written in responses too. a value
+// not in that representation is an error, rather than something for the data connector to reject.
+// values of scalars that data connectors represent differently are read as any of them can be.
+pub(super) fn coerce_value(
+    field_name: &FieldName,
+    field_type: &Type,
+    value: &str,
+) -> Result<serde_json::Value, String> {
+    coerce(field_type, value).map_err(|expected| {
+        format!("Invalid value {value} for field {field_name}, expected {expected}")
+    })
+}
+
+// read a value as one of `value_type`, or else say what is expected. lists and objects, which
+// only arguments can be, are JSON.
+pub(super) fn coerce(value_type: &Type, value: &str) -> Result<serde_json::Value, &'static str> {
+    if let Some(representation) = value_type.scalar_representation() {
+        check_value(representation, value)?;
+        // timestamps with a time zone given without an offset are in UTC, whatever `timezone`
+        // the response is written in
+        if matches!(
+            representation,
+            ScalarRepresentation::Timestamp {
+                with_time_zone: true
             }
-            Ok(open_dds::query::BooleanExpression::Or(expressions))
+        ) {
+            if let Some(timestamp) = utc_timestamp(value) {
+                return Ok(serde_json::Value::String(timestamp));
+            }
+        }
+    }
+    let representations = match value_type {
+        Type::Scalar(representation) => vec![representation],
+        Type::ScalarForDataConnector(scalar_type) => {
+            scalar_type.type_representations.iter().collect()
         }
-        JsonApiFilter::And { and } => {
-            let mut expressions = vec![];
-            for item in and {
-                let expression = expression_from_jsonapi_filter(item)?;
-                expressions.push(expression);
+        Type::List(_) | Type::Object(_) => {
+            return serde_json::from_str(value).map_err(|_| "JSON");
+        }
+    };
+    for representation in representations {
+        match representation {
+            ndc_models::TypeRepresentation::Boolean => {
+                if let Ok(boolean) = value.parse::<bool>() {
+                    return Ok(serde_json::Value::Bool(boolean));
+                }
+            }
+            // integers are read as such, rather than as a double that can't hold all of them
+            ndc_models::TypeRepresentation::Int8
+            | ndc_models::TypeRepresentation::Int16
+            | ndc_models::TypeRepresentation::Int32
+            | ndc_models::TypeRepresentation::Int64 => {
+                if let Ok(integer) = value.parse::<i64>() {
+                    return Ok(serde_json::Value::from(integer));
+                }
+            }
+            ndc_models::TypeRepresentation::Float32 | ndc_models::TypeRepresentation::Float64 => {
+                if let Ok(number) = value.parse::<serde_json::Number>() {
+                    return Ok(serde_json::Value::Number(number));
+                }
             }
-            Ok(open_dds::query::BooleanExpression::And(expressions))
+            ndc_models::TypeRepresentation::JSON => {
+                if let Ok(json) = serde_json::from_str(value) {
+                    return Ok(json);
+                }
+            }
+            // arbitrary-precision numbers are strings, in JSON:API and for the data connector, as
+            // are timestamps and dates
+            _ => {}
         }
-        JsonApiFilter::Nested { .. } => todo!("Nested fields not supported yet"),
     }
+    Ok(serde_json::Value::String(value.to_string()))
+}
+
+// whether a value is in the JSON:API representation of a scalar type, or else what is expected
+fn check_value(representation: ScalarRepresentation, value: &str) -> Result<(), &'static str> {
+    let (valid, expected) = match representation {
+        ScalarRepresentation::Number { integer: true }
+        | ScalarRepresentation::NumericString { integer: true } => (
+            value.parse::<serde_json::Number>().is_ok() && !value.contains(['.', 'e', 'E']),
+            "an integer",
+        ),
+        ScalarRepresentation::Number { integer: false }
+        | ScalarRepresentation::NumericString { integer: false } => {
+            (value.parse::<serde_json::Number>().is_ok(), "a number")
+        }
+        ScalarRepresentation::Boolean => (value.parse::<bool>().is_ok(), "true or false"),
+        ScalarRepresentation::Timestamp {
+            with_time_zone: true,
+        } => (
+            chrono::DateTime::parse_from_rfc3339(value).is_ok() || utc_timestamp(value).is_some(),
+            "an RFC 3339 timestamp, eg 2024-01-02T03:04:05Z",
+        ),
+        ScalarRepresentation::Timestamp {
+            with_time_zone: false,
+        } => (
+            chrono::NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f").is_ok(),
+            "an RFC 3339 timestamp without an offset, eg 2024-01-02T03:04:05",
+        ),
+        ScalarRepresentation::Date => (
+            chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d").is_ok(),
+            "a date, eg 2024-01-02",
+        ),
+        ScalarRepresentation::AsIs => (true, ""),
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(expected)
+    }
+}
+
+// a timestamp without an offset, in RFC 3339 format with the offset of UTC
+fn utc_timestamp(value: &str) -> Option<String> {
+    chrono::NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f")
+        .ok()
+        .map(|timestamp| timestamp.and_utc().to_rfc3339())
+}
+
+pub(super) fn field_operand(field_name: &FieldName) -> Operand {
+    field_operand_with_arguments(field_name, IndexMap::new())
+}
+
+// a field with arguments, like a computed field, is compared or sorted on with its arguments
+pub(super) fn field_operand_with_arguments(
+    field_name: &FieldName,
+    arguments: IndexMap<ArgumentName, Value>,
+) -> Operand {
+    Operand::Field(ObjectFieldOperand {
+        target: Box::new(ObjectFieldTarget {
+            field_name: field_name.clone(),
+            arguments,
+        }),
+        nested: None,
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use open_dds::{
+        data_connector::DataConnectorName,
+        identifier::{Identifier, SubgraphName},
+        models::ModelName,
+        types::OperatorName,
+    };
     use serde_json::json;
-    #[test]
-    fn test_parse_filter_value() {
-        let tests = vec![
+    use std::collections::BTreeSet;
+
+    fn field_name(name: &str) -> FieldName {
+        FieldName::new(Identifier::new(name).unwrap())
+    }
+
+    fn type_name(name: &str) -> Qualified<CustomTypeName> {
+        Qualified::new(
+            SubgraphName::try_new("default").unwrap(),
+            CustomTypeName(Identifier::new(name).unwrap()),
+        )
+    }
+
+    fn relationship_name(name: &str) -> RelationshipName {
+        RelationshipName::new(Identifier::new(name).unwrap())
+    }
+
+    fn scalar_field(
+        name: &str,
+        representation: ndc_models::TypeRepresentation,
+    ) -> (FieldName, Type) {
+        (field_name(name), Type::Scalar(representation))
+    }
+
+    // articles, with an object relationship to their author and an array relationship to their
+    // comments. the role can't see the `secret` field of articles.
+    fn object_types() -> BTreeMap<Qualified<CustomTypeName>, ObjectType> {
+        let model_target =
+            |model: &str, object_type: &str, relationship_type| RelationshipTarget::Model {
+                model_name: Qualified::new(
+                    SubgraphName::try_new("default").unwrap(),
+                    ModelName::new(Identifier::new(model).unwrap()),
+                ),
+                object_type: type_name(object_type),
+                relationship_type,
+                unique_identifier: vec![],
+                data_connector_name: None,
+                supports_relationships: true,
+                order_by_expression: None,
+                mappings: None,
+            };
+        BTreeMap::from_iter([
             (
-                json!({"name":{"$eq":"Horse"}}),
-                JsonApiFilter::Comparison {
-                    comparison_value: serde_json::Value::from("Horse"),
-                    field_name: "name".to_string(),
-                    operator: "eq".to_string(),
+                type_name("Article"),
+                ObjectType {
+                    type_fields: IndexMap::from_iter([
+                        scalar_field("article_id", ndc_models::TypeRepresentation::Int32),
+                        scalar_field("title", ndc_models::TypeRepresentation::String),
+                        scalar_field("word_count", ndc_models::TypeRepresentation::Int32),
+                        scalar_field("published_at", ndc_models::TypeRepresentation::TimestampTZ),
+                        scalar_field("draft", ndc_models::TypeRepresentation::Boolean),
+                        scalar_field("body", ndc_models::TypeRepresentation::String),
+                    ]),
+                    nullable_fields: BTreeSet::from_iter([field_name("published_at")]),
+                    field_arguments: BTreeMap::new(),
+                    type_relationships: IndexMap::from_iter([
+                        (
+                            relationship_name("author"),
+                            model_target("Authors", "Author", RelationshipType::Object),
+                        ),
+                        (
+                            relationship_name("comments"),
+                            model_target("Comments", "Comment", RelationshipType::Array),
+                        ),
+                    ]),
                 },
             ),
             (
-                json!({"name":{"$eq":"Horse"},"age":{"$eq":1}}),
-                JsonApiFilter::And {
-                    and: vec![
-                        JsonApiFilter::Comparison {
-                            comparison_value: serde_json::Value::from("Horse"),
-                            field_name: "name".to_string(),
-                            operator: "eq".to_string(),
-                        },
-                        JsonApiFilter::Comparison {
-                            comparison_value: serde_json::Value::from(1),
-                            field_name: "age".to_string(),
-                            operator: "eq".to_string(),
-                        },
-                    ],
+                type_name("Author"),
+                ObjectType {
+                    type_fields: IndexMap::from_iter([scalar_field(
+                        "name",
+                        ndc_models::TypeRepresentation::String,
+                    )]),
+                    nullable_fields: BTreeSet::new(),
+                    field_arguments: BTreeMap::new(),
+                    type_relationships: IndexMap::new(),
                 },
             ),
-            (
-                json!({"user":{"name":{"$eq":1}}}),
-                JsonApiFilter::Nested {
-                    field_name: "user".to_string(),
-                    rest: Box::new(JsonApiFilter::Comparison {
-                        comparison_value: serde_json::Value::from(1),
-                        field_name: "name".to_string(),
-                        operator: "eq".to_string(),
-                    }),
+        ])
+    }
+
+    // the comparison operators for a field, and whether they take a list
+    fn comparison(operators: &[(&str, bool)]) -> serde_json::Value {
+        comparison_with_mapping(operators, &[])
+    }
+
+    // the comparison operators for a field, with the names the `db` data connector gives some of
+    // them
+    fn comparison_with_mapping(
+        operators: &[(&str, bool)],
+        operator_mapping: &[(&str, &str)],
+    ) -> serde_json::Value {
+        let scalar = json!({"underlying_type": {"Named": {"Custom": {"name": "Scalar"}}}});
+        let operators = operators
+            .iter()
+            .map(|(operator, is_list)| {
+                let argument_type = if *is_list {
+                    json!({"underlying_type": {"List": scalar}})
+                } else {
+                    scalar.clone()
+                };
+                ((*operator).to_string(), argument_type)
+            })
+            .collect::<serde_json::Map<_, _>>();
+        json!({
+            "boolean_expression_type_name": {"FromBooleanExpressionType": {"name": "Scalar_bool_exp"}},
+            "operators": operators,
+            "operator_mapping": [[
+                {"name": "db"},
+                operator_mapping
+                    .iter()
+                    .map(|(operator, ndc_operator)| ((*operator).to_string(), json!(ndc_operator)))
+                    .collect::<serde_json::Map<_, _>>(),
+            ]],
+            "logical_operators": "Exclude",
+            "field_kind": "Scalar",
+        })
+    }
+
+    fn boolean_expression_types(
+    ) -> BTreeMap<Qualified<CustomTypeName>, ResolvedObjectBooleanExpressionType> {
+        let article_bool_exp = json!({
+            "name": {"name": "Article_bool_exp"},
+            "object_type": {"name": "Article"},
+            "graphql": null,
+            "fields": {
+                "object_fields": {},
+                "scalar_fields": {
+                    "article_id": comparison(&[("_eq", false), ("_in", true)]),
+                    "title": comparison(&[("_eq", false), ("_ilike", false)]),
+                    "word_count": comparison(&[("_gt", false)]),
+                    "published_at": comparison(&[("_lt", false)]),
+                    "draft": comparison(&[("_eq", false)]),
+                    "secret": comparison(&[("_eq", false)]),
+                    "body": comparison_with_mapping(&[("search", false)], &[("search", "_fts")]),
+                },
+                "relationship_fields": {
+                    "author": {
+                        "relationship_name": "author",
+                        "boolean_expression_type": {"name": "Author_bool_exp"},
+                    },
+                    "comments": {
+                        "relationship_name": "comments",
+                        "boolean_expression_type": {"name": "Comment_bool_exp"},
+                    },
                 },
+            },
+            "include_logical_operators": "Yes",
+            "data_connector": null,
+        });
+        let author_bool_exp = json!({
+            "name": {"name": "Author_bool_exp"},
+            "object_type": {"name": "Author"},
+            "graphql": null,
+            "fields": {
+                "object_fields": {},
+                "scalar_fields": {"name": comparison(&[("_ilike", false)])},
+                "relationship_fields": {},
+            },
+            "include_logical_operators": "No",
+            "data_connector": null,
+        });
+        BTreeMap::from_iter([
+            (
+                type_name("Article_bool_exp"),
+                serde_json::from_value(article_bool_exp).unwrap(),
             ),
-        ];
+            (
+                type_name("Author_bool_exp"),
+                serde_json::from_value(author_bool_exp).unwrap(),
+            ),
+        ])
+    }
 
-        for (input, expected) in tests {
-            assert_eq!(parse_filter_value(&input), Ok(expected));
-        }
+    fn filter(query: &str) -> Result<Option<BooleanExpression>, Vec<(String, String)>> {
+        filter_with_naming(&NamingStrategy::default(), query)
     }
-}
 
-#[derive(Debug, PartialEq, Eq)]
-enum ParseFilterExpressionError {
-    FilterExpressionIsNotObject,
-    MultipleComparisonsInOneObject,
-    ExpectedNonEmptyObjectForFieldComparison,
-    ExpectedArrayForAnd,
-    ExpectedArrayForOr,
-}
+    fn filter_with_naming(
+        naming: &NamingStrategy,
+        query: &str,
+    ) -> Result<Option<BooleanExpression>, Vec<(String, String)>> {
+        let boolean_expression_types = boolean_expression_types();
+        let model = Model {
+            name: Qualified::new(
+                SubgraphName::try_new("default").unwrap(),
+                ModelName::new(Identifier::new("Articles").unwrap()),
+            ),
+            description: None,
+            data_type: type_name("Article"),
+            data_connector_name: Qualified::new(
+                SubgraphName::try_new("default").unwrap(),
+                DataConnectorName::new(Identifier::new("db").unwrap()),
+            ),
+            filter_expression_type: boolean_expression_types
+                .get(&type_name("Article_bool_exp"))
+                .cloned(),
+            unique_identifier: vec![field_name("article_id")],
+            supports_aggregates: true,
+            supports_grouping: true,
+            order_by_expression: None,
+        };
+        let object_types = object_types();
+        let query_params = QueryParams::parse(query);
+        let field_arguments = FieldArguments::parse(
+            &model.data_type,
+            &object_types[&model.data_type],
+            naming,
+            &query_params,
+        )
+        .unwrap();
+        build_boolean_expression(
+            &model,
+            &object_types,
+            &boolean_expression_types,
+            naming,
+            &field_arguments,
+            &query_params,
+        )
+        .map_err(|errors| {
+            errors
+                .into_iter()
+                .map(|error| (error.parameter, error.detail))
+                .collect()
+        })
+    }
 
-/* Parse our filter shape from JSON value
- *
- * Example filter as query string - /movies?sort=...&filter=<json>
-{
-  "$or": [
-    {
-      "name": {
-        "$eq": "Braveheart"
-      }
-    },
-    {
-      "name": {
-        "$eq": "Gladiator"
-      }
-    },
-    {
-      "$and": [
-        {
-          "director": {
-            "last_name": {
-              "$eq": "Scorcese"
-            }
-          }
-        },
-        {
-          "director": {
-            "age": {
-              "$gt": 50
-            }
-          }
+    fn compare(
+        field: &str,
+        operator: ComparisonOperator,
+        value: serde_json::Value,
+    ) -> BooleanExpression {
+        BooleanExpression::Comparison {
+            operand: field_operand(&field_name(field)),
+            operator,
+            argument: Box::new(Value::Literal(value)),
         }
-      ]
     }
-  ],
-  "name": {
-    "$eq": "Foobar"
-  }
-}
-*/
-fn parse_filter_value(
-    value: &serde_json::Value,
-) -> Result<JsonApiFilter, ParseFilterExpressionError> {
-    match value.as_object() {
-        Some(items) => {
-            let mut jsonapi_filters = vec![];
-            // assume only one item for now
-            for (key, value) in items {
-                let jsonapi_filter = match key.as_str() {
-                    // `$and` and `$or` will need to be configurable in future
-                    "$and" => {
-                        // fail if items aren't an array
-                        let item_array = value
-                            .as_array()
-                            .ok_or(ParseFilterExpressionError::ExpectedArrayForAnd)?;
-
-                        // parse array of things
-                        let filter_array = item_array
-                            .iter()
-                            .map(parse_filter_value)
-                            .collect::<Result<Vec<_>, _>>()?;
-                        Ok(JsonApiFilter::And { and: filter_array })
-                    }
-                    "$or" => {
-                        // fail if items aren't an array
-                        let item_array = value
-                            .as_array()
-                            .ok_or(ParseFilterExpressionError::ExpectedArrayForOr)?;
-
-                        // parse array of things
-                        let filter_array = item_array
-                            .iter()
-                            .map(parse_filter_value)
-                            .collect::<Result<Vec<_>, _>>()?;
-                        Ok(JsonApiFilter::Or { or: filter_array })
-                    }
-                    field_name => {
-                        // if it's not a known operator, assume it's a field name
-                        let item_object = value.as_object().ok_or({
-                            ParseFilterExpressionError::ExpectedNonEmptyObjectForFieldComparison
-                        })?;
-
-                        if item_object.len() > 1 {
-                            return Err(ParseFilterExpressionError::MultipleComparisonsInOneObject);
-                        }
-                        if let Some((operator, comparison_value)) = item_object.iter().next() {
-                            match parse_operator(operator.clone()) {
-                                OperatorOrNested::Operator(parsed_operator) => {
-                                    Ok(JsonApiFilter::Comparison {
-                                        field_name: field_name.to_string(),
-                                        operator: parsed_operator,
-                                        comparison_value: comparison_value.clone(),
-                                    })
-                                }
-                                OperatorOrNested::Nested(_nested_field_name) => {
-                                    Ok(JsonApiFilter::Nested {
-                                        field_name: field_name.to_string(),
-                                        rest: Box::new(parse_filter_value(value)?),
-                                    })
-                                }
-                            }
-                        } else {
-                            Err(ParseFilterExpressionError::MultipleComparisonsInOneObject)
-                        }
-                    }
-                }?;
-                jsonapi_filters.push(jsonapi_filter);
-            }
 
-            // if there is only one filter, skip the AND wrapper
-            if jsonapi_filters.len() == 1 {
-                if let Some(jsonapi_filter) = jsonapi_filters.first() {
-                    return Ok(jsonapi_filter.clone());
-                }
-            }
-            Ok(JsonApiFilter::And {
-                and: jsonapi_filters,
-            })
+    fn custom(operator: &str) -> ComparisonOperator {
+        ComparisonOperator::Custom(OperatorName::from(operator))
+    }
+
+    #[test]
+    fn test_filter_comparisons() {
+        assert_eq!(filter("page[limit]=10"), Ok(None));
+
+        assert_eq!(
+            filter(
+                "filter[title][_eq]=Hello&filter[word_count][_gt]=100&filter[draft][_eq]=true\
+                 &filter[published_at][_is_null]=false&filter[article_id][_in]=1,2\
+                 &filter[title][_in]=A,B"
+            ),
+            Ok(Some(BooleanExpression::And(vec![
+                BooleanExpression::And(vec![
+                    compare("title", custom("_eq"), json!("Hello")),
+                    // `title` doesn't have an `_in` operator, so we compare each value
+                    BooleanExpression::Or(vec![
+                        compare("title", ComparisonOperator::Equals, json!("A")),
+                        compare("title", ComparisonOperator::Equals, json!("B")),
+                    ]),
+                ]),
+                compare("word_count", custom("_gt"), json!(100)),
+                compare("draft", custom("_eq"), json!(true)),
+                BooleanExpression::Not(Box::new(BooleanExpression::IsNull(field_operand(
+                    &field_name("published_at")
+                )))),
+                compare("article_id", custom("_in"), json!([1, 2])),
+            ])))
+        );
+    }
+
+    // operators can be named as the data connector names them, as well as by their names in
+    // metadata
+    #[test]
+    fn test_filter_data_connector_operators() {
+        assert_eq!(
+            filter("filter[body][search]=rust async&filter[body][_fts]=tokio"),
+            Ok(Some(BooleanExpression::And(vec![
+                compare("body", custom("search"), json!("rust async")),
+                compare("body", custom("search"), json!("tokio")),
+            ])))
+        );
+        assert_eq!(
+            filter("filter[body][_ilike]=rust"),
+            Err(vec![(
+                "filter[body][_ilike]".to_string(),
+                "Unknown operator _ilike for field body, expected one of: search, _in, _is_null"
+                    .to_string()
+            )])
+        );
+    }
+
+    // large integers used to be read as doubles, which round them
+    #[test]
+    fn test_coerce_bigint_values() {
+        let field = field_name("balance");
+        let int64 = Type::Scalar(ndc_models::TypeRepresentation::Int64);
+        assert_eq!(
+            coerce_value(&field, &int64, "9007199254740993")
+                .unwrap()
+                .as_i64(),
+            Some(9_007_199_254_740_993)
+        );
+        assert_eq!(
+            coerce_value(&field, &int64, "1.5"),
+            Err("Invalid value 1.5 for field balance, expected an integer".to_string())
+        );
+
+        let big_integer = Type::Scalar(ndc_models::TypeRepresentation::BigInteger);
+        assert_eq!(
+            coerce_value(&field, &big_integer, "12345678901234567890123"),
+            Ok(json!("12345678901234567890123"))
+        );
+    }
+
+    #[test]
+    fn test_coerce_values() {
+        let field = field_name("value");
+        let coerce = |representation, value| {
+            coerce_value(&field, &Type::Scalar(representation), value).map_err(|_| ())
+        };
+        let cases = [
+            (
+                ndc_models::TypeRepresentation::Float64,
+                "1.5",
+                Ok(json!(1.5)),
+            ),
+            (ndc_models::TypeRepresentation::Float64, "one", Err(())),
+            (
+                ndc_models::TypeRepresentation::BigDecimal,
+                "1.50",
+                Ok(json!("1.50")),
+            ),
+            (
+                ndc_models::TypeRepresentation::Boolean,
+                "false",
+                Ok(json!(false)),
+            ),
+            (ndc_models::TypeRepresentation::Boolean, "no", Err(())),
+            (
+                ndc_models::TypeRepresentation::TimestampTZ,
+                "2024-01-02T03:04:05+01:00",
+                Ok(json!("2024-01-02T03:04:05+01:00")),
+            ),
+            // without an offset, in UTC
+            (
+                ndc_models::TypeRepresentation::TimestampTZ,
+                "2024-01-02T03:04:05",
+                Ok(json!("2024-01-02T03:04:05+00:00")),
+            ),
+            (
+                ndc_models::TypeRepresentation::TimestampTZ,
+                "2024-01-02",
+                Err(()),
+            ),
+            (
+                ndc_models::TypeRepresentation::Timestamp,
+                "2024-01-02T03:04:05.5",
+                Ok(json!("2024-01-02T03:04:05.5")),
+            ),
+            (
+                ndc_models::TypeRepresentation::Date,
+                "2024-01-02",
+                Ok(json!("2024-01-02")),
+            ),
+            (ndc_models::TypeRepresentation::Date, "2024-13-01", Err(())),
+            (
+                ndc_models::TypeRepresentation::String,
+                "anything",
+                Ok(json!("anything")),
+            ),
+        ];
+        for (representation, value, expected) in cases {
+            assert_eq!(coerce(representation, value), expected, "{value}");
         }
-        None => Err(ParseFilterExpressionError::FilterExpressionIsNotObject),
+
+        // a scalar the data connectors represent differently is not checked
+        let mixed = Type::ScalarForDataConnector(crate::catalog::ScalarTypeForDataConnector {
+            type_representations: BTreeSet::from_iter([
+                ndc_models::TypeRepresentation::Int32,
+                ndc_models::TypeRepresentation::String,
+            ]),
+        });
+        assert_eq!(coerce_value(&field, &mixed, "1"), Ok(json!(1)));
+        assert_eq!(coerce_value(&field, &mixed, "one"), Ok(json!("one")));
     }
-}
 
-enum OperatorOrNested {
-    Operator(String),
-    Nested(String),
-}
+    // values are checked before we query, so the data connector doesn't get them
+    #[test]
+    fn test_filter_value_errors() {
+        let errors = filter(
+            "filter[published_at][_lt]=not-a-date&filter[word_count][_gt]=many\
+             &filter[draft][_eq]=yes&filter[article_id][_in]=1,x",
+        )
+        .unwrap_err();
+        assert_eq!(
+            errors,
+            [
+                ("filter[published_at][_lt]", "Invalid value not-a-date for field published_at, expected an RFC 3339 timestamp, eg 2024-01-02T03:04:05Z"),
+                ("filter[word_count][_gt]", "Invalid value many for field word_count, expected an integer"),
+                ("filter[draft][_eq]", "Invalid value yes for field draft, expected true or false"),
+                ("filter[article_id][_in]", "Invalid value x for field article_id, expected an integer"),
+            ]
+            .map(|(parameter, detail)| (parameter.to_string(), detail.to_string()))
+            .to_vec()
+        );
+    }
+
+    #[test]
+    fn test_filter_logical_operators_and_relationships() {
+        assert_eq!(
+            filter(
+                "filter[_or][0][title][_eq]=A&filter[_or][1][author][name][_ilike]=%25jane%25\
+                 &filter[_not][draft][_eq]=true"
+            ),
+            Ok(Some(BooleanExpression::And(vec![
+                BooleanExpression::Or(vec![
+                    compare("title", custom("_eq"), json!("A")),
+                    BooleanExpression::Relationship {
+                        operand: None,
+                        relationship_name: relationship_name("author"),
+                        predicate: Box::new(compare("name", custom("_ilike"), json!("%jane%"))),
+                    },
+                ]),
+                BooleanExpression::Not(Box::new(compare("draft", custom("_eq"), json!(true)))),
+            ])))
+        );
+    }
+
+    #[test]
+    fn test_filter_is_null() {
+        let exists = |relationship: &str| BooleanExpression::Relationship {
+            operand: None,
+            relationship_name: relationship_name(relationship),
+            predicate: Box::new(BooleanExpression::And(vec![])),
+        };
+
+        // a nullable column
+        assert_eq!(
+            filter("filter[published_at][_is_null]=true"),
+            Ok(Some(BooleanExpression::IsNull(field_operand(&field_name(
+                "published_at"
+            )))))
+        );
+        // articles with no comments, and those with some
+        assert_eq!(
+            filter("filter[comments][_is_null]=true"),
+            Ok(Some(BooleanExpression::Not(Box::new(exists("comments")))))
+        );
+        assert_eq!(
+            filter("filter[comments][_is_null]=false"),
+            Ok(Some(exists("comments")))
+        );
+        // alongside filters on the related resource
+        assert_eq!(
+            filter("filter[author][_is_null]=false&filter[author][name][_eq]=Jane"),
+            Ok(Some(BooleanExpression::And(vec![
+                exists("author"),
+                BooleanExpression::Relationship {
+                    operand: None,
+                    relationship_name: relationship_name("author"),
+                    predicate: Box::new(compare("name", custom("_eq"), json!("Jane"))),
+                },
+            ])))
+        );
 
-// we're either looking at an operator, ie '$eq`, or the name of another nested field
-// only return if it's an operator, and get rid of the `$`
-fn parse_operator(operator: String) -> OperatorOrNested {
-    match operator.strip_prefix("$") {
-        Some(op) => OperatorOrNested::Operator(op.to_string()),
-        None => OperatorOrNested::Nested(operator),
+        assert_eq!(
+            filter("filter[comments][_is_null]=yes&filter[author][_is_null][x]=true"),
+            Err(vec![
+                (
+                    "filter[comments][_is_null]".to_string(),
+                    "The _is_null operator expects true or false".to_string()
+                ),
+                (
+                    "filter[author][_is_null]".to_string(),
+                    "Invalid filter filter[author][_is_null][x], nothing may follow the operator _is_null"
+                        .to_string()
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_filter_errors() {
+        let errors = filter(
+            "filter[nope][_eq]=1&filter[secret][_eq]=1&filter[title][_gt]=A\
+             &filter[draft][_is_null]=maybe&filter[comments][body][_eq]=A&filter[title]=A\
+             &filter[author][_or][0][name][_ilike]=A&filter[title][_eq][x]=A&filter[title=A\
+             &filter={}",
+        )
+        .unwrap_err();
+        assert_eq!(
+            errors,
+            [
+                ("filter[title", "Invalid filter filter[title, expected filter[field][operator]=value"),
+                ("filter", "Filters must be given as filter[field][operator]=value"),
+                ("filter[nope]", "Unknown field nope in Article"),
+                // not visible to the role
+                ("filter[secret]", "Unknown field secret in Article"),
+                ("filter[title]", "Incomplete filter filter[title], expected an operator, eg filter[title][_eq]"),
+                ("filter[title][_gt]", "Unknown operator _gt for field title, expected one of: _eq, _ilike, _in, _is_null"),
+                ("filter[title][_eq]", "Invalid filter filter[title][_eq][x], nothing may follow the operator _eq"),
+                ("filter[draft][_is_null]", "The _is_null operator expects true or false"),
+                ("filter[comments]", "Relationship comments of Article is a to-many relationship, so can only be filtered on with _is_null"),
+                ("filter[author][_or]", "Logical operators are not supported when filtering Author"),
+            ]
+            .map(|(parameter, detail)| (parameter.to_string(), detail.to_string()))
+            .to_vec()
+        );
+    }
+
+    #[test]
+    fn test_filter_with_naming_strategy() {
+        let naming = NamingStrategy {
+            case: crate::naming::NameCase::KebabCase,
+            pluralize_types: false,
+        };
+        assert_eq!(
+            filter_with_naming(&naming, "filter[word-count][_gt]=100"),
+            Ok(Some(compare("word_count", custom("_gt"), json!(100))))
+        );
+        assert_eq!(
+            filter_with_naming(&naming, "filter[word_count][_gt]=100"),
+            Err(vec![(
+                "filter[word_count]".to_string(),
+                "Unknown field word_count in Article".to_string()
+            )])
+        );
     }
 }
@@ -1,4 +1,20 @@
-use std::collections::BTreeMap;
+use super::filter::field_operand;
+use crate::catalog::{FieldMapping, Model, ObjectType, RelationshipTarget};
+use crate::naming::NamingStrategy;
+use crate::types::ParameterError;
+use indexmap::IndexMap;
+use metadata_resolve::Qualified;
+use open_dds::models::ModelName;
+use open_dds::query::{
+    Alias, BooleanExpression, ComparisonOperator, ModelSelection, ModelTarget,
+    ObjectFieldSelection, ObjectFieldTarget, ObjectSubSelection, Query, QueryRequest,
+    QueryRequestV1, Value,
+};
+use open_dds::types::{CustomTypeName, FieldName};
+use open_dds::{identifier, identifier::Identifier};
+use std::collections::{BTreeMap, BTreeSet};
+
+const INCLUDE: &str = "include";
 
 // Represents a parsed "include" query parameter
 #[derive(Debug, Default)]
@@ -40,6 +56,295 @@ impl IncludeRelationships {
     }
 }
 
+// every include path is a join in the query we send to the connector, so we limit how deep and
+// how many of them a request may ask for. duplicate paths only count once.
+pub fn check_include_limits(
+    include_relationships: &[String],
+    max_depth: usize,
+    max_paths: usize,
+) -> Result<(), Vec<ParameterError>> {
+    let include_paths = include_relationships
+        .iter()
+        .map(|include_path| include_path.trim())
+        .filter(|include_path| !include_path.is_empty())
+        .collect::<BTreeSet<_>>();
+
+    let mut errors = vec![];
+    if include_paths.len() > max_paths {
+        errors.push(ParameterError::new(
+            INCLUDE,
+            format!("At most {max_paths} include paths may be requested"),
+        ));
+    }
+    for include_path in include_paths {
+        if include_path.split('.').count() > max_depth {
+            errors.push(ParameterError::new(
+                INCLUDE,
+                format!("Include path {include_path} is more than {max_depth} relationships deep"),
+            ));
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// An included relationship that is fetched by a query of its own, once the resources it relates
+/// are fetched, because their data connector cannot fetch it along with them: the related model
+/// is from another data connector, or the data connector does not support relationships. the
+/// related resources are joined to the resources by the fields of the relationship mapping.
+#[derive(Debug)]
+pub struct IncludedQuery {
+    /// the relationship, by its alias in the selection of the resources
+    pub relationship: Alias,
+    pub mappings: Vec<FieldMapping>,
+    /// the related resources of any resource. `query_request` narrows it down to those of the
+    /// resources that were fetched.
+    pub model_selection: ModelSelection,
+}
+
+impl IncludedQuery {
+    /// the query for the resources related to `rows`, or `None` if none of them can have any, ie
+    /// there are no rows or each has a null mapping field
+    pub fn query_request(
+        &self,
+        rows: &[IndexMap<ndc_models::FieldName, ndc_models::RowFieldValue>],
+    ) -> Option<QueryRequest> {
+        // the distinct join keys of the rows, in the order of the rows
+        let mut keys = IndexMap::new();
+        for row in rows {
+            if let Some(key) = join_key(row, self.mappings.iter().map(|m| &m.source_field)) {
+                keys.entry(serde_json::Value::Array(key.clone()).to_string())
+                    .or_insert(key);
+            }
+        }
+        if keys.is_empty() {
+            return None;
+        }
+
+        let key_filter = BooleanExpression::Or(
+            keys.into_values()
+                .map(|key| {
+                    BooleanExpression::And(
+                        self.mappings
+                            .iter()
+                            .zip(key)
+                            .map(|(mapping, value)| BooleanExpression::Comparison {
+                                operand: field_operand(&mapping.target_field),
+                                operator: ComparisonOperator::Equals,
+                                argument: Box::new(Value::Literal(value)),
+                            })
+                            .collect(),
+                    )
+                })
+                .collect(),
+        );
+        let mut model_selection = self.model_selection.clone();
+        model_selection.target.filter = Some(match model_selection.target.filter.take() {
+            Some(filter) => BooleanExpression::And(vec![filter, key_filter]),
+            None => key_filter,
+        });
+        Some(QueryRequest::V1(QueryRequestV1 {
+            queries: IndexMap::from_iter([(
+                Alias::new(identifier!("jsonapi_included_query")),
+                Query::Model(model_selection),
+            )]),
+        }))
+    }
+}
+
+/// The values of `fields` in a row, which it is joined on. `None` if any of them is null (or
+/// missing), as null matches nothing.
+pub fn join_key<'a>(
+    row: &IndexMap<ndc_models::FieldName, ndc_models::RowFieldValue>,
+    fields: impl Iterator<Item = &'a FieldName>,
+) -> Option<Vec<serde_json::Value>> {
+    fields
+        .map(|field_name| {
+            row.get(&ndc_models::FieldName::from(field_name.as_str()))
+                .map(|value| &value.0)
+                .filter(|value| !value.is_null())
+                .cloned()
+        })
+        .collect()
+}
+
+/// Take the included relationships of `selection`, which is of the resources of `model`, that the
+/// data connector of `model` cannot fetch along with them out of the selection, into queries of
+/// their own. the fields they join on are selected instead.
+///
+/// only relationships directly of the resources are taken out. those nested in them are fetched by
+/// the query of the relationship, so that no query waits on another to start.
+///
+/// a relationship that can't be joined this way, as the role can't see the fields of its mapping,
+/// is an error in the `include` parameter. those whose mapping targets arguments of the related
+/// model are left to the plan, which joins them itself.
+pub fn split_included_queries(
+    model: &Model,
+    object_type: &ObjectType,
+    object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectType>,
+    naming: &NamingStrategy,
+    selection: &mut IndexMap<Alias, ObjectSubSelection>,
+) -> Result<Vec<IncludedQuery>, Vec<ParameterError>> {
+    let mut included_queries = vec![];
+    let mut errors = vec![];
+    selection.retain(|alias, sub_selection| {
+        let ObjectSubSelection::Relationship(relationship_selection) = sub_selection else {
+            return true;
+        };
+        let relationship_name = &relationship_selection.target.relationship_name;
+        let Some(RelationshipTarget::Model {
+            model_name,
+            object_type: target_object_type,
+            data_connector_name: Some(data_connector_name),
+            supports_relationships,
+            mappings: Some(mappings),
+            ..
+        }) = object_type.type_relationships.get(relationship_name)
+        else {
+            return true;
+        };
+        if *supports_relationships && *data_connector_name == model.data_connector_name {
+            return true;
+        }
+
+        let mappings = match join_mappings(
+            mappings,
+            object_type,
+            object_types.get(target_object_type),
+            &model_name.name,
+            naming,
+        ) {
+            Ok(mappings) => mappings,
+            Err(reason) => {
+                errors.push(ParameterError::new(
+                    INCLUDE,
+                    format!(
+                        "Relationship {} of {} cannot be included, as {reason}",
+                        naming.field_name(relationship_name.as_str()),
+                        model.name.name
+                    ),
+                ));
+                return false;
+            }
+        };
+        let mut related_selection = relationship_selection.selection.take().unwrap_or_default();
+        for mapping in mappings {
+            select_field(&mut related_selection, &mapping.target_field);
+        }
+        included_queries.push(IncludedQuery {
+            relationship: alias.clone(),
+            mappings: mappings.to_vec(),
+            model_selection: ModelSelection {
+                target: ModelTarget {
+                    subgraph: model_name.subgraph.clone(),
+                    model_name: model_name.name.clone(),
+                    arguments: IndexMap::new(),
+                    filter: relationship_selection.target.filter.take(),
+                    order_by: std::mem::take(&mut relationship_selection.target.order_by),
+                    limit: None,
+                    offset: None,
+                },
+                selection: related_selection,
+            },
+        });
+        false
+    });
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    for included_query in &included_queries {
+        for mapping in &included_query.mappings {
+            select_field(selection, &mapping.source_field);
+        }
+    }
+    Ok(included_queries)
+}
+
+// whether a relationship of the resources of `model` can be included. relationships to models of
+// other data connectors are fetched by queries of their own, which need the role to see the
+// fields they are joined on, and relationships to commands need an object type to render.
+pub fn can_include(
+    model: &Model,
+    object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectType>,
+    object_type: &ObjectType,
+    target: &RelationshipTarget,
+    naming: &NamingStrategy,
+) -> bool {
+    match target {
+        RelationshipTarget::Model {
+            model_name,
+            object_type: target_object_type,
+            data_connector_name: Some(data_connector_name),
+            supports_relationships,
+            mappings: Some(mappings),
+            ..
+        } if !(*supports_relationships && *data_connector_name == model.data_connector_name) => {
+            join_mappings(
+                mappings,
+                object_type,
+                object_types.get(target_object_type),
+                &model_name.name,
+                naming,
+            )
+            .is_ok()
+        }
+        RelationshipTarget::Model { .. } => true,
+        RelationshipTarget::Command { type_reference } => {
+            metadata_resolve::unwrap_custom_type_name(type_reference).is_some()
+        }
+    }
+}
+
+// the mapping of a relationship fetched by a query of its own, if the resources and the related
+// resources can be joined on it: the role must see its fields on both sides, as we select them to
+// join on
+fn join_mappings<'a>(
+    mappings: &'a [FieldMapping],
+    object_type: &ObjectType,
+    target_object_type: Option<&ObjectType>,
+    target_model_name: &ModelName,
+    naming: &NamingStrategy,
+) -> Result<&'a [FieldMapping], String> {
+    let target_object_type = target_object_type
+        .ok_or_else(|| format!("the resources of {target_model_name} are not accessible"))?;
+    for mapping in mappings {
+        if !object_type.type_fields.contains_key(&mapping.source_field) {
+            return Err(format!(
+                "the field {} it is joined on is not accessible",
+                naming.field_name(mapping.source_field.as_str())
+            ));
+        }
+        if !target_object_type
+            .type_fields
+            .contains_key(&mapping.target_field)
+        {
+            return Err(format!(
+                "the field {} of {target_model_name} it is joined on is not accessible",
+                naming.field_name(mapping.target_field.as_str())
+            ));
+        }
+    }
+    Ok(mappings)
+}
+
+// select a field, by its own name, unless it is already selected
+fn select_field(selection: &mut IndexMap<Alias, ObjectSubSelection>, field_name: &FieldName) {
+    selection
+        .entry(Alias::new(Identifier::from(field_name.clone())))
+        .or_insert_with(|| {
+            ObjectSubSelection::Field(ObjectFieldSelection {
+                target: ObjectFieldTarget {
+                    arguments: IndexMap::new(),
+                    field_name: field_name.clone(),
+                },
+                selection: None,
+            })
+        });
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -137,4 +442,329 @@ mod tests {
             .unwrap();
         assert!(books_nested.include.contains_key("publisher"));
     }
+
+    #[test]
+    fn test_include_limits() {
+        let include = |paths: &[&str]| paths.iter().map(ToString::to_string).collect::<Vec<_>>();
+        let details = |errors: Vec<ParameterError>| {
+            errors
+                .into_iter()
+                .map(|error| error.detail)
+                .collect::<Vec<_>>()
+        };
+
+        assert!(check_include_limits(&include(&["authors.books.publisher"]), 3, 1).is_ok());
+        // duplicates are one path
+        assert!(check_include_limits(&include(&["authors", " authors"]), 3, 1).is_ok());
+
+        assert_eq!(
+            details(
+                check_include_limits(&include(&["authors.books.publisher.city", "awards"]), 3, 1)
+                    .unwrap_err()
+            ),
+            vec![
+                "At most 1 include paths may be requested",
+                "Include path authors.books.publisher.city is more than 3 relationships deep",
+            ]
+        );
+    }
+
+    fn field_name(name: &str) -> FieldName {
+        FieldName::new(Identifier::new(name).unwrap())
+    }
+
+    fn qualified<T>(name: &str, new: impl Fn(Identifier) -> T) -> metadata_resolve::Qualified<T> {
+        metadata_resolve::Qualified::new(
+            open_dds::identifier::SubgraphName::try_new("default").unwrap(),
+            new(Identifier::new(name).unwrap()),
+        )
+    }
+
+    fn field(name: &str) -> (Alias, ObjectSubSelection) {
+        let mut selection = IndexMap::new();
+        select_field(&mut selection, &field_name(name));
+        selection.into_iter().next().unwrap()
+    }
+
+    fn relationship(
+        name: &str,
+        selection: impl IntoIterator<Item = (Alias, ObjectSubSelection)>,
+    ) -> (Alias, ObjectSubSelection) {
+        (
+            Alias::new(Identifier::new(name).unwrap()),
+            ObjectSubSelection::Relationship(open_dds::query::RelationshipSelection {
+                target: open_dds::query::RelationshipTarget {
+                    relationship_name: open_dds::relationships::RelationshipName::new(
+                        Identifier::new(name).unwrap(),
+                    ),
+                    arguments: IndexMap::new(),
+                    filter: None,
+                    order_by: vec![],
+                    limit: None,
+                    offset: None,
+                },
+                selection: Some(selection.into_iter().collect()),
+            }),
+        )
+    }
+
+    fn fields(names: &[&str]) -> IndexMap<FieldName, crate::catalog::Type> {
+        names
+            .iter()
+            .map(|name| {
+                (
+                    field_name(name),
+                    crate::catalog::Type::Scalar(ndc_models::TypeRepresentation::Int32),
+                )
+            })
+            .collect()
+    }
+
+    fn related_object_type(
+        name: &str,
+        field_names: &[&str],
+    ) -> (Qualified<CustomTypeName>, ObjectType) {
+        (
+            qualified(name, CustomTypeName),
+            ObjectType {
+                type_fields: fields(field_names),
+                nullable_fields: BTreeSet::new(),
+                field_arguments: BTreeMap::new(),
+                type_relationships: IndexMap::new(),
+            },
+        )
+    }
+
+    fn row(
+        article_id: serde_json::Value,
+    ) -> IndexMap<ndc_models::FieldName, ndc_models::RowFieldValue> {
+        IndexMap::from_iter([(
+            ndc_models::FieldName::from("article_id"),
+            ndc_models::RowFieldValue(article_id),
+        )])
+    }
+
+    #[test]
+    fn test_split_included_queries() {
+        let data_connector =
+            |name: &str| qualified(name, open_dds::data_connector::DataConnectorName::new);
+        let model_target =
+            |model: &str, connector: &str, supports_relationships, target_field: &str| {
+                RelationshipTarget::Model {
+                    model_name: qualified(model, open_dds::models::ModelName::new),
+                    object_type: qualified(model, open_dds::types::CustomTypeName),
+                    relationship_type: open_dds::relationships::RelationshipType::Array,
+                    unique_identifier: vec![],
+                    data_connector_name: Some(data_connector(connector)),
+                    supports_relationships,
+                    order_by_expression: None,
+                    mappings: Some(vec![FieldMapping {
+                        source_field: field_name("article_id"),
+                        target_field: field_name(target_field),
+                    }]),
+                }
+            };
+        let model = Model {
+            name: qualified("Articles", open_dds::models::ModelName::new),
+            description: None,
+            data_type: qualified("Article", open_dds::types::CustomTypeName),
+            data_connector_name: data_connector("db"),
+            filter_expression_type: None,
+            unique_identifier: vec![],
+            supports_aggregates: false,
+            supports_grouping: false,
+            order_by_expression: None,
+        };
+        let object_type = ObjectType {
+            type_fields: fields(&["article_id", "title"]),
+            nullable_fields: BTreeSet::new(),
+            field_arguments: BTreeMap::new(),
+            type_relationships: IndexMap::from_iter(
+                [
+                    // fetched along with the articles
+                    ("author", model_target("Authors", "db", true, "article_id")),
+                    // a model of another data connector
+                    (
+                        "comments",
+                        model_target("Comments", "other", true, "article_id"),
+                    ),
+                    // the data connector can't fetch relationships
+                    (
+                        "reviews",
+                        model_target("Reviews", "db", false, "reviewed_id"),
+                    ),
+                ]
+                .map(|(name, target)| {
+                    (
+                        open_dds::relationships::RelationshipName::new(
+                            Identifier::new(name).unwrap(),
+                        ),
+                        target,
+                    )
+                }),
+            ),
+        };
+        let mut selection = IndexMap::from_iter([
+            field("title"),
+            relationship("author", [field("name")]),
+            relationship("comments", [field("body")]),
+            relationship("reviews", []),
+        ]);
+
+        let object_types = BTreeMap::from_iter([
+            related_object_type("Comments", &["body", "article_id"]),
+            related_object_type("Reviews", &["reviewed_id"]),
+        ]);
+
+        let included_queries = split_included_queries(
+            &model,
+            &object_type,
+            &object_types,
+            &NamingStrategy::default(),
+            &mut selection,
+        )
+        .unwrap();
+        assert_eq!(
+            selection.keys().map(Alias::as_str).collect::<Vec<_>>(),
+            vec!["title", "author", "article_id"]
+        );
+        assert_eq!(
+            included_queries
+                .iter()
+                .map(|included_query| included_query.relationship.as_str())
+                .collect::<Vec<_>>(),
+            vec!["comments", "reviews"]
+        );
+        assert_eq!(
+            included_queries[0]
+                .model_selection
+                .selection
+                .keys()
+                .map(Alias::as_str)
+                .collect::<Vec<_>>(),
+            vec!["body", "article_id"]
+        );
+
+        // the comments of the articles, by their distinct, non-null ids
+        let comments = &included_queries[0];
+        assert!(comments.query_request(&[]).is_none());
+        assert!(comments
+            .query_request(&[row(serde_json::Value::Null)])
+            .is_none());
+        let rows = [
+            row(serde_json::json!(1)),
+            row(serde_json::Value::Null),
+            row(serde_json::json!(2)),
+            row(serde_json::json!(1)),
+        ];
+        let QueryRequest::V1(query_request) = comments.query_request(&rows).unwrap();
+        let Some(Query::Model(model_selection)) = query_request.queries.values().next() else {
+            panic!("expected a model query");
+        };
+        assert_eq!(model_selection.target.model_name.as_str(), "Comments");
+        let comparison = |value| {
+            BooleanExpression::And(vec![BooleanExpression::Comparison {
+                operand: field_operand(&field_name("article_id")),
+                operator: ComparisonOperator::Equals,
+                argument: Box::new(Value::Literal(value)),
+            }])
+        };
+        assert_eq!(
+            model_selection.target.filter,
+            Some(BooleanExpression::Or(vec![
+                comparison(serde_json::json!(1)),
+                comparison(serde_json::json!(2)),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_split_included_queries_errors() {
+        let model_target = |model: &str, mappings| RelationshipTarget::Model {
+            model_name: qualified(model, open_dds::models::ModelName::new),
+            object_type: qualified(model, CustomTypeName),
+            relationship_type: open_dds::relationships::RelationshipType::Object,
+            unique_identifier: vec![],
+            data_connector_name: Some(qualified(
+                "other",
+                open_dds::data_connector::DataConnectorName::new,
+            )),
+            supports_relationships: true,
+            order_by_expression: None,
+            mappings,
+        };
+        let mapping = |source_field: &str, target_field: &str| FieldMapping {
+            source_field: field_name(source_field),
+            target_field: field_name(target_field),
+        };
+        let model = Model {
+            name: qualified("Articles", open_dds::models::ModelName::new),
+            description: None,
+            data_type: qualified("Article", CustomTypeName),
+            data_connector_name: qualified("db", open_dds::data_connector::DataConnectorName::new),
+            filter_expression_type: None,
+            unique_identifier: vec![],
+            supports_aggregates: false,
+            supports_grouping: false,
+            order_by_expression: None,
+        };
+        let object_type = ObjectType {
+            type_fields: fields(&["article_id"]),
+            nullable_fields: BTreeSet::new(),
+            field_arguments: BTreeMap::new(),
+            type_relationships: IndexMap::from_iter(
+                [
+                    // the role can't see the field of the related model it joins on
+                    (
+                        "licence",
+                        model_target("Licences", Some(vec![mapping("article_id", "article_id")])),
+                    ),
+                    // nor the field of the articles
+                    (
+                        "editor",
+                        model_target("Editors", Some(vec![mapping("editor_id", "editor_id")])),
+                    ),
+                ]
+                .map(|(name, target)| {
+                    (
+                        open_dds::relationships::RelationshipName::new(
+                            Identifier::new(name).unwrap(),
+                        ),
+                        target,
+                    )
+                }),
+            ),
+        };
+        let object_types = BTreeMap::from_iter([
+            related_object_type("Licences", &["name"]),
+            related_object_type("Editors", &["editor_id"]),
+        ]);
+        let mut selection =
+            IndexMap::from_iter([relationship("licence", []), relationship("editor", [])]);
+
+        let errors = split_included_queries(
+            &model,
+            &object_type,
+            &object_types,
+            &NamingStrategy::default(),
+            &mut selection,
+        )
+        .unwrap_err();
+        assert_eq!(
+            errors
+                .iter()
+                .map(|error| (error.parameter.as_str(), error.detail.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "include",
+                    "Relationship licence of Articles cannot be included, as the field article_id of Licences it is joined on is not accessible"
+                ),
+                (
+                    "include",
+                    "Relationship editor of Articles cannot be included, as the field editor_id it is joined on is not accessible"
+                ),
+            ]
+        );
+    }
 }
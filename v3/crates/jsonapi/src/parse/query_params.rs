@@ -0,0 +1,126 @@
+use crate::types::ParameterError;
+
+// The decoded `key=value` pairs of the request query string, in the order they were sent.
+//
+// `jsonapi_library::query::Query` only understands the parameters from the base spec, so anything
+// beyond those (eg, `page[cursor]`) is read from here.
+#[derive(Debug, Default)]
+pub struct QueryParams {
+    params: Vec<(String, String)>,
+}
+
+impl QueryParams {
+    pub fn parse(raw_query: &str) -> Self {
+        let params = url::form_urlencoded::parse(raw_query.as_bytes())
+            .map(|(key, value)| (key.into_owned(), value.into_owned()))
+            .collect();
+        QueryParams { params }
+    }
+
+    // the value of the first occurrence of `key`, if any
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(param_key, _)| param_key == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.params
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    // parameters that take a single value may only be given once: a request repeating one, like
+    // `page[limit]=10&page[limit]=50`, is most likely a client bug, and whichever value we picked
+    // would be a surprise to someone. parameters that are not in `single_valued`, like the
+    // `filter[...]` ones, may be repeated.
+    pub fn check_single_valued(&self, single_valued: &[&str]) -> Result<(), Vec<ParameterError>> {
+        let errors = single_valued
+            .iter()
+            .filter_map(|parameter| {
+                let count = self
+                    .params
+                    .iter()
+                    .filter(|(key, _)| key == parameter)
+                    .count();
+                (count > 1).then(|| {
+                    ParameterError::new(
+                        *parameter,
+                        format!("{parameter} may only be given once, but was given {count} times"),
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_query_params() {
+        let params = QueryParams::parse("page%5Bcursor%5D=abc.def&page[limit]=10&empty=");
+        assert_eq!(params.get("page[cursor]"), Some("abc.def"));
+        assert_eq!(params.get("page[limit]"), Some("10"));
+        assert_eq!(params.get("empty"), Some(""));
+        assert!(!params.contains("page[offset]"));
+    }
+
+    #[test]
+    fn test_check_single_valued() {
+        let single_valued = ["page[limit]", "page[offset]", "sort"];
+        let check = |query: &str| {
+            QueryParams::parse(query)
+                .check_single_valued(&single_valued)
+                .map_err(|errors| {
+                    errors
+                        .into_iter()
+                        .map(|error| (error.parameter, error.detail))
+                        .collect::<Vec<_>>()
+                })
+        };
+
+        assert!(check("page[limit]=10&page[offset]=20&sort=-title,body").is_ok());
+
+        // pagination, including when the brackets are encoded
+        assert_eq!(
+            check("page[limit]=10&page%5Blimit%5D=50"),
+            Err(vec![(
+                "page[limit]".to_string(),
+                "page[limit] may only be given once, but was given 2 times".to_string()
+            )])
+        );
+        assert_eq!(
+            check("page[offset]=0&page[limit]=10&page[offset]=20&page[offset]=40"),
+            Err(vec![(
+                "page[offset]".to_string(),
+                "page[offset] may only be given once, but was given 3 times".to_string()
+            )])
+        );
+
+        // sorting
+        assert_eq!(
+            check("sort=title&sort=-body"),
+            Err(vec![(
+                "sort".to_string(),
+                "sort may only be given once, but was given 2 times".to_string()
+            )])
+        );
+
+        // filters may be repeated, both for different fields and for the same one
+        assert!(
+            check("filter[title][_eq]=A&filter[body][_ilike]=%25b%25&filter[title][_eq]=A").is_ok()
+        );
+    }
+}
@@ -0,0 +1,179 @@
+use super::filter::{coerce_value, field_operand};
+use crate::catalog::{Model, ObjectType};
+use crate::types::RequestError;
+use open_dds::query::{BooleanExpression, ComparisonOperator, Value};
+use percent_encoding::percent_decode_str;
+
+// Fetching a single resource by its `id`, ie `/v1/rest/{subgraph}/{model}/{id}`
+// spec: <https://jsonapi.org/format/#fetching-resources>
+//
+// the `id` is the value of the model's unique identifier. for composite identifiers it is the
+// values of each of its fields in order, separated by commas, eg `/default/PlaylistTrack/1,3503`.
+// this is the same as the `id` we render for resources in responses, where commas within a value
+// are percent-encoded as `%2C`.
+//
+// we return a predicate matching the resource, or a 404 when the `id` cannot be the id of any
+// resource of the model.
+pub fn build_resource_filter(
+    model: &Model,
+    object_type: &ObjectType,
+    id: &str,
+) -> Result<BooleanExpression, RequestError> {
+    // without a unique identifier there is no way to fetch a single resource
+    if model.unique_identifier.is_empty() {
+        return Err(RequestError::NotFound);
+    }
+    let not_found = || RequestError::ResourceNotFound {
+        model_name: model.name.name.clone(),
+        id: id.to_string(),
+    };
+
+    let values = id
+        .split(',')
+        .map(|value| {
+            percent_decode_str(value)
+                .decode_utf8()
+                .map_err(|_| not_found())
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if values.len() != model.unique_identifier.len() {
+        return Err(not_found());
+    }
+
+    let mut comparisons = model
+        .unique_identifier
+        .iter()
+        .zip(values)
+        .map(|(field_name, value)| {
+            // a value not of the type of its field can't be the id of a resource
+            let argument = match object_type.type_fields.get(field_name) {
+                Some(field_type) => {
+                    coerce_value(field_name, field_type, &value).map_err(|_| not_found())?
+                }
+                None => serde_json::Value::String(value.into_owned()),
+            };
+            Ok(BooleanExpression::Comparison {
+                operand: field_operand(field_name),
+                operator: ComparisonOperator::Equals,
+                argument: Box::new(Value::Literal(argument)),
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(if comparisons.len() == 1 {
+        comparisons.remove(0)
+    } else {
+        BooleanExpression::And(comparisons)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::Type;
+    use indexmap::IndexMap;
+    use metadata_resolve::Qualified;
+    use open_dds::{
+        data_connector::DataConnectorName,
+        identifier::{Identifier, SubgraphName},
+        models::ModelName,
+        types::{CustomTypeName, FieldName},
+    };
+    use std::collections::{BTreeMap, BTreeSet};
+
+    fn field_name(name: &str) -> FieldName {
+        FieldName::new(Identifier::new(name).unwrap())
+    }
+
+    fn qualified<T>(name: T) -> Qualified<T> {
+        Qualified::new(SubgraphName::try_new("default").unwrap(), name)
+    }
+
+    // playlist tracks, identified by the playlist and the name of the track
+    fn playlist_track() -> (Model, ObjectType) {
+        let model = Model {
+            name: qualified(ModelName::new(Identifier::new("PlaylistTrack").unwrap())),
+            description: None,
+            data_type: qualified(CustomTypeName(Identifier::new("PlaylistTrack").unwrap())),
+            data_connector_name: qualified(DataConnectorName::new(Identifier::new("db").unwrap())),
+            filter_expression_type: None,
+            unique_identifier: vec![field_name("PlaylistId"), field_name("Name")],
+            supports_aggregates: true,
+            supports_grouping: true,
+            order_by_expression: None,
+        };
+        let object_type = ObjectType {
+            type_fields: IndexMap::from_iter([
+                (
+                    field_name("PlaylistId"),
+                    Type::Scalar(ndc_models::TypeRepresentation::Int32),
+                ),
+                (
+                    field_name("Name"),
+                    Type::Scalar(ndc_models::TypeRepresentation::String),
+                ),
+            ]),
+            nullable_fields: BTreeSet::new(),
+            field_arguments: BTreeMap::new(),
+            type_relationships: IndexMap::new(),
+        };
+        (model, object_type)
+    }
+
+    fn equals(field: &str, value: serde_json::Value) -> BooleanExpression {
+        BooleanExpression::Comparison {
+            operand: field_operand(&field_name(field)),
+            operator: ComparisonOperator::Equals,
+            argument: Box::new(Value::Literal(value)),
+        }
+    }
+
+    #[test]
+    fn test_resource_filter_composite_id() {
+        let (model, object_type) = playlist_track();
+        assert_eq!(
+            build_resource_filter(&model, &object_type, "1,Rock%2C%20Roll").unwrap(),
+            BooleanExpression::And(vec![
+                equals("PlaylistId", serde_json::json!(1)),
+                equals("Name", serde_json::json!("Rock, Roll")),
+            ])
+        );
+    }
+
+    // the `id`s we render for resources fetch those same resources
+    #[test]
+    fn test_resource_filter_round_trip() {
+        let (model, object_type) = playlist_track();
+        for name in ["Rock, Roll", "100%", "#1", "a%2Cb"] {
+            let row = serde_json::Map::from_iter([
+                ("PlaylistId".to_string(), serde_json::json!(1)),
+                ("Name".to_string(), serde_json::json!(name)),
+            ]);
+            let id = crate::process_response::resource_id(&mut 1, &model.unique_identifier, &row);
+            assert_eq!(
+                build_resource_filter(&model, &object_type, &id).unwrap(),
+                BooleanExpression::And(vec![
+                    equals("PlaylistId", serde_json::json!(1)),
+                    equals("Name", serde_json::json!(name)),
+                ])
+            );
+        }
+    }
+
+    #[test]
+    fn test_resource_filter_not_found() {
+        let (mut model, object_type) = playlist_track();
+        for id in ["1", "1,a,b", "1,%FF", "a,1"] {
+            assert!(matches!(
+                build_resource_filter(&model, &object_type, id),
+                Err(RequestError::ResourceNotFound { .. })
+            ));
+        }
+
+        model.unique_identifier = vec![];
+        assert!(matches!(
+            build_resource_filter(&model, &object_type, "1"),
+            Err(RequestError::NotFound)
+        ));
+    }
+}
@@ -0,0 +1,659 @@
+use super::arguments::FieldArguments;
+use super::filter::field_operand_with_arguments;
+use crate::catalog::{ObjectType, OrderByExpression, RelationshipTarget, Type};
+use crate::naming::NamingStrategy;
+use crate::types::ParameterError;
+use indexmap::IndexMap;
+use metadata_resolve::{OrderByExpressionIdentifier, Qualified};
+use open_dds::{
+    data_connector::DataConnectorName,
+    models::OrderByDirection,
+    query::{ObjectFieldOperand, ObjectFieldTarget, Operand, OrderByElement, RelationshipOperand},
+    relationships::{RelationshipName, RelationshipType},
+    types::{CustomTypeName, FieldName},
+};
+use std::collections::BTreeMap;
+
+pub(super) const SORT: &str = "sort";
+
+// Build the ordering for the "sort" query parameter
+// spec: <https://jsonapi.org/format/#fetching-sorting>
+//
+// `sort=-created_at,title` sorts by `created_at` descending, then `title` ascending. fields of
+// object relationships can be sorted on with a dotted path, eg `sort=author.name`.
+//
+// only the fields and relationships in the order by expression of the model can be sorted on,
+// and relationships only when the data connector of the model can sort on them in the same query.
+//
+// the unique identifier fields of the model are always appended (unless already sorted on), so
+// that the ordering is total and pages don't overlap or skip resources with equal sort values.
+// this also means that when `sort` is absent, resources are ordered by their unique identifier.
+//
+// fields and relationships are named as they are in documents, following the naming strategy.
+// fields with arguments are sorted on with the arguments given to them in `args[...]`.
+pub fn build_order_by(
+    sortable: &Sortable,
+    field_arguments: &FieldArguments,
+    object_type_name: &Qualified<CustomTypeName>,
+    order_by_expression: Option<&Qualified<OrderByExpressionIdentifier>>,
+    unique_identifier: &[FieldName],
+    sort: &[String],
+) -> Result<Vec<OrderByElement>, Vec<ParameterError>> {
+    let mut order_by = vec![];
+    let mut errors = vec![];
+    for sort_field in sort {
+        match build_order_by_element(
+            sortable,
+            field_arguments,
+            object_type_name,
+            order_by_expression,
+            sort_field.trim(),
+        ) {
+            Ok(element) => order_by.push(element),
+            Err(detail) => errors.push(ParameterError::new(SORT, detail)),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    for field_name in unique_identifier {
+        let operand = field_operand(field_name.clone());
+        if !order_by.iter().any(|element| element.operand == operand) {
+            order_by.push(OrderByElement {
+                operand,
+                direction: OrderByDirection::Asc,
+            });
+        }
+    }
+    Ok(order_by)
+}
+
+/// What we need to know about the metadata to check what a sort is allowed on
+pub struct Sortable<'a> {
+    pub object_types: &'a BTreeMap<Qualified<CustomTypeName>, ObjectType>,
+    pub order_by_expressions:
+        &'a BTreeMap<Qualified<OrderByExpressionIdentifier>, OrderByExpression>,
+    pub naming: &'a NamingStrategy,
+    /// the data connector of the resources we sort, which relationships must be local to
+    pub data_connector_name: Option<&'a Qualified<DataConnectorName>>,
+}
+
+impl Sortable<'_> {
+    /// Can a field of an object type be sorted on, with the given order by expression?
+    pub fn is_sortable_field(
+        &self,
+        object_type: &ObjectType,
+        order_by_expression: Option<&Qualified<OrderByExpressionIdentifier>>,
+        field_name: &FieldName,
+    ) -> bool {
+        // order by expressions can't list fields with arguments, so those can be sorted on
+        // whenever their type can
+        let orderable =
+            object_type.field_arguments.contains_key(field_name)
+                || self.order_by_expression(order_by_expression).is_some_and(
+                    |order_by_expression| order_by_expression.orderable_fields.contains(field_name),
+                );
+        orderable
+            && matches!(
+                object_type.type_fields.get(field_name),
+                Some(Type::Scalar(_) | Type::ScalarForDataConnector(_))
+            )
+    }
+
+    /// Can the fields of the target of a relationship be sorted on, with the given order by
+    /// expression for its source? If so, this is the order by expression for the target.
+    pub fn sortable_relationship(
+        &self,
+        order_by_expression: Option<&Qualified<OrderByExpressionIdentifier>>,
+        relationship_name: &RelationshipName,
+        target: &RelationshipTarget,
+    ) -> Option<Qualified<OrderByExpressionIdentifier>> {
+        let RelationshipTarget::Model {
+            relationship_type: RelationshipType::Object,
+            data_connector_name,
+            supports_relationships,
+            order_by_expression: target_order_by_expression,
+            ..
+        } = target
+        else {
+            return None;
+        };
+        // the data connector sorts on the relationship itself, so it has to be in the same query
+        if !(*supports_relationships && data_connector_name.as_ref() == self.data_connector_name) {
+            return None;
+        }
+        // the relationship names the order by expression for its target, or uses that of the
+        // target model
+        self.order_by_expression(order_by_expression)?
+            .orderable_relationships
+            .get(relationship_name)?
+            .clone()
+            .or_else(|| target_order_by_expression.clone())
+    }
+
+    fn order_by_expression(
+        &self,
+        identifier: Option<&Qualified<OrderByExpressionIdentifier>>,
+    ) -> Option<&OrderByExpression> {
+        self.order_by_expressions.get(identifier?)
+    }
+}
+
+fn build_order_by_element(
+    sortable: &Sortable,
+    field_arguments: &FieldArguments,
+    object_type_name: &Qualified<CustomTypeName>,
+    order_by_expression: Option<&Qualified<OrderByExpressionIdentifier>>,
+    sort_field: &str,
+) -> Result<OrderByElement, String> {
+    let (path, direction) = match sort_field.strip_prefix('-') {
+        Some(path) => (path, OrderByDirection::Desc),
+        None => (sort_field, OrderByDirection::Asc),
+    };
+    if path.is_empty() {
+        return Err("Sort fields must not be empty".to_string());
+    }
+    let segments = path.split('.').collect::<Vec<_>>();
+    let operand = build_operand(
+        sortable,
+        field_arguments,
+        object_type_name,
+        order_by_expression,
+        &segments,
+    )
+    .map_err(|reason| format!("Cannot sort on {path}: {reason}"))?;
+    Ok(OrderByElement { operand, direction })
+}
+
+// resolve a dotted sort path: every segment but the last is an object relationship, and the last
+// is a scalar field of the type we end up at
+fn build_operand(
+    sortable: &Sortable,
+    field_arguments: &FieldArguments,
+    object_type_name: &Qualified<CustomTypeName>,
+    order_by_expression: Option<&Qualified<OrderByExpressionIdentifier>>,
+    segments: &[&str],
+) -> Result<Operand, String> {
+    let object_type = sortable
+        .object_types
+        .get(object_type_name)
+        .ok_or_else(|| format!("type {} is not accessible", object_type_name.name))?;
+
+    match segments {
+        [] => Err("empty sort path".to_string()),
+        [field] => {
+            let field_name = object_type
+                .type_fields
+                .keys()
+                .find(|field_name| sortable.naming.field_name(field_name.as_str()) == *field)
+                .ok_or_else(|| format!("unknown field {field} in {}", object_type_name.name))?;
+            if !sortable.is_sortable_field(object_type, order_by_expression, field_name) {
+                return Err(format!(
+                    "field {field} in {} is not sortable",
+                    object_type_name.name
+                ));
+            }
+            let arguments = field_arguments.for_field(object_type_name, object_type, field_name)?;
+            Ok(field_operand_with_arguments(field_name, arguments))
+        }
+        [relationship, rest @ ..] => {
+            let (relationship_name, target) = object_type
+                .type_relationships
+                .iter()
+                .find(|(relationship_name, _)| {
+                    sortable.naming.field_name(relationship_name.as_str()) == *relationship
+                })
+                .ok_or_else(|| {
+                    format!(
+                        "unknown relationship {relationship} in {}",
+                        object_type_name.name
+                    )
+                })?;
+            match target {
+                RelationshipTarget::Model {
+                    object_type,
+                    relationship_type: RelationshipType::Object,
+                    ..
+                } => {
+                    let target_order_by_expression = sortable
+                        .sortable_relationship(order_by_expression, relationship_name, target)
+                        .ok_or_else(|| {
+                            format!(
+                                "relationship {relationship} in {} is not sortable",
+                                object_type_name.name
+                            )
+                        })?;
+                    let nested = build_operand(
+                        sortable,
+                        field_arguments,
+                        object_type,
+                        Some(&target_order_by_expression),
+                        rest,
+                    )?;
+                    Ok(relationship_operand(relationship_name.clone(), nested))
+                }
+                RelationshipTarget::Model { .. } | RelationshipTarget::Command { .. } => {
+                    Err(format!(
+                        "relationship {relationship} in {} is not an object relationship to a model",
+                        object_type_name.name
+                    ))
+                }
+            }
+        }
+    }
+}
+
+fn field_operand(field_name: FieldName) -> Operand {
+    Operand::Field(ObjectFieldOperand {
+        target: Box::new(ObjectFieldTarget {
+            field_name,
+            arguments: IndexMap::new(),
+        }),
+        nested: None,
+    })
+}
+
+fn relationship_operand(relationship_name: RelationshipName, nested: Operand) -> Operand {
+    Operand::Relationship(RelationshipOperand {
+        target: Box::new(open_dds::query::RelationshipTarget {
+            relationship_name,
+            arguments: IndexMap::new(),
+            filter: None,
+            order_by: Vec::new(),
+            limit: None,
+            offset: None,
+        }),
+        nested: Some(Box::new(nested)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use open_dds::{
+        identifier::{Identifier, SubgraphName},
+        models::ModelName,
+    };
+    use std::collections::BTreeSet;
+
+    fn field_name(name: &str) -> FieldName {
+        FieldName::new(Identifier::new(name).unwrap())
+    }
+
+    fn type_name(name: &str) -> Qualified<CustomTypeName> {
+        Qualified::new(
+            SubgraphName::try_new("default").unwrap(),
+            CustomTypeName(Identifier::new(name).unwrap()),
+        )
+    }
+
+    fn data_connector_name(name: &str) -> Qualified<DataConnectorName> {
+        Qualified::new(
+            SubgraphName::try_new("default").unwrap(),
+            DataConnectorName::new(Identifier::new(name).unwrap()),
+        )
+    }
+
+    fn order_by_expression_identifier(model: &str) -> Qualified<OrderByExpressionIdentifier> {
+        Qualified::new(
+            SubgraphName::try_new("default").unwrap(),
+            OrderByExpressionIdentifier::FromModel(ModelName::new(Identifier::new(model).unwrap())),
+        )
+    }
+
+    fn relationship_name(name: &str) -> RelationshipName {
+        RelationshipName::new(Identifier::new(name).unwrap())
+    }
+
+    fn string_field(name: &str) -> (FieldName, Type) {
+        (
+            field_name(name),
+            Type::Scalar(ndc_models::TypeRepresentation::String),
+        )
+    }
+
+    // articles, with an object relationship to their author and an array relationship to their
+    // comments, and an object relationship to their publisher in another data connector
+    fn object_types() -> BTreeMap<Qualified<CustomTypeName>, ObjectType> {
+        let model_target =
+            |model: &str, object_type: &str, relationship_type, data_connector: &str| {
+                RelationshipTarget::Model {
+                    model_name: Qualified::new(
+                        SubgraphName::try_new("default").unwrap(),
+                        ModelName::new(Identifier::new(model).unwrap()),
+                    ),
+                    object_type: type_name(object_type),
+                    relationship_type,
+                    unique_identifier: vec![],
+                    data_connector_name: Some(data_connector_name(data_connector)),
+                    supports_relationships: true,
+                    order_by_expression: Some(order_by_expression_identifier(model)),
+                    mappings: None,
+                }
+            };
+        BTreeMap::from_iter([
+            (
+                type_name("Article"),
+                ObjectType {
+                    type_fields: IndexMap::from_iter([
+                        string_field("article_id"),
+                        string_field("title"),
+                        string_field("created_at"),
+                        string_field("body"),
+                        (
+                            field_name("tags"),
+                            Type::List(Box::new(Type::Scalar(
+                                ndc_models::TypeRepresentation::String,
+                            ))),
+                        ),
+                    ]),
+                    nullable_fields: BTreeSet::new(),
+                    field_arguments: BTreeMap::new(),
+                    type_relationships: IndexMap::from_iter([
+                        (
+                            relationship_name("author"),
+                            model_target("Authors", "Author", RelationshipType::Object, "db"),
+                        ),
+                        (
+                            relationship_name("editor"),
+                            model_target("Authors", "Author", RelationshipType::Object, "db"),
+                        ),
+                        (
+                            relationship_name("comments"),
+                            model_target("Comments", "Comment", RelationshipType::Array, "db"),
+                        ),
+                        (
+                            relationship_name("publisher"),
+                            model_target(
+                                "Publishers",
+                                "Publisher",
+                                RelationshipType::Object,
+                                "other_db",
+                            ),
+                        ),
+                    ]),
+                },
+            ),
+            (
+                type_name("Author"),
+                ObjectType {
+                    type_fields: IndexMap::from_iter([string_field("name")]),
+                    nullable_fields: BTreeSet::new(),
+                    field_arguments: BTreeMap::new(),
+                    type_relationships: IndexMap::new(),
+                },
+            ),
+            (
+                type_name("Publisher"),
+                ObjectType {
+                    type_fields: IndexMap::from_iter([string_field("name")]),
+                    nullable_fields: BTreeSet::new(),
+                    field_arguments: BTreeMap::new(),
+                    type_relationships: IndexMap::new(),
+                },
+            ),
+        ])
+    }
+
+    // articles can't be sorted on their body or editor
+    fn order_by_expressions() -> BTreeMap<Qualified<OrderByExpressionIdentifier>, OrderByExpression>
+    {
+        let orderable_fields =
+            |fields: &[&str]| fields.iter().map(|name| field_name(name)).collect();
+        BTreeMap::from_iter([
+            (
+                order_by_expression_identifier("Articles"),
+                OrderByExpression {
+                    orderable_fields: orderable_fields(&["article_id", "title", "created_at"]),
+                    orderable_relationships: BTreeMap::from_iter([
+                        (relationship_name("author"), None),
+                        (relationship_name("publisher"), None),
+                    ]),
+                },
+            ),
+            (
+                order_by_expression_identifier("Authors"),
+                OrderByExpression {
+                    orderable_fields: orderable_fields(&["name"]),
+                    orderable_relationships: BTreeMap::new(),
+                },
+            ),
+            (
+                order_by_expression_identifier("Publishers"),
+                OrderByExpression {
+                    orderable_fields: orderable_fields(&["name"]),
+                    orderable_relationships: BTreeMap::new(),
+                },
+            ),
+        ])
+    }
+
+    fn build(
+        object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectType>,
+        naming: &NamingStrategy,
+        field_arguments: &FieldArguments,
+        unique_identifier: &[FieldName],
+        fields: &[&str],
+    ) -> Result<Vec<OrderByElement>, Vec<String>> {
+        let sortable = Sortable {
+            object_types,
+            order_by_expressions: &order_by_expressions(),
+            naming,
+            data_connector_name: Some(&data_connector_name("db")),
+        };
+        let fields = fields.iter().map(ToString::to_string).collect::<Vec<_>>();
+        build_order_by(
+            &sortable,
+            field_arguments,
+            &type_name("Article"),
+            Some(&order_by_expression_identifier("Articles")),
+            unique_identifier,
+            &fields,
+        )
+        .map_err(|errors| errors.into_iter().map(|error| error.detail).collect())
+    }
+
+    fn sort(fields: &[&str]) -> Result<Vec<OrderByElement>, Vec<String>> {
+        sort_with_naming(&NamingStrategy::default(), fields)
+    }
+
+    fn sort_with_naming(
+        naming: &NamingStrategy,
+        fields: &[&str],
+    ) -> Result<Vec<OrderByElement>, Vec<String>> {
+        build(
+            &object_types(),
+            naming,
+            &FieldArguments::none(&type_name("Article"), naming),
+            &[field_name("article_id")],
+            fields,
+        )
+    }
+
+    #[test]
+    fn test_sort_fields() {
+        assert_eq!(
+            sort(&["-created_at", "title"]).unwrap(),
+            vec![
+                OrderByElement {
+                    operand: field_operand(field_name("created_at")),
+                    direction: OrderByDirection::Desc,
+                },
+                OrderByElement {
+                    operand: field_operand(field_name("title")),
+                    direction: OrderByDirection::Asc,
+                },
+                // tiebreaker
+                OrderByElement {
+                    operand: field_operand(field_name("article_id")),
+                    direction: OrderByDirection::Asc,
+                },
+            ]
+        );
+
+        // no tiebreaker needed when we already sort on the unique identifier
+        assert_eq!(
+            sort(&["-article_id"]).unwrap(),
+            vec![OrderByElement {
+                operand: field_operand(field_name("article_id")),
+                direction: OrderByDirection::Desc,
+            }]
+        );
+
+        // default ordering
+        assert_eq!(
+            sort(&[]).unwrap(),
+            vec![OrderByElement {
+                operand: field_operand(field_name("article_id")),
+                direction: OrderByDirection::Asc,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_sort_relationship_field() {
+        assert_eq!(
+            sort(&["-author.name"]).unwrap()[0],
+            OrderByElement {
+                operand: relationship_operand(
+                    relationship_name("author"),
+                    field_operand(field_name("name"))
+                ),
+                direction: OrderByDirection::Desc,
+            }
+        );
+    }
+
+    #[test]
+    fn test_sort_errors() {
+        assert_eq!(
+            sort(&["nope", "-tags", "comments.body", "author.nope", "-"]).unwrap_err(),
+            vec![
+                "Cannot sort on nope: unknown field nope in Article",
+                "Cannot sort on tags: field tags in Article is not sortable",
+                "Cannot sort on comments.body: relationship comments in Article is not an object relationship to a model",
+                "Cannot sort on author.nope: unknown field nope in Author",
+                "Sort fields must not be empty",
+            ]
+        );
+
+        // fields and relationships left out of the order by expression, and relationships the
+        // data connector can't sort on in the same query
+        assert_eq!(
+            sort(&["body", "editor.name", "publisher.name"]).unwrap_err(),
+            vec![
+                "Cannot sort on body: field body in Article is not sortable",
+                "Cannot sort on editor.name: relationship editor in Article is not sortable",
+                "Cannot sort on publisher.name: relationship publisher in Article is not sortable",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sort_with_naming_strategy() {
+        let naming = NamingStrategy {
+            case: crate::naming::NameCase::CamelCase,
+            pluralize_types: false,
+        };
+        assert_eq!(
+            sort_with_naming(&naming, &["-createdAt"]).unwrap()[0],
+            OrderByElement {
+                operand: field_operand(field_name("created_at")),
+                direction: OrderByDirection::Desc,
+            }
+        );
+        assert_eq!(
+            sort_with_naming(&naming, &["created_at"]).unwrap_err(),
+            vec!["Cannot sort on created_at: unknown field created_at in Article"]
+        );
+    }
+
+    #[test]
+    fn test_sort_with_arguments() {
+        // a `word_count` computed field of articles, that needs the `language` of the article
+        let language = open_dds::query::ArgumentName::new(Identifier::new("language").unwrap());
+        let mut object_types = object_types();
+        let article = object_types.get_mut(&type_name("Article")).unwrap();
+        article.type_fields.insert(
+            field_name("word_count"),
+            Type::Scalar(ndc_models::TypeRepresentation::Int32),
+        );
+        article.field_arguments.insert(
+            field_name("word_count"),
+            IndexMap::from_iter([(
+                language.clone(),
+                crate::catalog::FieldArgument {
+                    argument_type: Type::Scalar(ndc_models::TypeRepresentation::String),
+                    required: true,
+                },
+            )]),
+        );
+
+        let naming = NamingStrategy::default();
+        let sort = |query: &str| {
+            let field_arguments = FieldArguments::parse(
+                &type_name("Article"),
+                &object_types[&type_name("Article")],
+                &naming,
+                &super::super::QueryParams::parse(query),
+            )
+            .unwrap();
+            build(
+                &object_types,
+                &naming,
+                &field_arguments,
+                &[],
+                &["-word_count"],
+            )
+        };
+
+        assert_eq!(
+            sort("args[word_count][language]=en").unwrap(),
+            vec![OrderByElement {
+                operand: field_operand_with_arguments(
+                    &field_name("word_count"),
+                    IndexMap::from_iter([(
+                        language,
+                        open_dds::query::Value::Literal(serde_json::json!("en"))
+                    )])
+                ),
+                direction: OrderByDirection::Desc,
+            }]
+        );
+        assert_eq!(
+            sort("").unwrap_err(),
+            vec![
+                "Cannot sort on word_count: field word_count of Article needs the arguments \
+                 language, given with args[word_count][language]=value"
+            ]
+        );
+
+        // which is a 400 for the `sort` parameter, rather than a query the data connector can't
+        // run
+        let sortable = Sortable {
+            object_types: &object_types,
+            order_by_expressions: &order_by_expressions(),
+            naming: &naming,
+            data_connector_name: Some(&data_connector_name("db")),
+        };
+        let errors = build_order_by(
+            &sortable,
+            &FieldArguments::none(&type_name("Article"), &naming),
+            &type_name("Article"),
+            Some(&order_by_expression_identifier("Articles")),
+            &[],
+            &["word_count".to_string()],
+        )
+        .unwrap_err();
+        let error = crate::types::RequestError::InvalidParameters(errors).into_http_error();
+        assert_eq!(error.status, axum::http::StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error.errors[0].source,
+            Some(crate::types::ErrorSource::Parameter("sort".to_string()))
+        );
+        assert!(error.errors[0]
+            .detail
+            .contains("needs the arguments language"));
+    }
+}
@@ -1,50 +1,124 @@
 use super::helpers::get_object_type;
-use super::types::{RelationshipNode, RelationshipTree};
-use crate::catalog::ObjectType;
+use super::parse::{join_key, AggregateIR, IncludedQuery, SparseFields};
+use super::types::{LinkageDocument, RelationshipNode, RelationshipTree};
+use crate::catalog::{ObjectType, ScalarRepresentation, Type};
+use crate::links::{self, LinkBase};
+use crate::naming::NamingStrategy;
 use crate::RequestError;
-use jsonapi_library::query::Query;
+use chrono_tz::Tz;
+use indexmap::IndexMap;
 use metadata_resolve::Qualified;
-use open_dds::{relationships::RelationshipType, types::CustomTypeName};
-use std::collections::BTreeMap;
+use open_dds::{
+    relationships::RelationshipType,
+    types::{CustomTypeName, FieldName},
+};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use std::collections::{BTreeMap, BTreeSet};
 
-// a cheap way to get a unique id for each resource. we should probably get this from the
-// underlying data instead.
+// Resources collected for the top-level `included` member, keyed by `(type, id)`.
+//
+// https://jsonapi.org/format/#document-compound-documents
+//
+// "A compound document MUST NOT include more than one resource object for each type and id pair."
+type IncludedResources = IndexMap<(String, String), jsonapi_library::model::Resource>;
+
+// a cheap way to get a unique id for each resource, used when the model has no unique identifier
+// for us to get this from the underlying data. these are `#1`, `#2` and so on: the values in ids
+// from the data have any `#` percent-encoded, so the two never collide, and a resource with a
+// fresh id is never mistaken for another when deduplicating `included`.
 //
 // https://jsonapi.org/format/#document-resource-object-identification
 //
 // "Within a given API, each resource object’s type and id pair MUST identify a single, unique
 // resource."
-fn fresh_id(unique_id: &mut i32) -> i32 {
+fn fresh_id(unique_id: &mut i32) -> String {
     let id = *unique_id;
 
     // increment counter
     *unique_id += 1;
 
-    id
+    format!("#{id}")
+}
+
+// the characters escaped in each value of an `id`: `,` as it separates the values of composite
+// identifiers, `%` so that values decode back to themselves, and `#` as it starts fresh ids
+const ID_VALUE: &AsciiSet = &CONTROLS.add(b',').add(b'%').add(b'#');
+
+// render the `id` of a resource from the values of its unique identifier fields. composite
+// identifiers are joined with a comma, and each value is percent-encoded so that the `id` can be
+// split back into its values, as when fetching a resource by its `id`. we fall back to a fresh id
+// if the model has no unique identifier, or if any of its values are missing from the row.
+pub(crate) fn resource_id(
+    unique_id: &mut i32,
+    unique_identifier: &[FieldName],
+    row: &serde_json::Map<String, serde_json::Value>,
+) -> String {
+    let values = unique_identifier
+        .iter()
+        .map(|field_name| match row.get(field_name.as_str()) {
+            None | Some(serde_json::Value::Null) => None,
+            Some(serde_json::Value::String(value)) => {
+                Some(utf8_percent_encode(value, ID_VALUE).to_string())
+            }
+            Some(value) => Some(utf8_percent_encode(&value.to_string(), ID_VALUE).to_string()),
+        })
+        .collect::<Option<Vec<_>>>();
+
+    match values {
+        Some(values) if !values.is_empty() => values.join(","),
+        _ => fresh_id(unique_id),
+    }
+}
+
+// the `self` link of a resource. only resources identified by a single field get one, as that is
+// what the route of a resource takes, and only those with a value for it, rather than a fresh id.
+fn resource_links(
+    link_base: &LinkBase,
+    model_route: Option<&str>,
+    unique_identifier: &[FieldName],
+    row: &serde_json::Map<String, serde_json::Value>,
+) -> Option<jsonapi_library::api::Links> {
+    let ([field_name], Some(model_route)) = (unique_identifier, model_route) else {
+        return None;
+    };
+    let id = match row.get(field_name.as_str())? {
+        serde_json::Value::Null => return None,
+        serde_json::Value::String(value) => value.clone(),
+        value => value.to_string(),
+    };
+    Some(links::resource_links(link_base, model_route, &id))
 }
 
 fn to_resource(
     unique_id: &mut i32,
     rowset: ndc_models::RowSet,
     type_name: &Qualified<CustomTypeName>,
+    unique_identifier: &[FieldName],
     relationship_tree: &RelationshipTree,
-    query: &Query,
+    sparse_fields: &SparseFields,
     object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectType>,
-    collect_relationships: &mut Vec<jsonapi_library::model::Resource>,
+    naming: &NamingStrategy,
+    time_zone: Tz,
+    link_base: &LinkBase,
+    model_route: &str,
+    included: &mut IncludedResources,
 ) -> Result<Vec<jsonapi_library::model::Resource>, RequestError> {
     let mut resources = vec![];
     if let Some(rows) = rowset.rows {
         for row in rows {
-            let resource_id = fresh_id(unique_id);
             let resource = row_to_resource(
                 unique_id,
                 relationship_tree,
-                collect_relationships,
-                resource_id,
+                included,
                 type_name,
-                query,
+                unique_identifier,
+                sparse_fields,
                 object_types,
-                row.into_iter().map(|(k, v)| (k.to_string(), v.0)),
+                naming,
+                time_zone,
+                link_base,
+                Some(model_route),
+                row.into_iter().map(|(k, v)| (k.to_string(), v.0)).collect(),
             )?;
             resources.push(resource);
         }
@@ -52,20 +126,128 @@ fn to_resource(
     Ok(resources)
 }
 
-fn render_type_name(type_name: &Qualified<CustomTypeName>) -> String {
-    format!("{}_{}", type_name.subgraph, type_name.name)
+// a value from the data connector, in the JSON:API representation of its type. values that
+// aren't in the representation we expect are passed on as they are. the fields of nested objects
+// are named by the naming strategy, as attributes are, and timestamps with a time zone are
+// written in `time_zone`.
+fn attribute_value(
+    field_type: &Type,
+    object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectType>,
+    naming: &NamingStrategy,
+    time_zone: Tz,
+    value: serde_json::Value,
+) -> serde_json::Value {
+    match (field_type, value) {
+        (Type::List(element_type), serde_json::Value::Array(elements)) => serde_json::Value::Array(
+            elements
+                .into_iter()
+                .map(|element| {
+                    attribute_value(element_type, object_types, naming, time_zone, element)
+                })
+                .collect(),
+        ),
+        (Type::Object(object_type_name), serde_json::Value::Object(fields)) => {
+            let object_type = object_types.get(object_type_name);
+            serde_json::Value::Object(
+                fields
+                    .into_iter()
+                    .map(|(key, value)| {
+                        let field_type = object_type.and_then(|object_type| {
+                            object_type
+                                .type_fields
+                                .iter()
+                                .find(|(field_name, _)| field_name.as_str() == key)
+                        });
+                        let value = match field_type {
+                            Some((_, field_type)) => {
+                                attribute_value(field_type, object_types, naming, time_zone, value)
+                            }
+                            None => value,
+                        };
+                        (naming.field_name(&key), value)
+                    })
+                    .collect(),
+            )
+        }
+        (field_type, value) => match field_type.scalar_representation() {
+            Some(representation) => scalar_value(representation, time_zone, value),
+            None => value,
+        },
+    }
+}
+
+fn scalar_value(
+    representation: ScalarRepresentation,
+    time_zone: Tz,
+    value: serde_json::Value,
+) -> serde_json::Value {
+    match (representation, value) {
+        (ScalarRepresentation::NumericString { .. }, serde_json::Value::Number(number)) => {
+            serde_json::Value::String(number.to_string())
+        }
+        (ScalarRepresentation::Number { .. }, serde_json::Value::String(string)) => {
+            match string.parse::<serde_json::Number>() {
+                Ok(number) => serde_json::Value::Number(number),
+                Err(_) => serde_json::Value::String(string),
+            }
+        }
+        (ScalarRepresentation::Boolean, serde_json::Value::String(string)) => {
+            match string.parse::<bool>() {
+                Ok(boolean) => serde_json::Value::Bool(boolean),
+                Err(_) => serde_json::Value::String(string),
+            }
+        }
+        (ScalarRepresentation::Timestamp { with_time_zone }, serde_json::Value::String(string)) => {
+            serde_json::Value::String(
+                rfc3339_timestamp(&string, with_time_zone, time_zone).unwrap_or(string),
+            )
+        }
+        (_, value) => value,
+    }
+}
+
+// a timestamp in RFC 3339 format, from the formats data connectors commonly use, eg
+// `2024-01-02 03:04:05+00` from PostgreSQL. timestamps with a time zone come back in whatever
+// time zone the data connector uses, so are written in `time_zone` instead, with its offset at
+// that instant. `None` if it is in a format we don't know, or is a timestamp without a time
+// zone already in RFC 3339 format.
+fn rfc3339_timestamp(timestamp: &str, with_time_zone: bool, time_zone: Tz) -> Option<String> {
+    if with_time_zone {
+        chrono::DateTime::parse_from_rfc3339(timestamp)
+            .ok()
+            .or_else(|| {
+                ["%Y-%m-%d %H:%M:%S%.f%#z", "%Y-%m-%dT%H:%M:%S%.f%#z"]
+                    .iter()
+                    .find_map(|format| chrono::DateTime::parse_from_str(timestamp, format).ok())
+            })
+            .map(|timestamp| timestamp.with_timezone(&time_zone).to_rfc3339())
+    } else {
+        let rfc3339 = "%Y-%m-%dT%H:%M:%S%.f";
+        if chrono::NaiveDateTime::parse_from_str(timestamp, rfc3339).is_ok() {
+            return None;
+        }
+        chrono::NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S%.f")
+            .ok()
+            .map(|timestamp| timestamp.format(rfc3339).to_string())
+    }
 }
 
 fn row_to_resource(
     unique_id: &mut i32,
     relationship_tree: &RelationshipTree,
-    collect_relationships: &mut Vec<jsonapi_library::model::Resource>,
-    resource_id: i32,
+    included: &mut IncludedResources,
     row_type: &Qualified<CustomTypeName>,
-    query: &Query,
+    unique_identifier: &[FieldName],
+    sparse_fields: &SparseFields,
     object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectType>,
-    row: impl Iterator<Item = (String, serde_json::Value)>,
+    naming: &NamingStrategy,
+    time_zone: Tz,
+    link_base: &LinkBase,
+    model_route: Option<&str>,
+    row: serde_json::Map<String, serde_json::Value>,
 ) -> Result<jsonapi_library::model::Resource, RequestError> {
+    let resource_id = resource_id(unique_id, unique_identifier, &row);
+    let links = resource_links(link_base, model_route, unique_identifier, &row);
     let mut attributes = BTreeMap::new();
     let mut relationships = BTreeMap::new();
 
@@ -76,88 +258,102 @@ fn row_to_resource(
                 nested,
                 relationship_type,
                 object_type,
+                unique_identifier,
+                model_route,
                 is_command_relationship,
             } = relationship_node;
-            let relationship_type_name = render_type_name(object_type);
-            let relationship_identifier_data = match value
-                .get_mut("rows")
-                .and_then(|rows| rows.as_array_mut())
-            {
-                None => jsonapi_library::model::IdentifierData::None,
-                Some(relationship_rows) => match relationship_type {
-                    RelationshipType::Object => {
-                        if let Some(object_row_value) = relationship_rows.pop() {
-                            let relationship_id = fresh_id(unique_id);
-                            let resource_identifier = jsonapi_library::model::ResourceIdentifier {
-                                _type: relationship_type_name,
-                                id: relationship_id.to_string(),
-                            };
-                            // collect this relationship value
-                            collect_relationship_value(
-                                unique_id,
-                                nested,
-                                collect_relationships,
-                                relationship_id,
-                                object_type,
-                                *is_command_relationship,
-                                query,
-                                object_types,
-                                object_row_value,
-                            )?;
-                            jsonapi_library::model::IdentifierData::Single(resource_identifier)
-                        } else {
-                            jsonapi_library::model::IdentifierData::None
+            let relationship_identifier_data =
+                match value.get_mut("rows").and_then(|rows| rows.as_array_mut()) {
+                    None => empty_linkage(relationship_type),
+                    Some(relationship_rows) => match relationship_type {
+                        RelationshipType::Object => {
+                            if let Some(object_row_value) = relationship_rows.pop() {
+                                // collect this relationship value
+                                let resource_identifier = collect_relationship_value(
+                                    unique_id,
+                                    nested,
+                                    included,
+                                    object_type,
+                                    unique_identifier,
+                                    model_route.as_deref(),
+                                    *is_command_relationship,
+                                    sparse_fields,
+                                    object_types,
+                                    naming,
+                                    time_zone,
+                                    link_base,
+                                    object_row_value,
+                                )?;
+                                jsonapi_library::model::IdentifierData::Single(resource_identifier)
+                            } else {
+                                jsonapi_library::model::IdentifierData::None
+                            }
                         }
-                    }
-                    RelationshipType::Array => {
-                        let mut resource_identifiers = vec![];
-                        for object_row_value in relationship_rows.iter_mut() {
-                            let relationship_id = fresh_id(unique_id);
-                            let resource_identifier = jsonapi_library::model::ResourceIdentifier {
-                                _type: relationship_type_name.clone(),
-                                id: relationship_id.to_string(),
-                            };
-                            // collect this relationship value
-                            collect_relationship_value(
-                                unique_id,
-                                nested,
-                                collect_relationships,
-                                relationship_id,
-                                object_type,
-                                *is_command_relationship,
-                                query,
-                                object_types,
-                                object_row_value.take(),
-                            )?;
-                            resource_identifiers.push(resource_identifier);
+                        RelationshipType::Array => {
+                            let mut resource_identifiers = vec![];
+                            for object_row_value in relationship_rows.iter_mut() {
+                                // collect this relationship value
+                                let resource_identifier = collect_relationship_value(
+                                    unique_id,
+                                    nested,
+                                    included,
+                                    object_type,
+                                    unique_identifier,
+                                    model_route.as_deref(),
+                                    *is_command_relationship,
+                                    sparse_fields,
+                                    object_types,
+                                    naming,
+                                    time_zone,
+                                    link_base,
+                                    object_row_value.take(),
+                                )?;
+                                resource_identifiers.push(resource_identifier);
+                            }
+                            jsonapi_library::model::IdentifierData::Multiple(resource_identifiers)
                         }
-                        jsonapi_library::model::IdentifierData::Multiple(resource_identifiers)
-                    }
-                },
-            };
+                    },
+                };
             let relationship = jsonapi_library::model::Relationship {
                 data: Some(relationship_identifier_data),
                 links: None,
             };
-            relationships.insert(key.to_string(), relationship);
+            relationships.insert(naming.field_name(&key), relationship);
         } else {
             let object_type =
                 get_object_type(object_types, row_type).map_err(RequestError::ParseError)?;
             let identifier = open_dds::identifier::Identifier::new(key.as_str()).unwrap();
             let field_name = open_dds::types::FieldName::new(identifier);
 
-            if object_type.type_fields.contains_key(&field_name) {
-                attributes.insert(key.to_string(), value);
+            // unique identifier fields are always fetched, so we need to drop them here if they
+            // were not asked for in the sparse fields
+            if let Some(field_type) = object_type.type_fields.get(&field_name) {
+                if sparse_fields.include_field(&row_type.name, &field_name) {
+                    attributes.insert(
+                        naming.field_name(&key),
+                        attribute_value(field_type, object_types, naming, time_zone, value),
+                    );
+                }
             }
         }
     }
 
-    let rendered_type_name = render_type_name(row_type);
+    // a relationship the data connector left out of the row has no related resources. clients
+    // tell an empty relationship from one that wasn't asked for, so it is never left out.
+    for (key, relationship_node) in &relationship_tree.relationships {
+        relationships
+            .entry(naming.field_name(key))
+            .or_insert_with(|| jsonapi_library::model::Relationship {
+                data: Some(empty_linkage(&relationship_node.relationship_type)),
+                links: None,
+            });
+    }
+
     Ok(jsonapi_library::api::Resource {
-        _type: rendered_type_name,
-        id: resource_id.to_string(),
+        _type: naming.resource_type(row_type),
+        id: resource_id,
         attributes,
-        links: None,
+        links,
         meta: None,
         relationships: if relationships.is_empty() {
             None
@@ -167,18 +363,31 @@ fn row_to_resource(
     })
 }
 
+// the linkage of a relationship without related resources: `null` for a to-one relationship, and
+// `[]` for a to-many one
+// spec: <https://jsonapi.org/format/#document-resource-object-linkage>
+fn empty_linkage(relationship_type: &RelationshipType) -> jsonapi_library::model::IdentifierData {
+    match relationship_type {
+        RelationshipType::Object => jsonapi_library::model::IdentifierData::None,
+        RelationshipType::Array => jsonapi_library::model::IdentifierData::Multiple(vec![]),
+    }
+}
+
 fn collect_relationship_value(
     unique_id: &mut i32,
     relationship_tree: &RelationshipTree,
-    collect_relationships: &mut Vec<jsonapi_library::model::Resource>,
-    resource_id: i32,
+    included: &mut IncludedResources,
     row_type: &Qualified<CustomTypeName>,
+    unique_identifier: &[FieldName],
+    model_route: Option<&str>,
     is_command_relationship: bool,
-    query: &Query,
+    sparse_fields: &SparseFields,
     object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectType>,
-
+    naming: &NamingStrategy,
+    time_zone: Tz,
+    link_base: &LinkBase,
     mut value: serde_json::Value,
-) -> Result<(), RequestError> {
+) -> Result<jsonapi_library::model::ResourceIdentifier, RequestError> {
     if is_command_relationship {
         // If this is a command relationship, we need to extract the value from the 'FUNCTION_IR_VALUE_COLUMN_NAME' key
         // We are ignoring the other keys
@@ -194,46 +403,91 @@ fn collect_relationship_value(
     let relationship_resource = row_to_resource(
         unique_id,
         relationship_tree,
-        collect_relationships,
-        resource_id,
+        included,
         row_type,
-        query,
+        unique_identifier,
+        sparse_fields,
         object_types,
-        row_object.into_iter(),
+        naming,
+        time_zone,
+        link_base,
+        model_route,
+        row_object,
     )?;
+    let resource_identifier = jsonapi_library::model::ResourceIdentifier {
+        _type: relationship_resource._type.clone(),
+        id: relationship_resource.id.clone(),
+    };
     // collect this relationship resource
-    collect_relationships.push(relationship_resource);
+    include_resource(included, relationship_resource);
 
-    Ok(())
+    Ok(resource_identifier)
+}
+
+// add a resource to `included`, unless we have already seen it. the same resource can be reached
+// through more than one include path (eg, a shared author of many comments), in which case we
+// merge the relationships each path asked for.
+fn include_resource(included: &mut IncludedResources, resource: jsonapi_library::model::Resource) {
+    match included.entry((resource._type.clone(), resource.id.clone())) {
+        indexmap::map::Entry::Vacant(entry) => {
+            entry.insert(resource);
+        }
+        indexmap::map::Entry::Occupied(mut entry) => {
+            if let Some(relationships) = resource.relationships {
+                entry
+                    .get_mut()
+                    .relationships
+                    .get_or_insert_with(BTreeMap::new)
+                    .extend(relationships);
+            }
+        }
+    }
 }
 
 pub fn process_result(
     rowsets: Vec<ndc_models::RowSet>,
     root_type_name: &Qualified<CustomTypeName>,
+    root_unique_identifier: &[FieldName],
     relationship_tree: &RelationshipTree,
-    query: &Query,
+    sparse_fields: &SparseFields,
     object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectType>,
+    naming: &NamingStrategy,
+    time_zone: Tz,
+    link_base: &LinkBase,
+    model_route: &str,
 ) -> Result<jsonapi_library::api::DocumentData, RequestError> {
     let mut unique_id = 1;
 
     let mut resources = vec![];
-    let mut collect_relationships = vec![];
+    let mut included = IndexMap::new();
     if let Some(first_rowset) = rowsets.into_iter().next() {
         resources.extend(to_resource(
             &mut unique_id,
             first_rowset,
             root_type_name,
+            root_unique_identifier,
             relationship_tree,
-            query,
+            sparse_fields,
             object_types,
-            &mut collect_relationships,
+            naming,
+            time_zone,
+            link_base,
+            model_route,
+            &mut included,
         )?);
     }
 
-    let included = if collect_relationships.is_empty() {
+    // resources in the primary data must not appear again in `included`
+    let primary_resources = resources
+        .iter()
+        .map(|resource| (resource._type.clone(), resource.id.clone()))
+        .collect::<BTreeSet<_>>();
+    included.retain(|key, _| !primary_resources.contains(key));
+
+    let included = if included.is_empty() {
         None
     } else {
-        Some(collect_relationships)
+        Some(included.into_values().collect())
     };
 
     Ok(jsonapi_library::api::DocumentData {
@@ -244,3 +498,1037 @@ pub fn process_result(
         jsonapi: None,
     })
 }
+
+/// The document for a page of resources without `include`, serialized one resource at a time
+/// as it is written out rather than as a list of resources built up front.
+///
+/// This holds on to all of the rows from the data connector, and renders each of them to a
+/// resource object only as it is serialized, in the same way `process_result` would. This saves
+/// building the resource objects and the serialized document in full, but not holding the rows.
+#[derive(Debug)]
+pub struct StreamedDocument {
+    rows: Vec<IndexMap<ndc_models::FieldName, ndc_models::RowFieldValue>>,
+    type_name: String,
+    unique_identifier: Vec<FieldName>,
+    // the fields of the rows that are rendered as attributes, ie the fields of the type that are
+    // in the sparse fields, with their names in documents
+    attributes: BTreeMap<String, String>,
+    link_base: LinkBase,
+    model_route: String,
+    pub links: Option<jsonapi_library::api::Links>,
+    pub meta: Option<jsonapi_library::api::Meta>,
+}
+
+impl StreamedDocument {
+    pub fn row_count(&self) -> usize {
+        self.rows.len()
+    }
+
+    fn to_resource(
+        &self,
+        unique_id: &mut i32,
+        row: &IndexMap<ndc_models::FieldName, ndc_models::RowFieldValue>,
+    ) -> jsonapi_library::api::Resource {
+        let row = row
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.0.clone()))
+            .collect::<serde_json::Map<_, _>>();
+        let id = resource_id(unique_id, &self.unique_identifier, &row);
+        let links = resource_links(
+            &self.link_base,
+            Some(&self.model_route),
+            &self.unique_identifier,
+            &row,
+        );
+        let attributes = row
+            .into_iter()
+            .filter_map(|(key, value)| {
+                self.attributes
+                    .get(&key)
+                    .map(|attribute| (attribute.clone(), value))
+            })
+            .collect();
+        jsonapi_library::api::Resource {
+            _type: self.type_name.clone(),
+            id,
+            attributes,
+            links,
+            meta: None,
+            relationships: None,
+        }
+    }
+}
+
+impl serde::Serialize for StreamedDocument {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        let mut document = serializer.serialize_map(None)?;
+        document.serialize_entry("data", &StreamedResources(self))?;
+        if let Some(links) = &self.links {
+            document.serialize_entry("links", links)?;
+        }
+        if let Some(meta) = &self.meta {
+            document.serialize_entry("meta", meta)?;
+        }
+        document.end()
+    }
+}
+
+struct StreamedResources<'a>(&'a StreamedDocument);
+
+impl serde::Serialize for StreamedResources<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeSeq;
+
+        let mut unique_id = 1;
+        let mut resources = serializer.serialize_seq(Some(self.0.rows.len()))?;
+        for row in &self.0.rows {
+            resources.serialize_element(&self.0.to_resource(&mut unique_id, row))?;
+        }
+        resources.end()
+    }
+}
+
+// the rows of the first rowset, as a document to be streamed. only for queries without
+// relationships, as the rows are rendered without looking for any.
+pub fn streamed_result(
+    rowsets: Vec<ndc_models::RowSet>,
+    root_type_name: &Qualified<CustomTypeName>,
+    root_unique_identifier: &[FieldName],
+    sparse_fields: &SparseFields,
+    object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectType>,
+    naming: &NamingStrategy,
+    time_zone: Tz,
+    link_base: &LinkBase,
+    model_route: &str,
+) -> Result<StreamedDocument, RequestError> {
+    let object_type =
+        get_object_type(object_types, root_type_name).map_err(RequestError::ParseError)?;
+    let attribute_types = object_type
+        .type_fields
+        .iter()
+        .filter(|(field_name, _)| sparse_fields.include_field(&root_type_name.name, field_name))
+        .map(|(field_name, field_type)| (field_name.as_str(), field_type))
+        .collect::<BTreeMap<_, _>>();
+    let attributes = attribute_types
+        .keys()
+        .map(|field_name| (field_name.to_string(), naming.field_name(field_name)))
+        .collect();
+    let mut rows = rowsets
+        .into_iter()
+        .next()
+        .and_then(|rowset| rowset.rows)
+        .unwrap_or_default();
+    // the attributes are rendered as they are serialized, so are put in their JSON:API
+    // representation here
+    for row in &mut rows {
+        for (field_name, field_type) in &attribute_types {
+            if let Some(value) = row.get_mut(&ndc_models::FieldName::from(*field_name)) {
+                value.0 =
+                    attribute_value(field_type, object_types, naming, time_zone, value.0.take());
+            }
+        }
+    }
+    Ok(StreamedDocument {
+        rows,
+        type_name: naming.resource_type(root_type_name),
+        unique_identifier: root_unique_identifier.to_vec(),
+        attributes,
+        link_base: link_base.clone(),
+        model_route: model_route.to_string(),
+        links: None,
+        meta: None,
+    })
+}
+
+// split the document for a resource into the linkage of one of its relationships, and a document
+// whose primary data is the related resources. the related resources are taken out of
+// `included`, where they were collected as the relationship was included in the query.
+//
+// returns `None` when the document has no resource, ie there is no resource with the requested
+// `id`.
+pub fn related_document(
+    mut document: jsonapi_library::api::DocumentData,
+    relationship: &str,
+    relationship_type: &RelationshipType,
+) -> Option<(
+    jsonapi_library::model::IdentifierData,
+    jsonapi_library::api::DocumentData,
+)> {
+    let resource = match document.data.take() {
+        Some(jsonapi_library::api::PrimaryData::Multiple(resources)) => {
+            resources.into_iter().next()
+        }
+        Some(jsonapi_library::api::PrimaryData::Single(resource)) => Some(*resource),
+        _ => None,
+    }?;
+
+    let linkage = resource
+        .relationships
+        .and_then(|mut relationships| relationships.remove(relationship))
+        .and_then(|relationship| relationship.data)
+        .unwrap_or_else(|| empty_linkage(relationship_type));
+
+    let mut included = document
+        .included
+        .take()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|resource| ((resource._type.clone(), resource.id.clone()), resource))
+        .collect::<IncludedResources>();
+    let mut take_related = |identifier: &jsonapi_library::model::ResourceIdentifier| {
+        included.shift_remove(&(identifier._type.clone(), identifier.id.clone()))
+    };
+    let data = match &linkage {
+        jsonapi_library::model::IdentifierData::None => jsonapi_library::api::PrimaryData::None,
+        jsonapi_library::model::IdentifierData::Single(identifier) => {
+            match take_related(identifier) {
+                Some(related) => jsonapi_library::api::PrimaryData::Single(Box::new(related)),
+                None => jsonapi_library::api::PrimaryData::None,
+            }
+        }
+        jsonapi_library::model::IdentifierData::Multiple(identifiers) => {
+            jsonapi_library::api::PrimaryData::Multiple(
+                identifiers.iter().filter_map(&mut take_related).collect(),
+            )
+        }
+    };
+
+    document.data = Some(data);
+    document.included = if included.is_empty() {
+        None
+    } else {
+        Some(included.into_values().collect())
+    };
+    Some((linkage, document))
+}
+
+/// Join the related rows fetched by the query of an included relationship to the rows of the
+/// resources, as the value of the relationship in each row, in the shape the data connector would
+/// have returned it in: `{"rows": [...]}`.
+pub fn join_included_rows(
+    rowsets: &mut [ndc_models::RowSet],
+    included_query: &IncludedQuery,
+    related_rows: Vec<IndexMap<ndc_models::FieldName, ndc_models::RowFieldValue>>,
+) {
+    let mut related_by_key = BTreeMap::<String, Vec<serde_json::Value>>::new();
+    for related_row in related_rows {
+        let target_fields = included_query.mappings.iter().map(|m| &m.target_field);
+        if let Some(key) = join_key(&related_row, target_fields) {
+            let related_row = related_row
+                .into_iter()
+                .map(|(field_name, value)| (field_name.to_string(), value.0))
+                .collect();
+            related_by_key
+                .entry(serde_json::Value::Array(key).to_string())
+                .or_default()
+                .push(serde_json::Value::Object(related_row));
+        }
+    }
+
+    let relationship = ndc_models::FieldName::from(included_query.relationship.as_str());
+    for row in rowsets
+        .iter_mut()
+        .filter_map(|rowset| rowset.rows.as_mut())
+        .flatten()
+    {
+        let source_fields = included_query.mappings.iter().map(|m| &m.source_field);
+        let related = join_key(row, source_fields)
+            .and_then(|key| related_by_key.get(&serde_json::Value::Array(key).to_string()))
+            .cloned()
+            .unwrap_or_default();
+        row.insert(
+            relationship.clone(),
+            ndc_models::RowFieldValue(serde_json::json!({ "rows": related })),
+        );
+    }
+}
+
+/// The document for `data=identifiers`, whose primary data is the identifiers of the resources of
+/// a document rather than the resources themselves. the links, included resources and meta of the
+/// document are kept.
+pub fn identifiers_document(document: jsonapi_library::api::DocumentData) -> LinkageDocument {
+    let identifier =
+        |resource: jsonapi_library::api::Resource| jsonapi_library::model::ResourceIdentifier {
+            _type: resource._type,
+            id: resource.id,
+        };
+    let data = match document.data {
+        Some(jsonapi_library::api::PrimaryData::Multiple(resources)) => {
+            jsonapi_library::model::IdentifierData::Multiple(
+                resources.into_iter().map(identifier).collect(),
+            )
+        }
+        Some(jsonapi_library::api::PrimaryData::Single(resource)) => {
+            jsonapi_library::model::IdentifierData::Single(identifier(*resource))
+        }
+        Some(jsonapi_library::api::PrimaryData::None) | None => {
+            jsonapi_library::model::IdentifierData::None
+        }
+    };
+    LinkageDocument {
+        data,
+        links: document.links.unwrap_or_default(),
+        included: document.included,
+        meta: document.meta,
+    }
+}
+
+/// The document for the aggregates of a model. it has no primary data, just the aggregates as
+/// `meta.aggregate`, eg `{"sum": {"amount": 120}, "max": {"amount": 80}}`.
+///
+/// With `group_by`, `meta.aggregate` is a list of these instead, one for each group, with the
+/// values of the fields the group is for as `group`.
+pub fn aggregate_document(
+    rowsets: Vec<ndc_models::RowSet>,
+    aggregate_ir: &AggregateIR,
+    naming: &NamingStrategy,
+) -> jsonapi_library::api::DocumentData {
+    let rowset = rowsets.into_iter().next();
+    let aggregate = if aggregate_ir.group_by.is_empty() {
+        let aggregates = rowset
+            .and_then(|rowset| rowset.aggregates)
+            .unwrap_or_default();
+        serde_json::Value::Object(aggregate_object(aggregate_ir, naming, &aggregates))
+    } else {
+        let groups = rowset
+            .and_then(|rowset| rowset.groups)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|group| {
+                let dimensions = aggregate_ir
+                    .group_by
+                    .iter()
+                    .map(|field_name| naming.field_name(field_name.as_str()))
+                    .zip(group.dimensions)
+                    .collect();
+                let mut object = serde_json::Map::from_iter([(
+                    "group".to_string(),
+                    serde_json::Value::Object(dimensions),
+                )]);
+                object.extend(aggregate_object(aggregate_ir, naming, &group.aggregates));
+                serde_json::Value::Object(object)
+            })
+            .collect();
+        serde_json::Value::Array(groups)
+    };
+    jsonapi_library::api::DocumentData {
+        data: None,
+        included: None,
+        links: None,
+        meta: Some(jsonapi_library::api::Meta::from([(
+            "aggregate".to_string(),
+            aggregate,
+        )])),
+        jsonapi: None,
+    }
+}
+
+// the aggregates returned by the data connector, by function and then field. an aggregate it did
+// not return (which it shouldn't do) is null.
+fn aggregate_object(
+    aggregate_ir: &AggregateIR,
+    naming: &NamingStrategy,
+    values: &IndexMap<ndc_models::FieldName, serde_json::Value>,
+) -> serde_json::Map<String, serde_json::Value> {
+    let mut object = serde_json::Map::new();
+    for aggregate in &aggregate_ir.aggregates {
+        let value = values
+            .get(&ndc_models::FieldName::from(aggregate.alias().as_str()))
+            .cloned()
+            .unwrap_or_default();
+        if let serde_json::Value::Object(fields) = object
+            .entry(aggregate.function.as_str())
+            .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new()))
+        {
+            fields.insert(naming.field_name(aggregate.field_name.as_str()), value);
+        }
+    }
+    object
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use open_dds::identifier::{Identifier, SubgraphName};
+
+    fn field_name(name: &str) -> FieldName {
+        FieldName::new(Identifier::new(name).unwrap())
+    }
+
+    fn type_name(name: &str) -> Qualified<CustomTypeName> {
+        Qualified::new(
+            SubgraphName::try_new("default").unwrap(),
+            CustomTypeName(Identifier::new(name).unwrap()),
+        )
+    }
+
+    fn link_base() -> LinkBase {
+        LinkBase::new(None, &crate::EndPoint::V1Rest)
+    }
+
+    fn object_types() -> BTreeMap<Qualified<CustomTypeName>, ObjectType> {
+        let string_field = |name: &str| {
+            (
+                field_name(name),
+                crate::catalog::Type::Scalar(ndc_models::TypeRepresentation::String),
+            )
+        };
+        BTreeMap::from_iter([(
+            type_name("Article"),
+            ObjectType {
+                type_fields: IndexMap::from_iter([
+                    string_field("article_id"),
+                    string_field("title"),
+                    string_field("body"),
+                ]),
+                nullable_fields: BTreeSet::new(),
+                field_arguments: BTreeMap::new(),
+                type_relationships: IndexMap::new(),
+            },
+        )])
+    }
+
+    fn rowsets() -> Vec<ndc_models::RowSet> {
+        let row = |id: Option<i64>, title: &str| {
+            IndexMap::from_iter([
+                (
+                    ndc_models::FieldName::from("article_id"),
+                    ndc_models::RowFieldValue(serde_json::json!(id)),
+                ),
+                (
+                    ndc_models::FieldName::from("title"),
+                    ndc_models::RowFieldValue(serde_json::json!(title)),
+                ),
+                (
+                    ndc_models::FieldName::from("body"),
+                    ndc_models::RowFieldValue(serde_json::json!("...")),
+                ),
+            ])
+        };
+        vec![ndc_models::RowSet {
+            aggregates: None,
+            // the last row has no id, and gets a fresh one
+            rows: Some(vec![
+                row(Some(1), "One"),
+                row(Some(2), "Two"),
+                row(None, ""),
+            ]),
+            groups: None,
+        }]
+    }
+
+    #[test]
+    fn test_streamed_document_matches_buffered() {
+        let fields = [("Article".to_string(), vec!["title".to_string()])];
+        let sparse_fields = SparseFields::parse(fields.iter().map(|(k, v)| (k, v)));
+
+        let buffered = process_result(
+            rowsets(),
+            &type_name("Article"),
+            &[field_name("article_id")],
+            &RelationshipTree::default(),
+            &sparse_fields,
+            &object_types(),
+            &NamingStrategy::default(),
+            Tz::UTC,
+            &link_base(),
+            "/default/Articles",
+        )
+        .unwrap();
+        let streamed = streamed_result(
+            rowsets(),
+            &type_name("Article"),
+            &[field_name("article_id")],
+            &sparse_fields,
+            &object_types(),
+            &NamingStrategy::default(),
+            Tz::UTC,
+            &link_base(),
+            "/default/Articles",
+        )
+        .unwrap();
+
+        assert_eq!(streamed.row_count(), 3);
+        assert_eq!(
+            serde_json::to_string(&streamed).unwrap(),
+            serde_json::to_string(&buffered).unwrap()
+        );
+
+        // resources link to themselves, unless they have a fresh id
+        let document = serde_json::to_value(&streamed).unwrap();
+        assert_eq!(
+            document["data"][0]["links"]["self"],
+            "/v1/rest/default/Articles/1"
+        );
+        assert!(document["data"][2]["links"].is_null());
+        // which can't be the id of another resource
+        assert_eq!(document["data"][0]["id"], "1");
+        assert_eq!(document["data"][2]["id"], "#1");
+    }
+
+    // a streamed response would have sent its status by the time it went over the limit, so
+    // documents are serialized in full when there is one
+    #[test]
+    fn test_streamed_document_with_max_response_size() {
+        let sparse_fields = SparseFields::parse(std::iter::empty());
+        let streamed = || {
+            crate::types::ResponseDocument::Streamed(
+                streamed_result(
+                    rowsets(),
+                    &type_name("Article"),
+                    &[field_name("article_id")],
+                    &sparse_fields,
+                    &object_types(),
+                    &NamingStrategy::default(),
+                    Tz::UTC,
+                    &link_base(),
+                    "/default/Articles",
+                )
+                .unwrap(),
+            )
+        };
+        let size = serde_json::to_vec(&streamed()).unwrap().len();
+        let headers = axum::http::HeaderMap::new();
+
+        let response = crate::response::document_response(&headers, None, Some(size), streamed());
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        assert!(response.headers().contains_key(axum::http::header::ETAG));
+
+        let response =
+            crate::response::document_response(&headers, None, Some(size - 1), streamed());
+        assert_eq!(response.status(), axum::http::StatusCode::BAD_REQUEST);
+    }
+
+    // clients tell an empty list from `null`, and both from a member that isn't there. no
+    // resources is `[]`, and a relationship without related resources is `[]` when it is to-many
+    // and `null` when it is to-one, even when the data connector leaves it out of the row.
+    #[test]
+    fn test_empty_results() {
+        let sparse_fields = SparseFields::parse(std::iter::empty());
+        let result = |rowsets: Vec<ndc_models::RowSet>, relationship_tree: &RelationshipTree| {
+            process_result(
+                rowsets,
+                &type_name("Article"),
+                &[field_name("article_id")],
+                relationship_tree,
+                &sparse_fields,
+                &object_types(),
+                &NamingStrategy::default(),
+                Tz::UTC,
+                &link_base(),
+                "/default/Articles",
+            )
+            .unwrap()
+        };
+        let render = |document| serde_json::to_value(document).unwrap();
+
+        for rowsets in [
+            vec![],
+            vec![ndc_models::RowSet {
+                aggregates: None,
+                rows: None,
+                groups: None,
+            }],
+            vec![ndc_models::RowSet {
+                aggregates: None,
+                rows: Some(vec![]),
+                groups: None,
+            }],
+        ] {
+            let document = render(result(rowsets, &RelationshipTree::default()));
+            assert_eq!(document["data"], serde_json::json!([]));
+            assert!(document.get("included").is_none());
+        }
+
+        let node = |relationship_type| RelationshipNode {
+            object_type: type_name("Comment"),
+            relationship_type,
+            unique_identifier: vec![field_name("comment_id")],
+            model_route: Some("/default/Comments".to_string()),
+            is_command_relationship: false,
+            nested: RelationshipTree::default(),
+        };
+        let relationship_tree = RelationshipTree {
+            relationships: BTreeMap::from_iter([
+                ("comments".to_string(), node(RelationshipType::Array)),
+                ("author".to_string(), node(RelationshipType::Object)),
+            ]),
+        };
+        let relationship_rows = |relationships: serde_json::Value| {
+            let mut rowsets = rowsets();
+            for row in rowsets[0].rows.iter_mut().flatten() {
+                for (relationship, rows) in relationships.as_object().unwrap() {
+                    row.insert(
+                        ndc_models::FieldName::from(relationship.as_str()),
+                        ndc_models::RowFieldValue(rows.clone()),
+                    );
+                }
+            }
+            rowsets
+        };
+        // no related rows, rows of null, and relationships left out of the rows altogether
+        for relationships in [
+            serde_json::json!({ "comments": { "rows": [] }, "author": { "rows": [] } }),
+            serde_json::json!({ "comments": { "rows": null }, "author": null }),
+            serde_json::json!({}),
+        ] {
+            let document = render(result(relationship_rows(relationships), &relationship_tree));
+            for resource in document["data"].as_array().unwrap() {
+                assert_eq!(
+                    resource["relationships"],
+                    serde_json::json!({ "author": { "data": null }, "comments": { "data": [] } })
+                );
+            }
+            assert!(document.get("included").is_none());
+        }
+
+        // and so is the linkage of the relationship routes
+        let (linkage, document) = related_document(
+            result(relationship_rows(serde_json::json!({})), &relationship_tree),
+            "comments",
+            &RelationshipType::Array,
+        )
+        .unwrap();
+        assert_eq!(
+            serde_json::to_value(linkage).unwrap(),
+            serde_json::json!([])
+        );
+        assert_eq!(render(document)["data"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn test_naming_strategy() {
+        let naming = NamingStrategy {
+            case: crate::naming::NameCase::CamelCase,
+            pluralize_types: true,
+        };
+        let sparse_fields = SparseFields::parse(std::iter::empty());
+        let buffered = process_result(
+            rowsets(),
+            &type_name("Article"),
+            &[field_name("article_id")],
+            &RelationshipTree::default(),
+            &sparse_fields,
+            &object_types(),
+            &naming,
+            Tz::UTC,
+            &link_base(),
+            "/default/Articles",
+        )
+        .unwrap();
+        let streamed = streamed_result(
+            rowsets(),
+            &type_name("Article"),
+            &[field_name("article_id")],
+            &sparse_fields,
+            &object_types(),
+            &naming,
+            Tz::UTC,
+            &link_base(),
+            "/default/Articles",
+        )
+        .unwrap();
+        for document in [
+            serde_json::to_value(&buffered).unwrap(),
+            serde_json::to_value(&streamed).unwrap(),
+        ] {
+            assert_eq!(document["data"][0]["type"], "defaultArticles");
+            assert_eq!(
+                document["data"][0]["attributes"],
+                serde_json::json!({ "articleId": 1, "title": "One", "body": "..." })
+            );
+        }
+    }
+
+    #[test]
+    fn test_scalar_representations() {
+        let scalar_field = |name: &str, representation| {
+            (
+                field_name(name),
+                crate::catalog::Type::Scalar(representation),
+            )
+        };
+        let object_types = BTreeMap::from_iter([(
+            type_name("Account"),
+            ObjectType {
+                type_fields: IndexMap::from_iter([
+                    scalar_field("account_id", ndc_models::TypeRepresentation::Int32),
+                    scalar_field("balance", ndc_models::TypeRepresentation::BigInteger),
+                    scalar_field("opened_at", ndc_models::TypeRepresentation::TimestampTZ),
+                    scalar_field("closed_at", ndc_models::TypeRepresentation::Timestamp),
+                    scalar_field("active", ndc_models::TypeRepresentation::Boolean),
+                ]),
+                nullable_fields: BTreeSet::new(),
+                field_arguments: BTreeMap::new(),
+                type_relationships: IndexMap::new(),
+            },
+        )]);
+        // a bigint beyond the integers a double holds exactly, as connectors return it
+        let rowsets = || {
+            vec![ndc_models::RowSet {
+                aggregates: None,
+                rows: Some(vec![IndexMap::from_iter(
+                    [
+                        ("account_id", serde_json::json!(1)),
+                        ("balance", serde_json::json!(9_007_199_254_740_993_i64)),
+                        ("opened_at", serde_json::json!("2024-01-02 03:04:05+00")),
+                        ("closed_at", serde_json::json!("2024-06-07 08:09:10.5")),
+                        ("active", serde_json::json!("true")),
+                    ]
+                    .map(|(key, value)| {
+                        (
+                            ndc_models::FieldName::from(key),
+                            ndc_models::RowFieldValue(value),
+                        )
+                    }),
+                )]),
+                groups: None,
+            }]
+        };
+        let expected = serde_json::json!({
+            "account_id": 1,
+            "balance": "9007199254740993",
+            "opened_at": "2024-01-02T03:04:05+00:00",
+            "closed_at": "2024-06-07T08:09:10.500",
+            "active": true,
+        });
+
+        let sparse_fields = SparseFields::parse(std::iter::empty());
+        let buffered = process_result(
+            rowsets(),
+            &type_name("Account"),
+            &[field_name("account_id")],
+            &RelationshipTree::default(),
+            &sparse_fields,
+            &object_types,
+            &NamingStrategy::default(),
+            Tz::UTC,
+            &link_base(),
+            "/default/Accounts",
+        )
+        .unwrap();
+        let streamed = streamed_result(
+            rowsets(),
+            &type_name("Account"),
+            &[field_name("account_id")],
+            &sparse_fields,
+            &object_types,
+            &NamingStrategy::default(),
+            Tz::UTC,
+            &link_base(),
+            "/default/Accounts",
+        )
+        .unwrap();
+        for document in [
+            serde_json::to_value(&buffered).unwrap(),
+            serde_json::to_value(&streamed).unwrap(),
+        ] {
+            assert_eq!(document["data"][0]["attributes"], expected);
+        }
+    }
+
+    #[test]
+    fn test_time_zones() {
+        let object_types = BTreeMap::from_iter([(
+            type_name("Account"),
+            ObjectType {
+                type_fields: IndexMap::from_iter([
+                    (
+                        field_name("account_id"),
+                        crate::catalog::Type::Scalar(ndc_models::TypeRepresentation::Int32),
+                    ),
+                    (
+                        field_name("opened_at"),
+                        crate::catalog::Type::Scalar(ndc_models::TypeRepresentation::TimestampTZ),
+                    ),
+                    (
+                        field_name("closed_at"),
+                        crate::catalog::Type::Scalar(ndc_models::TypeRepresentation::Timestamp),
+                    ),
+                ]),
+                nullable_fields: BTreeSet::new(),
+                field_arguments: BTreeMap::new(),
+                type_relationships: IndexMap::new(),
+            },
+        )]);
+        // timestamps in the time zones of the data connector, in winter and in summer
+        let rowsets = || {
+            vec![ndc_models::RowSet {
+                aggregates: None,
+                rows: Some(
+                    [
+                        (1, "2024-01-02 03:04:05+02", "2024-06-07T08:09:10"),
+                        (2, "2024-07-01T12:00:00-04:00", "2024-06-07T08:09:10"),
+                    ]
+                    .into_iter()
+                    .map(|(account_id, opened_at, closed_at)| {
+                        IndexMap::from_iter([
+                            (
+                                ndc_models::FieldName::from("account_id"),
+                                ndc_models::RowFieldValue(serde_json::json!(account_id)),
+                            ),
+                            (
+                                ndc_models::FieldName::from("opened_at"),
+                                ndc_models::RowFieldValue(serde_json::json!(opened_at)),
+                            ),
+                            (
+                                ndc_models::FieldName::from("closed_at"),
+                                ndc_models::RowFieldValue(serde_json::json!(closed_at)),
+                            ),
+                        ])
+                    })
+                    .collect(),
+                ),
+                groups: None,
+            }]
+        };
+
+        // timestamps without a time zone are left as they are
+        for (time_zone, expected) in [
+            (
+                Tz::UTC,
+                ["2024-01-02T01:04:05+00:00", "2024-07-01T16:00:00+00:00"],
+            ),
+            (
+                chrono_tz::America::New_York,
+                ["2024-01-01T20:04:05-05:00", "2024-07-01T12:00:00-04:00"],
+            ),
+            (
+                chrono_tz::Asia::Kolkata,
+                ["2024-01-02T06:34:05+05:30", "2024-07-01T21:30:00+05:30"],
+            ),
+        ] {
+            let sparse_fields = SparseFields::parse(std::iter::empty());
+            let buffered = process_result(
+                rowsets(),
+                &type_name("Account"),
+                &[field_name("account_id")],
+                &RelationshipTree::default(),
+                &sparse_fields,
+                &object_types,
+                &NamingStrategy::default(),
+                time_zone,
+                &link_base(),
+                "/default/Accounts",
+            )
+            .unwrap();
+            let streamed = streamed_result(
+                rowsets(),
+                &type_name("Account"),
+                &[field_name("account_id")],
+                &sparse_fields,
+                &object_types,
+                &NamingStrategy::default(),
+                time_zone,
+                &link_base(),
+                "/default/Accounts",
+            )
+            .unwrap();
+            for document in [
+                serde_json::to_value(&buffered).unwrap(),
+                serde_json::to_value(&streamed).unwrap(),
+            ] {
+                for (index, opened_at) in expected.iter().enumerate() {
+                    assert_eq!(
+                        document["data"][index]["attributes"],
+                        serde_json::json!({
+                            "account_id": index + 1,
+                            "opened_at": opened_at,
+                            "closed_at": "2024-06-07T08:09:10",
+                        }),
+                        "{time_zone}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_join_included_rows() {
+        let included_query = IncludedQuery {
+            relationship: open_dds::query::Alias::new(Identifier::new("comments").unwrap()),
+            mappings: vec![crate::catalog::FieldMapping {
+                source_field: field_name("article_id"),
+                target_field: field_name("comment_article_id"),
+            }],
+            model_selection: open_dds::query::ModelSelection {
+                target: open_dds::query::ModelTarget {
+                    subgraph: SubgraphName::try_new("default").unwrap(),
+                    model_name: open_dds::models::ModelName::new(
+                        Identifier::new("Comments").unwrap(),
+                    ),
+                    arguments: IndexMap::new(),
+                    filter: None,
+                    order_by: vec![],
+                    limit: None,
+                    offset: None,
+                },
+                selection: IndexMap::new(),
+            },
+        };
+        let comment = |id: i64, article_id: i64| {
+            IndexMap::from_iter([
+                (
+                    ndc_models::FieldName::from("comment_id"),
+                    ndc_models::RowFieldValue(serde_json::json!(id)),
+                ),
+                (
+                    ndc_models::FieldName::from("comment_article_id"),
+                    ndc_models::RowFieldValue(serde_json::json!(article_id)),
+                ),
+            ])
+        };
+
+        let mut rowsets = rowsets();
+        join_included_rows(
+            &mut rowsets,
+            &included_query,
+            vec![comment(10, 1), comment(11, 2), comment(12, 1)],
+        );
+        let comments = rowsets[0]
+            .rows
+            .iter()
+            .flatten()
+            .map(|row| row[&ndc_models::FieldName::from("comments")].0.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            comments,
+            vec![
+                serde_json::json!({ "rows": [
+                    { "comment_id": 10, "comment_article_id": 1 },
+                    { "comment_id": 12, "comment_article_id": 1 },
+                ] }),
+                serde_json::json!({ "rows": [{ "comment_id": 11, "comment_article_id": 2 }] }),
+                // the article without an id has no comments
+                serde_json::json!({ "rows": [] }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_identifiers_document() {
+        let sparse_fields = SparseFields::parse(std::iter::empty());
+        let mut document = process_result(
+            rowsets(),
+            &type_name("Article"),
+            &[field_name("article_id")],
+            &RelationshipTree::default(),
+            &sparse_fields,
+            &object_types(),
+            &NamingStrategy::default(),
+            Tz::UTC,
+            &link_base(),
+            "/default/Articles",
+        )
+        .unwrap();
+        document.meta = Some(jsonapi_library::api::Meta::from([(
+            "total".to_string(),
+            serde_json::json!(3),
+        )]));
+
+        let document = serde_json::to_value(identifiers_document(document)).unwrap();
+        assert_eq!(
+            document["data"][0],
+            serde_json::json!({ "type": "default_Article", "id": "1" })
+        );
+        assert_eq!(document["data"].as_array().map(Vec::len), Some(3));
+        assert_eq!(document["meta"]["total"], 3);
+        assert!(document.get("included").is_none());
+    }
+
+    #[test]
+    fn test_aggregate_document() {
+        let aggregate = |function, name: &str| crate::parse::Aggregate {
+            function,
+            field_name: field_name(name),
+        };
+        let aggregate_ir = |group_by| AggregateIR {
+            query_request: open_dds::query::QueryRequest::V1(open_dds::query::QueryRequestV1 {
+                queries: IndexMap::new(),
+            }),
+            aggregates: vec![
+                aggregate(crate::parse::AggregateFunction::Sum, "amount"),
+                aggregate(crate::parse::AggregateFunction::Max, "amount"),
+                aggregate(crate::parse::AggregateFunction::Max, "placed_at"),
+            ],
+            group_by,
+        };
+        let values = |sum: i64, max: i64| {
+            IndexMap::from_iter([
+                (
+                    ndc_models::FieldName::from("sum.amount"),
+                    serde_json::json!(sum),
+                ),
+                (
+                    ndc_models::FieldName::from("max.amount"),
+                    serde_json::json!(max),
+                ),
+                (
+                    ndc_models::FieldName::from("max.placed_at"),
+                    serde_json::json!("2024-01-01"),
+                ),
+            ])
+        };
+
+        let document = aggregate_document(
+            vec![ndc_models::RowSet {
+                aggregates: Some(values(120, 80)),
+                rows: None,
+                groups: None,
+            }],
+            &aggregate_ir(vec![]),
+            &NamingStrategy::default(),
+        );
+        assert_eq!(
+            serde_json::to_value(document).unwrap(),
+            serde_json::json!({
+                "meta": {
+                    "aggregate": {
+                        "sum": { "amount": 120 },
+                        "max": { "amount": 80, "placed_at": "2024-01-01" }
+                    }
+                }
+            })
+        );
+
+        let document = aggregate_document(
+            vec![ndc_models::RowSet {
+                aggregates: None,
+                rows: None,
+                groups: Some(vec![
+                    ndc_models::Group {
+                        dimensions: vec![serde_json::json!("paid")],
+                        aggregates: values(100, 80),
+                    },
+                    ndc_models::Group {
+                        dimensions: vec![serde_json::json!("open")],
+                        aggregates: values(20, 20),
+                    },
+                ]),
+            }],
+            &aggregate_ir(vec![field_name("status")]),
+            &NamingStrategy::default(),
+        );
+        assert_eq!(
+            serde_json::to_value(document).unwrap(),
+            serde_json::json!({
+                "meta": {
+                    "aggregate": [
+                        {
+                            "group": { "status": "paid" },
+                            "sum": { "amount": 100 },
+                            "max": { "amount": 80, "placed_at": "2024-01-01" }
+                        },
+                        {
+                            "group": { "status": "open" },
+                            "sum": { "amount": 20 },
+                            "max": { "amount": 20, "placed_at": "2024-01-01" }
+                        }
+                    ]
+                }
+            })
+        );
+    }
+}
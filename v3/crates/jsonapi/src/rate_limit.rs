@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use hasura_authn_core::Role;
+
+use crate::configuration::RateLimit;
+
+/// In-process state of a `RateLimit`, for each role and resource type that has made a request.
+/// there is one entry per role and model of the metadata at most, so this stays small.
+///
+/// the token buckets are kept as the time at which each bucket will be full again (the "generic
+/// cell rate algorithm"), which needs a single `Instant` per bucket and no refilling.
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate_limit: RateLimit,
+    full_at: Mutex<HashMap<(Role, String), Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new(rate_limit: RateLimit) -> Self {
+        Self {
+            rate_limit,
+            full_at: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// takes a token for a request of the role for resources of the type, or returns how long
+    /// until there is one
+    pub fn check(&self, role: &Role, resource_type: &str, now: Instant) -> Result<(), Duration> {
+        if self.rate_limit.exempt_roles.contains(role) {
+            return Ok(());
+        }
+        let capacity = self.rate_limit.requests_per_minute.get();
+        // how long it takes for a token to come back, and how far ahead of `now` a bucket may
+        // be full again while it still has a token left
+        let interval = Duration::from_secs(60) / capacity;
+        let tolerance = interval * (capacity - 1);
+
+        // the lock is only held to update the bucket, and a panic while holding it can't leave a
+        // bucket in a state worse than a stale one
+        let mut full_at = self
+            .full_at
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let full_at = full_at
+            .entry((role.clone(), resource_type.to_string()))
+            .or_insert(now);
+        let next_full_at = (*full_at).max(now);
+        let wait = next_full_at.duration_since(now);
+        if wait > tolerance {
+            Err(wait - tolerance)
+        } else {
+            *full_at = next_full_at + interval;
+            Ok(())
+        }
+    }
+}
+
+/// the value of a `Retry-After` header, in whole seconds, for a wait of at least `retry_after`
+pub fn retry_after_seconds(retry_after: Duration) -> u64 {
+    (retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0)).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+    use std::num::NonZeroU32;
+
+    fn rate_limiter(requests_per_minute: u32) -> RateLimiter {
+        RateLimiter::new(RateLimit {
+            requests_per_minute: NonZeroU32::new(requests_per_minute).unwrap(),
+            exempt_roles: BTreeSet::from([Role::new("admin")]),
+        })
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let rate_limiter = rate_limiter(2);
+        let user = Role::new("user");
+        let start = Instant::now();
+
+        assert!(rate_limiter.check(&user, "default_Articles", start).is_ok());
+        assert!(rate_limiter.check(&user, "default_Articles", start).is_ok());
+        // one token comes back every 30 seconds
+        assert_eq!(
+            rate_limiter.check(&user, "default_Articles", start),
+            Err(Duration::from_secs(30))
+        );
+
+        // other resource types and roles have buckets of their own
+        assert!(rate_limiter.check(&user, "default_Authors", start).is_ok());
+        assert!(rate_limiter
+            .check(&Role::new("editor"), "default_Articles", start)
+            .is_ok());
+
+        let later = start + Duration::from_secs(30);
+        assert!(rate_limiter.check(&user, "default_Articles", later).is_ok());
+        assert!(rate_limiter
+            .check(&user, "default_Articles", later)
+            .is_err());
+    }
+
+    #[test]
+    fn test_exempt_roles() {
+        let rate_limiter = rate_limiter(1);
+        let admin = Role::new("admin");
+        let now = Instant::now();
+        for _ in 0..10 {
+            assert!(rate_limiter.check(&admin, "default_Articles", now).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_retry_after_seconds() {
+        assert_eq!(retry_after_seconds(Duration::from_secs(30)), 30);
+        assert_eq!(retry_after_seconds(Duration::from_millis(29_001)), 30);
+        assert_eq!(retry_after_seconds(Duration::from_millis(10)), 1);
+    }
+}
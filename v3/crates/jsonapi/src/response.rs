@@ -0,0 +1,659 @@
+use std::io::Write;
+
+use axum::http::{header, HeaderMap, HeaderValue, Method, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::MethodRouter;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc;
+use tracing_util::{set_attribute_on_active_span, AttributeVisibility, SpanVisibility, Successful};
+
+use crate::types::{
+    ErrorCode, JsonApiHttpError, JsonApiObject, RequestError, ResponseDocument, WithJsonApiObject,
+    JSONAPI_MEDIA_TYPE,
+};
+
+// the size of the chunks a streamed document is written out in
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+// how many chunks may be waiting to be sent to a slow client before we stop serializing
+const STREAM_CHUNK_BUFFER: usize = 4;
+
+/// The response for a successful request, with a strong `ETag` over the serialized document.
+///
+/// When the request carries an `If-None-Match` matching the document, we send `304 Not Modified`
+/// without a body instead, so clients polling unchanged data don't download it again.
+///
+/// Streamed documents are written out as they are serialized instead, and so have no `ETag`.
+///
+/// `jsonapi` is the top-level `jsonapi` member of the document, if it has one.
+///
+/// Documents serializing to more than `max_size` bytes get a `400` instead, without serializing
+/// any more of the document than that. A streamed document would have sent its status by the time
+/// it went over, so documents are not streamed when there is a `max_size`: they are serialized in
+/// full first, which takes no more than `max_size` bytes.
+///
+/// spec: <https://www.rfc-editor.org/rfc/rfc9110#name-if-none-match>
+pub fn document_response(
+    request_headers: &HeaderMap,
+    jsonapi: Option<&JsonApiObject>,
+    max_size: Option<usize>,
+    document: ResponseDocument,
+) -> axum::response::Response {
+    let document = match (document, max_size) {
+        (ResponseDocument::Streamed(document), None) => {
+            let row_count = document.row_count();
+            return streamed_response(jsonapi.cloned(), document, row_count);
+        }
+        (document, _) => document,
+    };
+
+    let mut writer = LimitedWriter::new(Vec::new(), max_size);
+    let body = match serde_json::to_writer(
+        &mut writer,
+        &WithJsonApiObject {
+            jsonapi,
+            document: &document,
+        },
+    ) {
+        Ok(()) => writer.inner,
+        Err(_) if writer.exceeded() => {
+            let max_size = writer.max_size.unwrap_or_default();
+            record_response_too_large(max_size);
+            return RequestError::ResponseTooLarge { max_size }
+                .into_http_error()
+                .with_jsonapi_object(jsonapi.cloned())
+                .into_response();
+        }
+        Err(_) => return serialization_error_response(jsonapi),
+    };
+
+    let etag = etag(&body);
+    let not_modified = if_none_match(request_headers, &etag);
+    let etag = HeaderValue::from_str(&etag).expect("ETags are base64 and quotes");
+    if not_modified {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+    }
+    (
+        StatusCode::OK,
+        [
+            (header::ETAG, etag),
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(JSONAPI_MEDIA_TYPE),
+            ),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+// the response for a document that could not be serialized
+fn serialization_error_response(jsonapi: Option<&JsonApiObject>) -> axum::response::Response {
+    JsonApiHttpError::new(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        ErrorCode::InternalError,
+        "Internal error",
+    )
+    .with_jsonapi_object(jsonapi.cloned())
+    .into_response()
+}
+
+/// The methods of the routes for reading resources: `GET`, which also answers `HEAD`, and
+/// `OPTIONS`
+pub const READ_METHODS: &[Method] = &[Method::GET, Method::HEAD, Method::OPTIONS];
+
+/// Answer `OPTIONS` requests to a route with the methods it allows, and requests with any other
+/// method it doesn't have with `405 Method Not Allowed`. Both list the allowed methods in an
+/// `Allow` header, and the `405` has a JSON:API error document as its body.
+///
+/// This must be added after any layers of the route, so that requests with the wrong method are
+/// rejected before any middleware of the route runs for them.
+///
+/// spec: <https://www.rfc-editor.org/rfc/rfc9110#name-405-method-not-allowed>
+pub fn with_allowed_methods<S>(
+    method_router: MethodRouter<S>,
+    allowed: &'static [Method],
+) -> MethodRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    method_router
+        .options(move || async move {
+            (
+                StatusCode::NO_CONTENT,
+                [(header::ALLOW, allow_header(allowed))],
+            )
+                .into_response()
+        })
+        .fallback(move |method: Method| async move {
+            let mut response = JsonApiHttpError::new(
+                StatusCode::METHOD_NOT_ALLOWED,
+                ErrorCode::MethodNotAllowed,
+                format!(
+                    "The {method} method is not allowed for this route, only {}",
+                    method_list(allowed)
+                ),
+            )
+            .into_response();
+            response
+                .headers_mut()
+                .insert(header::ALLOW, allow_header(allowed));
+            response
+        })
+}
+
+fn method_list(methods: &[Method]) -> String {
+    methods
+        .iter()
+        .map(Method::as_str)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn allow_header(methods: &[Method]) -> HeaderValue {
+    HeaderValue::from_str(&method_list(methods)).expect("method names are tokens")
+}
+
+// serialize the document on a blocking thread, sending it to the client in chunks as we go. only
+// the serialization is streamed: the rows have all been read from the data connector already.
+//
+// the serialization has a span of its own, which stays open until the whole document is written
+// out (or the client goes away). the request span is kept open until the body is sent, so that
+// this span ends within it.
+fn streamed_response<T: serde::Serialize + Send + 'static>(
+    jsonapi: Option<JsonApiObject>,
+    document: T,
+    row_count: usize,
+) -> axum::response::Response {
+    let (sender, receiver) = mpsc::channel(STREAM_CHUNK_BUFFER);
+    let context = tracing_util::Context::current();
+    tokio::task::spawn_blocking(move || {
+        // the body ends when the writer is dropped, which must be after the spans of writing it
+        // out have ended, so it is declared before the context they are in
+        let mut writer = ChunkWriter::new(sender);
+        let _context = context.attach();
+        let tracer = tracing_util::global_tracer();
+        tracer.in_span(
+            "stream_response",
+            "Stream JSON:API document",
+            SpanVisibility::User,
+            || {
+                let streamed = WithJsonApiObject {
+                    jsonapi: jsonapi.as_ref(),
+                    document: &document,
+                };
+                let result = serde_json::to_writer(&mut writer, &streamed)
+                    .map_err(std::io::Error::from)
+                    .and_then(|()| writer.flush());
+                set_attribute_on_active_span(
+                    AttributeVisibility::Default,
+                    "row_count",
+                    i64::try_from(row_count).unwrap_or(i64::MAX),
+                );
+                set_attribute_on_active_span(
+                    AttributeVisibility::Default,
+                    "jsonapi.stream_completed",
+                    result.is_ok(),
+                );
+                // the body must not look complete if we failed part way, so end it with an error
+                if let Err(error) = result {
+                    let _ = writer.sender.blocking_send(Err(error));
+                }
+                Successful::new(())
+            },
+        );
+    });
+
+    let body = futures_util::stream::unfold(receiver, |mut receiver| async move {
+        let chunk = receiver.recv().await?;
+        Some((chunk, receiver))
+    });
+    (
+        StatusCode::OK,
+        [(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(JSONAPI_MEDIA_TYPE),
+        )],
+        axum::body::Body::from_stream(body),
+    )
+        .into_response()
+}
+
+// buffers what is written to it, and sends it on in chunks of about `STREAM_CHUNK_SIZE` bytes
+struct ChunkWriter {
+    buffer: Vec<u8>,
+    sender: mpsc::Sender<std::io::Result<Vec<u8>>>,
+}
+
+impl ChunkWriter {
+    fn new(sender: mpsc::Sender<std::io::Result<Vec<u8>>>) -> Self {
+        Self {
+            buffer: Vec::with_capacity(STREAM_CHUNK_SIZE),
+            sender,
+        }
+    }
+
+    fn send_buffer(&mut self) -> std::io::Result<()> {
+        let chunk = std::mem::replace(&mut self.buffer, Vec::with_capacity(STREAM_CHUNK_SIZE));
+        self.sender.blocking_send(Ok(chunk)).map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::BrokenPipe, "the client went away")
+        })
+    }
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(bytes);
+        if self.buffer.len() >= STREAM_CHUNK_SIZE {
+            self.send_buffer()?;
+        }
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.buffer.is_empty() {
+            Ok(())
+        } else {
+            self.send_buffer()
+        }
+    }
+}
+
+// record on the active span that the document was over `max_size` bytes, and so was not sent
+fn record_response_too_large(max_size: usize) {
+    set_attribute_on_active_span(
+        AttributeVisibility::Default,
+        "jsonapi.response_too_large",
+        true,
+    );
+    set_attribute_on_active_span(
+        AttributeVisibility::Default,
+        "jsonapi.max_response_size",
+        i64::try_from(max_size).unwrap_or(i64::MAX),
+    );
+    set_attribute_on_active_span(
+        AttributeVisibility::Internal,
+        "jsonapi.error_category",
+        RequestError::ResponseTooLarge { max_size }.category(),
+    );
+}
+
+// counts the bytes written through it, and fails the write that takes them over `max_size`, so
+// that serializing a document over the limit stops there rather than carrying on to the end
+struct LimitedWriter<W> {
+    inner: W,
+    written: usize,
+    max_size: Option<usize>,
+}
+
+impl<W> LimitedWriter<W> {
+    fn new(inner: W, max_size: Option<usize>) -> Self {
+        Self {
+            inner,
+            written: 0,
+            max_size,
+        }
+    }
+
+    // did we stop writing because of the limit?
+    fn exceeded(&self) -> bool {
+        self.max_size
+            .is_some_and(|max_size| self.written > max_size)
+    }
+}
+
+impl<W: Write> Write for LimitedWriter<W> {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        self.written = self.written.saturating_add(bytes.len());
+        if self.exceeded() {
+            return Err(std::io::Error::other(
+                "the response is larger than the limit",
+            ));
+        }
+        self.inner.write_all(bytes)?;
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// a strong entity tag, ie the same for byte-identical documents only
+fn etag(body: &[u8]) -> String {
+    format!("\"{}\"", URL_SAFE_NO_PAD.encode(Sha256::digest(body)))
+}
+
+// does the `If-None-Match` header of the request match this entity tag? `If-None-Match` uses the
+// weak comparison, so a `W/` prefix on the tags sent by the client is ignored.
+fn if_none_match(request_headers: &HeaderMap, etag: &str) -> bool {
+    request_headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_if_none_match() {
+        let etag = etag(b"{\"data\":[]}");
+        let headers = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::IF_NONE_MATCH, value.parse().unwrap());
+            headers
+        };
+
+        assert!(if_none_match(&headers(&etag), &etag));
+        assert!(if_none_match(
+            &headers(&format!("\"other\", W/{etag}")),
+            &etag
+        ));
+        assert!(if_none_match(&headers("*"), &etag));
+        assert!(!if_none_match(&headers("\"other\""), &etag));
+        assert!(!if_none_match(&HeaderMap::new(), &etag));
+    }
+
+    #[tokio::test]
+    async fn test_head_response() {
+        let app = axum::Router::new().route(
+            "/default/Articles/1/relationships/author",
+            axum::routing::get(|| async {
+                document_response(
+                    &HeaderMap::new(),
+                    None,
+                    None,
+                    ResponseDocument::Linkage(crate::types::LinkageDocument {
+                        data: jsonapi_library::model::IdentifierData::None,
+                        links: Default::default(),
+                        included: None,
+                        meta: None,
+                    }),
+                )
+            }),
+        );
+        let request = |method| {
+            axum::http::Request::builder()
+                .method(method)
+                .uri("/default/Articles/1/relationships/author")
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+
+        let get = app
+            .clone()
+            .oneshot(request(axum::http::Method::GET))
+            .await
+            .unwrap();
+        let head = app
+            .oneshot(request(axum::http::Method::HEAD))
+            .await
+            .unwrap();
+
+        // the same headers, without the body
+        assert_eq!(head.status(), StatusCode::OK);
+        for name in [header::CONTENT_TYPE, header::ETAG, header::CONTENT_LENGTH] {
+            assert!(head.headers().contains_key(&name), "{name}");
+            assert_eq!(
+                head.headers().get(&name),
+                get.headers().get(&name),
+                "{name}"
+            );
+        }
+        let body = axum::body::to_bytes(head.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_jsonapi_object() {
+        let jsonapi = JsonApiObject {
+            version: crate::types::JSONAPI_VERSION,
+            ext: vec![crate::types::ATOMIC_EXTENSION],
+        };
+        let document = || {
+            ResponseDocument::Linkage(crate::types::LinkageDocument {
+                data: jsonapi_library::model::IdentifierData::None,
+                links: Default::default(),
+                included: None,
+                meta: None,
+            })
+        };
+
+        let response = document_response(&HeaderMap::new(), Some(&jsonapi), None, document());
+        let etag = response.headers()[header::ETAG].clone();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let document_json: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_slice(&body).unwrap();
+        // the first member of the document
+        assert_eq!(
+            document_json.iter().next(),
+            Some((
+                &"jsonapi".to_string(),
+                &serde_json::json!({
+                    "version": "1.1",
+                    "ext": ["https://jsonapi.org/ext/atomic"],
+                })
+            ))
+        );
+
+        // not modified responses have no body to put it in
+        let mut request_headers = HeaderMap::new();
+        request_headers.insert(header::IF_NONE_MATCH, etag);
+        let response = document_response(&request_headers, Some(&jsonapi), None, document());
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(body.is_empty());
+
+        let response = document_response(&HeaderMap::new(), None, None, document());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let document_json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(document_json.get("jsonapi").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_max_response_size() {
+        let document = || {
+            ResponseDocument::Linkage(crate::types::LinkageDocument {
+                data: jsonapi_library::model::IdentifierData::None,
+                links: Default::default(),
+                included: None,
+                meta: None,
+            })
+        };
+        let size = serde_json::to_vec(&document()).unwrap().len();
+
+        let response = document_response(&HeaderMap::new(), None, Some(size), document());
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = document_response(&HeaderMap::new(), None, Some(size - 1), document());
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(!response.headers().contains_key(header::ETAG));
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let document: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(document["errors"][0]["code"], "response-too-large");
+        assert_eq!(
+            document["errors"][0]["detail"],
+            format!(
+                "The response is larger than the limit of {} bytes, ask for less of it with \
+                 fields, page[limit] or include",
+                size - 1
+            )
+        );
+    }
+
+    // error documents have the `jsonapi` member too
+    #[tokio::test]
+    async fn test_error_jsonapi_object() {
+        let jsonapi = JsonApiObject {
+            version: crate::types::JSONAPI_VERSION,
+            ext: vec![],
+        };
+        let document = ResponseDocument::Linkage(crate::types::LinkageDocument {
+            data: jsonapi_library::model::IdentifierData::None,
+            links: Default::default(),
+            included: None,
+            meta: None,
+        });
+
+        for response in [
+            document_response(&HeaderMap::new(), Some(&jsonapi), Some(1), document),
+            serialization_error_response(Some(&jsonapi)),
+        ] {
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let document: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(document["jsonapi"], serde_json::json!({"version": "1.1"}));
+            assert!(document["errors"][0]["code"].is_string());
+        }
+    }
+
+    // a document that fails to serialize once some of it has been sent, as a row might
+    struct FailingDocument;
+
+    impl serde::Serialize for FailingDocument {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            use serde::ser::{Error as _, SerializeMap};
+
+            let mut document = serializer.serialize_map(None)?;
+            document.serialize_entry("data", &"a".repeat(STREAM_CHUNK_SIZE))?;
+            Err(S::Error::custom("the row can't be rendered"))
+        }
+    }
+
+    // a streamed body has sent its status and a part of the document by the time it fails, so
+    // it ends with an error rather than looking like a complete document
+    #[tokio::test]
+    async fn test_streamed_response_error() {
+        use futures_util::StreamExt;
+
+        let response = streamed_response(None, FailingDocument, 1);
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut body = response.into_body().into_data_stream();
+        let chunk = body.next().await.unwrap().unwrap();
+        assert!(chunk.starts_with(b"{\"data\":\"aaa"));
+        assert!(body.next().await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_allowed_methods() {
+        let app = axum::Router::new().route(
+            "/default/Articles/*path",
+            with_allowed_methods(
+                axum::routing::get(|| async { StatusCode::OK }),
+                READ_METHODS,
+            ),
+        );
+        let request = |method, uri| {
+            axum::http::Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+
+        let get = app
+            .clone()
+            .oneshot(request(Method::GET, "/default/Articles/1"))
+            .await
+            .unwrap();
+        assert_eq!(get.status(), StatusCode::OK);
+
+        let options = app
+            .clone()
+            .oneshot(request(Method::OPTIONS, "/default/Articles/1"))
+            .await
+            .unwrap();
+        assert_eq!(options.status(), StatusCode::NO_CONTENT);
+        assert_eq!(options.headers()[header::ALLOW], "GET, HEAD, OPTIONS");
+
+        // collections, resources and relationships all reject writes in the same way
+        for uri in [
+            "/default/Articles/all",
+            "/default/Articles/1",
+            "/default/Articles/1/relationships/author",
+        ] {
+            for method in [Method::POST, Method::PATCH, Method::DELETE] {
+                let response = app
+                    .clone()
+                    .oneshot(request(method.clone(), uri))
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+                assert_eq!(response.headers()[header::ALLOW], "GET, HEAD, OPTIONS");
+                assert_eq!(response.headers()[header::CONTENT_TYPE], JSONAPI_MEDIA_TYPE);
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                let document: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                assert_eq!(document["errors"][0]["status"], "405");
+                assert_eq!(document["errors"][0]["code"], "method-not-allowed");
+                assert_eq!(
+                    document["errors"][0]["detail"],
+                    format!(
+                        "The {method} method is not allowed for this route, only GET, HEAD, OPTIONS"
+                    )
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_chunk_writer() {
+        let (sender, mut receiver) = mpsc::channel(8);
+        let mut writer = ChunkWriter::new(sender);
+        writer.write_all(&[b'a'; STREAM_CHUNK_SIZE + 1]).unwrap();
+        writer.write_all(b"bc").unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+
+        let mut chunks = vec![];
+        while let Ok(chunk) = receiver.try_recv() {
+            chunks.push(chunk.unwrap());
+        }
+        let sizes = chunks.iter().map(Vec::len).collect::<Vec<_>>();
+        assert_eq!(sizes, vec![STREAM_CHUNK_SIZE + 1, 2]);
+
+        // writing after the client went away fails
+        let (sender, receiver) = mpsc::channel(8);
+        let mut writer = ChunkWriter::new(sender);
+        drop(receiver);
+        writer.write_all(b"a").unwrap();
+        assert_eq!(
+            writer.flush().unwrap_err().kind(),
+            std::io::ErrorKind::BrokenPipe
+        );
+    }
+
+    #[test]
+    fn test_limited_writer() {
+        let mut writer = LimitedWriter::new(Vec::new(), Some(4));
+        writer.write_all(b"abcd").unwrap();
+        assert!(!writer.exceeded());
+        // the write that goes over fails, and nothing of it is written
+        assert!(writer.write_all(b"e").is_err());
+        assert!(writer.exceeded());
+        assert_eq!(writer.inner, b"abcd");
+
+        let mut writer = LimitedWriter::new(Vec::new(), None);
+        writer.write_all(&[b'a'; 1024]).unwrap();
+        assert!(!writer.exceeded());
+    }
+}
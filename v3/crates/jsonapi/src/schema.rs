@@ -1,4 +1,6 @@
 use crate::catalog::{Model, ObjectType, State};
+use crate::endpoint::EndPoint;
+use crate::naming::NamingStrategy;
 use std::collections::BTreeMap;
 mod output;
 mod parameters;
@@ -17,20 +19,11 @@ pub enum SchemaError {
     ObjectNotFound(Qualified<CustomTypeName>),
 }
 
-// JSONAPI specifies "application/vnd.api+json"
-// we're going with the more universally supported application/json
-static JSONAPI_MEDIA_TYPE: &str = "application/json";
+// the media type JSON:API specifies, which we send responses with
+static JSONAPI_MEDIA_TYPE: &str = "application/vnd.api+json";
 
-fn get_response(
-    model: &Model,
-    object_type: &ObjectType,
-    object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectType>,
-) -> oas3::spec::Response {
-    let schema = oas3::spec::ObjectOrReference::Object(output::jsonapi_document_schema(
-        model,
-        object_type,
-        object_types,
-    ));
+fn get_response(model: &Model, document_schema: oas3::spec::ObjectSchema) -> oas3::spec::Response {
+    let schema = oas3::spec::ObjectOrReference::Object(document_schema);
 
     let media_type = oas3::spec::MediaType {
         encoding: BTreeMap::new(),
@@ -54,35 +47,68 @@ fn get_route_for_model(
     model: &Model,
     object_type: &ObjectType,
     object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectType>,
-    schemas: &mut BTreeMap<String, oas3::spec::ObjectOrReference<oas3::spec::ObjectSchema>>,
-    filter_boolean_expression_types: &BTreeMap<
-        String,
+    boolean_expression_types: &BTreeMap<
+        Qualified<CustomTypeName>,
         metadata_resolve::ResolvedObjectBooleanExpressionType,
     >,
+    naming: &NamingStrategy,
+    schemas: &mut BTreeMap<String, oas3::spec::ObjectOrReference<oas3::spec::ObjectSchema>>,
 ) -> oas3::spec::Operation {
     let mut parameters = vec![
         oas3::spec::ObjectOrReference::Object(parameters::page_limit_parameter()),
         oas3::spec::ObjectOrReference::Object(parameters::page_offset_parameter()),
-        oas3::spec::ObjectOrReference::Object(parameters::ordering_parameter(model, object_type)),
-        oas3::spec::ObjectOrReference::Object(parameters::include_parameter(model, object_type)),
     ];
-    if let Some(filter_parameter) =
-        parameters::filter_parameters(model, object_type, schemas, filter_boolean_expression_types)
-    {
+    // cursor pagination needs a unique identifier to order by
+    if !model.unique_identifier.is_empty() {
+        parameters.push(oas3::spec::ObjectOrReference::Object(
+            parameters::page_cursor_parameter(),
+        ));
+    }
+    // counting the resources needs a data connector that supports aggregates
+    if model.supports_aggregates {
+        parameters.push(oas3::spec::ObjectOrReference::Object(
+            parameters::page_totals_parameter(),
+        ));
+    }
+    parameters.extend([
+        oas3::spec::ObjectOrReference::Object(parameters::ordering_parameter(
+            model,
+            object_type,
+            naming,
+        )),
+        oas3::spec::ObjectOrReference::Object(parameters::include_parameter(
+            model,
+            object_type,
+            naming,
+        )),
+    ]);
+    if let Some(filter_parameter) = parameters::filter_parameter(
+        model,
+        object_types,
+        boolean_expression_types,
+        naming,
+        schemas,
+    ) {
         parameters.push(oas3::spec::ObjectOrReference::Object(filter_parameter));
     }
 
     let fields_parameters =
-        parameters::fields_parameters(&model.data_type, object_type, object_types)
+        parameters::fields_parameters(&model.data_type, object_type, object_types, naming)
             .into_iter()
             .map(oas3::spec::ObjectOrReference::Object)
             .collect::<Vec<_>>();
     parameters.extend_from_slice(&fields_parameters);
+    parameters.push(oas3::spec::ObjectOrReference::Object(
+        parameters::data_parameter(),
+    ));
 
     let mut responses = BTreeMap::new();
     responses.insert(
         "200".into(),
-        oas3::spec::ObjectOrReference::Object(get_response(model, object_type, object_types)),
+        oas3::spec::ObjectOrReference::Object(get_response(
+            model,
+            output::jsonapi_document_schema(model, object_type, object_types, naming),
+        )),
     );
 
     oas3::spec::Operation {
@@ -101,6 +127,86 @@ fn get_route_for_model(
     }
 }
 
+// fetch a single resource of a model by its id
+fn get_route_for_resource(
+    model: &Model,
+    object_type: &ObjectType,
+    object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectType>,
+    naming: &NamingStrategy,
+) -> oas3::spec::Operation {
+    let mut parameters = vec![
+        oas3::spec::ObjectOrReference::Object(parameters::id_parameter(model)),
+        oas3::spec::ObjectOrReference::Object(parameters::include_parameter(
+            model,
+            object_type,
+            naming,
+        )),
+    ];
+
+    let fields_parameters =
+        parameters::fields_parameters(&model.data_type, object_type, object_types, naming)
+            .into_iter()
+            .map(oas3::spec::ObjectOrReference::Object)
+            .collect::<Vec<_>>();
+    parameters.extend_from_slice(&fields_parameters);
+    parameters.push(oas3::spec::ObjectOrReference::Object(
+        parameters::data_parameter(),
+    ));
+
+    let mut responses = BTreeMap::new();
+    responses.insert(
+        "200".into(),
+        oas3::spec::ObjectOrReference::Object(get_response(
+            model,
+            output::jsonapi_resource_document_schema(model, object_type, object_types, naming),
+        )),
+    );
+    responses.insert(
+        "404".into(),
+        oas3::spec::ObjectOrReference::Object(oas3::spec::Response {
+            description: Some(format!("No {} resource has this id", model.name.name)),
+            extensions: BTreeMap::new(),
+            headers: BTreeMap::new(),
+            links: BTreeMap::new(),
+            content: BTreeMap::new(),
+        }),
+    );
+
+    oas3::spec::Operation {
+        callbacks: BTreeMap::new(),
+        deprecated: None,
+        description: model.description.clone(),
+        extensions: BTreeMap::new(),
+        external_docs: None,
+        operation_id: None,
+        parameters,
+        request_body: None,
+        responses: Some(responses),
+        servers: vec![],
+        summary: Some(format!("Fetch one {} value by id", model.data_type.name)),
+        tags: vec![],
+    }
+}
+
+fn path_item(get: oas3::spec::Operation) -> oas3::spec::PathItem {
+    oas3::spec::PathItem {
+        delete: None,
+        description: None,
+        extensions: BTreeMap::new(),
+        get: Some(get),
+        head: None,
+        options: None,
+        parameters: vec![],
+        patch: None,
+        post: None,
+        put: None,
+        reference: None,
+        servers: vec![],
+        summary: None,
+        trace: None,
+    }
+}
+
 // output when we make a request for an unknown role
 pub fn empty_schema() -> oas3::Spec {
     let info = oas3::spec::Info {
@@ -126,7 +232,13 @@ pub fn empty_schema() -> oas3::Spec {
     }
 }
 
-pub fn openapi_schema(state: &State) -> Result<oas3::Spec, SchemaError> {
+/// The OpenAPI schema of the routes a role can access, at the path of the endpoint. names of
+/// types, attributes and relationships follow the naming strategy, as they do in documents.
+pub fn openapi_schema(
+    state: &State,
+    naming: &NamingStrategy,
+    endpoint: &EndPoint,
+) -> Result<oas3::Spec, SchemaError> {
     let info = oas3::spec::Info {
         title: "Hasura JSONAPI (alpha)".into(),
         summary: None,
@@ -143,17 +255,6 @@ pub fn openapi_schema(state: &State) -> Result<oas3::Spec, SchemaError> {
     let mut paths = BTreeMap::new();
     let mut schemas = BTreeMap::new();
 
-    // get all the filter boolean expression types that we need to include in the schema
-    let mut filter_boolean_expression_types = BTreeMap::new();
-    for model in state.routes.values() {
-        if let Some(boolean_expression_type) = &model.filter_expression_type {
-            filter_boolean_expression_types.insert(
-                pretty_typename(&boolean_expression_type.name),
-                boolean_expression_type.clone(),
-            );
-        }
-    }
-
     for (route_name, model) in &state.routes {
         let object_type = state
             .object_types
@@ -164,36 +265,30 @@ pub fn openapi_schema(state: &State) -> Result<oas3::Spec, SchemaError> {
             model,
             object_type,
             &state.object_types,
+            &state.boolean_expression_types,
+            naming,
             &mut schemas,
-            &filter_boolean_expression_types,
         );
 
-        let full_route_path = format!("/v1/rest{route_name}");
+        let full_route_path = format!("{}{route_name}", endpoint.as_str());
 
-        let path_item = oas3::spec::PathItem {
-            delete: None,
-            description: None,
-            extensions: BTreeMap::new(),
-            get: Some(get),
-            head: None,
-            options: None,
-            parameters: vec![],
-            patch: None,
-            post: None,
-            put: None,
-            reference: None,
-            servers: vec![],
-            summary: None,
-            trace: None,
-        };
-
-        paths.insert(full_route_path, path_item);
+        // models we can identify resources of can also be fetched one resource at a time
+        if !model.unique_identifier.is_empty() {
+            let get_resource =
+                get_route_for_resource(model, object_type, &state.object_types, naming);
+            paths.insert(format!("{full_route_path}/{{id}}"), path_item(get_resource));
+        }
+
+        paths.insert(full_route_path, path_item(get));
     }
 
     for (object_type_name, object_type) in &state.object_types {
         schemas.insert(
             pretty_typename(object_type_name),
-            oas3::spec::ObjectOrReference::Object(object_schema_for_object_type(object_type)),
+            oas3::spec::ObjectOrReference::Object(object_schema_for_object_type(
+                object_type,
+                naming,
+            )),
         );
     }
 
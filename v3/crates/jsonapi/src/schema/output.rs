@@ -1,5 +1,6 @@
-use super::shared::{any_of_schema, pretty_typename};
+use super::shared::{any_of_schema, nullable_schema, pretty_typename};
 use crate::catalog::{Model, ObjectType, RelationshipTarget, Type};
+use crate::naming::NamingStrategy;
 use crate::schema::{
     array_schema, bool_schema, enum_schema, float_schema, int_schema, json_schema, object_schema,
     string_schema,
@@ -52,15 +53,31 @@ fn type_schema(ty: &Type) -> ObjectOrReference<ObjectSchema> {
     }
 }
 
-// what we output for each type
-pub fn object_schema_for_object_type(object_type: &ObjectType) -> ObjectSchema {
+// the schema for each field of an object type
+fn field_schemas(
+    object_type: &ObjectType,
+    naming: &NamingStrategy,
+) -> BTreeMap<String, ObjectOrReference<ObjectSchema>> {
     let mut fields = BTreeMap::new();
     for (name, ty) in &object_type.type_fields {
-        fields.insert(name.to_string(), type_schema(ty));
+        let schema = if object_type.nullable_fields.contains(name) {
+            nullable_schema(type_schema(ty))
+        } else {
+            type_schema(ty)
+        };
+        fields.insert(naming.field_name(name.as_str()), schema);
     }
-    let required = vec![]; // these are used as output types for the moment so everything is
-                           // nullable
-    object_schema(fields, required)
+    fields
+}
+
+// what we output for each type
+pub fn object_schema_for_object_type(
+    object_type: &ObjectType,
+    naming: &NamingStrategy,
+) -> ObjectSchema {
+    let required = vec![]; // fields can be left out with sparse fieldsets, so none of them are
+                           // required
+    object_schema(field_schemas(object_type, naming), required)
 }
 
 #[allow(deprecated)]
@@ -69,19 +86,22 @@ fn from_type_representation(type_representation: &ndc_models::TypeRepresentation
         ndc_models::TypeRepresentation::Boolean => bool_schema(),
         ndc_models::TypeRepresentation::Int8
         | ndc_models::TypeRepresentation::Int16
-        | ndc_models::TypeRepresentation::Int32
-        | ndc_models::TypeRepresentation::Int64
-        | ndc_models::TypeRepresentation::BigInteger => int_schema(),
-        ndc_models::TypeRepresentation::Float32
-        | ndc_models::TypeRepresentation::Float64
-        | ndc_models::TypeRepresentation::BigDecimal => float_schema(),
+        | ndc_models::TypeRepresentation::Int32 => int_schema(),
+        ndc_models::TypeRepresentation::Float32 | ndc_models::TypeRepresentation::Float64 => {
+            float_schema()
+        }
+        // written as strings, so that they keep their precision
+        ndc_models::TypeRepresentation::Int64 => string_schema(Some("int64".into())),
+        ndc_models::TypeRepresentation::BigInteger | ndc_models::TypeRepresentation::BigDecimal => {
+            string_schema(None)
+        }
         ndc_models::TypeRepresentation::JSON => json_schema(),
         ndc_models::TypeRepresentation::UUID => string_schema(Some("uuid".into())),
         ndc_models::TypeRepresentation::Date => string_schema(Some("date".into())),
         ndc_models::TypeRepresentation::Bytes
         | ndc_models::TypeRepresentation::String
-        | ndc_models::TypeRepresentation::Timestamp
-        | ndc_models::TypeRepresentation::TimestampTZ => string_schema(None),
+        | ndc_models::TypeRepresentation::Timestamp => string_schema(None),
+        ndc_models::TypeRepresentation::TimestampTZ => string_schema(Some("date-time".into())),
         ndc_models::TypeRepresentation::Geography | ndc_models::TypeRepresentation::Geometry => {
             json_schema()
         }
@@ -90,13 +110,17 @@ fn from_type_representation(type_representation: &ndc_models::TypeRepresentation
 }
 
 // Generate schema of the "relationships" property of  "data" object
-fn jsonapi_relationships_property(object_type: &ObjectType) -> Option<ObjectSchema> {
+fn jsonapi_relationships_property(
+    object_type: &ObjectType,
+    naming: &NamingStrategy,
+) -> Option<ObjectSchema> {
     let mut relationships = BTreeMap::new();
     for (relationship_name, relationship_target) in &object_type.type_relationships {
         let (type_name, relationship_type) = match relationship_target {
             RelationshipTarget::Model {
                 object_type,
                 relationship_type,
+                ..
             } => (object_type, relationship_type),
             RelationshipTarget::Command { type_reference } => {
                 match unwrap_custom_type_name(type_reference) {
@@ -114,7 +138,7 @@ fn jsonapi_relationships_property(object_type: &ObjectType) -> Option<ObjectSche
                 .insert("id".into(), ObjectOrReference::Object(string_schema(None)));
             relationship_object_properties.insert(
                 "_type".into(),
-                ObjectOrReference::Object(enum_schema(vec![pretty_typename(type_name)])),
+                ObjectOrReference::Object(enum_schema(vec![naming.resource_type(type_name)])),
             );
             object_schema(
                 relationship_object_properties,
@@ -123,10 +147,11 @@ fn jsonapi_relationships_property(object_type: &ObjectType) -> Option<ObjectSche
         };
         let schema = match relationship_type {
             RelationshipType::Object => {
+                // `data` is null when there is no related resource
                 let mut properties = BTreeMap::new();
                 properties.insert(
                     "data".into(),
-                    ObjectOrReference::Object(relationship_object_schema),
+                    nullable_schema(ObjectOrReference::Object(relationship_object_schema)),
                 );
                 object_schema(properties, vec!["data".into()])
             }
@@ -142,7 +167,7 @@ fn jsonapi_relationships_property(object_type: &ObjectType) -> Option<ObjectSche
             }
         };
         relationships.insert(
-            relationship_name.to_string(),
+            naming.field_name(relationship_name.as_str()),
             ObjectOrReference::Object(schema),
         );
     }
@@ -159,11 +184,9 @@ fn jsonapi_relationships_property(object_type: &ObjectType) -> Option<ObjectSche
 fn jsonapi_data_schema(
     type_name: &Qualified<CustomTypeName>,
     object_type: &ObjectType,
+    naming: &NamingStrategy,
 ) -> ObjectSchema {
-    let mut attributes = BTreeMap::new();
-    for (field_name, field_type) in &object_type.type_fields {
-        attributes.insert(field_name.to_string(), type_schema(field_type));
-    }
+    let attributes = field_schemas(object_type, naming);
 
     let mut properties = BTreeMap::new();
 
@@ -171,7 +194,7 @@ fn jsonapi_data_schema(
 
     properties.insert(
         "_type".into(),
-        ObjectOrReference::Object(enum_schema(vec![pretty_typename(type_name)])),
+        ObjectOrReference::Object(enum_schema(vec![naming.resource_type(type_name)])),
     );
 
     properties.insert(
@@ -180,7 +203,7 @@ fn jsonapi_data_schema(
     );
 
     // relationships property
-    if let Some(relationships) = jsonapi_relationships_property(object_type) {
+    if let Some(relationships) = jsonapi_relationships_property(object_type, naming) {
         properties.insert(
             "relationships".into(),
             ObjectOrReference::Object(relationships),
@@ -197,14 +220,12 @@ fn jsonapi_data_schema(
 fn jsonapi_included_schema(
     object_type: &ObjectType,
     object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectType>,
+    naming: &NamingStrategy,
 ) -> Option<ObjectSchema> {
     let mut relationships = Vec::new();
     for (_relationship_name, relationship_target) in &object_type.type_relationships {
         let type_name = match relationship_target {
-            RelationshipTarget::Model {
-                object_type,
-                relationship_type: _,
-            } => object_type,
+            RelationshipTarget::Model { object_type, .. } => object_type,
             RelationshipTarget::Command { type_reference } => {
                 match unwrap_custom_type_name(type_reference) {
                     Some(type_name) => type_name,
@@ -214,7 +235,11 @@ fn jsonapi_included_schema(
         };
         // Omit the relationship field if the role lacks permission to access the relationship type.
         if let Some(relationship_object_type) = object_types.get(type_name) {
-            relationships.push(jsonapi_data_schema(type_name, relationship_object_type));
+            relationships.push(jsonapi_data_schema(
+                type_name,
+                relationship_object_type,
+                naming,
+            ));
         }
     }
     if relationships.is_empty() {
@@ -224,22 +249,43 @@ fn jsonapi_included_schema(
     }
 }
 
-// top level jsonapi document
+// top level jsonapi document for a collection of resources
 pub fn jsonapi_document_schema(
     model: &Model,
     object_type: &ObjectType,
     object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectType>,
+    naming: &NamingStrategy,
+) -> ObjectSchema {
+    let data = array_schema(ObjectOrReference::Object(jsonapi_data_schema(
+        &model.data_type,
+        object_type,
+        naming,
+    )));
+    jsonapi_document_schema_with_data(data, object_type, object_types, naming)
+}
+
+// top level jsonapi document for a single resource, fetched by its id
+pub fn jsonapi_resource_document_schema(
+    model: &Model,
+    object_type: &ObjectType,
+    object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectType>,
+    naming: &NamingStrategy,
+) -> ObjectSchema {
+    let data = jsonapi_data_schema(&model.data_type, object_type, naming);
+    jsonapi_document_schema_with_data(data, object_type, object_types, naming)
+}
+
+fn jsonapi_document_schema_with_data(
+    data: ObjectSchema,
+    object_type: &ObjectType,
+    object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectType>,
+    naming: &NamingStrategy,
 ) -> ObjectSchema {
     let mut properties = BTreeMap::new();
 
-    properties.insert(
-        "data".into(),
-        ObjectOrReference::Object(array_schema(ObjectOrReference::Object(
-            jsonapi_data_schema(&model.data_type, object_type),
-        ))),
-    );
+    properties.insert("data".into(), ObjectOrReference::Object(data));
 
-    if let Some(included) = jsonapi_included_schema(object_type, object_types) {
+    if let Some(included) = jsonapi_included_schema(object_type, object_types, naming) {
         properties.insert(
             "included".into(),
             ObjectOrReference::Object(array_schema(ObjectOrReference::Object(included))),
@@ -1,9 +1,16 @@
-use metadata_resolve::{Qualified, QualifiedBaseType, QualifiedTypeName, QualifiedTypeReference};
-use open_dds::types::CustomTypeName;
+use metadata_resolve::{
+    ComparisonExpressionInfo, IncludeLogicalOperators, Qualified, QualifiedBaseType,
+    QualifiedTypeName, QualifiedTypeReference, ResolvedObjectBooleanExpressionType,
+};
+use oas3::spec::{ObjectOrReference, ObjectSchema};
+use open_dds::relationships::RelationshipType;
+use open_dds::types::{CustomTypeName, FieldName};
 
-use super::shared::{array_schema, enum_schema, int_schema, pretty_typename, string_schema};
-use crate::catalog::{Model, ObjectType, Type};
-use crate::schema::shared::json_schema;
+use super::shared::{
+    array_schema, bool_schema, enum_schema, int_schema, pretty_typename, string_schema,
+};
+use crate::catalog::{Model, ObjectType, RelationshipTarget, Type};
+use crate::naming::NamingStrategy;
 use std::collections::BTreeMap;
 use std::string::ToString;
 
@@ -47,6 +54,113 @@ pub fn page_limit_parameter() -> oas3::spec::Parameter {
     }
 }
 
+pub fn page_cursor_parameter() -> oas3::spec::Parameter {
+    let schema = oas3::spec::ObjectOrReference::Object(string_schema(None));
+    oas3::spec::Parameter {
+        name: "page[cursor]".into(),
+        allow_empty_value: None,
+        allow_reserved: None,
+        content: None,
+        deprecated: None,
+        description: Some(
+            "Optional cursor to fetch the page after, as found in the `next` link of a previous \
+             page. An empty cursor fetches the first page. Cannot be used with page[offset]"
+                .into(),
+        ),
+        example: None,
+        explode: None,
+        examples: BTreeMap::new(),
+        extensions: BTreeMap::new(),
+        location: oas3::spec::ParameterIn::Query,
+        schema: Some(schema),
+        style: None,
+        required: None,
+    }
+}
+
+pub fn page_totals_parameter() -> oas3::spec::Parameter {
+    let schema = oas3::spec::ObjectOrReference::Object(bool_schema());
+    oas3::spec::Parameter {
+        name: "page[totals]".into(),
+        allow_empty_value: None,
+        allow_reserved: None,
+        content: None,
+        deprecated: None,
+        description: Some(
+            "Optionally count the resources matching the filter, returned as `meta.total`. \
+             Counting can be slower than fetching a page, so is off by default"
+                .into(),
+        ),
+        example: Some("true".into()),
+        explode: None,
+        examples: BTreeMap::new(),
+        extensions: BTreeMap::new(),
+        location: oas3::spec::ParameterIn::Query,
+        schema: Some(schema),
+        style: None,
+        required: None,
+    }
+}
+
+pub fn data_parameter() -> oas3::spec::Parameter {
+    let schema =
+        oas3::spec::ObjectOrReference::Object(enum_schema(vec!["identifiers".to_string()]));
+    oas3::spec::Parameter {
+        name: "data".into(),
+        allow_empty_value: None,
+        allow_reserved: None,
+        content: None,
+        deprecated: None,
+        description: Some(
+            "Optionally return the primary data as resource identifier objects only, without \
+             attributes or relationships. Included resources are returned in full"
+                .into(),
+        ),
+        example: Some("identifiers".into()),
+        explode: None,
+        examples: BTreeMap::new(),
+        extensions: BTreeMap::new(),
+        location: oas3::spec::ParameterIn::Query,
+        schema: Some(schema),
+        style: None,
+        required: None,
+    }
+}
+
+// Generate the "id" path parameter for fetching a single resource of the given model
+pub fn id_parameter(model: &Model) -> oas3::spec::Parameter {
+    let schema = oas3::spec::ObjectOrReference::Object(string_schema(None));
+    let fields = model
+        .unique_identifier
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    let description = match fields.as_slice() {
+        [field] => format!("The {field} of the {} resource", model.name.name),
+        _ => format!(
+            "The {} of the {} resource, separated by commas",
+            fields.join(", "),
+            model.name.name
+        ),
+    };
+    oas3::spec::Parameter {
+        name: "id".into(),
+        allow_empty_value: None,
+        allow_reserved: None,
+        content: None,
+        deprecated: None,
+        description: Some(description),
+        example: None,
+        explode: None,
+        examples: BTreeMap::new(),
+        extensions: BTreeMap::new(),
+        location: oas3::spec::ParameterIn::Path,
+        schema: Some(schema),
+        style: None,
+        required: Some(true),
+    }
+}
+
 /// To collect and store nested field types of an object type
 struct NestedFieldTypes<'a>(BTreeMap<&'a Qualified<CustomTypeName>, &'a ObjectType>);
 
@@ -101,6 +215,7 @@ pub fn fields_parameters(
     type_name: &Qualified<CustomTypeName>,
     object_type: &ObjectType,
     object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectType>,
+    naming: &NamingStrategy,
 ) -> Vec<oas3::spec::Parameter> {
     // Collect all nested field types of this object type
     let NestedFieldTypes(mut types) = NestedFieldTypes::collect(object_type, object_types);
@@ -109,7 +224,7 @@ pub fn fields_parameters(
     // Build "fields[TYPE]" parameter for all collected types
     types
         .into_iter()
-        .map(|(type_name, object_type)| build_fields_parameter(type_name, object_type))
+        .map(|(type_name, object_type)| build_fields_parameter(type_name, object_type, naming))
         .collect()
 }
 
@@ -117,6 +232,7 @@ pub fn fields_parameters(
 fn build_fields_parameter(
     type_name: &Qualified<CustomTypeName>,
     object_type: &ObjectType,
+    naming: &NamingStrategy,
 ) -> oas3::spec::Parameter {
     let schema = oas3::spec::ObjectOrReference::Object(oas3::spec::ObjectSchema {
         items: Some(Box::new(oas3::spec::ObjectOrReference::Object(
@@ -124,7 +240,7 @@ fn build_fields_parameter(
                 object_type
                     .type_fields
                     .keys()
-                    .map(ToString::to_string)
+                    .map(|field_name| naming.field_name(field_name.as_str()))
                     .collect(),
             ),
         ))),
@@ -136,11 +252,11 @@ fn build_fields_parameter(
         if i > 0 && i < object_type.type_fields.len() {
             example.push(',');
         }
-        example.push_str(&field.to_string());
+        example.push_str(&naming.field_name(field.as_str()));
     }
 
     oas3::spec::Parameter {
-        name: format!("fields[{}]", type_name.name),
+        name: format!("fields[{}]", naming.type_name(&type_name.name)),
         allow_empty_value: None,
         allow_reserved: None,
         content: None,
@@ -157,13 +273,19 @@ fn build_fields_parameter(
     }
 }
 
-pub fn ordering_parameter(model: &Model, object_type: &ObjectType) -> oas3::spec::Parameter {
+pub fn ordering_parameter(
+    model: &Model,
+    object_type: &ObjectType,
+    naming: &NamingStrategy,
+) -> oas3::spec::Parameter {
     // each field can be `thing` (sort ascending by 'thing') or `-thing` (sort descending by
     // 'thing')
     let mut sort_keys = Vec::new();
     for type_field in object_type.type_fields.keys() {
-        sort_keys.push(format!("{type_field}"));
-        sort_keys.push(format!("-{type_field}"));
+        let type_field = naming.field_name(type_field.as_str());
+        let descending = format!("-{type_field}");
+        sort_keys.push(type_field);
+        sort_keys.push(descending);
     }
 
     let schema = oas3::spec::ObjectOrReference::Object(oas3::spec::ObjectSchema {
@@ -202,14 +324,18 @@ pub fn ordering_parameter(model: &Model, object_type: &ObjectType) -> oas3::spec
     }
 }
 
-pub fn include_parameter(model: &Model, object_type: &ObjectType) -> oas3::spec::Parameter {
+pub fn include_parameter(
+    model: &Model,
+    object_type: &ObjectType,
+    naming: &NamingStrategy,
+) -> oas3::spec::Parameter {
     let schema = oas3::spec::ObjectOrReference::Object(array_schema(
         oas3::spec::ObjectOrReference::Object(string_schema(None)),
     ));
     let example = object_type
         .type_relationships
         .keys()
-        .map(ToString::to_string)
+        .map(|relationship_name| naming.field_name(relationship_name.as_str()))
         .collect::<Vec<String>>()
         .join(",");
     let description = format!(
@@ -235,184 +361,195 @@ pub fn include_parameter(model: &Model, object_type: &ObjectType) -> oas3::spec:
     }
 }
 
-// Generate "filter" parameter for the given model with a given object type
-pub fn filter_parameters(
+// Generate "filter" parameter for the given model, adding the schemas of the boolean expression
+// types it uses to `schemas`.
+//
+// filters are given as `filter[field][operator]=value`, which OpenAPI describes as a `deepObject`
+// style parameter. the fields and relationships on offer are those the role can see.
+pub fn filter_parameter(
     model: &Model,
-    // We don't need this right away, this will be used once we start supporting nested filters
-    _object_type: &ObjectType,
-    schemas: &mut BTreeMap<String, oas3::spec::ObjectOrReference<oas3::spec::ObjectSchema>>,
-    // We don't need this right away, this will be used once we start supporting nested filters
-    _filter_boolean_expression_types: &BTreeMap<
-        String,
-        metadata_resolve::ResolvedObjectBooleanExpressionType,
+    object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectType>,
+    boolean_expression_types: &BTreeMap<
+        Qualified<CustomTypeName>,
+        ResolvedObjectBooleanExpressionType,
     >,
+    naming: &NamingStrategy,
+    schemas: &mut BTreeMap<String, ObjectOrReference<ObjectSchema>>,
 ) -> Option<oas3::spec::Parameter> {
     // only include a filter if the model has a `BooleanExpressionType`
-    match &model.filter_expression_type {
-        Some(boolean_expression_type) => {
-            if schemas
-                .get(&pretty_typename(&boolean_expression_type.name))
-                .is_none()
-            {
-                // Add basic information about the filter
-                let mut filter_schema = oas3::spec::ObjectSchema {
-                    title: Some(pretty_typename(&boolean_expression_type.name)),
-                    description: Some(format!("Filter expression for {}", model.name.name)),
-                    schema_type: Some(oas3::spec::SchemaTypeSet::Single(
-                        oas3::spec::SchemaType::Object,
-                    )),
-                    ..Default::default()
-                };
-
-                // Keep track of the keys we add to the schema so we can add the `oneOf`
-                let mut filter_schema_keys = Vec::new();
-
-                // Add the filter schema for each scalar field
-                for (field_name, field_comparison) in &boolean_expression_type.fields.scalar_fields
-                {
-                    // Add basic information about the filter field
-                    let mut field_schema = oas3::spec::ObjectSchema {
-                        title: Some(field_name.to_string()),
-                        description: Some(format!(
-                            "Filter expression for filtering on {field_name}"
-                        )),
-                        schema_type: Some(oas3::spec::SchemaTypeSet::Single(
-                            oas3::spec::SchemaType::Object,
-                        )),
-                        ..Default::default()
-                    };
-
-                    // Add the operators
-                    for (operator_name, operator_type) in &field_comparison.operators {
-                        field_schema.properties.insert(
-                            format!("${operator_name}"),
-                            oas3::spec::ObjectOrReference::Object(type_schema(operator_type)),
-                        );
-                    }
-                    filter_schema.properties.insert(
-                        field_name.to_string(),
-                        oas3::spec::ObjectOrReference::Object(field_schema),
-                    );
-                    filter_schema_keys.push(field_name.to_string());
-                }
+    let boolean_expression_type = model.filter_expression_type.as_ref()?;
+    add_filter_schema(
+        boolean_expression_type,
+        object_types,
+        boolean_expression_types,
+        naming,
+        schemas,
+    );
 
-                // Add the filter schema for each object field
-                for field_name in boolean_expression_type.fields.object_fields.keys() {
-                    filter_schema.properties.insert(
-                        field_name.to_string(),
-                        // TODO: Get the valid filter schema for the object type
-                        oas3::spec::ObjectOrReference::Object(json_schema()),
-                    );
-                }
+    Some(oas3::spec::Parameter {
+        name: "filter".into(),
+        allow_empty_value: None,
+        allow_reserved: None,
+        content: None,
+        deprecated: None,
+        description: Some(format!(
+            "Optional filter on {}, given as filter[field][operator]=value. \
+             A resource must match all of the filters given to be returned.",
+            model.name.name
+        )),
+        example: None,
+        explode: Some(true),
+        // TODO: add examples
+        examples: BTreeMap::new(),
+        extensions: BTreeMap::new(),
+        location: oas3::spec::ParameterIn::Query,
+        schema: Some(filter_schema_reference(&boolean_expression_type.name)),
+        style: Some(oas3::spec::ParameterStyle::DeepObject),
+        required: None,
+    })
+}
 
-                // UNCOMMENT this once we start supporting nested filters. This will add the filter schema for each
-                // relationship field
-                //
-                // for (field_name, field_comparison) in
-                //     &boolean_expression_type.fields.relationship_fields
-                // {
-                //     // only include the filter if the object type has the relationship
-                //     if object_type
-                //         .type_relationships
-                //         .contains_key(&field_comparison.relationship_name)
-                //     {
-                //         if let Some(filter_boolean_expression_type) =
-                //             filter_boolean_expression_types
-                //                 .get(&pretty_typename(&field_comparison.boolean_expression_type))
-                //         {
-                //             filter_schema.properties.insert(
-                //                 field_name.to_string(),
-                //                 oas3::spec::ObjectOrReference::Ref {
-                //                     ref_path: format!(
-                //                         "#/components/schemas/{}",
-                //                         pretty_typename(&filter_boolean_expression_type.name)
-                //                     ),
-                //                 },
-                //             );
-                //         }
-                //     }
-                // }
-
-                // Add the filter schema for $and
-                filter_schema.properties.insert(
-                    "$and".into(),
-                    oas3::spec::ObjectOrReference::Object(array_schema(
-                        oas3::spec::ObjectOrReference::Ref {
-                            ref_path: format!(
-                                "#/components/schemas/{}",
-                                pretty_typename(&boolean_expression_type.name)
-                            ),
-                        },
-                    )),
+fn filter_schema_reference(
+    boolean_expression_type_name: &Qualified<CustomTypeName>,
+) -> ObjectOrReference<ObjectSchema> {
+    ObjectOrReference::Ref {
+        ref_path: format!(
+            "#/components/schemas/{}",
+            pretty_typename(boolean_expression_type_name)
+        ),
+    }
+}
+
+// Add the schema for a boolean expression type to `schemas`, along with those of the boolean
+// expression types of any relationships it can filter on
+fn add_filter_schema(
+    boolean_expression_type: &ResolvedObjectBooleanExpressionType,
+    object_types: &BTreeMap<Qualified<CustomTypeName>, ObjectType>,
+    boolean_expression_types: &BTreeMap<
+        Qualified<CustomTypeName>,
+        ResolvedObjectBooleanExpressionType,
+    >,
+    naming: &NamingStrategy,
+    schemas: &mut BTreeMap<String, ObjectOrReference<ObjectSchema>>,
+) {
+    let schema_name = pretty_typename(&boolean_expression_type.name);
+    if schemas.contains_key(&schema_name) {
+        return;
+    }
+    // a placeholder until we're done, so that cyclic relationships don't recurse forever
+    schemas.insert(
+        schema_name.clone(),
+        ObjectOrReference::Object(ObjectSchema::default()),
+    );
+
+    let mut properties = BTreeMap::new();
+
+    if let Some(object_type) = object_types.get(&boolean_expression_type.object_type) {
+        // Add the filter schema for each scalar field
+        for (field_name, field_comparison) in &boolean_expression_type.fields.scalar_fields {
+            if object_type.type_fields.contains_key(field_name) {
+                properties.insert(
+                    naming.field_name(field_name.as_str()),
+                    ObjectOrReference::Object(field_filter_schema(field_name, field_comparison)),
                 );
-                filter_schema_keys.push("$and".into());
-
-                // Add the filter schema for $or
-                filter_schema.properties.insert(
-                    "$or".into(),
-                    oas3::spec::ObjectOrReference::Object(array_schema(
-                        oas3::spec::ObjectOrReference::Ref {
-                            ref_path: format!(
-                                "#/components/schemas/{}",
-                                pretty_typename(&boolean_expression_type.name)
-                            ),
-                        },
-                    )),
+            }
+        }
+
+        // Add a reference to the filter schema of each object relationship
+        for (field_name, comparable_relationship) in
+            &boolean_expression_type.fields.relationship_fields
+        {
+            let is_object_relationship = matches!(
+                object_type
+                    .type_relationships
+                    .get(&comparable_relationship.relationship_name),
+                Some(RelationshipTarget::Model {
+                    relationship_type: RelationshipType::Object,
+                    ..
+                })
+            );
+            if !is_object_relationship {
+                continue;
+            }
+            if let Some(target_boolean_expression_type) =
+                boolean_expression_types.get(&comparable_relationship.boolean_expression_type)
+            {
+                add_filter_schema(
+                    target_boolean_expression_type,
+                    object_types,
+                    boolean_expression_types,
+                    naming,
+                    schemas,
                 );
-                filter_schema_keys.push("$or".into());
-
-                // Add the oneOf
-                filter_schema.one_of = filter_schema_keys
-                    .into_iter()
-                    .map(|key| {
-                        oas3::spec::ObjectOrReference::Object(oas3::spec::ObjectSchema {
-                            required: vec![key],
-                            ..oas3::spec::ObjectSchema::default()
-                        })
-                    })
-                    .collect();
-                schemas.insert(
-                    pretty_typename(&boolean_expression_type.name),
-                    oas3::spec::ObjectOrReference::Object(filter_schema.clone()),
+                properties.insert(
+                    naming.field_name(field_name.as_str()),
+                    filter_schema_reference(&target_boolean_expression_type.name),
                 );
-            };
-
-            // Note: We are using the content field here because the filter is a JSON object. We cannot use schema here.
-            let mut content = BTreeMap::new();
-            content.insert(
-                // This is how we tell OpenAPI that this is a JSON media object
-                "application/json".into(),
-                oas3::spec::MediaType {
-                    encoding: BTreeMap::new(),
-                    examples: None,
-                    // This is the schema for the filter
-                    schema: Some(oas3::spec::ObjectOrReference::Ref {
-                        ref_path: format!(
-                            "#/components/schemas/{}",
-                            pretty_typename(&boolean_expression_type.name)
-                        ),
-                    }),
-                },
-            );
-            Some(oas3::spec::Parameter {
-                name: "filter".into(),
-                allow_empty_value: None,
-                allow_reserved: None,
-                content: Some(content),
-                deprecated: None,
-                description: Some(format!("Filter expression for {}", model.name.name)),
-                example: None,
-                explode: None,
-                // TODO: add examples
-                examples: BTreeMap::new(),
-                extensions: BTreeMap::new(),
-                location: oas3::spec::ParameterIn::Query,
-                schema: None,
-                style: None,
-                required: None,
-            })
+            }
         }
-        None => None,
+    }
+
+    // Add the logical operators
+    if boolean_expression_type.include_logical_operators == IncludeLogicalOperators::Yes {
+        let self_reference = filter_schema_reference(&boolean_expression_type.name);
+        properties.insert(
+            "_and".into(),
+            ObjectOrReference::Object(array_schema(self_reference.clone())),
+        );
+        properties.insert(
+            "_or".into(),
+            ObjectOrReference::Object(array_schema(self_reference.clone())),
+        );
+        properties.insert("_not".into(), self_reference);
+    }
+
+    let filter_schema = ObjectSchema {
+        title: Some(schema_name.clone()),
+        description: Some(format!(
+            "Filter expression for {}",
+            boolean_expression_type.object_type.name
+        )),
+        schema_type: Some(oas3::spec::SchemaTypeSet::Single(
+            oas3::spec::SchemaType::Object,
+        )),
+        properties,
+        ..ObjectSchema::default()
+    };
+    schemas.insert(schema_name, ObjectOrReference::Object(filter_schema));
+}
+
+// the operators a field can be filtered with: those of its boolean expression type, and the
+// built-in `_in` and `_is_null`
+fn field_filter_schema(
+    field_name: &FieldName,
+    field_comparison: &ComparisonExpressionInfo,
+) -> ObjectSchema {
+    let mut properties = BTreeMap::new();
+    for (operator_name, operator_type) in &field_comparison.operators {
+        properties.insert(
+            operator_name.to_string(),
+            ObjectOrReference::Object(type_schema(operator_type)),
+        );
+    }
+    // operators of the boolean expression type take precedence over the built-in ones
+    properties.entry("_in".into()).or_insert_with(|| {
+        ObjectOrReference::Object(ObjectSchema {
+            description: Some("Comma separated list of values, any of which may match".into()),
+            ..string_schema(None)
+        })
+    });
+    properties
+        .entry("_is_null".into())
+        .or_insert_with(|| ObjectOrReference::Object(bool_schema()));
+
+    ObjectSchema {
+        title: Some(field_name.to_string()),
+        description: Some(format!("Filter expression for filtering on {field_name}")),
+        schema_type: Some(oas3::spec::SchemaTypeSet::Single(
+            oas3::spec::SchemaType::Object,
+        )),
+        properties,
+        ..ObjectSchema::default()
     }
 }
 
@@ -78,3 +78,35 @@ pub fn any_of_schema(schemas: Vec<ObjectSchema>) -> ObjectSchema {
         ..ObjectSchema::default()
     }
 }
+
+// a schema that also accepts `null`
+pub fn nullable_schema(schema: ObjectOrReference<ObjectSchema>) -> ObjectOrReference<ObjectSchema> {
+    match schema {
+        ObjectOrReference::Object(mut object_schema) => match object_schema.schema_type.take() {
+            Some(SchemaTypeSet::Single(schema_type)) => {
+                object_schema.schema_type =
+                    Some(SchemaTypeSet::Multiple(vec![schema_type, SchemaType::Null]));
+                ObjectOrReference::Object(object_schema)
+            }
+            schema_type => {
+                object_schema.schema_type = schema_type;
+                any_of_null_schema(ObjectOrReference::Object(object_schema))
+            }
+        },
+        reference @ ObjectOrReference::Ref { .. } => any_of_null_schema(reference),
+    }
+}
+
+// used for references and untyped schemas, which have no `type` we can add `null` to
+fn any_of_null_schema(schema: ObjectOrReference<ObjectSchema>) -> ObjectOrReference<ObjectSchema> {
+    ObjectOrReference::Object(ObjectSchema {
+        any_of: vec![
+            schema,
+            ObjectOrReference::Object(ObjectSchema {
+                schema_type: Some(SchemaTypeSet::Single(SchemaType::Null)),
+                ..ObjectSchema::default()
+            }),
+        ],
+        ..ObjectSchema::default()
+    })
+}
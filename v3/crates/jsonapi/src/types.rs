@@ -2,11 +2,15 @@ use crate::parse;
 use hasura_authn_core::Role;
 use metadata_resolve::Qualified;
 use open_dds::{
-    identifier::SubgraphName, models::ModelName, relationships::RelationshipType,
-    types::CustomTypeName,
+    identifier::SubgraphName,
+    models::ModelName,
+    relationships::RelationshipType,
+    types::{CustomTypeName, FieldName},
 };
 use std::collections::BTreeMap;
-use tracing_util::{ErrorVisibility, TraceableError};
+use tracing_util::{
+    set_attribute_on_active_span, AttributeVisibility, ErrorVisibility, TraceableError,
+};
 
 #[derive(Debug, Clone)]
 pub enum Warning {
@@ -44,34 +48,247 @@ pub enum ModelWarning {
         object_type_name: Qualified<CustomTypeName>,
     },
     NoModelSource,
+    /// the route of the model would shadow one of the endpoint itself, like `/__schema`
+    ReservedRoute {
+        route: String,
+    },
 }
 
 #[derive(Debug, derive_more::Display)]
 pub enum RequestError {
     NotFound,
+    /// the route exists, but the model has no resource with this `id`
+    #[display("No {model_name} resource has the id {id}")]
+    ResourceNotFound {
+        model_name: ModelName,
+        id: String,
+    },
     BadRequest(String),
+    /// one or more of the query parameters are invalid. we report all of them, rather than just
+    /// the first, so that clients can fix their request in one go
+    #[display(
+        "{}",
+        _0.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")
+    )]
+    InvalidParameters(Vec<ParameterError>),
     InternalError(InternalError),
     PlanError(plan::PlanError),
-    ExecuteError(execute::FieldError),
+    ExecuteError(ConnectorError),
     ParseError(parse::ParseError),
+    /// the request took longer than the configured timeout, and was cancelled
+    #[display("The request did not complete within {timeout:?}")]
+    Timeout {
+        timeout: std::time::Duration,
+    },
+    /// the document of the response is larger than the configured limit, so was not sent
+    #[display(
+        "The response is larger than the limit of {max_size} bytes, ask for less of it with \
+         fields, page[limit] or include"
+    )]
+    ResponseTooLarge {
+        max_size: usize,
+    },
+    /// one of the operations of an atomic request failed, which fails the request as a whole
+    #[display("Operation {index} failed: {error}")]
+    AtomicOperation {
+        index: usize,
+        error: Box<RequestError>,
+    },
+}
+
+/// A query that failed to run against its data connector
+#[derive(Debug, derive_more::Display)]
+#[display("{error}")]
+pub struct ConnectorError {
+    pub error: execute::FieldError,
+    /// whether the role may see the message of the error. for other roles, it is redacted, as it
+    /// can say more about the data source than its schema does.
+    pub expose_detail: bool,
+}
+
+impl ConnectorError {
+    /// the status of the response: 400 for queries the data connector rejects, 502 or 503 when
+    /// the data connector fails or can't be reached, and 500 for our own failures.
+    pub fn status(&self) -> axum::http::StatusCode {
+        use execute::ndc::client as ndc_client;
+        match &self.error {
+            execute::FieldError::NDCExpected { .. } => axum::http::StatusCode::BAD_REQUEST,
+            execute::FieldError::InternalError(execute::FieldInternalError::NDCUnexpected(
+                error,
+            )) => match error {
+                execute::NDCUnexpectedError::NDCClientError(ndc_client::Error::Reqwest(error))
+                    if error.is_connect() =>
+                {
+                    axum::http::StatusCode::SERVICE_UNAVAILABLE
+                }
+                execute::NDCUnexpectedError::NDCClientError(ndc_client::Error::Connector(
+                    error,
+                )) if error.status.as_u16() == 503 => axum::http::StatusCode::SERVICE_UNAVAILABLE,
+                execute::NDCUnexpectedError::NDCClientError(
+                    ndc_client::Error::Io(_)
+                    | ndc_client::Error::InvalidBaseURL
+                    | ndc_client::Error::ProjectIdHeaderValueConversion(_),
+                ) => axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                execute::NDCUnexpectedError::NDCClientError(
+                    ndc_client::Error::Reqwest(_)
+                    | ndc_client::Error::Serde(_)
+                    | ndc_client::Error::ResponseTooLarge(_)
+                    | ndc_client::Error::Connector(_)
+                    | ndc_client::Error::InvalidConnector(_),
+                )
+                | execute::NDCUnexpectedError::BadNDCResponse { .. } => {
+                    axum::http::StatusCode::BAD_GATEWAY
+                }
+            },
+            execute::FieldError::FieldNotFoundInService { .. }
+            | execute::FieldError::SubscriptionsNotSupported
+            | execute::FieldError::RelationshipPredicatesNotSupported { .. }
+            | execute::FieldError::InternalError(_) => {
+                axum::http::StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    fn code(&self) -> ErrorCode {
+        match self.status() {
+            axum::http::StatusCode::BAD_REQUEST => ErrorCode::ConnectorError,
+            axum::http::StatusCode::BAD_GATEWAY | axum::http::StatusCode::SERVICE_UNAVAILABLE => {
+                ErrorCode::ConnectorUnavailable
+            }
+            _ => ErrorCode::InternalError,
+        }
+    }
+
+    fn detail(&self) -> String {
+        if self.expose_detail {
+            return self.error.to_string();
+        }
+        match self.status() {
+            axum::http::StatusCode::BAD_REQUEST => "The data connector rejected the query",
+            axum::http::StatusCode::BAD_GATEWAY => "The data connector failed to run the query",
+            axum::http::StatusCode::SERVICE_UNAVAILABLE => "The data connector is unavailable",
+            _ => "Internal error",
+        }
+        .to_string()
+    }
+}
+
+/// A problem with a single query parameter of the request
+#[derive(Debug, derive_more::Display)]
+#[display("{detail}")]
+pub struct ParameterError {
+    /// the query parameter at fault, eg `sort` or `fields[Articles]`
+    pub parameter: String,
+    pub detail: String,
+    /// the `code` of the error object, by default that of the family of parameters `parameter`
+    /// belongs to
+    pub code: ErrorCode,
+}
+
+impl ParameterError {
+    pub fn new(parameter: impl Into<String>, detail: impl Into<String>) -> Self {
+        let parameter = parameter.into();
+        Self {
+            code: ErrorCode::for_parameter(&parameter),
+            parameter,
+            detail: detail.into(),
+        }
+    }
+
+    /// the same error, with a more specific `code` than that of its parameter
+    #[must_use]
+    pub fn with_code(self, code: ErrorCode) -> Self {
+        Self { code, ..self }
+    }
+}
+
+impl From<ParameterError> for RequestError {
+    fn from(error: ParameterError) -> Self {
+        RequestError::InvalidParameters(vec![error])
+    }
 }
 
 impl RequestError {
+    /// a short, stable name for the kind of error, recorded on traces
+    pub fn category(&self) -> &'static str {
+        match self {
+            RequestError::NotFound => "not_found",
+            RequestError::ResourceNotFound { .. } => "resource_not_found",
+            RequestError::BadRequest(_) => "bad_request",
+            RequestError::InvalidParameters(_) => "invalid_parameters",
+            RequestError::InternalError(_) => "internal_error",
+            RequestError::PlanError(_) => "plan_error",
+            RequestError::ExecuteError(_) => "execute_error",
+            RequestError::ParseError(_) => "parse_error",
+            RequestError::Timeout { .. } => "timeout",
+            RequestError::ResponseTooLarge { .. } => "response_too_large",
+            RequestError::AtomicOperation { error, .. } => error.category(),
+        }
+    }
+
     pub fn into_http_error(self) -> JsonApiHttpError {
-        let (status_code, message) = match self {
-            RequestError::BadRequest(err) => (axum::http::StatusCode::BAD_REQUEST, err),
-            RequestError::ParseError(err) => (axum::http::StatusCode::BAD_REQUEST, err.to_string()),
-            RequestError::NotFound => (
+        let (status_code, code, message) = match self {
+            // the errors of the operation, pointing at it in the request document
+            RequestError::AtomicOperation { index, error } => {
+                let mut error = error.into_http_error();
+                for error in &mut error.errors {
+                    error.source.get_or_insert_with(|| {
+                        ErrorSource::Pointer(format!("/atomic:operations/{index}"))
+                    });
+                }
+                return error;
+            }
+            RequestError::InvalidParameters(errors) => {
+                return JsonApiHttpError::from_errors(
+                    errors
+                        .into_iter()
+                        .map(|error| JsonApiErrorObject {
+                            source: Some(ErrorSource::Parameter(error.parameter.clone())),
+                            ..JsonApiErrorObject::new(
+                                axum::http::StatusCode::BAD_REQUEST,
+                                error.code,
+                                error.detail,
+                            )
+                        })
+                        .collect(),
+                );
+            }
+            RequestError::BadRequest(err) => (
+                axum::http::StatusCode::BAD_REQUEST,
+                ErrorCode::BadRequest,
+                err,
+            ),
+            RequestError::ParseError(err) => (
+                axum::http::StatusCode::BAD_REQUEST,
+                ErrorCode::InvalidRoute,
+                err.to_string(),
+            ),
+            // a role without select permission on what it asks for gets the same 404 as it would
+            // for something that does not exist, so that we don't leak what exists. this is what
+            // GraphQL does too, where the role's schema has no such field.
+            RequestError::NotFound | RequestError::PlanError(plan::PlanError::Permission(_)) => (
                 axum::http::StatusCode::NOT_FOUND,
+                ErrorCode::NotFound,
                 "invalid route or path".to_string(),
             ),
-            RequestError::PlanError(plan::PlanError::Permission(_msg)) => (
-                axum::http::StatusCode::FORBIDDEN,
-                "Access forbidden".to_string(), // need to decide how much
-                                                // we tell the user, for
-                                                // now default to nothing
+            error @ RequestError::ResourceNotFound { .. } => (
+                axum::http::StatusCode::NOT_FOUND,
+                ErrorCode::ResourceNotFound,
+                error.to_string(),
+            ),
+            // a permission predicate (or argument preset) needs a session variable that the
+            // request does not have, or has in the wrong shape. this is for the client to fix, so
+            // we say which one, rather than treating the value as null.
+            RequestError::PlanError(plan::PlanError::InternalError(
+                plan::InternalError::Developer(error),
+            )) if is_session_variable_error(&error) => (
+                axum::http::StatusCode::BAD_REQUEST,
+                ErrorCode::InvalidSessionVariable,
+                error.to_string(),
             ),
-            RequestError::InternalError(InternalError::EmptyQuerySet)
+            RequestError::InternalError(
+                InternalError::EmptyQuerySet | InternalError::MissingTotalCount,
+            )
             | RequestError::PlanError(
                 plan::PlanError::Internal(_)
                 | plan::PlanError::InternalError(_)
@@ -81,16 +298,148 @@ impl RequestError {
                 | plan::PlanError::ArgumentPresetExecutionError(_),
             ) => (
                 axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorCode::InternalError,
                 "Internal error".to_string(),
             ),
-            RequestError::ExecuteError(field_error) => (
-                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                field_error.to_string(),
+            RequestError::ExecuteError(error) => (error.status(), error.code(), error.detail()),
+            error @ RequestError::Timeout { .. } => (
+                axum::http::StatusCode::GATEWAY_TIMEOUT,
+                ErrorCode::Timeout,
+                error.to_string(),
+            ),
+            error @ RequestError::ResponseTooLarge { .. } => (
+                axum::http::StatusCode::BAD_REQUEST,
+                ErrorCode::ResponseTooLarge,
+                error.to_string(),
             ),
         };
-        JsonApiHttpError {
-            status: status_code,
-            error: message,
+        JsonApiHttpError::new(status_code, code, message)
+    }
+}
+
+/// The machine-readable `code` of a JSON:API error object, for clients to switch on rather than
+/// parsing the `detail`.
+///
+/// These are part of the API: a code is never renamed or given a different meaning, though new
+/// codes may be added.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// `bad-request`: the request is malformed in some other way, eg an invalid batch
+    BadRequest,
+    /// `invalid-body`: the request body can't be read, eg it is not valid JSON
+    InvalidBody,
+    /// `invalid-route`: the path of the request can't be parsed
+    InvalidRoute,
+    /// `not-found`: nothing exists at the route, or nothing the role is allowed to see
+    NotFound,
+    /// `resource-not-found`: the model has no resource with the requested `id`
+    ResourceNotFound,
+    /// `invalid-parameter`: a query parameter not covered by a more specific code is invalid
+    InvalidParameter,
+    /// `invalid-sort`: the `sort` parameter names something that can't be sorted on
+    InvalidSort,
+    /// `unknown-field`: a `fields[{type}]` parameter names an unknown type or field
+    UnknownField,
+    /// `invalid-filter`: a `filter` parameter is invalid
+    InvalidFilter,
+    /// `invalid-include`: the `include` parameter is invalid, or over the include limits
+    InvalidInclude,
+    /// `invalid-pagination`: a `page[...]` parameter is invalid, eg a bad `page[cursor]`
+    InvalidPagination,
+    /// `pagination-limit-exceeded`: `page[limit]` is greater than the maximum page size
+    PaginationLimitExceeded,
+    /// `invalid-aggregate`: an `aggregate[...]` or `group_by` parameter is invalid
+    InvalidAggregate,
+    /// `invalid-session-variable`: a session variable the permissions of the role need is
+    /// missing, or has the wrong type
+    InvalidSessionVariable,
+    /// `unauthorized`: the request could not be authenticated
+    Unauthorized,
+    /// `permission-denied`: the request was authenticated, but is not allowed
+    PermissionDenied,
+    /// `rate-limited`: the role has made too many requests for the resources of the model, and
+    /// should retry after the number of seconds in the `Retry-After` header
+    RateLimited,
+    /// `not-acceptable`: the `Accept` header does not allow the JSON:API media type
+    NotAcceptable,
+    /// `unsupported-media-type`: the `Content-Type` of the request is not the JSON:API media
+    /// type
+    UnsupportedMediaType,
+    /// `method-not-allowed`: the route does not support the method of the request. the methods
+    /// it does support are in the `Allow` header.
+    MethodNotAllowed,
+    /// `connector-error`: the data connector rejected the query, eg because it violates a
+    /// constraint of the data source
+    ConnectorError,
+    /// `connector-unavailable`: the data connector failed, or could not be reached
+    ConnectorUnavailable,
+    /// `timeout`: the request took longer than the configured timeout, and was cancelled
+    Timeout,
+    /// `response-too-large`: the document of the response is larger than the configured limit.
+    /// the request should ask for fewer fields, resources or included relationships.
+    ResponseTooLarge,
+    /// `internal-error`: something went wrong on our side
+    InternalError,
+}
+
+impl ErrorCode {
+    /// the code as it is serialized
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::BadRequest => "bad-request",
+            ErrorCode::InvalidBody => "invalid-body",
+            ErrorCode::InvalidRoute => "invalid-route",
+            ErrorCode::NotFound => "not-found",
+            ErrorCode::ResourceNotFound => "resource-not-found",
+            ErrorCode::InvalidParameter => "invalid-parameter",
+            ErrorCode::InvalidSort => "invalid-sort",
+            ErrorCode::UnknownField => "unknown-field",
+            ErrorCode::InvalidFilter => "invalid-filter",
+            ErrorCode::InvalidInclude => "invalid-include",
+            ErrorCode::InvalidPagination => "invalid-pagination",
+            ErrorCode::PaginationLimitExceeded => "pagination-limit-exceeded",
+            ErrorCode::InvalidAggregate => "invalid-aggregate",
+            ErrorCode::InvalidSessionVariable => "invalid-session-variable",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::PermissionDenied => "permission-denied",
+            ErrorCode::RateLimited => "rate-limited",
+            ErrorCode::NotAcceptable => "not-acceptable",
+            ErrorCode::UnsupportedMediaType => "unsupported-media-type",
+            ErrorCode::MethodNotAllowed => "method-not-allowed",
+            ErrorCode::ConnectorError => "connector-error",
+            ErrorCode::ConnectorUnavailable => "connector-unavailable",
+            ErrorCode::Timeout => "timeout",
+            ErrorCode::ResponseTooLarge => "response-too-large",
+            ErrorCode::InternalError => "internal-error",
+        }
+    }
+
+    // the code for a problem with a query parameter, by the family of parameters it belongs to
+    fn for_parameter(parameter: &str) -> Self {
+        match parameter {
+            "sort" => ErrorCode::InvalidSort,
+            "include" => ErrorCode::InvalidInclude,
+            "group_by" => ErrorCode::InvalidAggregate,
+            _ if parameter.starts_with("fields[") => ErrorCode::UnknownField,
+            _ if parameter.starts_with("filter") => ErrorCode::InvalidFilter,
+            _ if parameter.starts_with("page[") => ErrorCode::InvalidPagination,
+            _ if parameter.starts_with("aggregate") => ErrorCode::InvalidAggregate,
+            _ => ErrorCode::InvalidParameter,
+        }
+    }
+
+    /// the code for an error we only know the HTTP status of, like those of middleware
+    pub fn for_status(status: axum::http::StatusCode) -> Self {
+        match status {
+            axum::http::StatusCode::UNAUTHORIZED => ErrorCode::Unauthorized,
+            axum::http::StatusCode::FORBIDDEN => ErrorCode::PermissionDenied,
+            axum::http::StatusCode::NOT_FOUND => ErrorCode::NotFound,
+            axum::http::StatusCode::NOT_ACCEPTABLE => ErrorCode::NotAcceptable,
+            axum::http::StatusCode::TOO_MANY_REQUESTS => ErrorCode::RateLimited,
+            axum::http::StatusCode::UNSUPPORTED_MEDIA_TYPE => ErrorCode::UnsupportedMediaType,
+            axum::http::StatusCode::METHOD_NOT_ALLOWED => ErrorCode::MethodNotAllowed,
+            status if status.is_client_error() => ErrorCode::BadRequest,
+            _ => ErrorCode::InternalError,
         }
     }
 }
@@ -98,56 +447,282 @@ impl RequestError {
 #[derive(Debug, derive_more::Display)]
 pub enum InternalError {
     EmptyQuerySet,
+    /// the data connector did not return the count asked for with `page[totals]`
+    MissingTotalCount,
+}
+
+// whether a permission predicate (or argument preset) could not be planned because of the
+// session variables of the request
+fn is_session_variable_error(error: &plan::InternalDeveloperError) -> bool {
+    matches!(
+        error,
+        plan::InternalDeveloperError::MissingSessionVariable { .. }
+            | plan::InternalDeveloperError::VariableJsonNotSupported { .. }
+            | plan::InternalDeveloperError::VariableTypeCast { .. }
+            | plan::InternalDeveloperError::VariableArrayTypeCastNotSupported { .. }
+            | plan::InternalDeveloperError::VariableExpectedJson { .. }
+    )
 }
 
+// errors the client can fix are user errors, and those we respond to with a 5xx are internal
 impl TraceableError for RequestError {
     fn visibility(&self) -> ErrorVisibility {
-        ErrorVisibility::User
+        match self {
+            RequestError::PlanError(plan::PlanError::InternalError(
+                plan::InternalError::Developer(error),
+            )) if is_session_variable_error(error) => ErrorVisibility::User,
+            RequestError::ExecuteError(error) if error.status().is_client_error() => {
+                ErrorVisibility::User
+            }
+            RequestError::AtomicOperation { error, .. } => error.visibility(),
+            RequestError::PlanError(plan::PlanError::Permission(_))
+            | RequestError::NotFound
+            | RequestError::ResourceNotFound { .. }
+            | RequestError::BadRequest(_)
+            | RequestError::InvalidParameters(_)
+            | RequestError::ParseError(_)
+            | RequestError::ResponseTooLarge { .. } => ErrorVisibility::User,
+            RequestError::InternalError(_)
+            | RequestError::PlanError(_)
+            | RequestError::ExecuteError(_)
+            | RequestError::Timeout { .. } => ErrorVisibility::Internal,
+        }
     }
 }
 
+/// The JSON:API media type, used as the `Content-Type` of all JSON:API responses
+pub const JSONAPI_MEDIA_TYPE: &str = "application/vnd.api+json";
+
+/// The URI of the atomic operations extension, which we support for reads
+/// spec: <https://jsonapi.org/ext/atomic/>
+pub const ATOMIC_EXTENSION: &str = "https://jsonapi.org/ext/atomic";
+
+/// The JSON:API extensions a request may apply
+pub const SUPPORTED_EXTENSIONS: [&str; 1] = [ATOMIC_EXTENSION];
+
+/// The version of JSON:API our documents follow
+pub const JSONAPI_VERSION: &str = "1.1";
+
+/// The top-level `jsonapi` member of a document, declaring the version of JSON:API it follows and
+/// the extensions applied to it
+/// spec: <https://jsonapi.org/format/1.1/#document-jsonapi-object>
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct JsonApiObject {
+    pub version: &'static str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub ext: Vec<&'static str>,
+}
+
+/// A document, with the top-level `jsonapi` member ahead of its other members when there is one
+#[derive(Debug, serde::Serialize)]
+pub struct WithJsonApiObject<'a, T> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jsonapi: Option<&'a JsonApiObject>,
+    #[serde(flatten)]
+    pub document: &'a T,
+}
+
 /// JSON:API error over HTTP. Readily convertible to an HTTP response
 /// using `axum::response::IntoResponse`'s `.into_response()`.
 pub struct JsonApiHttpError {
+    /// the HTTP status of the response, ie the most severe status of the errors
     pub status: axum::http::StatusCode,
-    pub error: String,
+    pub errors: Vec<JsonApiErrorObject>,
+    /// the top-level `jsonapi` member of the error document, if it has one
+    pub jsonapi: Option<JsonApiObject>,
 }
 
-impl JsonApiHttpError {
-    // Converts the error into a JSON:API error document
-    pub fn into_document_error(self) -> jsonapi_library::api::DocumentError {
-        let jsonapi_error = jsonapi_library::api::JsonApiError {
+/// A single JSON:API error object
+/// Ref: https://jsonapi.org/format/#error-objects
+#[derive(Debug)]
+pub struct JsonApiErrorObject {
+    pub status: axum::http::StatusCode,
+    /// application-specific error code, stable across releases
+    pub code: ErrorCode,
+    pub detail: String,
+    pub source: Option<ErrorSource>,
+}
+
+/// The part of the request an error object is about
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorSource {
+    /// JSON pointer into the request document
+    Pointer(String),
+    /// name of the offending query parameter
+    Parameter(String),
+}
+
+impl JsonApiErrorObject {
+    pub fn new(status: axum::http::StatusCode, code: ErrorCode, detail: impl Into<String>) -> Self {
+        Self {
+            status,
+            code,
+            detail: detail.into(),
+            source: None,
+        }
+    }
+
+    fn into_jsonapi_error(self) -> jsonapi_library::api::JsonApiError {
+        let source = self.source.map(|source| match source {
+            ErrorSource::Pointer(pointer) => jsonapi_library::api::ErrorSource {
+                pointer: Some(pointer),
+                parameter: None,
+            },
+            ErrorSource::Parameter(parameter) => jsonapi_library::api::ErrorSource {
+                pointer: None,
+                parameter: Some(parameter),
+            },
+        });
+        jsonapi_library::api::JsonApiError {
             // The spec mandates the compulsory inclusion of status code
             // Ref: https://jsonapi.org/format/#error-objects
             status: Some(self.status.as_u16().to_string()),
-            detail: Some(self.error),
+            code: Some(self.code.as_str().to_string()),
+            title: self.status.canonical_reason().map(ToString::to_string),
+            detail: Some(self.detail),
+            source,
             ..Default::default()
-        };
+        }
+    }
+}
+
+impl JsonApiHttpError {
+    /// a response with a single error object
+    pub fn new(status: axum::http::StatusCode, code: ErrorCode, detail: impl Into<String>) -> Self {
+        Self::from_errors(vec![JsonApiErrorObject::new(status, code, detail)])
+    }
+
+    /// a response listing all of the given errors, whose status is the most severe of theirs
+    pub fn from_errors(errors: Vec<JsonApiErrorObject>) -> Self {
+        let status = errors
+            .iter()
+            .map(|error| error.status)
+            .max_by_key(axum::http::StatusCode::as_u16)
+            .unwrap_or(axum::http::StatusCode::INTERNAL_SERVER_ERROR);
+        Self {
+            status,
+            errors,
+            jsonapi: None,
+        }
+    }
+
+    /// the error, with `jsonapi` as the top-level `jsonapi` member of its document
+    #[must_use]
+    pub fn with_jsonapi_object(mut self, jsonapi: Option<JsonApiObject>) -> Self {
+        self.jsonapi = jsonapi;
+        self
+    }
+
+    // Converts the error into a JSON:API error document
+    pub fn into_document_error(self) -> jsonapi_library::api::DocumentError {
         jsonapi_library::api::DocumentError {
-            errors: vec![jsonapi_error],
+            errors: self
+                .errors
+                .into_iter()
+                .map(JsonApiErrorObject::into_jsonapi_error)
+                .collect(),
             ..Default::default()
         }
     }
 
+    /// The error for a failure in a middleware layer, like authentication. It is recorded on the
+    /// active span, ie that of the request, as the handler that would have done so never runs.
     pub fn from_middleware_error(error: engine_types::MiddlewareError) -> Self {
         let message = if error.is_internal {
             "Internal error".to_string()
         } else {
             error.message
         };
-        Self {
-            status: error.status,
-            error: message,
-        }
+        let code = ErrorCode::for_status(error.status);
+        tracing_util::get_active_span(|span| {
+            span.set_status(tracing_util::Status::error(message.clone()));
+        });
+        set_attribute_on_active_span(
+            AttributeVisibility::Default,
+            "jsonapi.status",
+            i64::from(error.status.as_u16()),
+        );
+        set_attribute_on_active_span(
+            AttributeVisibility::Default,
+            "jsonapi.error_code",
+            code.as_str(),
+        );
+        set_attribute_on_active_span(
+            AttributeVisibility::Default,
+            "jsonapi.error_detail",
+            message.clone(),
+        );
+        Self::new(error.status, code, message)
     }
 }
 
 impl axum::response::IntoResponse for JsonApiHttpError {
-    fn into_response(self) -> axum::response::Response {
-        (self.status, axum::Json(self.into_document_error())).into_response()
+    fn into_response(mut self) -> axum::response::Response {
+        let status = self.status;
+        let jsonapi = self.jsonapi.take();
+        let document = self.into_document_error();
+        (
+            status,
+            [(axum::http::header::CONTENT_TYPE, JSONAPI_MEDIA_TYPE)],
+            axum::Json(WithJsonApiObject {
+                jsonapi: jsonapi.as_ref(),
+                document: &document,
+            }),
+        )
+            .into_response()
+    }
+}
+
+/// The document for a successful request
+#[derive(Debug, serde::Serialize)]
+#[serde(untagged)]
+pub enum ResponseDocument {
+    /// resources (and any included resources) as primary data
+    Resources(jsonapi_library::api::DocumentData),
+    /// resource linkage, for the `/{id}/relationships/{relationship}` routes
+    Linkage(LinkageDocument),
+    /// a large page of resources, without included resources, for streaming to the client
+    Streamed(crate::process_response::StreamedDocument),
+    /// the queries a request would send to data connectors, for the `/__explain` routes
+    Explain(crate::explain::ExplainDocument),
+    /// what can be read of the resources of a model, for the `/__capabilities` routes
+    Capabilities(crate::capabilities::CapabilitiesDocument),
+}
+
+impl ResponseDocument {
+    /// the number of resources, or resource identifiers, in the primary data
+    pub fn row_count(&self) -> usize {
+        match self {
+            ResponseDocument::Resources(document) => match &document.data {
+                Some(jsonapi_library::api::PrimaryData::Multiple(resources)) => resources.len(),
+                Some(jsonapi_library::api::PrimaryData::Single(_)) => 1,
+                Some(jsonapi_library::api::PrimaryData::None) | None => 0,
+            },
+            ResponseDocument::Linkage(document) => match &document.data {
+                jsonapi_library::model::IdentifierData::Multiple(identifiers) => identifiers.len(),
+                jsonapi_library::model::IdentifierData::Single(_) => 1,
+                jsonapi_library::model::IdentifierData::None => 0,
+            },
+            ResponseDocument::Streamed(document) => document.row_count(),
+            ResponseDocument::Explain(_) | ResponseDocument::Capabilities(_) => 0,
+        }
     }
 }
 
+/// A document whose primary data is resource identifiers, the resource linkage of a relationship
+/// or the resources of `data=identifiers`
+/// spec: <https://jsonapi.org/format/#fetching-relationships-responses-200>
+#[derive(Debug, serde::Serialize)]
+pub struct LinkageDocument {
+    pub data: jsonapi_library::model::IdentifierData,
+    pub links: jsonapi_library::api::Links,
+    /// resources included with `include`, for `data=identifiers`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub included: Option<jsonapi_library::api::Resources>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<jsonapi_library::api::Meta>,
+}
+
 /// Model related info derived from URI path
 #[allow(dead_code)]
 pub struct ModelInfo {
@@ -169,6 +744,276 @@ pub struct RelationshipTree {
 pub struct RelationshipNode {
     pub object_type: Qualified<CustomTypeName>,
     pub relationship_type: RelationshipType,
+    /// fields used to render the `id` of the related resources
+    pub unique_identifier: Vec<FieldName>,
+    /// route of the related model, for the `self` links of the related resources. `None` for
+    /// command relationships, whose results have no route.
+    pub model_route: Option<String>,
     pub is_command_relationship: bool,
     pub nested: RelationshipTree,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_error_document() {
+        let error = RequestError::InvalidParameters(vec![
+            ParameterError::new("sort", "Unknown field `nope`"),
+            ParameterError::new("fields[Articles]", "Unknown field in sparse fields: nope"),
+            ParameterError::new("page[limit]", "page[limit] must not be greater than 10")
+                .with_code(ErrorCode::PaginationLimitExceeded),
+            ParameterError::new("page[limit]", "page[limit] must be a positive integer"),
+        ])
+        .into_http_error();
+        assert_eq!(error.status, StatusCode::BAD_REQUEST);
+
+        let document = serde_json::to_value(error.into_document_error()).unwrap();
+        let errors = document["errors"].as_array().unwrap();
+        assert_eq!(errors.len(), 4);
+        for (error, parameter, code, detail) in [
+            (&errors[0], "sort", "invalid-sort", "Unknown field `nope`"),
+            (
+                &errors[1],
+                "fields[Articles]",
+                "unknown-field",
+                "Unknown field in sparse fields: nope",
+            ),
+            (
+                &errors[2],
+                "page[limit]",
+                "pagination-limit-exceeded",
+                "page[limit] must not be greater than 10",
+            ),
+            (
+                &errors[3],
+                "page[limit]",
+                "invalid-pagination",
+                "page[limit] must be a positive integer",
+            ),
+        ] {
+            assert_eq!(error["status"], "400");
+            assert_eq!(error["code"], code);
+            assert_eq!(error["title"], "Bad Request");
+            assert_eq!(error["detail"], detail);
+            assert_eq!(error["source"]["parameter"], parameter);
+        }
+    }
+
+    #[test]
+    fn test_error_code_strings() {
+        // these are documented, so must not change
+        for (code, expected) in [
+            (ErrorCode::BadRequest, "bad-request"),
+            (ErrorCode::InvalidBody, "invalid-body"),
+            (ErrorCode::InvalidRoute, "invalid-route"),
+            (ErrorCode::NotFound, "not-found"),
+            (ErrorCode::ResourceNotFound, "resource-not-found"),
+            (ErrorCode::InvalidParameter, "invalid-parameter"),
+            (ErrorCode::InvalidSort, "invalid-sort"),
+            (ErrorCode::UnknownField, "unknown-field"),
+            (ErrorCode::InvalidFilter, "invalid-filter"),
+            (ErrorCode::InvalidInclude, "invalid-include"),
+            (ErrorCode::InvalidPagination, "invalid-pagination"),
+            (
+                ErrorCode::PaginationLimitExceeded,
+                "pagination-limit-exceeded",
+            ),
+            (ErrorCode::InvalidAggregate, "invalid-aggregate"),
+            (
+                ErrorCode::InvalidSessionVariable,
+                "invalid-session-variable",
+            ),
+            (ErrorCode::Unauthorized, "unauthorized"),
+            (ErrorCode::PermissionDenied, "permission-denied"),
+            (ErrorCode::RateLimited, "rate-limited"),
+            (ErrorCode::NotAcceptable, "not-acceptable"),
+            (ErrorCode::UnsupportedMediaType, "unsupported-media-type"),
+            (ErrorCode::MethodNotAllowed, "method-not-allowed"),
+            (ErrorCode::ConnectorError, "connector-error"),
+            (ErrorCode::ConnectorUnavailable, "connector-unavailable"),
+            (ErrorCode::Timeout, "timeout"),
+            (ErrorCode::ResponseTooLarge, "response-too-large"),
+            (ErrorCode::InternalError, "internal-error"),
+        ] {
+            assert_eq!(code.as_str(), expected);
+        }
+    }
+
+    #[test]
+    fn test_error_codes_for_parameters() {
+        for (parameter, code) in [
+            ("sort", ErrorCode::InvalidSort),
+            ("include", ErrorCode::InvalidInclude),
+            ("fields[Articles]", ErrorCode::UnknownField),
+            ("filter", ErrorCode::InvalidFilter),
+            ("filter[title][_eq]", ErrorCode::InvalidFilter),
+            ("page[limit]", ErrorCode::InvalidPagination),
+            ("page[cursor]", ErrorCode::InvalidPagination),
+            ("aggregate[sum]", ErrorCode::InvalidAggregate),
+            ("group_by", ErrorCode::InvalidAggregate),
+            ("whatever", ErrorCode::InvalidParameter),
+        ] {
+            assert_eq!(ErrorCode::for_parameter(parameter), code, "{parameter}");
+        }
+    }
+
+    #[test]
+    fn test_error_status_is_most_severe() {
+        let error = JsonApiHttpError::from_errors(vec![
+            JsonApiErrorObject::new(StatusCode::BAD_REQUEST, ErrorCode::BadRequest, "bad"),
+            JsonApiErrorObject::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                ErrorCode::ConnectorError,
+                "unavailable",
+            ),
+            JsonApiErrorObject::new(StatusCode::NOT_FOUND, ErrorCode::NotFound, "missing"),
+        ]);
+        assert_eq!(error.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(error.errors.len(), 3);
+    }
+
+    fn connector_error(status: reqwest::StatusCode, expose_detail: bool) -> RequestError {
+        let error = execute::ndc::client::ConnectorError {
+            status,
+            error_response: execute::ndc::NdcErrorResponse::V02(ndc_models::ErrorResponse {
+                message: "duplicate key value violates unique constraint".to_string(),
+                details: serde_json::Value::Null,
+            }),
+        };
+        RequestError::ExecuteError(ConnectorError {
+            error: execute::ndc::client::Error::Connector(error).into(),
+            expose_detail,
+        })
+    }
+
+    #[test]
+    fn test_connector_errors() {
+        for (connector_status, status, code, visibility, redacted_detail) in [
+            (
+                reqwest::StatusCode::UNPROCESSABLE_ENTITY,
+                StatusCode::BAD_REQUEST,
+                ErrorCode::ConnectorError,
+                ErrorVisibility::User,
+                "The data connector rejected the query",
+            ),
+            (
+                reqwest::StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::SERVICE_UNAVAILABLE,
+                ErrorCode::ConnectorUnavailable,
+                ErrorVisibility::Internal,
+                "The data connector is unavailable",
+            ),
+            (
+                reqwest::StatusCode::INTERNAL_SERVER_ERROR,
+                StatusCode::BAD_GATEWAY,
+                ErrorCode::ConnectorUnavailable,
+                ErrorVisibility::Internal,
+                "The data connector failed to run the query",
+            ),
+        ] {
+            let redacted = connector_error(connector_status, false);
+            assert_eq!(redacted.visibility(), visibility);
+            let redacted = redacted.into_http_error();
+            assert_eq!(redacted.status, status);
+            assert_eq!(redacted.errors[0].code, code);
+            assert_eq!(redacted.errors[0].detail, redacted_detail);
+
+            let exposed = connector_error(connector_status, true).into_http_error();
+            assert_eq!(exposed.status, status);
+            assert!(
+                exposed.errors[0].detail.contains("unique constraint"),
+                "{}",
+                exposed.errors[0].detail
+            );
+        }
+    }
+
+    // keeps the spans that end, to check what was recorded on them
+    #[derive(Debug, Clone, Default)]
+    struct RecordingSpanProcessor(
+        std::sync::Arc<std::sync::Mutex<Vec<opentelemetry_sdk::export::trace::SpanData>>>,
+    );
+
+    impl opentelemetry_sdk::trace::SpanProcessor for RecordingSpanProcessor {
+        fn on_start(
+            &self,
+            _span: &mut opentelemetry_sdk::trace::Span,
+            _cx: &opentelemetry::Context,
+        ) {
+        }
+
+        fn on_end(&self, span: opentelemetry_sdk::export::trace::SpanData) {
+            self.0.lock().unwrap().push(span);
+        }
+
+        fn force_flush(&self) -> opentelemetry::trace::TraceResult<()> {
+            Ok(())
+        }
+
+        fn shutdown(&self) -> opentelemetry::trace::TraceResult<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_middleware_error_is_recorded_on_span() {
+        use opentelemetry::trace::{Tracer, TracerProvider};
+
+        let processor = RecordingSpanProcessor::default();
+        let provider = opentelemetry_sdk::trace::TracerProvider::builder()
+            .with_span_processor(processor.clone())
+            .build();
+        let error = provider.tracer("test").in_span("request", |_cx| {
+            JsonApiHttpError::from_middleware_error(engine_types::MiddlewareError {
+                status: StatusCode::UNAUTHORIZED,
+                message: "Invalid admin secret".to_string(),
+                is_internal: false,
+            })
+        });
+        assert_eq!(error.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(error.errors[0].code, ErrorCode::Unauthorized);
+
+        let spans = processor.0.lock().unwrap();
+        assert_eq!(spans.len(), 1);
+        let span = &spans[0];
+        assert_eq!(
+            span.status,
+            opentelemetry::trace::Status::error("Invalid admin secret")
+        );
+        let attribute = |key: &str| {
+            span.attributes
+                .iter()
+                .find(|attribute| attribute.key.as_str() == key)
+                .map(|attribute| attribute.value.clone())
+        };
+        assert_eq!(attribute("jsonapi.status"), Some(401_i64.into()));
+        assert_eq!(attribute("jsonapi.error_code"), Some("unauthorized".into()));
+        assert_eq!(
+            attribute("jsonapi.error_detail"),
+            Some("Invalid admin secret".into())
+        );
+    }
+
+    #[test]
+    fn test_missing_session_variable_is_bad_request() {
+        let error = RequestError::PlanError(plan::PlanError::InternalError(
+            plan::InternalError::Developer(plan::InternalDeveloperError::MissingSessionVariable {
+                session_variable: open_dds::session_variables::SessionVariableName::from_str(
+                    "x-hasura-tenant-id",
+                )
+                .unwrap(),
+            }),
+        ))
+        .into_http_error();
+        assert_eq!(error.status, StatusCode::BAD_REQUEST);
+        assert_eq!(error.errors[0].code, ErrorCode::InvalidSessionVariable);
+        assert_eq!(
+            error.errors[0].detail,
+            "Required session variable not found in the request: x-hasura-tenant-id"
+        );
+    }
+}
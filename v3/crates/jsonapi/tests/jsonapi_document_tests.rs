@@ -0,0 +1,538 @@
+//! Golden tests of the JSON:API documents we send, rendered from canned data connector responses
+//! rather than from a running data connector.
+//!
+//! Each directory in `tests/documents` is a test, with:
+//!  - `request.txt`: the route and query string of the request, eg `/default/Artist?page[limit]=2`
+//!  - `ndc_responses.json`: the query response the data connector sends for each collection it is
+//!    asked about, keyed by collection. not needed when the request fails before we query.
+//!  - `expected.json`: the status and body of the response we send
+//!
+//! Run with `UPDATE_GOLDENS=1` to write the `expected.json` of every test from what we send.
+//!
+//! The mock data connector also keeps the headers of the query requests it gets, to check what we
+//! send it besides the query. It explains any query as being of its collection.
+
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use engine_types::HttpContext;
+use hasura_authn_core::{Identity, Role};
+use std::collections::{BTreeMap, BTreeSet};
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use tower::ServiceExt;
+
+// set to regenerate the `expected.json` of each test
+const UPDATE_GOLDENS: &str = "UPDATE_GOLDENS";
+
+// the data connector URLs of the fixture metadata, which we point at the mock data connector
+const METADATA_CONNECTOR_URLS: [&str; 2] = ["http://localhost:8080", "http://localhost:8101"];
+
+#[test]
+fn test_documents() {
+    let documents_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("documents");
+    let mut test_dirs = std::fs::read_dir(&documents_dir)
+        .unwrap_or_else(|error| panic!("{}: Could not read: {error}", documents_dir.display()))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_dir())
+        .collect::<Vec<_>>();
+    test_dirs.sort();
+
+    let update_goldens = std::env::var_os(UPDATE_GOLDENS).is_some();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all() // this enables time and IO
+        .build()
+        .unwrap();
+
+    for test_dir in test_dirs {
+        let actual = runtime.block_on(render_response(&test_dir));
+        let expected_path = test_dir.join("expected.json");
+        if update_goldens {
+            let mut golden = serde_json::to_string_pretty(&actual).unwrap();
+            golden.push('\n');
+            std::fs::write(&expected_path, golden).unwrap_or_else(|error| {
+                panic!("{}: Could not write: {error}", expected_path.display())
+            });
+        } else {
+            let expected = std::fs::read_to_string(&expected_path).unwrap_or_else(|error| {
+                panic!(
+                    "{}: Could not read: {error}. Run with {UPDATE_GOLDENS}=1 to create it",
+                    expected_path.display()
+                )
+            });
+            let expected: serde_json::Value = serde_json::from_str(&expected).unwrap();
+            pretty_assertions::assert_eq!(
+                expected,
+                actual,
+                "{}: the response does not match the golden document. Run with {UPDATE_GOLDENS}=1 to update it",
+                test_dir.display()
+            );
+        }
+    }
+}
+
+// the trace context is sent to the data connector, with the span of the request to it as the
+// parent, so that the spans of the data connector are part of the trace of the request
+#[test]
+fn test_trace_context_is_sent_to_connector() {
+    use opentelemetry::trace::{
+        FutureExt, SpanId, TraceContextExt, TraceId, Tracer, TracerProvider,
+    };
+
+    let processor = RecordingSpanProcessor::default();
+    let provider = opentelemetry_sdk::trace::TracerProvider::builder()
+        .with_span_processor(processor.clone())
+        .build();
+    opentelemetry::global::set_tracer_provider(provider.clone());
+    opentelemetry::global::set_text_map_propagator(
+        opentelemetry_sdk::propagation::TraceContextPropagator::new(),
+    );
+
+    let test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("documents")
+        .join("pagination");
+    let ndc_responses = serde_json::from_str(
+        &std::fs::read_to_string(test_dir.join("ndc_responses.json")).unwrap(),
+    )
+    .unwrap();
+    let request = std::fs::read_to_string(test_dir.join("request.txt")).unwrap();
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let request_span = provider.tracer("test").start("request");
+    let context = opentelemetry::Context::current_with_span(request_span);
+    let trace_id = context.span().span_context().trace_id();
+    let (status, connector) = runtime.block_on(
+        async {
+            let connector = start_mock_connector(ndc_responses).await;
+            let response = send_request(&connector.url, &request).await;
+            (response.status(), connector)
+        }
+        .with_context(context),
+    );
+    assert_eq!(status, StatusCode::OK);
+
+    let spans = processor.0.lock().unwrap();
+    let request_headers = connector.request_headers.lock().unwrap();
+    assert!(!request_headers.is_empty());
+    for headers in request_headers.iter() {
+        let traceparent = headers
+            .get("traceparent")
+            .expect("no traceparent header on the request to the data connector")
+            .to_str()
+            .unwrap();
+        // `{version}-{trace id}-{parent span id}-{flags}`
+        let [_version, traceparent_trace_id, parent_span_id, _flags] =
+            traceparent.split('-').collect::<Vec<_>>()[..]
+        else {
+            panic!("invalid traceparent header {traceparent}");
+        };
+        assert_eq!(TraceId::from_hex(traceparent_trace_id), Ok(trace_id));
+
+        let parent_span_id = SpanId::from_hex(parent_span_id).unwrap();
+        let parent_span = spans
+            .iter()
+            .find(|span| span.span_context.span_id() == parent_span_id)
+            .unwrap_or_else(|| panic!("no span {parent_span_id} was recorded"));
+        assert_eq!(parent_span.name, "send_request");
+    }
+}
+
+// an explained request gets the query we would send to the data connector, and the plan of the
+// data connector for it, without the query being run. only the roles allowed to explain requests
+// may do so.
+#[test]
+fn test_explain() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let connector = start_mock_connector(BTreeMap::new()).await;
+        let (jsonapi_catalog, metadata) = test_environment_setup(&connector.url);
+        let metadata = Arc::new(metadata);
+        let explain = |configuration: jsonapi::Configuration| {
+            let jsonapi_catalog = &jsonapi_catalog;
+            let metadata = metadata.clone();
+            async move {
+                jsonapi::explain_handler_internal(
+                    Arc::new(HeaderMap::new()),
+                    Arc::new(HttpContext {
+                        client: reqwest::Client::new(),
+                        ndc_response_size_limit: None,
+                    }),
+                    Arc::new(admin_session()),
+                    jsonapi_catalog,
+                    &configuration,
+                    metadata,
+                    axum::http::Uri::from_static("/__explain/default/Authors"),
+                    "page[limit]=2",
+                )
+                .await
+            }
+        };
+
+        let configuration = jsonapi::Configuration {
+            explain_roles: [Role::new("admin")].into(),
+            ..jsonapi::Configuration::default()
+        };
+        let document = match explain(configuration).await {
+            Ok(jsonapi::ResponseDocument::Explain(document)) => {
+                serde_json::to_value(document).unwrap()
+            }
+            Ok(document) => panic!("expected an explanation, got {document:?}"),
+            Err(error) => panic!("expected an explanation, got {error}"),
+        };
+        let queries = document["meta"]["queries"].as_array().unwrap();
+        assert_eq!(queries.len(), 1);
+        assert_eq!(queries[0]["request"]["collection"], "author");
+        assert_eq!(queries[0]["request"]["query"]["limit"], 2);
+        assert_eq!(queries[0]["plan"]["details"]["collection"], "author");
+        assert!(connector.request_headers.lock().unwrap().is_empty());
+
+        assert!(matches!(
+            explain(jsonapi::Configuration::default()).await,
+            Err(jsonapi::RequestError::NotFound)
+        ));
+    });
+}
+
+// the capabilities of a model are those of the role asking for them, and are known without asking
+// the data connector
+#[test]
+fn test_capabilities() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let connector = start_mock_connector(BTreeMap::new()).await;
+        let (jsonapi_catalog, metadata) = test_environment_setup(&connector.url);
+        let metadata = Arc::new(metadata);
+        let capabilities = |role: &str| {
+            let session = Identity::admin(Role::new("admin"))
+                .get_role_authorization(Some(&Role::new(role)))
+                .unwrap()
+                .build_session(BTreeMap::new());
+            let jsonapi_catalog = &jsonapi_catalog;
+            let metadata = metadata.clone();
+            async move {
+                match jsonapi::handler_internal(
+                    Arc::new(HeaderMap::new()),
+                    Arc::new(HttpContext {
+                        client: reqwest::Client::new(),
+                        ndc_response_size_limit: None,
+                    }),
+                    Arc::new(session),
+                    jsonapi_catalog,
+                    &jsonapi::Configuration::default(),
+                    metadata,
+                    &jsonapi::EndPoint::V1Rest,
+                    axum::http::Method::GET,
+                    axum::http::Uri::from_static("/default/Album/__capabilities"),
+                    "",
+                )
+                .await
+                {
+                    Ok(jsonapi::ResponseDocument::Capabilities(document)) => {
+                        serde_json::to_value(document).unwrap()["meta"]["capabilities"].clone()
+                    }
+                    Ok(document) => panic!("expected capabilities, got {document:?}"),
+                    Err(error) => panic!("expected capabilities, got {error}"),
+                }
+            }
+        };
+
+        let admin = capabilities("admin").await;
+        assert_eq!(admin["type"], "default_Album");
+        assert_eq!(
+            admin["attributes"],
+            serde_json::json!(["AlbumId", "ArtistId", "Title"])
+        );
+        assert_eq!(
+            admin["filters"]["Title"],
+            serde_json::json!(["eq", "like", "_in", "_is_null"])
+        );
+        assert_eq!(admin["logicalOperators"], true);
+        assert_eq!(admin["relationships"]["Tracks"]["cardinality"], "many");
+
+        // a role that can't see the `ArtistId` of albums can't select, sort or filter on it
+        let user = capabilities("user_1").await;
+        assert_eq!(user["attributes"], serde_json::json!(["AlbumId", "Title"]));
+        assert_eq!(user["sortable"], serde_json::json!(["AlbumId", "Title"]));
+        assert!(user["filters"].get("ArtistId").is_none());
+
+        assert!(connector.request_headers.lock().unwrap().is_empty());
+    });
+}
+
+// each read of a batch takes a token for its own model, so a batch alone can be over the rate
+// limit
+#[test]
+fn test_rate_limited_batch() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let (jsonapi_catalog, _) = test_environment_setup(METADATA_CONNECTOR_URLS[0]);
+        let rate_limiter = Arc::new(jsonapi::RateLimiter::new(jsonapi::RateLimit {
+            requests_per_minute: NonZeroU32::new(2).unwrap(),
+            exempt_roles: BTreeSet::new(),
+        }));
+        // the handler gets the batch as it was sent
+        let app = axum::Router::new()
+            .route(
+                jsonapi::BATCH_ROUTE,
+                axum::routing::post(|body: axum::body::Bytes| async move { body }),
+            )
+            .layer(axum::middleware::from_fn_with_state(
+                (
+                    rate_limiter,
+                    Arc::new(jsonapi_catalog),
+                    jsonapi::NamingStrategy::default(),
+                ),
+                jsonapi::jsonapi_rate_limit_middleware,
+            ))
+            .layer(axum::Extension(admin_session()))
+            .layer(axum::Extension(jsonapi::EndPoint::V1Rest));
+        let send_batch = |hrefs: &[&str]| {
+            let batch = serde_json::json!({
+                "operations": hrefs
+                    .iter()
+                    .enumerate()
+                    .map(|(index, href)| {
+                        serde_json::json!({ "key": index.to_string(), "href": href })
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .to_string();
+            let app = app.clone();
+            async move {
+                let response = app
+                    .oneshot(
+                        axum::http::Request::post(jsonapi::BATCH_ROUTE)
+                            .header(header::CONTENT_TYPE, "application/json")
+                            .body(axum::body::Body::from(batch.clone()))
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                let status = response.status();
+                let retry_after = response.headers().get(header::RETRY_AFTER).cloned();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (status, retry_after, body == batch.as_bytes())
+            }
+        };
+
+        let (status, retry_after, _) = send_batch(&[
+            "/default/Album",
+            "/v1/rest/default/Album/1",
+            "/default/Album?page[limit]=1",
+        ])
+        .await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert!(retry_after.is_some());
+
+        // other models have tokens of their own
+        assert_eq!(
+            send_batch(&["/default/Artist", "/default/Artist/1"]).await,
+            (StatusCode::OK, None, true)
+        );
+    });
+}
+
+// keeps the spans that end, to check the trace context we send
+#[derive(Debug, Clone, Default)]
+struct RecordingSpanProcessor(Arc<Mutex<Vec<opentelemetry_sdk::export::trace::SpanData>>>);
+
+impl opentelemetry_sdk::trace::SpanProcessor for RecordingSpanProcessor {
+    fn on_start(&self, _span: &mut opentelemetry_sdk::trace::Span, _cx: &opentelemetry::Context) {}
+
+    fn on_end(&self, span: opentelemetry_sdk::export::trace::SpanData) {
+        self.0.lock().unwrap().push(span);
+    }
+
+    fn force_flush(&self) -> opentelemetry::trace::TraceResult<()> {
+        Ok(())
+    }
+
+    fn shutdown(&self) -> opentelemetry::trace::TraceResult<()> {
+        Ok(())
+    }
+}
+
+// run the request of a test against a mock data connector serving its canned responses, and
+// return the status and body of the response we would send
+async fn render_response(test_dir: &Path) -> serde_json::Value {
+    let ndc_responses_path = test_dir.join("ndc_responses.json");
+    let ndc_responses = match std::fs::read_to_string(&ndc_responses_path) {
+        Ok(ndc_responses) => serde_json::from_str(&ndc_responses).unwrap_or_else(|error| {
+            panic!(
+                "{}: Could not parse canned responses: {error}",
+                ndc_responses_path.display()
+            )
+        }),
+        Err(_) => BTreeMap::new(),
+    };
+    let connector = start_mock_connector(ndc_responses).await;
+
+    let request_path = test_dir.join("request.txt");
+    let request = std::fs::read_to_string(&request_path)
+        .unwrap_or_else(|error| panic!("{}: Could not read: {error}", request_path.display()));
+    let response = send_request(&connector.url, &request).await;
+
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    serde_json::json!({
+        "status": status.as_u16(),
+        "body": serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+    })
+}
+
+// send a request, the route and query string, to the fixture metadata served by the data
+// connector at `connector_url`
+async fn send_request(connector_url: &str, request: &str) -> axum::response::Response {
+    let (jsonapi_catalog, metadata) = test_environment_setup(connector_url);
+    let (path, query) = request
+        .trim_end()
+        .split_once('?')
+        .unwrap_or((request.trim_end(), ""));
+
+    let http_context = HttpContext {
+        client: reqwest::Client::new(),
+        ndc_response_size_limit: None,
+    };
+    let request_headers = HeaderMap::new();
+    let configuration = jsonapi::Configuration::default();
+    let jsonapi_object = configuration.jsonapi_object(&jsonapi::MediaTypeExtensions::default());
+    let result = jsonapi::handler_internal(
+        Arc::new(request_headers.clone()),
+        Arc::new(http_context),
+        Arc::new(admin_session()),
+        &jsonapi_catalog,
+        &configuration,
+        Arc::new(metadata),
+        &jsonapi::EndPoint::V1Rest,
+        axum::http::Method::GET,
+        axum::http::Uri::from_str(path).unwrap(),
+        query,
+    )
+    .await;
+
+    match result {
+        Ok(document) => jsonapi::document_response(
+            &request_headers,
+            jsonapi_object.as_ref(),
+            configuration.max_response_size,
+            document,
+        ),
+        Err(error) => error
+            .into_http_error()
+            .with_jsonapi_object(jsonapi_object)
+            .into_response(),
+    }
+}
+
+struct MockConnector {
+    url: String,
+    ndc_responses: BTreeMap<String, serde_json::Value>,
+    // the headers of each query request, in the order they arrived
+    request_headers: Mutex<Vec<HeaderMap>>,
+}
+
+// serve `POST /query` with the canned response for the collection of the query
+async fn start_mock_connector(
+    ndc_responses: BTreeMap<String, serde_json::Value>,
+) -> Arc<MockConnector> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let connector = Arc::new(MockConnector {
+        url: format!("http://{address}"),
+        ndc_responses,
+        request_headers: Mutex::new(vec![]),
+    });
+    let app = axum::Router::new()
+        .route("/query", axum::routing::post(mock_query))
+        .route("/query/explain", axum::routing::post(mock_explain))
+        .with_state(connector.clone());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    connector
+}
+
+async fn mock_query(
+    State(connector): State<Arc<MockConnector>>,
+    headers: HeaderMap,
+    axum::Json(query_request): axum::Json<serde_json::Value>,
+) -> axum::response::Response {
+    connector.request_headers.lock().unwrap().push(headers);
+    let collection = query_request["collection"].as_str().unwrap_or_default();
+    match connector.ndc_responses.get(collection) {
+        Some(ndc_response) => axum::Json(ndc_response.clone()).into_response(),
+        None => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(serde_json::json!({
+                "message": format!("No canned response for collection {collection}"),
+                "details": null,
+            })),
+        )
+            .into_response(),
+    }
+}
+
+async fn mock_explain(
+    axum::Json(query_request): axum::Json<serde_json::Value>,
+) -> axum::response::Response {
+    axum::Json(serde_json::json!({
+        "details": {"collection": query_request["collection"]},
+    }))
+    .into_response()
+}
+
+fn test_environment_setup(connector_url: &str) -> (jsonapi::Catalog, metadata_resolve::Metadata) {
+    let metadata_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("static")
+        .join("metadata.json");
+    let mut metadata_string = std::fs::read_to_string(&metadata_path).unwrap_or_else(|error| {
+        panic!("{}: Could not read file: {error}", metadata_path.display())
+    });
+    for url in METADATA_CONNECTOR_URLS {
+        metadata_string = metadata_string.replace(url, connector_url);
+    }
+
+    let input_metadata: open_dds::Metadata = open_dds::traits::OpenDd::deserialize(
+        serde_json::from_str(&metadata_string).unwrap(),
+        jsonpath::JSONPath::new(),
+    )
+    .unwrap();
+
+    let configuration = metadata_resolve::configuration::Configuration {
+        unstable_features: metadata_resolve::configuration::UnstableFeatures {
+            enable_aggregation_predicates: false,
+        },
+    };
+    let (resolved_metadata, _) = metadata_resolve::resolve(input_metadata, &configuration)
+        .unwrap_or_else(|error| panic!("Could not resolve metadata: {error}"));
+    let (jsonapi_catalog, _warnings) = jsonapi::Catalog::new(&resolved_metadata);
+
+    (jsonapi_catalog, resolved_metadata)
+}
+
+fn admin_session() -> hasura_authn_core::Session {
+    Identity::admin(Role::new("admin"))
+        .get_role_authorization(None)
+        .unwrap()
+        .build_session(BTreeMap::new())
+}
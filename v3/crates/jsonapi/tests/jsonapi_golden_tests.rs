@@ -1,10 +1,11 @@
 //! Tests that run JSONAPI to see if it works
 
 use engine_types::HttpContext;
-use hasura_authn_core::{Identity, Role};
+use hasura_authn_core::{Identity, JsonSessionVariableValue, Role, SessionVariableName};
 use jsonapi_library::api::{DocumentData, IdentifierData, PrimaryData};
+use open_dds::session_variables::SESSION_VARIABLE_ROLE;
 use reqwest::header::HeaderMap;
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Arc;
@@ -29,43 +30,62 @@ fn test_get_succeeding_requests() {
                     metadata,
                 } = test_environment_setup();
 
-                let TestRequest { query, model_name } = test_request_setup(path);
-
-                // always test in `default` subgraph for now
-                let request_path = format!("/default/{model_name}");
+                let TestRequest { query, request_path } = test_request_setup(path);
+                let metadata = Arc::new(metadata);
 
                 let http_context = HttpContext {
                     client: reqwest::Client::new(),
                     ndc_response_size_limit: None,
                 };
 
-                let session = create_default_session();
-
-                let result = jsonapi::handler_internal(
-                    Arc::new(HeaderMap::default()),
-                    Arc::new(http_context.clone()),
-                    Arc::new(session.clone()),
-                    &jsonapi_catalog,
-                    metadata.into(),
-                    axum::http::method::Method::GET,
-                    axum::http::uri::Uri::from_str(&request_path).unwrap(),
-                    query,
-                )
-                    .await;
-
-                match result {
-                    Ok(result) => {
-                        // Assert uniqueness of resources in the response
-                        validate_resource_uniqueness(&result).unwrap();
-                        // Assert all relationships have corresponding included resources
-                        validate_relationships_in_included(&result).unwrap();
-                        let file_name = path.file_name().unwrap().to_str().unwrap();
-                        insta::assert_debug_snapshot!(
-                            format!("result_for_role_{}__{file_name}", session.role),
-                            result
-                        );
+                // each fixture runs once for each session of its directory
+                for session in test_sessions(directory) {
+                    let result = jsonapi::handler_internal(
+                        Arc::new(HeaderMap::default()),
+                        Arc::new(http_context.clone()),
+                        Arc::new(session.clone()),
+                        &jsonapi_catalog,
+                        &jsonapi::Configuration::default(),
+                        metadata.clone(),
+                        &jsonapi::EndPoint::V1Rest,
+                        axum::http::method::Method::GET,
+                        axum::http::uri::Uri::from_str(&request_path).unwrap(),
+                        &query,
+                    )
+                        .await;
+
+                    let file_name = path.file_name().unwrap().to_str().unwrap();
+                    match result {
+                        Ok(jsonapi::ResponseDocument::Resources(result)) => {
+                            // Assert uniqueness of resources in the response
+                            validate_resource_uniqueness(&result).unwrap();
+                            // Assert all relationships have corresponding included resources
+                            validate_relationships_in_included(&result).unwrap();
+                            insta::assert_debug_snapshot!(
+                                format!("result_for_role_{}__{file_name}", session.role),
+                                result
+                            );
+                        }
+                        Ok(jsonapi::ResponseDocument::Linkage(result)) => {
+                            insta::assert_debug_snapshot!(
+                                format!("result_for_role_{}__{file_name}", session.role),
+                                result
+                            );
+                        }
+                        Ok(jsonapi::ResponseDocument::Streamed(result)) => {
+                            insta::assert_debug_snapshot!(
+                                format!("result_for_role_{}__{file_name}", session.role),
+                                result
+                            );
+                        }
+                        Ok(jsonapi::ResponseDocument::Explain(_)) => {
+                            panic!("expected resources for {path:?}, instead got an explanation")
+                        }
+                        Ok(jsonapi::ResponseDocument::Capabilities(_)) => {
+                            panic!("expected resources for {path:?}, instead got capabilities")
+                        }
+                        Err(e) => panic!("expected success for {path:?}, instead got {e}"),
                     }
-                    Err(e) => panic!("expected success for {path:?}, instead got {e}"),
                 }
             });
         });
@@ -92,32 +112,32 @@ fn test_get_failing_requests() {
                     jsonapi_catalog,
                 } = test_environment_setup();
 
-                let TestRequest { query, model_name } = test_request_setup(path);
-
-                // always test in `default` subgraph for now
-                let request_path = format!("/default/{model_name}");
+                let TestRequest { query, request_path } = test_request_setup(path);
+                let metadata = Arc::new(metadata);
 
                 let http_context = HttpContext {
                     client: reqwest::Client::new(),
                     ndc_response_size_limit: None,
                 };
 
-                let session = create_default_session();
-
-                let result = jsonapi::handler_internal(
-                    Arc::new(HeaderMap::default()),
-                    Arc::new(http_context.clone()),
-                    Arc::new(session.clone()),
-                    &jsonapi_catalog,
-                    metadata.into(),
-                    axum::http::method::Method::GET,
-                    axum::http::uri::Uri::from_str(&request_path).unwrap(),
-                    query,
-                )
-                    .await;
-
-                let file_name = path.file_name().unwrap().to_str().unwrap();
-                insta::assert_debug_snapshot!(format!("error_for_role_{}__{file_name}", session.role), result);
+                for session in test_sessions(directory) {
+                    let result = jsonapi::handler_internal(
+                        Arc::new(HeaderMap::default()),
+                        Arc::new(http_context.clone()),
+                        Arc::new(session.clone()),
+                        &jsonapi_catalog,
+                        &jsonapi::Configuration::default(),
+                        metadata.clone(),
+                        &jsonapi::EndPoint::V1Rest,
+                        axum::http::method::Method::GET,
+                        axum::http::uri::Uri::from_str(&request_path).unwrap(),
+                        &query,
+                    )
+                        .await;
+
+                    let file_name = path.file_name().unwrap().to_str().unwrap();
+                    insta::assert_debug_snapshot!(format!("error_for_role_{}__{file_name}", session.role), result);
+                }
             });
         });
     });
@@ -138,7 +158,11 @@ fn test_openapi_generation() {
         prepend_module_to_snapshot => false,
     }, {
         for (role, state) in &jsonapi_catalog.state_per_role {
-            let generated_openapi = jsonapi::openapi_schema(state);
+            let generated_openapi = jsonapi::openapi_schema(
+                state,
+                &jsonapi::NamingStrategy::default(),
+                &jsonapi::EndPoint::V1Rest,
+            );
 
             // if the test fails, let's take a look at what was generated
             dbg!(&serde_json::to_value(&generated_openapi)
@@ -153,9 +177,101 @@ fn test_openapi_generation() {
     });
 }
 
+// the same collection, planned for two roles, is filtered by the select permissions of each role,
+// as it is for GraphQL
+#[test]
+fn test_row_permissions_per_role() {
+    let user_1 = plan_articles(
+        serde_json::json!({ "x-hasura-role": "user_1", "x-hasura-user-id": "1" }),
+        None,
+    );
+    let user_2 = plan_articles(serde_json::json!({ "x-hasura-role": "user_2" }), None);
+
+    assert_ne!(user_1, user_2);
+    assert!(!user_1.contains("%Functional%"));
+    assert!(user_2.contains("%Functional%"));
+}
+
+// a filter of the request narrows down what the permissions allow, rather than replacing them
+#[test]
+fn test_filter_with_row_permissions() {
+    let filter = serde_json::json!({
+        "comparison": {
+            "operand": { "field": { "fieldName": "article_id", "nested": null } },
+            "operator": "_eq",
+            "argument": { "literal": 4242 }
+        }
+    });
+    let plan = plan_articles(
+        serde_json::json!({ "x-hasura-role": "user_2" }),
+        Some(filter),
+    );
+
+    assert!(plan.contains("%Functional%"));
+    assert!(plan.contains("4242"));
+}
+
+// the count for `page[totals]` is of the resources the role can see, not of the whole model
+#[test]
+fn test_total_count_with_row_permissions() {
+    let TestEnvironment { metadata, .. } = test_environment_setup();
+    let query_request: open_dds::query::QueryRequest = serde_json::from_value(serde_json::json!({
+        "version": "v1",
+        "queries": {
+            "jsonapi_total_count": {
+                "modelAggregate": {
+                    "subgraph": "default",
+                    "modelName": "Articles",
+                    "selection": {
+                        "total": { "function": { "count": {} }, "operand": null }
+                    }
+                }
+            }
+        }
+    }))
+    .unwrap();
+
+    let session = build_session(
+        serde_json::from_value(serde_json::json!({ "x-hasura-role": "user_2" })).unwrap(),
+    );
+    let plan =
+        plan::plan_query_request(&query_request, &metadata, &session, &HeaderMap::new()).unwrap();
+    assert!(format!("{plan:?}").contains("%Functional%"));
+}
+
+// the execution plan for reading `Articles` as the session, with an optional OpenDD filter like
+// the one built from a `filter` query parameter
+fn plan_articles(
+    session_variables: serde_json::Value,
+    filter: Option<serde_json::Value>,
+) -> String {
+    let TestEnvironment { metadata, .. } = test_environment_setup();
+    let query_request: open_dds::query::QueryRequest = serde_json::from_value(serde_json::json!({
+        "version": "v1",
+        "queries": {
+            "articles": {
+                "model": {
+                    "subgraph": "default",
+                    "modelName": "Articles",
+                    "filter": filter,
+                    "selection": {
+                        "article_id": { "field": { "fieldName": "article_id" } }
+                    }
+                }
+            }
+        }
+    }))
+    .unwrap();
+
+    let session = build_session(serde_json::from_value(session_variables).unwrap());
+    let plan =
+        plan::plan_query_request(&query_request, &metadata, &session, &HeaderMap::new()).unwrap();
+    format!("{plan:?}")
+}
+
 struct TestRequest {
-    query: jsonapi_library::query::Query,
-    model_name: String,
+    query: String,
+    request_path: String,
 }
 
 struct TestEnvironment {
@@ -214,23 +330,61 @@ fn test_request_setup(path: &Path) -> TestRequest {
     // our input files contain trailing newlines that break the JSONAPI parser
     trim_newline(&mut query_params);
 
-    let jsonapi_query = jsonapi_library::query::Query::from_params(&query_params);
+    // input files starting with `/` carry a path below the model, eg `/1?include=tracks` fetches
+    // the resource with id `1`
+    let (path_suffix, query) = match query_params.strip_prefix('/') {
+        Some(rest) => match rest.split_once('?') {
+            Some((path_suffix, query)) => (format!("/{path_suffix}"), query.to_string()),
+            None => (format!("/{rest}"), String::new()),
+        },
+        None => (String::new(), query_params),
+    };
 
     TestRequest {
-        query: jsonapi_query,
-        model_name: model_name.to_string(),
+        query,
+        // always test in `default` subgraph for now
+        request_path: format!("/default/{model_name}{path_suffix}"),
     }
 }
 
-// we will need to allow tests to define their own session options at some point
-// the way that the GraphQL tests defined in `crates/engine/tests/execution.rs` do
-fn create_default_session() -> hasura_authn_core::Session {
+// the sessions to run the fixtures of a directory with. like the GraphQL tests in
+// `crates/engine/tests/execution.rs`, a directory can have a `session_variables.json` with a list
+// of sessions, eg to compare what different roles get. without one, we run as `admin`.
+fn test_sessions(directory: &Path) -> Vec<hasura_authn_core::Session> {
+    let session_variables_path = directory.join("session_variables.json");
+    let sessions: Vec<HashMap<SessionVariableName, JsonSessionVariableValue>> =
+        match std::fs::read_to_string(&session_variables_path) {
+            Ok(sessions) => serde_json::from_str(&sessions).unwrap_or_else(|error| {
+                panic!(
+                    "{}: Could not parse sessions: {error}",
+                    session_variables_path.display()
+                )
+            }),
+            Err(_) => vec![HashMap::from_iter([(
+                SESSION_VARIABLE_ROLE.clone(),
+                JsonSessionVariableValue(serde_json::Value::String("admin".to_string())),
+            )])],
+        };
+
+    sessions.into_iter().map(build_session).collect()
+}
+
+fn build_session(
+    session_variables: HashMap<SessionVariableName, JsonSessionVariableValue>,
+) -> hasura_authn_core::Session {
     //return an arbitrary identity with role emulation enabled
     let authorization = Identity::admin(Role::new("admin"));
-    let role = Role::new("admin");
-    let role_authorization = authorization.get_role_authorization(Some(&role)).unwrap();
-
-    role_authorization.build_session(BTreeMap::new())
+    let role = session_variables
+        .get(&SESSION_VARIABLE_ROLE)
+        .and_then(|role| role.0.as_str())
+        .map(Role::new);
+    let role_authorization = authorization.get_role_authorization(role.as_ref()).unwrap();
+    role_authorization.build_session(
+        session_variables
+            .into_iter()
+            .map(|(name, value)| (name, value.into()))
+            .collect(),
+    )
 }
 
 fn get_metadata_resolve_configuration() -> metadata_resolve::configuration::Configuration {
@@ -39,7 +39,7 @@ pub use stages::model_permissions::{
     FilterPermission, ModelPredicate, ModelTargetSource, ModelWithPermissions, SelectPermission,
     UnaryComparisonOperator,
 };
-pub use stages::models::{Model, ModelSource, ModelsError};
+pub use stages::models::{Model, ModelOrderBy, ModelSource, ModelsError};
 pub use stages::models_graphql::{
     ModelOrderByExpression, SelectAggregateGraphQlDefinition, SelectManyGraphQlDefinition,
     SelectUniqueGraphQlDefinition, SubscriptionGraphQlDefinition, UniqueIdentifierField,
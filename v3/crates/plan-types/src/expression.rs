@@ -2,7 +2,9 @@ use metadata_resolve::{Qualified, UnaryComparisonOperator};
 use open_dds::data_connector::{DataConnectorColumnName, DataConnectorOperatorName};
 use open_dds::models::ModelName;
 use open_dds::relationships::RelationshipName;
+use open_dds::types::DataConnectorArgumentName;
 use serde::Serialize;
+use std::collections::BTreeMap;
 use std::sync::Arc;
 
 use crate::{LocalModelRelationshipInfo, NdcRelationshipName, VariableName};
@@ -169,6 +171,8 @@ pub enum ComparisonTarget {
     Column {
         /// The name of the column
         name: DataConnectorColumnName,
+        /// Arguments of the column, for fields with arguments
+        arguments: BTreeMap<DataConnectorArgumentName, serde_json::Value>,
         /// Path to a nested field within an object column
         field_path: Vec<DataConnectorColumnName>,
     },
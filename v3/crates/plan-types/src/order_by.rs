@@ -1,5 +1,6 @@
-use open_dds::data_connector::DataConnectorColumnName;
+use open_dds::{data_connector::DataConnectorColumnName, types::DataConnectorArgumentName};
 use serde::Serialize;
+use std::collections::BTreeMap;
 
 use super::relationships::RelationshipPathElement;
 
@@ -20,6 +21,8 @@ pub enum OrderByTarget<TExpression> {
     Column {
         relationship_path: Vec<RelationshipPathElement<TExpression>>,
         name: DataConnectorColumnName,
+        /// Arguments of the column, for fields with arguments
+        arguments: BTreeMap<DataConnectorArgumentName, serde_json::Value>,
         field_path: Vec<DataConnectorColumnName>,
     },
 }
@@ -1,19 +1,50 @@
 use super::types::PlanError;
 use crate::metadata_accessor::OutputObjectTypeView;
 use hasura_authn_core::Role;
+use indexmap::IndexMap;
 use metadata_resolve::{
     FieldMapping, Qualified, QualifiedBaseType, QualifiedTypeName, TypeMapping,
 };
-use open_dds::{data_connector::DataConnectorColumnName, types::CustomTypeName};
+use open_dds::{
+    data_connector::DataConnectorColumnName,
+    query::{ArgumentName, Value},
+    types::{CustomTypeName, DataConnectorArgumentName, FieldName},
+};
 use std::collections::BTreeMap;
 
 #[derive(Debug, Clone)]
 pub struct ResolvedColumn {
     pub column_name: DataConnectorColumnName,
+    /// Arguments of the top-level column, for fields with arguments
+    pub arguments: BTreeMap<DataConnectorArgumentName, serde_json::Value>,
     pub field_path: Vec<DataConnectorColumnName>,
     pub field_mapping: FieldMapping,
 }
 
+/// Resolve the arguments of a field used in a filter or an order by to the arguments of its
+/// column. Only literals are supported, as for field arguments in selections.
+pub fn resolve_column_arguments(
+    field_name: &FieldName,
+    input_arguments: &IndexMap<ArgumentName, Value>,
+    field_mapping: &FieldMapping,
+) -> Result<BTreeMap<DataConnectorArgumentName, serde_json::Value>, PlanError> {
+    let mut arguments = BTreeMap::new();
+    for (argument_name, argument_value) in input_arguments {
+        let ndc_argument_name = field_mapping.argument_mappings.get(argument_name).ok_or_else(|| {
+            PlanError::Internal(format!(
+                "couldn't find the argument mapping for argument {argument_name} in field {field_name}"
+            ))
+        })?;
+        let Value::Literal(value) = argument_value else {
+            return Err(PlanError::Internal(format!(
+                "boolean expression arguments are not supported in field {field_name}",
+            )));
+        };
+        arguments.insert(ndc_argument_name.clone(), value.clone());
+    }
+    Ok(arguments)
+}
+
 /// Convert an ObjectFieldOperand into an NDC comparison target.
 /// Also returns the FieldMapping for the enclosing object type, so
 /// that additional mapping data (e.g. operators) can be extracted.
@@ -48,6 +79,11 @@ pub fn to_resolved_column(
 
     // The NDC field name of the top-level column
     let column_name = field_mapping.column.clone();
+    let arguments = resolve_column_arguments(
+        &operand.target.field_name,
+        &operand.target.arguments,
+        field_mapping,
+    )?;
 
     // Keep track of the current field path (consisting of NDC names):
     let mut field_path = vec![];
@@ -77,6 +113,7 @@ pub fn to_resolved_column(
 
                 return Ok(ResolvedColumn {
                     column_name,
+                    arguments,
                     field_path,
                     field_mapping: field_mapping.clone(),
                 });
@@ -123,6 +123,7 @@ fn to_filter_expression_internal<'metadata>(
         BooleanExpression::IsNull(open_dds::query::Operand::Field(field)) => {
             let ResolvedColumn {
                 column_name,
+                arguments,
                 field_path,
                 ..
             } = to_resolved_column(
@@ -137,6 +138,7 @@ fn to_filter_expression_internal<'metadata>(
                 plan_types::LocalFieldComparison::UnaryComparison {
                     column: plan_types::ComparisonTarget::Column {
                         name: column_name,
+                        arguments,
                         field_path,
                     },
                     operator: metadata_resolve::UnaryComparisonOperator::IsNull,
@@ -273,6 +275,7 @@ fn to_filter_expression_internal<'metadata>(
                             Some(open_dds::query::Operand::Field(object_field_operand)) => {
                                 let ResolvedColumn {
                                     column_name,
+                                    arguments: _,
                                     field_path,
                                     field_mapping: _,
                                 } = to_resolved_column(
@@ -650,6 +653,7 @@ fn to_scalar_comparison_field<'metadata, 'other>(
 ) -> Result<Expression<'metadata>, PlanError> {
     let ResolvedColumn {
         column_name: source_column,
+        arguments,
         field_path: more_column_path,
         field_mapping,
     } = to_resolved_column(
@@ -661,6 +665,14 @@ fn to_scalar_comparison_field<'metadata, 'other>(
         object_field_operand,
     )?;
 
+    // arguments are those of the top-level column, so fields nested in an object can't have any
+    if !column_path.is_empty() && !arguments.is_empty() {
+        return Err(PlanError::Internal(format!(
+            "arguments are not supported in nested field {}",
+            object_field_operand.target.field_name
+        )));
+    }
+
     // add field path to existing
     let mut column_path: Vec<_> = column_path.iter().map(|a| (*a).clone()).collect();
     column_path.extend(more_column_path);
@@ -724,6 +736,7 @@ fn to_scalar_comparison_field<'metadata, 'other>(
                 Expression::LocalField(plan_types::LocalFieldComparison::BinaryComparison {
                     column: plan_types::ComparisonTarget::Column {
                         name: column_name,
+                        arguments,
                         field_path: column_path,
                     },
                     operator: data_connector_operator_name.clone(),
@@ -851,6 +864,7 @@ fn to_scalar_comparison_field<'metadata, 'other>(
                 plan_types::LocalFieldComparison::BinaryComparison {
                     column: plan_types::ComparisonTarget::Column {
                         name: column_name,
+                        arguments,
                         field_path: column_path,
                     },
                     operator: data_connector_operator_name.clone(),
@@ -881,6 +895,7 @@ fn to_scalar_comparison_field<'metadata, 'other>(
             let expr = Expression::LocalField(plan_types::LocalFieldComparison::BinaryComparison {
                 column: plan_types::ComparisonTarget::Column {
                     name: column_name,
+                    arguments,
                     field_path: column_path,
                 },
                 operator: data_connector_operators.get(custom_operator).clone(),
@@ -135,6 +135,19 @@ fn resolve_field_operand(
             })?,
     };
     let column_name = field_mapping.column.clone();
+    let arguments = crate::column::resolve_column_arguments(
+        &operand.target.field_name,
+        &operand.target.arguments,
+        field_mapping,
+    )?;
+    // arguments are those of the top-level column, so fields nested in an object can't have any
+    if !arguments.is_empty() && (!field_path.is_empty() || operand.nested.is_some()) {
+        return Err(OrderByError::NestedOrderByNotSupported(format!(
+            "arguments are not supported in nested field {}",
+            operand.target.field_name
+        ))
+        .into_plan_error());
+    }
 
     if let Some(nested_operand) = &operand.nested {
         let field = object_type
@@ -188,6 +201,7 @@ fn resolve_field_operand(
         Ok(plan_types::OrderByTarget::Column {
             relationship_path,
             name: first_column,
+            arguments,
             field_path: field_path
                 .into_iter()
                 .chain([column_name]) // Append column_name to field_path
@@ -148,6 +148,7 @@ fn make_permission_binary_boolean_expression<'s>(
         LocalFieldComparison::BinaryComparison {
             column: ComparisonTarget::Column {
                 name: ndc_column.clone(),
+                arguments: BTreeMap::new(),
                 field_path: vec![],
             },
             operator: operator.clone(),
@@ -165,6 +166,7 @@ fn make_permission_unary_boolean_expression<'s>(
     Expression::LocalField(LocalFieldComparison::UnaryComparison {
         column: ComparisonTarget::Column {
             name: ndc_column.clone(),
+            arguments: BTreeMap::new(),
             field_path: vec![],
         },
         operator,
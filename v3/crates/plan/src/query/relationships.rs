@@ -390,6 +390,7 @@ pub fn calculate_remote_relationship_fields_for_model_target(
                 let comparison_exp = LocalFieldComparison::BinaryComparison {
                     column: ComparisonTarget::Column {
                         name: target_ndc_column.column.clone(),
+                        arguments: BTreeMap::new(),
                         field_path: vec![],
                     },
                     operator: target_ndc_column.equal_operator.clone(),